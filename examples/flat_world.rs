@@ -0,0 +1,26 @@
+use glam::Vec3;
+use graphics_test::{
+    engine::Engine, engine_config::EngineConfig, voxels::voxel_scene::WorldConfig,
+};
+use winit::event_loop::EventLoop;
+
+// A few voxels of ground around the spawn, the biomes only pick what the ground is made of.
+// Run with cargo run --example flat_world
+fn main() {
+    let config = EngineConfig {
+        title: "Flat world".to_string(),
+        world: WorldConfig {
+            min_height: 0,
+            max_height: 4,
+            ..Default::default()
+        },
+        view_distance: 4,
+        ..Default::default()
+    };
+
+    let event_loop = EventLoop::new();
+    let mut engine = Engine::new(config, &event_loop);
+    engine.spawn_player(Vec3::new(0.0, 6.0, 0.0));
+    engine.spawn_sun();
+    engine.run(event_loop);
+}
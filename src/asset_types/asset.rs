@@ -3,6 +3,7 @@ use bus::BusReader;
 #[derive(Debug, Clone, Copy)]
 pub enum AssetChangeType {
     Modified,
+    Destroyed,
 }
 
 pub trait Asset {
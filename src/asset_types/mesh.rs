@@ -1,8 +1,12 @@
-use crate::{next_id, rendering::vertex::Vertex};
+use crate::{asset_types::vertex::Vertex, next_id};
 use bus::Bus;
 use core::fmt::Debug;
-use glam::Vec3;
+use glam::{DVec3, Vec3};
 use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
 
 use super::asset::{Asset, AssetChangeType};
 
@@ -49,6 +53,10 @@ impl Mesh {
                 color,
                 normal,
                 uv: [0.0, 0.0],
+                tangent: [1.0, 0.0, 0.0, 1.0],
+                texture_layer: 0,
+                ao: 1.0,
+                light: 0.0,
             }) // TODO: Add UVs
         });
 
@@ -76,6 +84,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [0.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         // v1
@@ -84,6 +96,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         // v2
@@ -92,6 +108,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         // v3
@@ -100,6 +120,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [1.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         self.send_changes(AssetChangeType::Modified);
@@ -120,6 +144,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [0.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         // v1
@@ -128,6 +156,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         // v2
@@ -136,6 +168,10 @@ impl Mesh {
             color: color,
             normal,
             uv: [0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
         });
 
         self.vertex_count = self.vertices.len();
@@ -175,6 +211,11 @@ impl Mesh {
         self.send_changes(AssetChangeType::Modified);
     }
 
+    // Tells everything using this mesh to let go of it (renderers drop their geometry from the render passes)
+    pub fn destroy(&mut self) {
+        self.send_changes(AssetChangeType::Destroyed);
+    }
+
     pub fn get_vertices(&self) -> &Vec<Vertex> {
         &self.vertices
     }
@@ -190,6 +231,391 @@ impl Mesh {
             vertex.position[2] += offset.z;
         });
     }
+
+    // Per-vertex tangents using Lengyel's method. Triangles with degenerate uvs don't contribute,
+    // vertices left without a tangent get an arbitrary one orthonormal to their normal
+    pub fn generate_tangents(&mut self) {
+        let mut tan1 = vec![Vec3::ZERO; self.vertices.len()];
+        let mut tan2 = vec![Vec3::ZERO; self.vertices.len()];
+
+        for tri in self.indices.chunks_exact(3) {
+            let (i0, i1, i2) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            let (v0, v1, v2) = (self.vertices[i0], self.vertices[i1], self.vertices[i2]);
+
+            let e1 = Vec3::from(v1.position) - Vec3::from(v0.position);
+            let e2 = Vec3::from(v2.position) - Vec3::from(v0.position);
+            let (s1, t1) = (v1.uv[0] - v0.uv[0], v1.uv[1] - v0.uv[1]);
+            let (s2, t2) = (v2.uv[0] - v0.uv[0], v2.uv[1] - v0.uv[1]);
+
+            let determinant = s1 * t2 - s2 * t1;
+            if determinant.abs() <= f32::EPSILON {
+                continue; // Zero uv area
+            }
+            let r = 1.0 / determinant;
+            let sdir = (e1 * t2 - e2 * t1) * r;
+            let tdir = (e2 * s1 - e1 * s2) * r;
+
+            for i in [i0, i1, i2] {
+                tan1[i] += sdir;
+                tan2[i] += tdir;
+            }
+        }
+
+        self.vertices
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, vertex)| {
+                let normal = Vec3::from(vertex.normal).normalize_or_zero();
+                // Gram-Schmidt orthogonalize
+                let tangent = (tan1[i] - normal * normal.dot(tan1[i])).normalize_or_zero();
+                vertex.tangent = if tangent == Vec3::ZERO || normal == Vec3::ZERO {
+                    let fallback = if normal == Vec3::ZERO {
+                        Vec3::X
+                    } else {
+                        normal.any_orthonormal_vector()
+                    };
+                    [fallback.x, fallback.y, fallback.z, 1.0]
+                } else {
+                    let handedness = if normal.cross(tangent).dot(tan2[i]) < 0.0 {
+                        -1.0
+                    } else {
+                        1.0
+                    };
+                    [tangent.x, tangent.y, tangent.z, handedness]
+                };
+            });
+
+        self.send_changes(AssetChangeType::Modified);
+    }
+
+    // Edge collapse decimation using quadric error metrics (Garland & Heckbert).
+    // Vertices are welded by position so that the split vertices of voxel meshes still share topology,
+    // but every original vertex keeps its own normal, color and uv. Vertices on boundary or non-manifold
+    // edges are locked, so open chunk borders and holes keep their exact outline.
+    pub fn simplify(&mut self, target_ratio: f32) {
+        let triangle_count = self.indices.len() / 3;
+        let target_triangles =
+            (triangle_count as f32 * target_ratio.clamp(0.0, 1.0)).round() as usize;
+        if triangle_count == 0 || target_triangles >= triangle_count {
+            return;
+        }
+
+        // Weld vertices that share a position
+        let mut weld_lookup: HashMap<[i64; 3], usize> = HashMap::new();
+        let mut positions: Vec<DVec3> = Vec::new();
+        let welded: Vec<usize> = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let key = vertex
+                    .position
+                    .map(|v| (v as f64 * 10_000.0).round() as i64);
+                *weld_lookup.entry(key).or_insert_with(|| {
+                    positions.push(Vec3::from(vertex.position).as_dvec3());
+                    positions.len() - 1
+                })
+            })
+            .collect();
+
+        let mut triangles: Vec<[usize; 3]> = self
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                [
+                    welded[tri[0] as usize],
+                    welded[tri[1] as usize],
+                    welded[tri[2] as usize],
+                ]
+            })
+            .collect();
+        let mut triangle_alive: Vec<bool> = triangles
+            .iter()
+            .map(|tri| tri[0] != tri[1] && tri[1] != tri[2] && tri[0] != tri[2])
+            .collect();
+        let mut alive_count = triangle_alive.iter().filter(|alive| **alive).count();
+
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+        for (tri_index, tri) in triangles.iter().enumerate() {
+            if !triangle_alive[tri_index] {
+                continue;
+            }
+            let quadric =
+                Quadric::from_triangle(positions[tri[0]], positions[tri[1]], positions[tri[2]]);
+            for corner in 0..3 {
+                vertex_triangles[tri[corner]].push(tri_index);
+                quadrics[tri[corner]].add(&quadric);
+                *edge_uses
+                    .entry(edge_key(tri[corner], tri[(corner + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+
+        // Anything that isn't shared by exactly two triangles is a boundary (or non-manifold) edge
+        let mut locked = vec![false; positions.len()];
+        edge_uses.iter().for_each(|((a, b), uses)| {
+            if *uses != 2 {
+                locked[*a] = true;
+                locked[*b] = true;
+            }
+        });
+
+        let mut versions = vec![0_u32; positions.len()];
+        let mut removed = vec![false; positions.len()];
+        let mut heap = BinaryHeap::new();
+        for (a, b) in edge_uses.keys() {
+            if let Some(candidate) =
+                CollapseCandidate::new(*a, *b, &positions, &quadrics, &versions, &locked)
+            {
+                heap.push(candidate);
+            }
+        }
+
+        while alive_count > target_triangles {
+            let candidate = match heap.pop() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            let (from, to) = (candidate.from, candidate.to);
+            if removed[from]
+                || removed[to]
+                || versions[from] != candidate.from_version
+                || versions[to] != candidate.to_version
+            {
+                continue; // Stale candidate
+            }
+
+            // Reject collapses that would flip a surviving triangle
+            let flips = [from, to].iter().any(|moved| {
+                vertex_triangles[*moved].iter().any(|tri_index| {
+                    let tri = triangles[*tri_index];
+                    if !triangle_alive[*tri_index] || (tri.contains(&from) && tri.contains(&to)) {
+                        return false;
+                    }
+                    let before = tri.map(|v| positions[v]);
+                    let after = tri.map(|v| {
+                        if v == from || v == to {
+                            candidate.position
+                        } else {
+                            positions[v]
+                        }
+                    });
+                    let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                    let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                    normal_before.dot(normal_after) <= 0.0
+                })
+            });
+            if flips {
+                continue; // The edge is queued again once a neighbour collapses and it changes
+            }
+
+            // Collapse `from` into `to`
+            positions[to] = candidate.position;
+            let from_quadric = quadrics[from];
+            quadrics[to].add(&from_quadric);
+            removed[from] = true;
+            versions[to] += 1;
+
+            let from_triangles = std::mem::take(&mut vertex_triangles[from]);
+            for tri_index in from_triangles {
+                if !triangle_alive[tri_index] {
+                    continue;
+                }
+                let tri = &mut triangles[tri_index];
+                if tri.contains(&to) {
+                    triangle_alive[tri_index] = false;
+                    alive_count -= 1;
+                    continue;
+                }
+                tri.iter_mut().filter(|v| **v == from).for_each(|v| *v = to);
+                vertex_triangles[to].push(tri_index);
+            }
+            vertex_triangles[to].retain(|tri_index| triangle_alive[*tri_index]);
+            vertex_triangles[to].sort_unstable();
+            vertex_triangles[to].dedup();
+
+            // Queue the edges around the merged vertex again, the other edges of its neighbours didn't change and
+            // stay queued
+            let mut neighbours: Vec<usize> = vertex_triangles[to]
+                .iter()
+                .flat_map(|tri_index| triangles[*tri_index])
+                .filter(|v| *v != to)
+                .collect();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            for neighbour in neighbours {
+                if let Some(candidate) =
+                    CollapseCandidate::new(to, neighbour, &positions, &quadrics, &versions, &locked)
+                {
+                    heap.push(candidate);
+                }
+            }
+        }
+
+        // Rebuild the buffers from the surviving triangles
+        let mut remap: Vec<Option<u32>> = vec![None; self.vertices.len()];
+        let mut new_vertices = Vec::new();
+        let mut new_indices = Vec::with_capacity(alive_count * 3);
+        for (tri_index, original) in self.indices.chunks_exact(3).enumerate() {
+            if !triangle_alive[tri_index] {
+                continue;
+            }
+            for corner in 0..3 {
+                let original_index = original[corner] as usize;
+                let index = *remap[original_index].get_or_insert_with(|| {
+                    let mut vertex = self.vertices[original_index];
+                    vertex.position = positions[triangles[tri_index][corner]].as_vec3().into();
+                    new_vertices.push(vertex);
+                    new_vertices.len() as u32 - 1
+                });
+                new_indices.push(index);
+            }
+        }
+
+        self.vertex_count = new_vertices.len();
+        self.index_count = new_indices.len();
+        self.vertices = new_vertices;
+        self.indices = new_indices;
+        self.send_changes(AssetChangeType::Modified);
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Symmetric 4x4 error quadric, stored as its upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+    data: [f64; 10],
+}
+
+impl Quadric {
+    fn from_triangle(p0: DVec3, p1: DVec3, p2: DVec3) -> Self {
+        let normal = (p1 - p0).cross(p2 - p0);
+        let area = normal.length();
+        if area <= f64::EPSILON {
+            return Self::default();
+        }
+        let normal = normal / area;
+        let (a, b, c, d) = (normal.x, normal.y, normal.z, -normal.dot(p0));
+        // Weight by area so large faces dominate small slivers
+        let w = area * 0.5;
+        Self {
+            data: [
+                a * a * w,
+                a * b * w,
+                a * c * w,
+                a * d * w,
+                b * b * w,
+                b * c * w,
+                b * d * w,
+                c * c * w,
+                c * d * w,
+                d * d * w,
+            ],
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for i in 0..10 {
+            self.data[i] += other.data[i];
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.data;
+        q[0] * p.x * p.x
+            + 2.0 * q[1] * p.x * p.y
+            + 2.0 * q[2] * p.x * p.z
+            + 2.0 * q[3] * p.x
+            + q[4] * p.y * p.y
+            + 2.0 * q[5] * p.y * p.z
+            + 2.0 * q[6] * p.y
+            + q[7] * p.z * p.z
+            + 2.0 * q[8] * p.z
+            + q[9]
+    }
+}
+
+struct CollapseCandidate {
+    cost: f64,
+    from: usize,
+    to: usize,
+    position: DVec3,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl CollapseCandidate {
+    fn new(
+        a: usize,
+        b: usize,
+        positions: &[DVec3],
+        quadrics: &[Quadric],
+        versions: &[u32],
+        locked: &[bool],
+    ) -> Option<Self> {
+        if locked[a] && locked[b] {
+            return None;
+        }
+        let mut quadric = quadrics[a];
+        quadric.add(&quadrics[b]);
+
+        // Locked vertices can't move, so the collapse has to end on them
+        let options: Vec<(usize, usize, DVec3)> = if locked[a] {
+            vec![(b, a, positions[a])]
+        } else if locked[b] {
+            vec![(a, b, positions[b])]
+        } else {
+            vec![
+                (a, b, positions[b]),
+                (b, a, positions[a]),
+                (a, b, (positions[a] + positions[b]) * 0.5),
+            ]
+        };
+
+        options
+            .into_iter()
+            .map(|(from, to, position)| Self {
+                cost: quadric.error(position),
+                from,
+                to,
+                position,
+                from_version: versions[from],
+                to_version: versions[to],
+            })
+            .min_by(|x, y| x.cost.partial_cmp(&y.cost).unwrap_or(Ordering::Equal))
+    }
+}
+
+// BinaryHeap is a max-heap, so order candidates by lowest cost first
+impl PartialEq for CollapseCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for CollapseCandidate {}
+
+impl PartialOrd for CollapseCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CollapseCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
 }
 
 impl Asset for Mesh {
@@ -229,3 +655,128 @@ impl Debug for Mesh {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod simplify_tests {
+    use super::Mesh;
+
+    fn grid(size: u32) -> Mesh {
+        let mut mesh = Mesh::new();
+        for x in 0..size {
+            for z in 0..size {
+                let (x, z) = (x as f32, z as f32);
+                mesh = mesh.append_quad(
+                    [
+                        [x, 0.0, z],
+                        [x, 0.0, z + 1.0],
+                        [x + 1.0, 0.0, z],
+                        [x + 1.0, 0.0, z + 1.0],
+                    ],
+                    [0.0, 1.0, 0.0],
+                );
+            }
+        }
+        mesh.vertex_count = mesh.get_vertices().len();
+        mesh.index_count = mesh.get_indices().len();
+        mesh
+    }
+
+    #[test]
+    fn flat_grid_reaches_the_target() {
+        for ratio in [0.5, 0.25] {
+            let mut mesh = grid(8);
+            let target = (mesh.index_count / 3) as f32 * ratio;
+            mesh.simplify(ratio);
+            let after = (mesh.index_count / 3) as f32;
+            assert!(
+                (after - target).abs() <= 2.0,
+                "{after} triangles left for {target}"
+            );
+        }
+        // Only the outline is left once every inner vertex is gone, 28 locked vertices span 30 triangles
+        let mut mesh = grid(8);
+        mesh.simplify(0.1);
+        assert_eq!(mesh.index_count / 3, 30);
+    }
+
+    #[test]
+    fn boundary_is_preserved() {
+        let mut mesh = grid(8);
+        mesh.simplify(0.1);
+        // All corners of the grid must survive
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [8.0, 0.0, 0.0],
+            [0.0, 0.0, 8.0],
+            [8.0, 0.0, 8.0],
+        ] {
+            assert!(mesh.get_vertices().iter().any(|v| v.position == corner));
+        }
+        // Every vertex stays on the original boundary square or inside it
+        assert!(mesh
+            .get_vertices()
+            .iter()
+            .all(|v| v.position[0] >= 0.0 && v.position[0] <= 8.0 && v.position[1] == 0.0));
+    }
+}
+
+#[cfg(test)]
+mod tangent_tests {
+    use super::Mesh;
+    use crate::asset_types::vertex::Vertex;
+    use glam::Vec3;
+
+    fn mesh(vertices: Vec<Vertex>, indices: Vec<u32>) -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.set_vertices(vertices);
+        mesh.set_indices(indices);
+        mesh.generate_tangents();
+        mesh
+    }
+
+    // A unit quad facing +Z with u along +X, and v along +Y or -Y when mirrored
+    fn quad(mirrored: bool) -> Mesh {
+        let v = if mirrored { -1.0 } else { 1.0 };
+        let vertices = [
+            ([0.0, 0.0, 0.0], [0.0, 0.0]),
+            ([1.0, 0.0, 0.0], [1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [0.0, v]),
+            ([1.0, 1.0, 0.0], [1.0, v]),
+        ]
+        .map(|(position, uv)| Vertex {
+            uv,
+            tangent: [0.0; 4],
+            ..Vertex::new(position)
+        });
+        mesh(vertices.to_vec(), vec![0, 1, 2, 2, 1, 3])
+    }
+
+    #[test]
+    fn uv_mapped_quad_has_tangents_along_u() {
+        for (mirrored, handedness) in [(false, 1.0), (true, -1.0)] {
+            for vertex in quad(mirrored).get_vertices() {
+                let tangent = Vec3::from_slice(&vertex.tangent[..3]);
+                assert!(tangent.abs_diff_eq(Vec3::X, 1e-5), "{tangent}");
+                assert_eq!(vertex.tangent[3], handedness);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_uv_area_gets_an_orthonormal_fallback() {
+        let normal = Vec3::new(1.0, 2.0, 3.0).normalize();
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .map(|position| Vertex {
+                normal: normal.into(),
+                uv: [0.5, 0.5],
+                ..Vertex::new(position)
+            })
+            .to_vec();
+        for vertex in mesh(vertices, vec![0, 1, 2]).get_vertices() {
+            let tangent = Vec3::from_slice(&vertex.tangent[..3]);
+            assert!((tangent.length() - 1.0).abs() < 1e-5, "{tangent}");
+            assert!(tangent.dot(normal).abs() < 1e-5, "{tangent}");
+            assert_eq!(vertex.tangent[3].abs(), 1.0);
+        }
+    }
+}
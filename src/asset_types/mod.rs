@@ -1,2 +1,3 @@
 pub mod asset;
 pub mod mesh;
+pub mod vertex;
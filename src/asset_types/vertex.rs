@@ -0,0 +1,179 @@
+use glam::Vec3;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub tangent: [f32; 4],  // w stores the handedness of the bitangent
+    pub texture_layer: u32, // Layer of the texture array, 0 is plain white
+    pub ao: f32,            // Ambient occlusion, 0 is fully occluded
+    pub light: f32, // Block light reaching the face, from 0 in the dark to 1 right next to a light
+}
+
+impl Vertex {
+    pub fn new(position: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            color: [1.0, 1.0, 1.0, 1.0],
+            normal: [0.0, 0.0, 1.0],
+            uv: [0.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            texture_layer: 0,
+            ao: 1.0,
+            light: 0.0,
+        }
+    }
+}
+
+// Steps per unit the positions and UVs of a packed vertex are rounded to, fine enough for slabs and stairs
+const PACKED_STEPS: f32 = 16.0;
+// Positions start half a voxel below the chunk origin, voxels span half a unit around their position
+const PACKED_POSITION_OFFSET: f32 = 0.5;
+const PACKED_UV_OFFSET: f32 = 2048.0;
+const PACKED_NORMAL_STEPS: f32 = 255.0; // Either side of zero, so axis aligned normals stay exact
+
+// A chunk vertex squeezed into 16 bytes, a fifth of a full vertex.
+// data[0]: position x, y and z in 10 bits each, then the ambient occlusion level in 2 bits
+// data[1]: u and v in 16 bits each
+// data[2]: the octahedral normal in 9 bits per axis, the texture layer in 10 bits, then the block light in 4 bits
+// Positions cover a chunk and UVs the faces stretched over it by greedy meshing, tangents are left out
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct PackedVertex {
+    pub data: [u32; 3],
+    pub color: [u8; 4],
+}
+
+impl PackedVertex {
+    // Values outside of what the format can hold are clamped
+    pub fn pack(vertex: &Vertex) -> PackedVertex {
+        let quantize = |value: f32, offset: f32, max: u32| {
+            ((value + offset) * PACKED_STEPS)
+                .round()
+                .clamp(0.0, max as f32) as u32
+        };
+        let position = vertex
+            .position
+            .map(|value| quantize(value, PACKED_POSITION_OFFSET, 1023));
+        let ao = (vertex.ao.clamp(0.0, 1.0) * 3.0).round() as u32;
+        let uv = vertex
+            .uv
+            .map(|value| quantize(value, PACKED_UV_OFFSET, u16::MAX as u32));
+        let [normal_x, normal_y] = encode_normal(Vec3::from(vertex.normal));
+        let light = (vertex.light.clamp(0.0, 1.0) * 15.0).round() as u32;
+
+        PackedVertex {
+            data: [
+                position[0] | position[1] << 10 | position[2] << 20 | ao << 30,
+                uv[0] | uv[1] << 16,
+                normal_x | normal_y << 9 | vertex.texture_layer.min(1023) << 18 | light << 28,
+            ],
+            color: vertex
+                .color
+                .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8),
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        let axis = |shift: u32| (self.data[0] >> shift & 1023) as f32 / PACKED_STEPS;
+        Vec3::new(axis(0), axis(10), axis(20)) - Vec3::splat(PACKED_POSITION_OFFSET)
+    }
+
+    pub fn uv(&self) -> [f32; 2] {
+        let axis = |shift: u32| (self.data[1] >> shift & 0xffff) as f32 / PACKED_STEPS;
+        [axis(0), axis(16)].map(|value| value - PACKED_UV_OFFSET)
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        decode_normal([self.data[2] & 511, self.data[2] >> 9 & 511])
+    }
+
+    pub fn ao(&self) -> f32 {
+        (self.data[0] >> 30) as f32 / 3.0
+    }
+
+    pub fn texture_layer(&self) -> u32 {
+        self.data[2] >> 18 & 1023
+    }
+
+    pub fn light(&self) -> f32 {
+        (self.data[2] >> 28) as f32 / 15.0
+    }
+}
+
+// Folds the normal onto an octahedron and flattens it to two axes in 0..=510
+fn encode_normal(normal: Vec3) -> [u32; 2] {
+    let length = normal.x.abs() + normal.y.abs() + normal.z.abs();
+    if length <= f32::EPSILON {
+        return encode_normal(Vec3::Z);
+    }
+    let normal = normal / length;
+    let (x, y) = if normal.z < 0.0 {
+        (
+            (1.0 - normal.y.abs()) * normal.x.signum(),
+            (1.0 - normal.x.abs()) * normal.y.signum(),
+        )
+    } else {
+        (normal.x, normal.y)
+    };
+    [x, y].map(|value| ((value + 1.0) * PACKED_NORMAL_STEPS).round() as u32)
+}
+
+// Mirrored by unpack_normal in the voxel shaders
+fn decode_normal(encoded: [u32; 2]) -> Vec3 {
+    let [x, y] = encoded.map(|value| value as f32 / PACKED_NORMAL_STEPS - 1.0);
+    let mut normal = Vec3::new(x, y, 1.0 - x.abs() - y.abs());
+    let fold = (-normal.z).max(0.0);
+    normal.x += if normal.x >= 0.0 { -fold } else { fold };
+    normal.y += if normal.y >= 0.0 { -fold } else { fold };
+    normal.normalize()
+}
+
+#[cfg(test)]
+mod packed_vertex_tests {
+    use super::*;
+
+    #[test]
+    fn packing_keeps_what_chunk_meshes_need() {
+        let slope = Vec3::new(0.0, 1.0, -1.0).normalize();
+        let vertex = Vertex {
+            position: [15.5, -0.5, 7.25],
+            color: [0.2, 0.4, 1.0, 0.5],
+            normal: slope.into(),
+            uv: [16.0, -14.5],
+            texture_layer: 37,
+            ao: 2.0 / 3.0,
+            light: 0.6,
+            ..Vertex::new([0.0; 3])
+        };
+        let packed = PackedVertex::pack(&vertex);
+
+        assert_eq!(packed.position(), Vec3::from(vertex.position));
+        assert_eq!(packed.uv(), vertex.uv);
+        assert!(packed.normal().dot(slope) > 0.9999);
+        assert_eq!(packed.texture_layer(), 37);
+        assert!((packed.ao() - vertex.ao).abs() < 1e-6);
+        assert!((packed.light() - vertex.light).abs() < 1e-6);
+        assert_eq!(packed.color, [51, 102, 255, 128]);
+    }
+
+    #[test]
+    fn axis_normals_stay_exact() {
+        for normal in [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z] {
+            let vertex = Vertex {
+                normal: normal.into(),
+                ..Vertex::new([0.0; 3])
+            };
+            assert_eq!(PackedVertex::pack(&vertex).normal(), normal);
+        }
+    }
+
+    #[test]
+    fn packed_vertices_are_a_fifth_of_the_size() {
+        assert_eq!(std::mem::size_of::<Vertex>(), 76);
+        assert_eq!(std::mem::size_of::<PackedVertex>(), 16);
+    }
+}
@@ -0,0 +1,305 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+};
+#[cfg(feature = "audio")]
+use std::{collections::HashMap, fs, io::Cursor, time::Duration};
+
+use glam::{Quat, Vec3};
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "audio")]
+use rodio::{
+    source::{Buffered, SamplesConverter},
+    Decoder, OutputStream, OutputStreamHandle, Source,
+};
+
+#[cfg(feature = "audio")]
+const SOUND_FOLDER: &str = "./src/resources/sounds/";
+const REFERENCE_DISTANCE: f32 = 2.0; // Sounds play at full volume up to this far from the listener
+const MAX_HEARING_DISTANCE: f32 = 48.0; // And are silent from here on
+
+// Sounds only play while it's alive
+#[cfg(feature = "audio")]
+pub type AudioOutput = OutputStream;
+// Built without the audio feature there's no output to keep alive
+#[cfg(not(feature = "audio"))]
+pub struct AudioOutput;
+
+// Decoded on first use and shared by everything that plays it
+#[cfg(feature = "audio")]
+type Sound = Buffered<SamplesConverter<Decoder<Cursor<Vec<u8>>>, f32>>;
+
+#[cfg(feature = "audio")]
+fn load_sound(bytes: Vec<u8>) -> Result<Sound, String> {
+    Decoder::new(Cursor::new(bytes))
+        .map(|decoder| decoder.convert_samples().buffered())
+        .map_err(|e| e.to_string())
+}
+
+// Left and right volume of a sound from where it is relative to the listener. Past the reference distance the volume
+// falls off inversely with the distance, fading out completely towards the max hearing distance
+pub fn spatial_gains(
+    listener_position: Vec3,
+    listener_rotation: Quat,
+    source_position: Vec3,
+    volume: f32,
+) -> (f32, f32) {
+    let offset = source_position - listener_position;
+    let distance = offset.length();
+    let falloff = REFERENCE_DISTANCE / distance.max(REFERENCE_DISTANCE);
+    let fade = (1.0 - distance / MAX_HEARING_DISTANCE).clamp(0.0, 1.0);
+    let gain = volume * falloff * fade;
+
+    // Constant power panning, a sound in front of or behind the listener is equally loud on both sides
+    let pan = listener_rotation
+        .mul_vec3(Vec3::X)
+        .dot(offset.normalize_or_zero());
+    let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+    (gain * angle.cos(), gain * angle.sin())
+}
+
+// Shared between a playing sound and whoever controls it, the gains are changed while the sound plays
+#[derive(Debug, Default)]
+pub struct SoundControls {
+    left: AtomicU32,
+    right: AtomicU32,
+    stopped: AtomicBool,
+    finished: AtomicBool,
+}
+
+impl SoundControls {
+    pub fn set_gains(&self, (left, right): (f32, f32)) {
+        self.left.store(left.to_bits(), Ordering::Relaxed);
+        self.right.store(right.to_bits(), Ordering::Relaxed);
+    }
+
+    fn gains(&self) -> (f32, f32) {
+        (
+            f32::from_bits(self.left.load(Ordering::Relaxed)),
+            f32::from_bits(self.right.load(Ordering::Relaxed)),
+        )
+    }
+
+    // The sound ends at its next sample
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+// Mixes the sound down to mono and plays it in stereo at the gains of its controls
+#[cfg(feature = "audio")]
+struct Spatialized<I> {
+    input: I,
+    controls: Arc<SoundControls>,
+    right_sample: Option<f32>, // Second half of the frame being played
+}
+
+#[cfg(feature = "audio")]
+impl<I: Source<Item = f32>> Iterator for Spatialized<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(sample) = self.right_sample.take() {
+            return Some(sample);
+        }
+        if self.controls.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        let channels = self.input.channels().max(1);
+        let mut sum = 0.0;
+        for _ in 0..channels {
+            sum += self.input.next()?;
+        }
+        let sample = sum / channels as f32;
+        let (left, right) = self.controls.gains();
+        self.right_sample = Some(sample * right);
+        Some(sample * left)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl<I: Source<Item = f32>> Source for Spatialized<I> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+// The output thread drops the sound once it's done with it
+#[cfg(feature = "audio")]
+impl<I> Drop for Spatialized<I> {
+    fn drop(&mut self) {
+        self.controls.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+// Plays sounds from the sound folder. Without an output device every sound is skipped and the game runs silently.
+// Sounds that are missing or fail to decode are logged once and stay silent, they never take the game down. Built
+// without the audio feature nothing is ever played
+#[derive(Default)]
+pub struct AudioEngine {
+    #[cfg(feature = "audio")]
+    output: Option<OutputStreamHandle>,
+    #[cfg(feature = "audio")]
+    sounds: Mutex<HashMap<String, Option<Sound>>>,
+    listener: RwLock<(Vec3, Quat)>,
+    one_shots: Mutex<Vec<(Vec3, f32, Arc<SoundControls>)>>, // Position and volume of every playing one-shot
+}
+
+impl AudioEngine {
+    // The output stream has to stay alive as long as sounds should play, and it can't leave the thread it's opened on
+    #[cfg(feature = "audio")]
+    pub fn new() -> (Self, Option<AudioOutput>) {
+        match OutputStream::try_default() {
+            Ok((stream, output)) => (
+                Self {
+                    output: Some(output),
+                    ..Default::default()
+                },
+                Some(stream),
+            ),
+            Err(e) => {
+                println!("[INFO] No audio output, the game runs without sound: {e}");
+                (Self::default(), None)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn new() -> (Self, Option<AudioOutput>) {
+        println!("[INFO] Built without the audio feature, the game runs without sound");
+        (Self::default(), None)
+    }
+
+    #[cfg(feature = "audio")]
+    fn sound(&self, name: &str) -> Option<Sound> {
+        self.sounds
+            .lock()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let sound = fs::read(format!("{SOUND_FOLDER}{name}"))
+                    .map_err(|e| e.to_string())
+                    .and_then(load_sound);
+                match sound {
+                    Ok(sound) => Some(sound),
+                    Err(e) => {
+                        println!("[INFO] Can't play sound {name}: {e}");
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    pub fn set_listener(&self, position: Vec3, rotation: Quat) {
+        *self.listener.write() = (position, rotation);
+    }
+
+    pub fn listener_gains(&self, position: Vec3, volume: f32) -> (f32, f32) {
+        let (listener_position, listener_rotation) = *self.listener.read();
+        spatial_gains(listener_position, listener_rotation, position, volume)
+    }
+
+    // Starts a sound at the given gains, returns None when it can't be played
+    #[cfg(feature = "audio")]
+    pub fn play(&self, name: &str, looping: bool, gains: (f32, f32)) -> Option<Arc<SoundControls>> {
+        let output = self.output.as_ref()?;
+        let sound = self.sound(name)?;
+        let controls = Arc::new(SoundControls::default());
+        controls.set_gains(gains);
+        let source = |input| Spatialized {
+            input,
+            controls: Arc::clone(&controls),
+            right_sample: None,
+        };
+        let result = if looping {
+            output.play_raw(source(sound).repeat_infinite())
+        } else {
+            output.play_raw(source(sound))
+        };
+        match result {
+            Ok(()) => Some(controls),
+            Err(e) => {
+                println!("[INFO] Can't play sound {name}: {e}");
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn play(
+        &self,
+        _name: &str,
+        _looping: bool,
+        _gains: (f32, f32),
+    ) -> Option<Arc<SoundControls>> {
+        None
+    }
+
+    // Fire and forget, the sound keeps its place in the world while the listener moves
+    pub fn play_at(&self, name: &str, position: Vec3) {
+        self.play_at_volume(name, position, 1.0);
+    }
+
+    pub fn play_at_volume(&self, name: &str, position: Vec3, volume: f32) {
+        let gains = self.listener_gains(position, volume);
+        if let Some(controls) = self.play(name, false, gains) {
+            self.one_shots.lock().push((position, volume, controls));
+        }
+    }
+
+    // Pans and fades the playing one-shots to where the listener is now, finished ones are forgotten
+    pub fn update_one_shots(&self) {
+        let mut one_shots = self.one_shots.lock();
+        one_shots.retain(|(_, _, controls)| !controls.is_finished());
+        for (position, volume, controls) in one_shots.iter() {
+            controls.set_gains(self.listener_gains(*position, *volume));
+        }
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    #[test]
+    fn sounds_fade_with_distance_and_pan_to_their_side() {
+        let near = spatial_gains(Vec3::ZERO, Quat::IDENTITY, Vec3::new(0.0, 0.0, 4.0), 1.0);
+        let far = spatial_gains(Vec3::ZERO, Quat::IDENTITY, Vec3::new(0.0, 0.0, 20.0), 1.0);
+        assert!((near.0 - near.1).abs() < 1e-5);
+        assert!(far.0 < near.0 && far.0 > 0.0);
+        let silent = spatial_gains(Vec3::ZERO, Quat::IDENTITY, Vec3::splat(100.0), 1.0);
+        assert_eq!(silent, (0.0, 0.0));
+
+        // Turned around, the sound on the right ends up on the left
+        let right = spatial_gains(Vec3::ZERO, Quat::IDENTITY, Vec3::new(3.0, 0.0, 0.0), 1.0);
+        assert!(right.1 > right.0);
+        let turned = Quat::from_rotation_y(std::f32::consts::PI);
+        let left = spatial_gains(Vec3::ZERO, turned, Vec3::new(3.0, 0.0, 0.0), 1.0);
+        assert!(left.0 > left.1);
+    }
+
+    #[test]
+    fn broken_sounds_dont_panic() {
+        #[cfg(feature = "audio")]
+        assert!(load_sound(b"definitely not audio".to_vec()).is_err());
+        let audio = AudioEngine::default();
+        audio.play_at("missing.wav", Vec3::ZERO);
+        audio.update_one_shots();
+    }
+}
@@ -0,0 +1 @@
+pub mod audio_engine;
@@ -0,0 +1,357 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+use legion::IntoQuery;
+use parking_lot::RwLock;
+
+use super::{ArgKind, ArgSpec, ArgValue, Command, CommandError, Console, EngineContext};
+use crate::{
+    ecs::{
+        components::{
+            physics_components::KinematicCharacterBody,
+            player_components::Player,
+            transformation_components::{Position, PreviousPosition},
+        },
+        systems::player_controller::teleport_player,
+    },
+    physics::physics_scene::{lock_physics, PhysicsScene},
+    time::Time,
+    voxels::{
+        biome_profile::reload_biomes, voxel_registry::get_voxel_by_name, voxel_scene::VoxelScene,
+    },
+};
+
+pub fn register_builtin_commands(console: &mut Console) {
+    console.register(Teleport);
+    console.register(Give);
+    console.register(Seed);
+    console.register(RegenerateChunk);
+    console.register(SetTimeScale);
+    console.register(ReloadBiomes);
+    console.register(Help);
+}
+
+// The scenes are shared as Arc<RwLock<T>> resources, cloned so the resources aren't borrowed while they're locked
+fn shared<T: Send + Sync + 'static>(
+    context: &EngineContext,
+) -> Result<Arc<RwLock<T>>, CommandError> {
+    context
+        .resources
+        .get::<Arc<RwLock<T>>>()
+        .map(|shared| Arc::clone(&shared))
+        .ok_or_else(|| {
+            CommandError::Failed(format!("{} isn't available", std::any::type_name::<T>()))
+        })
+}
+
+fn no_player() -> CommandError {
+    CommandError::Failed("There is no player yet".to_string())
+}
+
+// Sends the player to the position, dropping its speed
+pub struct Teleport;
+
+impl Command for Teleport {
+    fn name(&self) -> &str {
+        "tp"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[
+            ArgSpec {
+                name: "x",
+                kind: ArgKind::Float,
+            },
+            ArgSpec {
+                name: "y",
+                kind: ArgKind::Float,
+            },
+            ArgSpec {
+                name: "z",
+                kind: ArgKind::Float,
+            },
+        ]
+    }
+
+    fn description(&self) -> &str {
+        "Teleports the player"
+    }
+
+    fn execute(
+        &self,
+        args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let coordinate = |index: usize| args[index].as_f64().unwrap_or_default() as f32;
+        let position = Vec3::new(coordinate(0), coordinate(1), coordinate(2));
+        let physics = shared::<PhysicsScene>(context)?;
+        let mut query = <(
+            &mut Position,
+            Option<&mut PreviousPosition>,
+            &Player,
+            Option<&mut KinematicCharacterBody>,
+        )>::query();
+        let (pos, previous, _, character) = query
+            .iter_mut(&mut context.world.legion_world)
+            .next()
+            .ok_or_else(no_player)?;
+        teleport_player(
+            pos,
+            previous,
+            character,
+            &mut lock_physics(&physics),
+            position,
+        );
+        Ok(format!(
+            "Teleported to {}, {}, {}",
+            position.x, position.y, position.z
+        ))
+    }
+}
+
+// Selects the voxel the player places
+pub struct Give;
+
+impl Command for Give {
+    fn name(&self) -> &str {
+        "give"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[ArgSpec {
+            name: "voxel_name",
+            kind: ArgKind::Word,
+        }]
+    }
+
+    fn description(&self) -> &str {
+        "Selects a voxel to place"
+    }
+
+    fn execute(
+        &self,
+        args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let name = args[0].as_str().unwrap_or_default();
+        let profile = get_voxel_by_name(name.to_string())
+            .filter(|profile| profile.id != 0)
+            .ok_or_else(|| CommandError::Failed(format!("There is no voxel called {name}")))?;
+        let player = <&mut Player>::query()
+            .iter_mut(&mut context.world.legion_world)
+            .next()
+            .ok_or_else(no_player)?;
+        player.selected_voxel = profile.id;
+        Ok(format!("Selected {name}"))
+    }
+}
+
+// Lists every registered command with its arguments
+pub struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[]
+    }
+
+    fn description(&self) -> &str {
+        "Lists the commands"
+    }
+
+    fn execute(
+        &self,
+        _args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let console = shared::<Console>(context)?;
+        let list = console.read().command_list();
+        Ok(list.join("\n"))
+    }
+}
+
+pub struct Seed;
+
+impl Command for Seed {
+    fn name(&self) -> &str {
+        "seed"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[]
+    }
+
+    fn description(&self) -> &str {
+        "Shows the world seed"
+    }
+
+    fn execute(
+        &self,
+        _args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let scene = shared::<VoxelScene>(context)?;
+        let seed = scene.read().config.seed;
+        Ok(format!("Seed: {seed}"))
+    }
+}
+
+// Generates the chunk the player is in again, edits to it are lost
+pub struct RegenerateChunk;
+
+impl Command for RegenerateChunk {
+    fn name(&self) -> &str {
+        "regen-chunk"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[]
+    }
+
+    fn description(&self) -> &str {
+        "Regenerates the chunk the player is in"
+    }
+
+    fn execute(
+        &self,
+        _args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let position = <(&Position, &Player)>::query()
+            .iter(&context.world.legion_world)
+            .next()
+            .map(|(pos, _)| pos.0)
+            .ok_or_else(no_player)?;
+        let chunk = VoxelScene::chunk_at(&position.floor().as_ivec3());
+        let scene = shared::<VoxelScene>(context)?;
+        scene.read().regenerate_chunks(vec![chunk]);
+        Ok(format!("Regenerating chunk {chunk}"))
+    }
+}
+
+// 0 pauses, 1 is real time
+pub struct SetTimeScale;
+
+impl Command for SetTimeScale {
+    fn name(&self) -> &str {
+        "set-time"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[ArgSpec {
+            name: "scale",
+            kind: ArgKind::Float,
+        }]
+    }
+
+    fn description(&self) -> &str {
+        "Sets how fast time passes"
+    }
+
+    fn execute(
+        &self,
+        args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let scale = args[0].as_f64().unwrap_or_default();
+        if !scale.is_finite() || scale < 0.0 {
+            return Err(CommandError::Failed(format!(
+                "The time scale can't be {scale}"
+            )));
+        }
+        let mut time = context
+            .resources
+            .get_mut::<Time>()
+            .ok_or_else(|| CommandError::Failed("Time isn't available".to_string()))?;
+        time.time_scale = scale;
+        Ok(format!("Time scale set to {scale}"))
+    }
+}
+
+// Biomes that fail to parse keep their previous version, the loaded chunks are regenerated either way
+pub struct ReloadBiomes;
+
+impl Command for ReloadBiomes {
+    fn name(&self) -> &str {
+        "reload-biomes"
+    }
+
+    fn args(&self) -> &[ArgSpec] {
+        &[]
+    }
+
+    fn description(&self) -> &str {
+        "Reloads the biome profiles and regenerates the loaded chunks"
+    }
+
+    fn execute(
+        &self,
+        _args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError> {
+        let errors = reload_biomes();
+        shared::<VoxelScene>(context)?
+            .read()
+            .regenerate_loaded_chunks();
+        if errors.is_empty() {
+            return Ok("Reloaded the biomes".to_string());
+        }
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        Err(CommandError::Failed(format!(
+            "Reloaded the biomes, {} failed to parse:\n{}",
+            errors.len(),
+            errors.join("\n")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod command_tests {
+    use legion::Resources;
+    use parking_lot::RwLock;
+
+    use super::*;
+    use crate::{console::run_console_commands, ecs::world::World};
+
+    #[test]
+    fn teleport_and_time_scale_change_the_world() {
+        let console = Arc::new(RwLock::new(Console::new()));
+        register_builtin_commands(&mut console.write());
+        let world = RwLock::new(World {
+            legion_world: legion::World::default(),
+        });
+        let player = world
+            .write()
+            .legion_world
+            .push((Position(Vec3::ZERO), Player::new(10.0)));
+        let mut resources = Resources::default();
+        resources.insert(Arc::clone(&console));
+        resources.insert(Arc::new(RwLock::new(PhysicsScene::new(60))));
+        resources.insert(Time::new(1.0 / 60.0));
+
+        console
+            .write()
+            .type_text("tp 1 100 -2\rset-time 0.5\rset-time -1\rgive nothing\r");
+        run_console_commands(&world, &mut resources);
+
+        let position = world
+            .write()
+            .legion_world
+            .entry(player)
+            .unwrap()
+            .get_component::<Position>()
+            .unwrap()
+            .0;
+        assert_eq!(position, Vec3::new(1.0, 100.0, -2.0));
+        assert_eq!(resources.get::<Time>().unwrap().time_scale, 0.5);
+        let errors = console
+            .read()
+            .output()
+            .filter(|line| line.starts_with("[ERROR]"))
+            .count();
+        assert_eq!(errors, 2);
+    }
+}
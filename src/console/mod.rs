@@ -0,0 +1,484 @@
+pub mod commands;
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::Arc,
+};
+
+use legion::Resources;
+use parking_lot::RwLock;
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    ecs::world::World,
+    input_manager::{get_text_key, get_typed_text, set_text_input},
+    rendering::text::draw_text,
+};
+
+// The key that opens and closes the console, the character it types is never added to the input
+pub const CONSOLE_KEY: VirtualKeyCode = VirtualKeyCode::Grave;
+const CONSOLE_CHARACTER: char = '`';
+const MAX_OUTPUT_LINES: usize = 200;
+const MAX_HISTORY: usize = 100;
+const VISIBLE_LINES: usize = 12;
+const LINE_HEIGHT: f32 = 20.0;
+const TEXT_SIZE: f32 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Int,
+    Float,
+    Word, // Anything without whitespace
+}
+
+// One argument a command takes, in the order they're typed
+#[derive(Debug, Clone, Copy)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgValue {
+    Int(i64),
+    Float(f64),
+    Word(String),
+}
+
+impl ArgValue {
+    // Ints read as floats too, so commands taking a Float don't have to care
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ArgValue::Int(value) => Some(*value as f64),
+            ArgValue::Float(value) => Some(*value),
+            ArgValue::Word(_) => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ArgValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ArgValue::Word(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidArgument {
+        name: &'static str,
+        value: String,
+        kind: ArgKind,
+    },
+    TooManyArguments(usize), // How many the command takes
+    Failed(String),          // The command ran but couldn't do what it was asked
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand(name) => write!(f, "Unknown command {name}"),
+            CommandError::MissingArgument(name) => write!(f, "Missing argument {name}"),
+            CommandError::InvalidArgument { name, value, kind } => {
+                write!(f, "Argument {name} should be {kind:?}, got {value}")
+            }
+            CommandError::TooManyArguments(count) => {
+                write!(f, "Too many arguments, the command takes {count}")
+            }
+            CommandError::Failed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+// Everything a command can reach. The world is already locked, so the physics scene can be locked after it
+pub struct EngineContext<'a> {
+    pub world: &'a mut World,
+    pub resources: &'a mut Resources,
+}
+
+pub trait Command: Send + Sync {
+    fn name(&self) -> &str;
+    fn args(&self) -> &[ArgSpec];
+    // Shown after the arguments in the command list
+    fn description(&self) -> &str;
+    // The arguments are already parsed by the schema. Returns what to print to the console
+    fn execute(
+        &self,
+        args: &[ArgValue],
+        context: &mut EngineContext,
+    ) -> Result<String, CommandError>;
+}
+
+// A line of text input with a history of what was entered, and a registry of the commands it can run. Entered lines
+// wait in pending until the engine runs them after the next tick, see run_console_commands
+pub struct Console {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    history_cursor: Option<usize>, // The history entry shown in the input while browsing with Up and Down
+    output: VecDeque<String>,
+    pending: Vec<String>,
+    commands: BTreeMap<String, Arc<dyn Command>>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            input: String::new(),
+            history: Vec::new(),
+            history_cursor: None,
+            output: VecDeque::new(),
+            pending: Vec::new(),
+            commands: BTreeMap::new(),
+        }
+    }
+
+    // Replaces whatever was registered under the same name
+    pub fn register(&mut self, command: impl Command + 'static) {
+        self.commands
+            .insert(command.name().to_string(), Arc::new(command));
+    }
+
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.output.push_back(line.into());
+        while self.output.len() > MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn output(&self) -> impl Iterator<Item = &String> {
+        self.output.iter()
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        set_text_input(self.open);
+    }
+
+    // Reads the keyboard once per tick, before any system runs so typing never reaches gameplay
+    pub fn update_input(&mut self) {
+        if get_text_key(CONSOLE_KEY) {
+            self.toggle();
+        }
+        if !self.open {
+            return;
+        }
+        if get_text_key(VirtualKeyCode::Escape) {
+            self.toggle();
+            return;
+        }
+        if get_text_key(VirtualKeyCode::Up) {
+            self.history_previous();
+        }
+        if get_text_key(VirtualKeyCode::Down) {
+            self.history_next();
+        }
+        self.type_text(&get_typed_text());
+    }
+
+    // Backspace removes a character and Return enters the line, other control characters are dropped
+    pub fn type_text(&mut self, text: &str) {
+        for character in text.chars() {
+            match character {
+                '\u{8}' => {
+                    self.input.pop();
+                }
+                '\r' | '\n' => self.submit(),
+                CONSOLE_CHARACTER => {}
+                character if character.is_control() => {}
+                character => self.input.push(character),
+            }
+        }
+    }
+
+    pub fn submit(&mut self) {
+        let line = std::mem::take(&mut self.input);
+        self.history_cursor = None;
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        if self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+        self.pending.push(line.to_string());
+    }
+
+    pub fn history_previous(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let index = match self.history_cursor {
+            Some(index) => index.saturating_sub(1),
+            None => self.history.len() - 1,
+        };
+        self.history_cursor = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    // Past the newest entry the input is empty again
+    pub fn history_next(&mut self) {
+        let index = match self.history_cursor {
+            Some(index) => index + 1,
+            None => return,
+        };
+        if index < self.history.len() {
+            self.history_cursor = Some(index);
+            self.input = self.history[index].clone();
+        } else {
+            self.history_cursor = None;
+            self.input.clear();
+        }
+    }
+
+    pub fn take_pending(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending)
+    }
+
+    // Splits the line on whitespace and checks the arguments against the command's schema
+    pub fn parse(&self, line: &str) -> Result<(Arc<dyn Command>, Vec<ArgValue>), CommandError> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let command = self.commands.get(name).ok_or_else(|| {
+            CommandError::UnknownCommand(format!(
+                "{name}, try one of:\n{}",
+                self.command_list().join("\n")
+            ))
+        })?;
+        let words: Vec<&str> = words.collect();
+        let specs = command.args();
+        if words.len() > specs.len() {
+            return Err(CommandError::TooManyArguments(specs.len()));
+        }
+        let mut args = Vec::with_capacity(specs.len());
+        for (index, spec) in specs.iter().enumerate() {
+            let word = words
+                .get(index)
+                .ok_or(CommandError::MissingArgument(spec.name))?;
+            let invalid = || CommandError::InvalidArgument {
+                name: spec.name,
+                value: word.to_string(),
+                kind: spec.kind,
+            };
+            args.push(match spec.kind {
+                ArgKind::Int => ArgValue::Int(word.parse().map_err(|_| invalid())?),
+                ArgKind::Float => ArgValue::Float(word.parse().map_err(|_| invalid())?),
+                ArgKind::Word => ArgValue::Word(word.to_string()),
+            });
+        }
+        Ok((Arc::clone(command), args))
+    }
+
+    // Every command with its arguments, e.g. "tp x y z - Teleports the player"
+    pub fn command_list(&self) -> Vec<String> {
+        self.commands
+            .values()
+            .map(|command| {
+                let args: Vec<&str> = command.args().iter().map(|spec| spec.name).collect();
+                format!(
+                    "{} {} - {}",
+                    command.name(),
+                    args.join(" "),
+                    command.description()
+                )
+            })
+            .collect()
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Runs the lines entered since the last call. The console is unlocked while a command runs, so commands can reach it
+// through the resources. Errors are printed to the console
+pub fn run_console_commands(world: &RwLock<World>, resources: &mut Resources) {
+    let console = match resources.get::<Arc<RwLock<Console>>>() {
+        Some(console) => Arc::clone(&console),
+        None => return,
+    };
+    let lines = console.write().take_pending();
+    for line in lines {
+        console.write().print(format!("> {line}"));
+        let parsed = console.read().parse(&line);
+        let result = parsed.and_then(|(command, args)| {
+            let mut world_lock = World::lock(world);
+            let mut context = EngineContext {
+                world: &mut world_lock,
+                resources: &mut *resources,
+            };
+            command.execute(&args, &mut context)
+        });
+        let mut console_lock = console.write();
+        match result {
+            Ok(output) => {
+                for line in output.lines() {
+                    console_lock.print(line);
+                }
+            }
+            Err(e) => {
+                for line in e.to_string().lines() {
+                    console_lock.print(format!("[ERROR] {line}"));
+                }
+            }
+        }
+    }
+}
+
+// The last lines of output above the input, at the bottom of the window
+pub fn draw_console(console: &Console, window_height: f32) {
+    if !console.open {
+        return;
+    }
+    let input_y = window_height - 10.0 - LINE_HEIGHT;
+    let lines: Vec<&String> = console.output().collect();
+    let visible = &lines[lines.len().saturating_sub(VISIBLE_LINES)..];
+    for (i, line) in visible.iter().enumerate() {
+        let y = input_y - (visible.len() - i) as f32 * LINE_HEIGHT;
+        let color = if line.starts_with("[ERROR]") {
+            [1.0, 0.45, 0.4, 1.0]
+        } else {
+            [0.85, 0.85, 0.85, 1.0]
+        };
+        draw_text(line, 10.0, y, TEXT_SIZE, color);
+    }
+    draw_text(
+        &format!("> {}_", console.input()),
+        10.0,
+        input_y,
+        TEXT_SIZE,
+        [1.0, 1.0, 1.0, 1.0],
+    );
+}
+
+#[cfg(test)]
+mod console_tests {
+    use super::*;
+
+    struct Add;
+
+    impl Command for Add {
+        fn name(&self) -> &str {
+            "add"
+        }
+
+        fn args(&self) -> &[ArgSpec] {
+            &[
+                ArgSpec {
+                    name: "a",
+                    kind: ArgKind::Int,
+                },
+                ArgSpec {
+                    name: "b",
+                    kind: ArgKind::Float,
+                },
+            ]
+        }
+
+        fn description(&self) -> &str {
+            "Adds two numbers"
+        }
+
+        fn execute(
+            &self,
+            args: &[ArgValue],
+            _context: &mut EngineContext,
+        ) -> Result<String, CommandError> {
+            Ok(format!(
+                "{}",
+                args[0].as_f64().unwrap() + args[1].as_f64().unwrap()
+            ))
+        }
+    }
+
+    #[test]
+    fn arguments_are_checked_against_the_schema() {
+        let mut console = Console::new();
+        console.register(Add);
+
+        let (_, args) = console.parse("add 1 2.5").unwrap();
+        assert_eq!(args, [ArgValue::Int(1), ArgValue::Float(2.5)]);
+        assert!(matches!(
+            console.parse("sub 1 2"),
+            Err(CommandError::UnknownCommand(_))
+        ));
+        assert_eq!(
+            console.parse("add 1").err(),
+            Some(CommandError::MissingArgument("b"))
+        );
+        assert_eq!(
+            console.parse("add 1 2 3").err(),
+            Some(CommandError::TooManyArguments(2))
+        );
+        assert_eq!(
+            console.parse("add 1.5 2").err(),
+            Some(CommandError::InvalidArgument {
+                name: "a",
+                value: "1.5".to_string(),
+                kind: ArgKind::Int
+            })
+        );
+    }
+
+    #[test]
+    fn entered_lines_are_queued_and_kept_in_the_history() {
+        let mut console = Console::new();
+        console.type_text("seed\r`tp 1 2 3x\u{8}\r\r");
+        assert_eq!(console.take_pending(), ["seed", "tp 1 2 3"]);
+
+        console.history_previous();
+        assert_eq!(console.input(), "tp 1 2 3");
+        console.history_previous();
+        console.history_previous();
+        assert_eq!(console.input(), "seed");
+        console.history_next();
+        console.history_next();
+        assert_eq!(console.input(), "");
+    }
+
+    #[test]
+    fn commands_run_after_the_tick_and_report_errors() {
+        let console = Arc::new(RwLock::new(Console::new()));
+        console.write().register(Add);
+        console.write().type_text("add 2 0.5\radd two 1\r");
+        let world = RwLock::new(World {
+            legion_world: legion::World::default(),
+        });
+        let mut resources = Resources::default();
+        resources.insert(Arc::clone(&console));
+
+        run_console_commands(&world, &mut resources);
+        let output: Vec<String> = console.read().output().cloned().collect();
+        assert_eq!(output[1], "2.5");
+        assert!(output[3].starts_with("[ERROR] Argument a"));
+    }
+
+    #[test]
+    fn unknown_commands_list_the_commands() {
+        let mut console = Console::new();
+        console.register(Add);
+
+        let error = console.parse("sub 1 2").err().unwrap().to_string();
+        assert!(error.contains("add a b - Adds two numbers"));
+    }
+}
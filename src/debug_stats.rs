@@ -0,0 +1,203 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::voxels::voxel_scene::VoxelScene;
+
+// Counters shown on the debug HUD. Every subsystem writes its own counters from the thread it already runs on,
+// so collecting them never takes a lock another thread is waiting for
+#[derive(Debug, Default)]
+pub struct DebugStats {
+    visible: AtomicBool,
+    frame_micros: AtomicU64,
+    tick_micros: AtomicU64,
+    loaded_chunks: AtomicUsize,
+    pending_initialization: AtomicUsize,
+    pending_generation: AtomicUsize,
+    generation_completed: AtomicUsize,
+    generation_queued: AtomicUsize,
+    rendered_vertices: AtomicUsize,
+    rigidbodies: AtomicUsize,
+    colliders: AtomicUsize,
+    voxel_bytes: AtomicUsize,
+    busy_workers: [AtomicUsize; 3], // Initialization, meshing and pre-processing workers
+    workers: [AtomicUsize; 3],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DebugStatsSnapshot {
+    pub frame_ms: f64,
+    pub tick_ms: f64,
+    pub loaded_chunks: usize,
+    pub pending_initialization: usize,
+    pub pending_generation: usize,
+    pub generation_completed: usize,
+    pub generation_queued: usize,
+    pub rendered_vertices: usize,
+    pub rigidbodies: usize,
+    pub colliders: usize,
+    pub voxel_bytes: usize,
+    pub busy_workers: [usize; 3],
+    pub workers: [usize; 3],
+}
+
+impl DebugStats {
+    pub fn toggle_visible(&self) {
+        self.visible.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::Relaxed)
+    }
+
+    pub fn record_frame(&self, frame_time: Duration, rendered_vertices: usize) {
+        self.frame_micros
+            .store(frame_time.as_micros() as u64, Ordering::Relaxed);
+        self.rendered_vertices
+            .store(rendered_vertices, Ordering::Relaxed);
+    }
+
+    pub fn record_tick(&self, tick_time: Duration) {
+        self.tick_micros
+            .store(tick_time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // Only reads lengths, VoxelScene::stats walks every chunk which is too slow to do every tick
+    pub fn record_scene(&self, scene: &VoxelScene) {
+        self.loaded_chunks
+            .store(scene.loaded_chunk_count(), Ordering::Relaxed);
+        self.pending_initialization
+            .store(scene.pending_initialization_count(), Ordering::Relaxed);
+        self.pending_generation
+            .store(scene.pending_generation_count(), Ordering::Relaxed);
+        let (completed, queued) = scene.generation_progress();
+        self.generation_completed
+            .store(completed, Ordering::Relaxed);
+        self.generation_queued.store(queued, Ordering::Relaxed);
+        let utilization = scene.utilization();
+        for (i, stage) in [
+            &utilization.initialization,
+            &utilization.generation,
+            &utilization.pre_processor,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let (busy, workers) = stage.busy_workers();
+            self.busy_workers[i].store(busy, Ordering::Relaxed);
+            self.workers[i].store(workers, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_rigidbodies(&self, count: usize) {
+        self.rigidbodies.store(count, Ordering::Relaxed);
+    }
+
+    pub fn record_colliders(&self, count: usize) {
+        self.colliders.store(count, Ordering::Relaxed);
+    }
+
+    pub fn record_voxel_bytes(&self, bytes: usize) {
+        self.voxel_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> DebugStatsSnapshot {
+        DebugStatsSnapshot {
+            frame_ms: self.frame_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            tick_ms: self.tick_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            loaded_chunks: self.loaded_chunks.load(Ordering::Relaxed),
+            pending_initialization: self.pending_initialization.load(Ordering::Relaxed),
+            pending_generation: self.pending_generation.load(Ordering::Relaxed),
+            generation_completed: self.generation_completed.load(Ordering::Relaxed),
+            generation_queued: self.generation_queued.load(Ordering::Relaxed),
+            rendered_vertices: self.rendered_vertices.load(Ordering::Relaxed),
+            rigidbodies: self.rigidbodies.load(Ordering::Relaxed),
+            colliders: self.colliders.load(Ordering::Relaxed),
+            voxel_bytes: self.voxel_bytes.load(Ordering::Relaxed),
+            busy_workers: self
+                .busy_workers
+                .each_ref()
+                .map(|busy| busy.load(Ordering::Relaxed)),
+            workers: self
+                .workers
+                .each_ref()
+                .map(|workers| workers.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl DebugStatsSnapshot {
+    pub fn hud_lines(&self) -> Vec<String> {
+        let fps = 1000.0 / self.frame_ms.max(0.001);
+        vec![
+            format!("FPS: {:.0} ({:.2} ms)", fps, self.frame_ms),
+            format!("Tick: {:.2} ms", self.tick_ms),
+            format!("Chunks: {} loaded", self.loaded_chunks),
+            format!(
+                "Queued: {} initializing, {} meshing",
+                self.pending_initialization, self.pending_generation
+            ),
+            format!("Vertices: {}", self.rendered_vertices),
+            format!(
+                "Rigidbodies: {}, colliders: {}",
+                self.rigidbodies, self.colliders
+            ),
+            format!("Generation: {:.0}%", self.generation_percent()),
+            format!(
+                "Voxel memory: {:.1} MB",
+                self.voxel_bytes as f64 / (1024.0 * 1024.0)
+            ),
+            format!(
+                "Workers: {}/{} initializing, {}/{} meshing, {}/{} pre-processing",
+                self.busy_workers[0],
+                self.workers[0],
+                self.busy_workers[1],
+                self.workers[1],
+                self.busy_workers[2],
+                self.workers[2]
+            ),
+        ]
+    }
+
+    // Of everything queued since the start, nothing queued counts as done
+    pub fn generation_percent(&self) -> f64 {
+        if self.generation_queued == 0 {
+            return 100.0;
+        }
+        self.generation_completed as f64 / self.generation_queued as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod debug_stats_tests {
+    use super::*;
+
+    #[test]
+    fn recorded_counters_show_up_on_the_hud() {
+        let stats = DebugStats::default();
+        stats.record_frame(Duration::from_millis(20), 1200);
+        stats.record_tick(Duration::from_micros(1500));
+        stats.record_scene(&VoxelScene::new());
+        stats.record_rigidbodies(7);
+        stats.record_colliders(12);
+        stats.record_voxel_bytes(3 * 1024 * 1024);
+
+        let lines = stats.snapshot().hud_lines();
+        assert_eq!(lines[0], "FPS: 50 (20.00 ms)");
+        assert_eq!(lines[1], "Tick: 1.50 ms");
+        assert_eq!(lines[2], "Chunks: 0 loaded");
+        assert_eq!(lines[3], "Queued: 0 initializing, 0 meshing");
+        assert_eq!(lines[5], "Rigidbodies: 7, colliders: 12");
+        assert_eq!(lines[6], "Generation: 100%");
+        assert_eq!(lines[7], "Voxel memory: 3.0 MB");
+        assert_eq!(
+            lines[8],
+            "Workers: 0/0 initializing, 0/0 meshing, 0/0 pre-processing"
+        );
+
+        assert!(!stats.is_visible());
+        stats.toggle_visible();
+        assert!(stats.is_visible());
+    }
+}
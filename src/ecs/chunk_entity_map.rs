@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use dashmap::DashMap;
+use glam::IVec3;
+use legion::Entity;
+
+// The entities showing a chunk's meshes by the name of their material, materials whose mesh is empty have none
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkEntities {
+    pub meshes: BTreeMap<String, Entity>,
+}
+
+impl ChunkEntities {
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+}
+
+// Which entities belong to which chunk, kept up to date by the mesh consumer in generate_world so a remeshed chunk
+// reuses its entities instead of spawning new ones. Chunks without any entities have no entry
+#[derive(Default)]
+pub struct ChunkEntityMap {
+    entities: DashMap<IVec3, ChunkEntities, ahash::RandomState>,
+}
+
+impl ChunkEntityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, chunk_pos: IVec3) -> ChunkEntities {
+        self.entities
+            .get(&chunk_pos)
+            .map_or_else(ChunkEntities::default, |entities| entities.clone())
+    }
+
+    pub fn set(&self, chunk_pos: IVec3, entities: ChunkEntities) {
+        if entities.is_empty() {
+            self.entities.remove(&chunk_pos);
+        } else {
+            self.entities.insert(chunk_pos, entities);
+        }
+    }
+
+    pub fn remove(&self, chunk_pos: IVec3) -> ChunkEntities {
+        self.entities
+            .remove(&chunk_pos)
+            .map_or_else(ChunkEntities::default, |(_, entities)| entities)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod chunk_entity_map_tests {
+    use super::*;
+
+    #[test]
+    fn chunks_without_entities_have_no_entry() {
+        let mut world = legion::World::default();
+        let entity = world.push((0u32,));
+        let map = ChunkEntityMap::new();
+        let chunk_pos = IVec3::new(1, 0, -2);
+
+        map.set(
+            chunk_pos,
+            ChunkEntities {
+                meshes: BTreeMap::from([("voxels/default".to_string(), entity)]),
+            },
+        );
+        assert_eq!(map.get(chunk_pos).meshes["voxels/default"], entity);
+        assert_eq!(map.len(), 1);
+
+        map.set(chunk_pos, ChunkEntities::default());
+        assert!(map.is_empty());
+        assert!(map.remove(chunk_pos).is_empty());
+    }
+}
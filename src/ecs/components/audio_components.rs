@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use crate::audio::audio_engine::SoundControls;
+
+// A sound playing from the entity's position. Spatial sounds pan and fade with their distance to the listener, the
+// others play at their volume wherever the listener is. Starts on the next audio update, stops once removed
+pub struct AudioSource {
+    pub sound: String, // File in the sound folder
+    pub looping: bool,
+    pub volume: f32,
+    pub spatial: bool,
+    pub(crate) playback: Option<Arc<SoundControls>>,
+    pub(crate) started: bool, // Sounds that failed to start aren't retried every tick
+}
+
+impl AudioSource {
+    pub fn new(sound: &str, looping: bool, volume: f32, spatial: bool) -> Self {
+        Self {
+            sound: sound.to_string(),
+            looping,
+            volume,
+            spatial,
+            playback: None,
+            started: false,
+        }
+    }
+}
+
+impl Drop for AudioSource {
+    fn drop(&mut self) {
+        if let Some(playback) = &self.playback {
+            playback.stop();
+        }
+    }
+}
+
+// Sounds are heard from this entity, there should only be one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AudioListener;
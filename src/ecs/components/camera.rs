@@ -7,3 +7,21 @@ use crate::rendering;
 pub struct Camera {
     pub camera: Arc<RwLock<rendering::camera::Camera>>,
 }
+
+// Smoothly moves the camera's field of view towards target_fov
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraZoom {
+    pub target_fov: f32,
+    pub smoothing: f32,   // Higher is snappier
+    pub scroll_step: f32, // Degrees per scroll line
+}
+
+impl Default for CameraZoom {
+    fn default() -> Self {
+        Self {
+            target_fov: 50.0,
+            smoothing: 12.0,
+            scroll_step: 5.0,
+        }
+    }
+}
@@ -1,4 +1,6 @@
+pub mod audio_components;
 pub mod camera;
+pub mod physics_components;
 pub mod player_components;
 pub mod rendering_components;
 pub mod transformation_components;
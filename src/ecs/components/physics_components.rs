@@ -0,0 +1,783 @@
+use std::sync::Arc;
+
+use bus::BusReader;
+use glam::{Quat, Vec3};
+use legion::Entity;
+use parking_lot::{Mutex, RwLock};
+use rapier3d::prelude::*;
+
+use crate::{
+    asset_types::{
+        asset::{Asset, AssetChangeType},
+        mesh::Mesh,
+    },
+    ecs::components::transformation_components::{Position, PreviousPosition},
+    physics::physics_scene::{from_isometry, to_isometry, PhysicsScene},
+    voxels::voxel_registry::DEFAULT_FRICTION,
+};
+
+pub trait ColliderComponent {
+    fn get_collider_handle(&self) -> ColliderHandle;
+}
+
+// A collider wrapping the convex hull of a mesh. Much cheaper and more stable than a trimesh for moving props.
+#[derive(Clone, Copy, Debug)]
+pub struct ConvexHullCollider {
+    collider_handle: ColliderHandle,
+}
+
+impl ConvexHullCollider {
+    pub fn new(physics_scene: &mut PhysicsScene, mesh: &Mesh, position: Vec3) -> Self {
+        let collider = Self::build_collider(mesh)
+            .translation(vector![position.x, position.y, position.z])
+            .build();
+        Self {
+            collider_handle: physics_scene.register_collider(collider),
+        }
+    }
+
+    // Falls back to the axis aligned bounding box of the mesh if a hull can't be built (flat or degenerate meshes)
+    pub fn build_collider(mesh: &Mesh) -> ColliderBuilder {
+        let points: Vec<Point<Real>> = mesh
+            .get_vertices()
+            .iter()
+            .map(|v| point![v.position[0], v.position[1], v.position[2]])
+            .collect();
+
+        ColliderBuilder::convex_hull(&points).unwrap_or_else(|| {
+            let (min, max) = mesh.get_vertices().iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), v| (min.min(v.position.into()), max.max(v.position.into())),
+            );
+            let (center, half_extents) = if points.is_empty() {
+                (Vec3::ZERO, Vec3::splat(0.001))
+            } else {
+                (
+                    (min + max) * 0.5,
+                    ((max - min) * 0.5).max(Vec3::splat(0.001)),
+                )
+            };
+            ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+                .translation(vector![center.x, center.y, center.z])
+        })
+    }
+}
+
+impl ColliderComponent for ConvexHullCollider {
+    fn get_collider_handle(&self) -> ColliderHandle {
+        self.collider_handle
+    }
+}
+
+// A sensor volume. Enter/exit events are read through PhysicsScene::trigger_events
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerCollider {
+    collider_handle: ColliderHandle,
+}
+
+impl TriggerCollider {
+    pub fn new(physics_scene: &mut PhysicsScene, shape: SharedShape, position: Vec3) -> Self {
+        let collider = ColliderBuilder::new(shape)
+            .translation(vector![position.x, position.y, position.z])
+            .sensor(true)
+            .active_events(ActiveEvents::INTERSECTION_EVENTS)
+            .build();
+        Self {
+            collider_handle: physics_scene.register_trigger(collider),
+        }
+    }
+
+    pub fn cuboid(physics_scene: &mut PhysicsScene, half_extents: Vec3, position: Vec3) -> Self {
+        Self::new(
+            physics_scene,
+            SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            position,
+        )
+    }
+}
+
+// A static collider with the exact triangles of a mesh, used for terrain. Rapier can't build a trimesh without
+// triangles, so there's no collider while the mesh is empty. Follows changes to the mesh through update
+pub struct MeshCollider {
+    mesh: Arc<RwLock<Mesh>>,
+    position: Vec3,
+    collider_handle: Option<ColliderHandle>,
+    change_listener: Mutex<BusReader<AssetChangeType>>,
+    active: bool, // Inactive colliders keep their mesh but aren't registered with the physics scene
+    entity: Option<Entity>, // Reattached to every collider built for the mesh
+    friction: f32,
+}
+
+impl MeshCollider {
+    pub fn new(physics_scene: &mut PhysicsScene, mesh: Arc<RwLock<Mesh>>, position: Vec3) -> Self {
+        let mut mesh_collider = Self::new_inactive(mesh, position);
+        mesh_collider.set_active(physics_scene, true);
+        mesh_collider
+    }
+
+    // Nothing is built until the collider is activated
+    pub fn new_inactive(mesh: Arc<RwLock<Mesh>>, position: Vec3) -> Self {
+        let change_listener = Mutex::new(mesh.write().get_change_receiver());
+        Self {
+            mesh,
+            position,
+            collider_handle: None,
+            change_listener,
+            active: false,
+            entity: None,
+            friction: DEFAULT_FRICTION,
+        }
+    }
+
+    // Applies to the colliders built from now on, set it before activating the collider
+    pub fn with_friction(mut self, friction: f32) -> Self {
+        self.friction = friction;
+        self
+    }
+
+    // The collider is rebuilt whenever the mesh changes or it's activated again, each one belongs to the entity
+    pub fn attach_entity(&mut self, physics_scene: &mut PhysicsScene, entity: Entity) {
+        self.entity = Some(entity);
+        if let Some(collider_handle) = self.collider_handle {
+            physics_scene.attach_entity(collider_handle, entity);
+        }
+    }
+
+    // Activating builds the collider from the mesh as it is now, deactivating unregisters it
+    pub fn set_active(&mut self, physics_scene: &mut PhysicsScene, active: bool) {
+        if self.active == active {
+            return;
+        }
+        self.active = active;
+        if active {
+            self.rebuild(physics_scene);
+        } else {
+            self.remove(physics_scene);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn mesh(&self) -> &Arc<RwLock<Mesh>> {
+        &self.mesh
+    }
+
+    // Rebuilds the collider if the mesh changed since the last update, however many changes there were.
+    // Inactive colliders only catch up on the changes. Returns whether it was rebuilt
+    pub fn update(&mut self, physics_scene: &mut PhysicsScene) -> bool {
+        let mut modified = false;
+        let mut destroyed = false;
+        let change_listener = self.change_listener.get_mut();
+        while let Ok(change) = change_listener.try_recv() {
+            match change {
+                AssetChangeType::Modified => modified = true,
+                AssetChangeType::Destroyed => destroyed = true,
+            }
+        }
+        if destroyed {
+            self.remove(physics_scene);
+            return false;
+        }
+        if modified && self.active {
+            self.rebuild(physics_scene);
+            return true;
+        }
+        false
+    }
+
+    fn rebuild(&mut self, physics_scene: &mut PhysicsScene) {
+        self.remove(physics_scene);
+        let mesh = self.mesh.read();
+        if mesh.index_count < 3 {
+            return;
+        }
+        let vertices: Vec<Point<Real>> = mesh
+            .get_vertices()
+            .iter()
+            .map(|v| point![v.position[0], v.position[1], v.position[2]])
+            .collect();
+        let indices: Vec<[u32; 3]> = mesh
+            .get_indices()
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        let collider = ColliderBuilder::trimesh(vertices, indices)
+            .translation(vector![self.position.x, self.position.y, self.position.z])
+            .friction(self.friction)
+            .build();
+        let collider_handle = physics_scene.register_collider(collider);
+        if let Some(entity) = self.entity {
+            physics_scene.attach_entity(collider_handle, entity);
+        }
+        self.collider_handle = Some(collider_handle);
+    }
+
+    // Unregisters the collider, an update after the mesh changes again registers a new one
+    pub fn remove(&mut self, physics_scene: &mut PhysicsScene) {
+        if let Some(collider_handle) = self.collider_handle.take() {
+            physics_scene.remove_collider(collider_handle);
+        }
+    }
+
+    // None while the mesh has no triangles
+    pub fn get_collider_handle(&self) -> Option<ColliderHandle> {
+        self.collider_handle
+    }
+}
+
+impl ColliderComponent for TriggerCollider {
+    fn get_collider_handle(&self) -> ColliderHandle {
+        self.collider_handle
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DynamicBody {
+    pub rigidbody_handle: RigidBodyHandle,
+    collider_handle: ColliderHandle,
+}
+
+impl DynamicBody {
+    pub fn new(physics_scene: &mut PhysicsScene, position: Vec3, collider: Collider) -> Self {
+        let rigidbody = RigidBodyBuilder::new_dynamic()
+            .translation(vector![position.x, position.y, position.z])
+            .build();
+        let rigidbody_handle = physics_scene.register_rigidbody(rigidbody);
+        let collider_handle =
+            physics_scene.register_collider_with_parent(collider, rigidbody_handle);
+        Self {
+            rigidbody_handle,
+            collider_handle,
+        }
+    }
+
+    // Uses the convex hull of the mesh as the collision shape
+    pub fn with_convex_hull(physics_scene: &mut PhysicsScene, position: Vec3, mesh: &Mesh) -> Self {
+        Self::new(
+            physics_scene,
+            position,
+            ConvexHullCollider::build_collider(mesh).build(),
+        )
+    }
+
+    // Replaces the collision shape, the body keeps its position and velocity
+    pub fn set_collider(&mut self, physics_scene: &mut PhysicsScene, collider: Collider) {
+        let entity = physics_scene.get_collider_entity(self.collider_handle);
+        physics_scene.remove_collider(self.collider_handle);
+        self.collider_handle =
+            physics_scene.register_collider_with_parent(collider, self.rigidbody_handle);
+        if let Some(entity) = entity {
+            physics_scene.attach_entity(self.collider_handle, entity);
+        }
+    }
+
+    // Slows the body down over time, 0 lets it drift until something stops it
+    pub fn set_linear_damping(&self, physics_scene: &mut PhysicsScene, damping: f32) {
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            rigidbody.set_linear_damping(damping);
+        }
+    }
+
+    // A kinematic body stops being simulated and stays where it is until it's moved, switching back hands it to the
+    // simulation again with the velocity it had
+    pub fn set_kinematic(&self, physics_scene: &mut PhysicsScene, kinematic: bool) {
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            let body_type = if kinematic {
+                RigidBodyType::KinematicPositionBased
+            } else {
+                RigidBodyType::Dynamic
+            };
+            rigidbody.set_body_type(body_type);
+        }
+    }
+
+    // Lets everything pass through the body
+    pub fn set_sensor(&self, physics_scene: &mut PhysicsScene, is_sensor: bool) {
+        physics_scene.set_sensor(self.collider_handle, is_sensor);
+    }
+
+    // Moves the body straight there and wakes it up, its velocity is kept. The entity's Position and Rotation follow
+    // after the next step
+    pub fn set_transform(&self, physics_scene: &mut PhysicsScene, position: Vec3, rotation: Quat) {
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            rigidbody.set_position(to_isometry(position, rotation), true);
+        }
+    }
+
+    pub fn set_velocity(&self, physics_scene: &mut PhysicsScene, velocity: Vec3) {
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            rigidbody.set_linvel(vector![velocity.x, velocity.y, velocity.z], true);
+        }
+    }
+
+    // The linear velocity, None if the body was removed from the scene
+    pub fn get_velocity(&self, physics_scene: &PhysicsScene) -> Option<Vec3> {
+        physics_scene
+            .get_rigidbody(self.rigidbody_handle)
+            .map(|rigidbody| {
+                let velocity = rigidbody.linvel();
+                Vec3::new(velocity.x, velocity.y, velocity.z)
+            })
+    }
+
+    // Moves the body and drops all of its speed, so it doesn't carry any momentum to where it's sent. The entity's
+    // position and previous position move along, otherwise the body is drawn sliding there over the next tick
+    pub fn teleport(
+        &self,
+        physics_scene: &mut PhysicsScene,
+        pos: &mut Position,
+        previous: Option<&mut PreviousPosition>,
+        position: Vec3,
+        rotation: Quat,
+    ) {
+        pos.0 = position;
+        if let Some(previous) = previous {
+            previous.0 = position;
+        }
+        self.set_transform(physics_scene, position, rotation);
+        self.set_velocity(physics_scene, Vec3::ZERO);
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            rigidbody.set_angvel(vector![0.0, 0.0, 0.0], true);
+        }
+    }
+
+    // The simulated position and rotation, None if the body was removed from the scene
+    pub fn get_transform(&self, physics_scene: &PhysicsScene) -> Option<(Vec3, Quat)> {
+        physics_scene
+            .get_rigidbody(self.rigidbody_handle)
+            .map(|rigidbody| from_isometry(rigidbody.position()))
+    }
+}
+
+impl ColliderComponent for DynamicBody {
+    fn get_collider_handle(&self) -> ColliderHandle {
+        self.collider_handle
+    }
+}
+
+// A body moved by its entity's Position and Rotation rather than by the simulation, dynamic bodies are pushed aside
+#[derive(Clone, Copy, Debug)]
+pub struct KinematicBody {
+    pub rigidbody_handle: RigidBodyHandle,
+    collider_handle: ColliderHandle,
+}
+
+impl KinematicBody {
+    pub fn new(physics_scene: &mut PhysicsScene, position: Vec3, collider: Collider) -> Self {
+        let rigidbody = RigidBodyBuilder::new_kinematic_position_based()
+            .translation(vector![position.x, position.y, position.z])
+            .build();
+        let rigidbody_handle = physics_scene.register_rigidbody(rigidbody);
+        let collider_handle =
+            physics_scene.register_collider_with_parent(collider, rigidbody_handle);
+        Self {
+            rigidbody_handle,
+            collider_handle,
+        }
+    }
+}
+
+impl ColliderComponent for KinematicBody {
+    fn get_collider_handle(&self) -> ColliderHandle {
+        self.collider_handle
+    }
+}
+
+// Gap kept between a character and whatever it touches so the next cast doesn't start inside it
+const CHARACTER_SKIN: f32 = 0.01;
+const MAX_SLIDE_ITERATIONS: usize = 4;
+// Surfaces whose normal points up more than this count as ground
+const MIN_GROUND_NORMAL_Y: f32 = 0.7;
+
+// A capsule moved by sweeping it through the world instead of by simulation, so it never tumbles or drifts.
+// Gravity and jumping are up to whoever moves it
+#[derive(Clone, Copy, Debug)]
+pub struct KinematicCharacterBody {
+    pub rigidbody_handle: RigidBodyHandle,
+    collider_handle: ColliderHandle,
+    position: Vec3,
+    pub max_step_height: f32,
+    pub jump_speed: f32,
+    pub max_fall_speed: f32,
+    pub horizontal_velocity: Vec3, // Y is always 0
+    pub vertical_velocity: f32,
+    pub grounded: bool,
+}
+
+impl KinematicCharacterBody {
+    pub fn new(
+        physics_scene: &mut PhysicsScene,
+        position: Vec3,
+        half_height: f32,
+        radius: f32,
+    ) -> Self {
+        let rigidbody = RigidBodyBuilder::new_kinematic_position_based()
+            .translation(vector![position.x, position.y, position.z])
+            .build();
+        let rigidbody_handle = physics_scene.register_rigidbody(rigidbody);
+        let collider_handle = physics_scene.register_collider_with_parent(
+            ColliderBuilder::capsule_y(half_height, radius).build(),
+            rigidbody_handle,
+        );
+        Self {
+            rigidbody_handle,
+            collider_handle,
+            position,
+            max_step_height: 0.55,
+            jump_speed: 8.0,
+            max_fall_speed: 50.0,
+            horizontal_velocity: Vec3::ZERO,
+            vertical_velocity: 0.0,
+            grounded: false,
+        }
+    }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.position
+    }
+
+    // Lets other bodies pass through the character, its own movement is up to whoever moves it
+    pub fn set_sensor(&self, physics_scene: &mut PhysicsScene, is_sensor: bool) {
+        physics_scene.set_sensor(self.collider_handle, is_sensor);
+    }
+
+    // Moves the character without checking for collisions. The body jumps there instead of travelling, so nothing
+    // on the way is pushed aside
+    pub fn set_position(&mut self, physics_scene: &mut PhysicsScene, position: Vec3) {
+        self.position = position;
+        self.horizontal_velocity = Vec3::ZERO;
+        self.vertical_velocity = 0.0;
+        if let Some(rigidbody) = physics_scene.get_rigidbody_mut(self.rigidbody_handle) {
+            rigidbody.set_position(to_isometry(position, Quat::IDENTITY), true);
+        }
+        physics_scene.set_kinematic_target(self.rigidbody_handle, position, Quat::IDENTITY);
+    }
+
+    // Moves as far along the translation as the world allows, sliding along walls and stepping up ledges no higher
+    // than max_step_height. Returns the new position
+    pub fn move_and_slide(
+        &mut self,
+        physics_scene: &mut PhysicsScene,
+        desired_translation: Vec3,
+    ) -> Vec3 {
+        let shape = match physics_scene.get_collider(self.collider_handle) {
+            Some(collider) => collider.shared_shape().clone(),
+            None => return self.position,
+        };
+        let horizontal = desired_translation * Vec3::new(1.0, 0.0, 1.0);
+        let vertical = Vec3::Y * desired_translation.y;
+
+        let (mut position, wall) = self.slide(physics_scene, &*shape, self.position, horizontal);
+        if wall.is_some() && self.grounded && self.max_step_height > 0.0 {
+            // Try the same move from higher up and drop back down onto whatever is there
+            let up = Vec3::Y * self.max_step_height;
+            let (raised, _) = self.slide(physics_scene, &*shape, self.position, up);
+            let (stepped, _) = self.slide(physics_scene, &*shape, raised, horizontal);
+            let down = Vec3::Y * (self.position.y - raised.y);
+            let (lowered, _) = self.slide(physics_scene, &*shape, stepped, down);
+            let distance = |to: Vec3| ((to - self.position) * Vec3::new(1.0, 0.0, 1.0)).length();
+            if distance(lowered) > distance(position) + CHARACTER_SKIN {
+                position = lowered;
+            }
+        }
+
+        let (position, floor) = self.slide(physics_scene, &*shape, position, vertical);
+        self.grounded =
+            vertical.y <= 0.0 && floor.map_or(false, |normal| normal.y > MIN_GROUND_NORMAL_Y);
+
+        self.position = position;
+        physics_scene.set_kinematic_target(self.rigidbody_handle, position, Quat::IDENTITY);
+        position
+    }
+
+    // Returns the end position and the normal of the last surface that was hit
+    fn slide(
+        &self,
+        physics_scene: &PhysicsScene,
+        shape: &dyn Shape,
+        mut position: Vec3,
+        translation: Vec3,
+    ) -> (Vec3, Option<Vec3>) {
+        let mut remaining = translation;
+        let mut hit_normal = None;
+        for _ in 0..MAX_SLIDE_ITERATIONS {
+            let length = remaining.length();
+            if length < 1e-5 {
+                break;
+            }
+            let direction = remaining / length;
+            match physics_scene.cast_shape(shape, position, direction, length, self.collider_handle)
+            {
+                None => {
+                    position += remaining;
+                    break;
+                }
+                Some((distance, normal)) => {
+                    let travel = (distance - CHARACTER_SKIN).max(0.0);
+                    position += direction * travel;
+                    remaining -= direction * travel;
+                    // Keep only the part of the move that runs along the surface
+                    remaining -= normal * remaining.dot(normal).min(0.0);
+                    hit_normal = Some(normal);
+                }
+            }
+        }
+        (position, hit_normal)
+    }
+}
+
+impl ColliderComponent for KinematicCharacterBody {
+    fn get_collider_handle(&self) -> ColliderHandle {
+        self.collider_handle
+    }
+}
+
+#[cfg(test)]
+mod collider_tests {
+    use std::sync::Arc;
+
+    use glam::{Quat, Vec3};
+    use parking_lot::RwLock;
+    use rapier3d::prelude::{vector, ColliderBuilder};
+
+    use crate::{asset_types::mesh::Mesh, physics::physics_scene::PhysicsScene};
+
+    use super::{
+        ColliderComponent, ConvexHullCollider, DynamicBody, KinematicCharacterBody, MeshCollider,
+        Position, PreviousPosition,
+    };
+
+    #[test]
+    fn cube_hull_has_eight_vertices() {
+        #[rustfmt::skip]
+        let cube = Mesh::new()
+            .append_quad([[0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]], [0.0, 0.0, 1.0])
+            .append_quad([[-0.5, -0.5, -0.5], [-0.5, 0.5, -0.5], [0.5, -0.5, -0.5], [0.5, 0.5, -0.5]], [0.0, 0.0, -1.0])
+            .append_quad([[0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [1.0, 0.0, 0.0])
+            .append_quad([[-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5], [-0.5, -0.5, -0.5], [-0.5, 0.5, -0.5]], [-1.0, 0.0, 0.0])
+            .append_quad([[-0.5, 0.5, -0.5], [-0.5, 0.5, 0.5], [0.5, 0.5, -0.5], [0.5, 0.5, 0.5]], [0.0, 1.0, 0.0])
+            .append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]);
+
+        let collider = ConvexHullCollider::build_collider(&cube).build();
+        let hull = collider.shape().as_convex_polyhedron().unwrap();
+        assert_eq!(hull.points().len(), 8);
+    }
+
+    #[test]
+    fn ball_rests_on_a_trimesh() {
+        let mut physics_scene = PhysicsScene::new(60);
+        let ground = Mesh::new().append_quad(
+            [
+                [-5.0, 0.0, -5.0],
+                [-5.0, 0.0, 5.0],
+                [5.0, 0.0, -5.0],
+                [5.0, 0.0, 5.0],
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        MeshCollider::new(
+            &mut physics_scene,
+            Arc::new(RwLock::new(ground)),
+            Vec3::ZERO,
+        );
+        let mut ball = DynamicBody::new(
+            &mut physics_scene,
+            Vec3::new(0.0, 3.0, 0.0),
+            ColliderBuilder::ball(0.5).build(),
+        );
+
+        for _ in 0..120 {
+            physics_scene.update(1.0 / 60.0);
+        }
+        let (position, _) = ball.get_transform(&physics_scene).unwrap();
+        assert!(
+            (position.y - 0.5).abs() < 0.05,
+            "ball ended up at {position}"
+        );
+
+        // Swapping in a bigger shape keeps the body on top of the ground
+        let old_collider = ball.get_collider_handle();
+        ball.set_collider(&mut physics_scene, ColliderBuilder::ball(1.0).build());
+        assert!(physics_scene.get_collider(old_collider).is_none());
+        for _ in 0..120 {
+            physics_scene.update(1.0 / 60.0);
+        }
+        let (position, _) = ball.get_transform(&physics_scene).unwrap();
+        assert!(
+            (position.y - 1.0).abs() < 0.05,
+            "ball ended up at {position}"
+        );
+    }
+
+    #[test]
+    fn teleported_body_keeps_no_momentum() {
+        let mut physics_scene = PhysicsScene::new(60);
+        let body = DynamicBody::new(
+            &mut physics_scene,
+            Vec3::new(0.0, 10.0, 0.0),
+            ColliderBuilder::ball(0.5).build(),
+        );
+        for _ in 0..30 {
+            physics_scene.update(1.0 / 60.0);
+        }
+        let (fallen, _) = body.get_transform(&physics_scene).unwrap();
+        assert!(body.get_velocity(&physics_scene).unwrap().y < 0.0);
+
+        let target = fallen + Vec3::Y * 100.0;
+        let (mut pos, mut previous) = (Position(fallen), PreviousPosition(fallen));
+        body.teleport(
+            &mut physics_scene,
+            &mut pos,
+            Some(&mut previous),
+            target,
+            Quat::IDENTITY,
+        );
+        let (position, _) = body.get_transform(&physics_scene).unwrap();
+        assert_eq!(position, target);
+        assert_eq!((pos.0, previous.0), (target, target));
+        assert_eq!(body.get_velocity(&physics_scene), Some(Vec3::ZERO));
+
+        // Falls from a standstill again, one step of gravity is all the speed it has
+        physics_scene.update(1.0 / 60.0);
+        let velocity = body.get_velocity(&physics_scene).unwrap();
+        assert!(
+            velocity.y < 0.0 && velocity.y > -0.5,
+            "velocity is {velocity}"
+        );
+    }
+
+    #[test]
+    fn kinematic_body_hangs_in_the_air_until_switched_back() {
+        let mut physics_scene = PhysicsScene::new(60);
+        let body = DynamicBody::new(
+            &mut physics_scene,
+            Vec3::new(0.0, 10.0, 0.0),
+            ColliderBuilder::ball(0.5).build(),
+        );
+        body.set_kinematic(&mut physics_scene, true);
+        body.set_sensor(&mut physics_scene, true);
+        for _ in 0..30 {
+            physics_scene.update(1.0 / 60.0);
+        }
+        let (position, _) = body.get_transform(&physics_scene).unwrap();
+        assert_eq!(position.y, 10.0);
+
+        body.set_kinematic(&mut physics_scene, false);
+        for _ in 0..30 {
+            physics_scene.update(1.0 / 60.0);
+        }
+        let (position, _) = body.get_transform(&physics_scene).unwrap();
+        assert!(position.y < 9.0, "body stayed at {position}");
+    }
+
+    #[test]
+    fn character_steps_onto_low_ledges_only() {
+        let mut physics_scene = PhysicsScene::new(60);
+        // Ground with its top at 0, a 0.3 high ledge from x = 1 and a 2 high wall from x = 4
+        physics_scene.register_collider(
+            ColliderBuilder::cuboid(10.0, 0.5, 10.0)
+                .translation(vector![0.0, -0.5, 0.0])
+                .build(),
+        );
+        physics_scene.register_collider(
+            ColliderBuilder::cuboid(1.5, 0.15, 10.0)
+                .translation(vector![2.5, 0.15, 0.0])
+                .build(),
+        );
+        physics_scene.register_collider(
+            ColliderBuilder::cuboid(0.5, 1.0, 10.0)
+                .translation(vector![4.5, 1.0, 0.0])
+                .build(),
+        );
+        let mut character =
+            KinematicCharacterBody::new(&mut physics_scene, Vec3::new(0.0, 1.0, 0.0), 0.5, 0.3);
+        physics_scene.update(1.0 / 60.0); // Builds the query pipeline
+
+        character.move_and_slide(&mut physics_scene, Vec3::new(0.0, -1.0, 0.0));
+        assert!(character.grounded);
+        for _ in 0..60 {
+            character.move_and_slide(&mut physics_scene, Vec3::new(0.1, -0.1, 0.0));
+        }
+        let position = character.get_position();
+        assert!(character.grounded);
+        assert!(
+            (position.y - 1.1).abs() < 0.05,
+            "character ended up at {position}"
+        );
+        assert!(
+            (position.x - 3.7).abs() < 0.05,
+            "character ended up at {position}"
+        );
+    }
+
+    #[test]
+    fn mesh_collider_waits_for_triangles_and_follows_changes() {
+        let mut physics_scene = PhysicsScene::new(60);
+        let mesh = Arc::new(RwLock::new(Mesh::new()));
+        let mut mesh_collider =
+            MeshCollider::new(&mut physics_scene, Arc::clone(&mesh), Vec3::ZERO);
+        assert!(mesh_collider.get_collider_handle().is_none());
+
+        let quad = Mesh::new().append_quad(
+            [
+                [-1.0, 0.0, -1.0],
+                [-1.0, 0.0, 1.0],
+                [1.0, 0.0, -1.0],
+                [1.0, 0.0, 1.0],
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        {
+            let mut mesh_lock = mesh.write();
+            mesh_lock.set_vertices(quad.get_vertices().clone());
+            mesh_lock.set_indices(quad.get_indices().clone());
+        }
+        // Both changes are applied by a single rebuild
+        assert!(mesh_collider.update(&mut physics_scene));
+        assert!(!mesh_collider.update(&mut physics_scene));
+        physics_scene.update(1.0 / 60.0);
+
+        let collider_handle = mesh_collider.get_collider_handle().unwrap();
+        let collider = physics_scene.get_collider(collider_handle).unwrap();
+        assert_eq!(collider.shape().as_trimesh().unwrap().indices().len(), 2);
+
+        mesh_collider.remove(&mut physics_scene);
+        assert!(physics_scene.get_collider(collider_handle).is_none());
+    }
+
+    #[test]
+    fn inactive_mesh_collider_is_rebuilt_from_its_mesh() {
+        let mut physics_scene = PhysicsScene::new(60);
+        let quad = Mesh::new().append_quad(
+            [
+                [-1.0, 0.0, -1.0],
+                [-1.0, 0.0, 1.0],
+                [1.0, 0.0, -1.0],
+                [1.0, 0.0, 1.0],
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        let mesh = Arc::new(RwLock::new(quad));
+        let mut mesh_collider =
+            MeshCollider::new_inactive(Arc::clone(&mesh), Vec3::ZERO).with_friction(0.9);
+        assert_eq!(physics_scene.collider_count(), 0);
+
+        // Changes while inactive don't register anything
+        mesh.write().set_indices(vec![0, 1, 2]);
+        assert!(!mesh_collider.update(&mut physics_scene));
+        assert_eq!(physics_scene.collider_count(), 0);
+
+        mesh_collider.set_active(&mut physics_scene, true);
+        assert_eq!(physics_scene.collider_count(), 1);
+        let collider_handle = mesh_collider.get_collider_handle().unwrap();
+        let collider = physics_scene.get_collider(collider_handle).unwrap();
+        assert_eq!(collider.shape().as_trimesh().unwrap().indices().len(), 1);
+        assert_eq!(collider.friction(), 0.9);
+
+        mesh_collider.set_active(&mut physics_scene, false);
+        assert_eq!(physics_scene.collider_count(), 0);
+        assert!(mesh_collider.get_collider_handle().is_none());
+    }
+}
@@ -1,4 +1,55 @@
+use glam::{IVec3, Quat, Vec3};
+
+use crate::voxels::voxel_shapes::{voxel_shape, VoxelShape};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    Walk,
+    Fly, // No gravity, Space and LShift move straight up and down
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Player {
+    pub movement_mode: MovementMode,
+    pub noclip: bool,           // Flying passes through terrain and everything else
+    pub sprint_multiplier: f32, // Applied to fly_speed while LControl is held
     pub fly_speed: f32,
+    pub move_speed: f32,     // Walking speed when the player has a character body
+    pub speed_up_time: f32,  // Seconds to get from standing still to move_speed
+    pub stop_time: f32,      // Seconds to come to a stop from move_speed once no key is held
+    pub selected_voxel: u16, // Voxel id placed on right click
+    pub selected_shape: VoxelShape, // Oriented against the hit face when placed
+    pub half_extents: Vec3,  // Box around the player's position that voxels can't be placed in
+    pub eye_height: f32,     // How far above the player's position its camera sits
+    pub spawn_point: Vec3,   // Where the player is sent back to after falling out of the world
+    pub breaking: Option<(IVec3, f32)>, // The voxel being broken and for how many seconds it's been held on
+    pub look_drag: f32, // How far the mouse moved since the right button went down, in pixels
+    pub pitch: f32, // Looking up or down, the player's Rotation only turns it around Y and its camera is tilted
+}
+
+impl Player {
+    pub fn new(fly_speed: f32) -> Self {
+        Self {
+            movement_mode: MovementMode::Walk,
+            noclip: true,
+            sprint_multiplier: 3.0,
+            fly_speed,
+            move_speed: 6.0,
+            speed_up_time: 0.1,
+            stop_time: 0.1,
+            selected_voxel: 1,
+            selected_shape: voxel_shape::CUBE,
+            half_extents: Vec3::new(0.3, 0.9, 0.3),
+            eye_height: 0.7,
+            spawn_point: Vec3::ZERO,
+            breaking: None,
+            look_drag: 0.0,
+            pitch: 0.0,
+        }
+    }
+
+    // Where the player looks, its Rotation with the camera's tilt on top
+    pub fn look_rotation(&self, rotation: Quat) -> Quat {
+        rotation * Quat::from_rotation_x(self.pitch)
+    }
 }
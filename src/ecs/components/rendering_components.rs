@@ -1,12 +1,17 @@
 use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
+    atomic::{AtomicBool, Ordering},
     Arc,
 };
 
-use parking_lot::RwLock;
+use bus::BusReader;
+use glam::{Mat4, Vec3};
+use parking_lot::{Mutex, RwLock};
 
 use crate::{
-    asset_types::{asset::Asset, mesh::Mesh},
+    asset_types::{
+        asset::{Asset, AssetChangeType},
+        mesh::Mesh,
+    },
     next_id,
     rendering::material::Material,
 };
@@ -17,6 +22,10 @@ pub struct MeshRenderer {
     pub material: Arc<RwLock<dyn Material>>,
     pub render_layer: String,
     pub dirty: Arc<AtomicBool>,
+    pub destroyed: Arc<AtomicBool>,
+    pub culled: Arc<AtomicBool>, // Outside of the render distance
+    pub uploaded_transform: Arc<Mutex<Option<Mat4>>>, // The transform last written to the render pass
+    change_listener: Arc<Mutex<BusReader<AssetChangeType>>>,
     id: u64,
 }
 
@@ -26,27 +35,57 @@ impl MeshRenderer {
         material: Arc<RwLock<dyn Material>>,
         render_layer: String,
     ) -> Self {
-        let mut r = Self {
+        let change_listener = Arc::new(Mutex::new(mesh.write().get_change_receiver()));
+        Self {
             mesh,
             material,
             render_layer,
             dirty: Arc::new(AtomicBool::new(true)),
+            destroyed: Arc::new(AtomicBool::new(false)),
+            culled: Arc::new(AtomicBool::new(false)),
+            uploaded_transform: Arc::new(Mutex::new(None)),
+            change_listener,
             id: next_id(),
-        };
-        r.listen_for_changes();
-        r
+        }
     }
 
-    fn listen_for_changes(&mut self) {
-        let mut change_listener = self.mesh.write().get_change_receiver();
-        let dirty_clone = Arc::clone(&self.dirty);
-        rayon::spawn(move || {
-            change_listener.recv().unwrap();
-            dirty_clone.store(true, std::sync::atomic::Ordering::Relaxed);
-        });
+    // Applies any changes the mesh has broadcast since the last poll
+    pub fn poll_changes(&self) {
+        let mut change_listener = self.change_listener.lock();
+        while let Ok(change) = change_listener.try_recv() {
+            match change {
+                AssetChangeType::Modified => self.dirty.store(true, Ordering::Relaxed),
+                AssetChangeType::Destroyed => self.destroyed.store(true, Ordering::Relaxed),
+            }
+        }
     }
 
     pub fn get_id(&self) -> u64 {
         self.id
     }
 }
+
+// The directional light the scene is lit by, only the first one found is used
+pub struct SunLight {
+    pub direction: Vec3, // Towards the sun
+    pub color: Vec3,
+    pub ambient: f32,        // Light that reaches faces turned away from the sun
+    pub rotation_speed: f32, // Radians per second the sun circles the world with
+}
+
+impl Default for SunLight {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(-0.5, 0.6, -0.3).normalize(),
+            color: Vec3::ONE,
+            ambient: 0.3,
+            rotation_speed: 0.0,
+        }
+    }
+}
+
+// Outlines the voxel the player is looking at, bounds are relative to the entity and None hides the outline
+#[derive(Default)]
+pub struct BlockHighlight {
+    pub bounds: Option<(Vec3, Vec3)>,
+}
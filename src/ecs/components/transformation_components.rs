@@ -1,4 +1,5 @@
-use glam::{Quat, Vec3};
+use glam::{Mat4, Quat, Vec3};
+use legion::Entity;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Position(pub Vec3);
@@ -8,3 +9,24 @@ pub struct Rotation(pub Quat);
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Scale(pub Vec3);
+
+// Angular velocity in radians per second around the vector's axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spin(pub Vec3);
+
+// Makes the entity's Position, Rotation and Scale relative to the parent entity's
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parent(pub Entity);
+
+// The entity's transform in world space, the local one composed with those of all its parents.
+// Kept up to date by propagate_transforms, which adds it to every entity with a Parent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldTransform(pub Mat4);
+
+// Where the entity was at the end of the previous tick. Entities with them are drawn between the two ticks by the
+// frame's alpha, so they move smoothly at frame rates above the tick rate. Recorded by record_previous_transforms
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PreviousPosition(pub Vec3);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PreviousRotation(pub Quat);
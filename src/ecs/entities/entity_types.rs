@@ -1,4 +1,4 @@
-use crate::components::{
+use crate::ecs::components::{
     camera::Camera,
     player_components::Player,
     transformation_components::{Position, Rotation},
@@ -0,0 +1,200 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use glam::IVec3;
+use legion::{Entity, Resources};
+use parking_lot::Mutex;
+
+use crate::voxels::voxel_data::VoxelData;
+
+// A voxel was edited, sent by VoxelScene::set_voxel
+#[derive(Clone, Copy, PartialEq)]
+pub struct BlockChanged {
+    pub pos: IVec3,
+    pub old: VoxelData,
+    pub new: VoxelData,
+}
+
+// The first meshes of a chunk reached the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLoaded {
+    pub pos: IVec3,
+}
+
+// A chunk that was loaded left the world along with its entities
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkUnloaded {
+    pub pos: IVec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerSpawned {
+    pub entity: Entity,
+}
+
+// Events sent from any thread, read by systems through their own EventReader. Double buffered: update swaps the
+// buffers once per tick, so an event is kept for two ticks and a system running before the sender still sees it on
+// the next one
+pub struct Events<T> {
+    buffers: Mutex<EventBuffers<T>>,
+}
+
+struct EventBuffers<T> {
+    previous: Vec<T>,
+    current: Vec<T>,
+    previous_start: u64, // Number of events sent before the first one in previous
+    current_start: u64,
+}
+
+impl<T: Clone + Send + Sync + 'static> Events<T> {
+    pub fn new() -> Self {
+        Self {
+            buffers: Mutex::new(EventBuffers {
+                previous: Vec::new(),
+                current: Vec::new(),
+                previous_start: 0,
+                current_start: 0,
+            }),
+        }
+    }
+
+    pub fn send(&self, event: T) {
+        self.buffers.lock().current.push(event);
+    }
+
+    // Every event the reader hasn't seen yet, oldest first. Events older than two ticks are gone
+    pub fn read(&self, reader: &mut EventReader<T>) -> Vec<T> {
+        let buffers = self.buffers.lock();
+        let mut events = Vec::new();
+        for (start, buffer) in [
+            (buffers.previous_start, &buffers.previous),
+            (buffers.current_start, &buffers.current),
+        ] {
+            let seen = reader.next.saturating_sub(start) as usize;
+            events.extend(buffer.iter().skip(seen).cloned());
+        }
+        reader.next = buffers.current_start + buffers.current.len() as u64;
+        events
+    }
+
+    // Drops the events sent two ticks ago, called once at the end of every tick
+    pub fn update(&self) {
+        let mut buffers = self.buffers.lock();
+        let sent = std::mem::take(&mut buffers.current);
+        buffers.previous_start = buffers.current_start;
+        buffers.current_start += sent.len() as u64;
+        buffers.previous = sent;
+    }
+
+    // Events still kept, across both buffers
+    pub fn len(&self) -> usize {
+        let buffers = self.buffers.lock();
+        buffers.previous.len() + buffers.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for Events<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How far a system has read into an Events<T>, kept as the system's state
+pub struct EventReader<T> {
+    next: u64,
+    _event: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for EventReader<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            _event: PhantomData,
+        }
+    }
+}
+
+// The engine's event channels, every one of them is a resource of its own. The block events are sent by the scene,
+// see VoxelScene::set_block_events
+#[derive(Clone, Default)]
+pub struct WorldEvents {
+    pub block_changed: Arc<Events<BlockChanged>>,
+    pub chunk_loaded: Arc<Events<ChunkLoaded>>,
+    pub chunk_unloaded: Arc<Events<ChunkUnloaded>>,
+    pub player_spawned: Arc<Events<PlayerSpawned>>,
+}
+
+impl WorldEvents {
+    pub fn insert_into(&self, resources: &mut Resources) {
+        resources.insert(Arc::clone(&self.block_changed));
+        resources.insert(Arc::clone(&self.chunk_loaded));
+        resources.insert(Arc::clone(&self.chunk_unloaded));
+        resources.insert(Arc::clone(&self.player_spawned));
+    }
+}
+
+// Swaps the buffers of every event channel in the resources, see run_tick
+pub fn update_events(resources: &Resources) {
+    update::<BlockChanged>(resources);
+    update::<ChunkLoaded>(resources);
+    update::<ChunkUnloaded>(resources);
+    update::<PlayerSpawned>(resources);
+}
+
+fn update<T: Clone + Send + Sync + 'static>(resources: &Resources) {
+    if let Some(events) = resources.get::<Arc<Events<T>>>() {
+        events.update();
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use super::*;
+
+    fn chunk(x: i32) -> ChunkLoaded {
+        ChunkLoaded {
+            pos: IVec3::new(x, 0, 0),
+        }
+    }
+
+    #[test]
+    fn every_reader_sees_every_event_once() {
+        let events = Events::new();
+        let mut early = EventReader::default();
+        let mut late = EventReader::default();
+
+        // The early reader runs before the sender in the tick, the late one after it
+        assert!(events.read(&mut early).is_empty());
+        events.send(chunk(1));
+        events.send(chunk(2));
+        assert_eq!(events.read(&mut late), vec![chunk(1), chunk(2)]);
+        events.update();
+
+        assert_eq!(events.read(&mut early), vec![chunk(1), chunk(2)]);
+        events.send(chunk(3));
+        assert_eq!(events.read(&mut late), vec![chunk(3)]);
+        events.update();
+
+        assert_eq!(events.read(&mut early), vec![chunk(3)]);
+        assert!(events.read(&mut late).is_empty());
+        events.update();
+        assert!(events.read(&mut early).is_empty());
+    }
+
+    #[test]
+    fn events_are_dropped_after_two_ticks() {
+        let events = Events::new();
+        events.send(chunk(1));
+        events.update();
+        assert_eq!(events.len(), 1);
+        events.update();
+        assert!(events.is_empty());
+
+        // A reader that starts late only gets what's still kept
+        events.send(chunk(2));
+        assert_eq!(events.read(&mut EventReader::default()), vec![chunk(2)]);
+    }
+}
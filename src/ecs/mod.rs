@@ -1,4 +1,6 @@
+pub mod chunk_entity_map;
 pub mod components;
 pub mod entities;
+pub mod events;
 pub mod systems;
 pub mod world;
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use glam::{Quat, Vec3};
+use legion::{query::component, system, world::SubWorld, IntoQuery};
+
+use crate::{
+    audio::audio_engine::AudioEngine,
+    ecs::{
+        components::{
+            audio_components::{AudioListener, AudioSource},
+            transformation_components::{Position, Rotation, WorldTransform},
+        },
+        events::{BlockChanged, EventReader, Events},
+    },
+};
+
+fn world_pose(
+    position: &Position,
+    rotation: Option<&Rotation>,
+    world_transform: Option<&WorldTransform>,
+) -> (Vec3, Quat) {
+    match world_transform {
+        Some(world_transform) => {
+            let (_, rotation, position) = world_transform.0.to_scale_rotation_translation();
+            (position, rotation)
+        }
+        None => (
+            position.0,
+            rotation.map_or(Quat::IDENTITY, |rotation| rotation.0),
+        ),
+    }
+}
+
+// Moves the listener to its entity, starts new sources and pans and fades everything playing to where it's heard from
+#[system]
+#[read_component(AudioListener)]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[read_component(WorldTransform)]
+#[write_component(AudioSource)]
+pub fn update_audio(world: &mut SubWorld, #[resource] audio: &Arc<AudioEngine>) {
+    let listener = <(&Position, Option<&Rotation>, Option<&WorldTransform>)>::query()
+        .filter(component::<AudioListener>())
+        .iter(world)
+        .next()
+        .map(|(position, rotation, world_transform)| {
+            world_pose(position, rotation, world_transform)
+        });
+    if let Some((position, rotation)) = listener {
+        audio.set_listener(position, rotation);
+    }
+
+    let mut query = <(&mut AudioSource, &Position, Option<&WorldTransform>)>::query();
+    for (source, position, world_transform) in query.iter_mut(world) {
+        let gains = if source.spatial {
+            let (position, _) = world_pose(position, None, world_transform);
+            audio.listener_gains(position, source.volume)
+        } else {
+            (source.volume, source.volume)
+        };
+        if !source.started {
+            source.started = true;
+            source.playback = audio.play(&source.sound, source.looping, gains);
+        } else if let Some(playback) = &source.playback {
+            playback.set_gains(gains);
+        }
+    }
+    audio.update_one_shots();
+}
+
+// A sound from where a voxel was broken or placed. Big edits like the shape showcase play a single sound of each
+// kind per tick instead of one per voxel
+#[system]
+pub fn play_block_sounds(
+    #[resource] audio: &Arc<AudioEngine>,
+    #[resource] block_changed: &Arc<Events<BlockChanged>>,
+    #[state] reader: &mut EventReader<BlockChanged>,
+) {
+    let mut broken = None;
+    let mut placed = None;
+    for event in block_changed.read(reader) {
+        if event.new.id == 0 && event.old.id != 0 {
+            broken.get_or_insert(event.pos);
+        } else if event.new.id != 0 {
+            placed.get_or_insert(event.pos);
+        }
+    }
+    if let Some(position) = broken {
+        audio.play_at("block_break.wav", position.as_vec3());
+    }
+    if let Some(position) = placed {
+        audio.play_at("block_place.wav", position.as_vec3());
+    }
+}
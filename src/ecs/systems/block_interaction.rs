@@ -0,0 +1,391 @@
+use std::sync::Arc;
+
+use glam::{IVec3, Quat, Vec2, Vec3};
+use legion::{system, world::SubWorld, IntoQuery};
+use parking_lot::RwLock;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::{
+    asset_types::{mesh::Mesh, vertex::Vertex},
+    ecs::components::{
+        player_components::Player,
+        rendering_components::{BlockHighlight, MeshRenderer},
+        transformation_components::{Position, Rotation},
+    },
+    input_manager::{
+        get_button, get_button_down, get_button_up, get_key, get_key_down, get_mouse_delta,
+    },
+    time::Time,
+    voxels::{
+        voxel_data::VoxelData,
+        voxel_mesh::get_voxel_bounds,
+        voxel_registry::{get_voxel_by_id, DEFAULT_HARDNESS},
+        voxel_scene::VoxelScene,
+        voxel_shapes::{voxel_orientations, voxel_shape, VoxelOrientation, VoxelShape},
+    },
+};
+
+pub const REACH: f32 = 8.0;
+
+// The outline sits slightly outside of the voxel and its edges are this wide
+const HIGHLIGHT_INFLATE: f32 = 0.005;
+const HIGHLIGHT_EDGE: f32 = 0.03;
+const HIGHLIGHT_COLOR: [f32; 4] = [0.05, 0.05, 0.05, 0.7];
+// A right click that moved the mouse further than this, in pixels, was looking around and places nothing
+const PLACEMENT_MAX_DRAG: f32 = 4.0;
+
+const NUMBER_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+
+// Tab cycles through the shapes placed on right click
+const PLACEABLE_SHAPES: [VoxelShape; 3] =
+    [voxel_shape::CUBE, voxel_shape::SLAB, voxel_shape::STAIR];
+
+const SHOWCASE_ORIENTATIONS: [VoxelOrientation; 6] = [
+    voxel_orientations::DEFAULT,
+    voxel_orientations::TOP,
+    voxel_orientations::BOTTOM_SOUTH,
+    voxel_orientations::BOTTOM_NORTH_WEST,
+    voxel_orientations::NORTH,
+    voxel_orientations::WEST,
+];
+
+// Holding left click breaks the voxel under the crosshair after its hardness in seconds, right click places the
+// selected voxel against the hit face. Dragging with the right button held looks around instead, see placement_click.
+// Shaped voxels are oriented by where the face was hit and the way the player is looking, see
+// VoxelOrientation::for_placement.
+// The sounds are played by play_block_sounds.
+// The number keys select one of the first nine registered voxels, F6 builds the shape showcase beside the player.
+// Every click and showcase is a stroke of its own, Ctrl+Z undoes the last one and Ctrl+Y redoes it
+#[system(for_each)]
+pub fn update_block_interaction(
+    pos: &Position,
+    rot: &Rotation,
+    player: &mut Player,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] time: &Time,
+) {
+    for (index, key) in NUMBER_KEYS.iter().enumerate() {
+        let id = index as u16 + 1;
+        if get_key_down(*key) && get_voxel_by_id(id).is_some() {
+            player.selected_voxel = id;
+        }
+    }
+
+    if get_key_down(VirtualKeyCode::Tab) {
+        let current = PLACEABLE_SHAPES
+            .iter()
+            .position(|shape| *shape == player.selected_shape)
+            .unwrap_or_default();
+        player.selected_shape = PLACEABLE_SHAPES[(current + 1) % PLACEABLE_SHAPES.len()];
+    }
+
+    if get_key(VirtualKeyCode::LControl) {
+        let undo = get_key_down(VirtualKeyCode::Z);
+        if undo || get_key_down(VirtualKeyCode::Y) {
+            let scene = scene.read();
+            let result = if undo { scene.undo() } else { scene.redo() };
+            if let Err(e) = result {
+                println!("[INFO] Could not undo or redo the last edit yet: {:?}", e);
+            }
+        }
+    }
+
+    if get_key_down(VirtualKeyCode::F6) {
+        let origin = pos.0.round().as_ivec3() + IVec3::new(2, 0, 2);
+        let scene = scene.read();
+        scene.begin_stroke();
+        for (position, voxel) in shape_showcase(origin, player.selected_voxel) {
+            if let Err(e) = scene.edit_voxel(position, voxel) {
+                println!(
+                    "[INFO] Could not place showcase voxel at {}: {:?}",
+                    position, e
+                );
+            }
+        }
+    }
+
+    let breaking = get_button(MouseButton::Left);
+    let placing = placement_click(
+        &mut player.look_drag,
+        get_button_down(MouseButton::Right),
+        get_button(MouseButton::Right),
+        get_button_up(MouseButton::Right),
+        get_mouse_delta(),
+    );
+    if !breaking {
+        player.breaking = None;
+    }
+    if !breaking && !placing {
+        return;
+    }
+
+    let scene = scene.read();
+    let (origin, look_dir) = eye_ray(pos.0, rot.0, player);
+    let hit = match scene.raycast(origin, look_dir, REACH) {
+        Some(hit) => hit,
+        None => {
+            player.breaking = None;
+            return;
+        }
+    };
+
+    if breaking {
+        let hardness =
+            get_voxel_by_id(hit.voxel.id).map_or(DEFAULT_HARDNESS, |profile| profile.hardness);
+        // Looking at another voxel starts over
+        let held = match player.breaking {
+            Some((target, held)) if target == hit.position => held + time.delta_time as f32,
+            _ => 0.0,
+        };
+        let broken = if hardness <= 0.0 {
+            get_button_down(MouseButton::Left)
+        } else {
+            held >= hardness
+        };
+        if !broken {
+            player.breaking = Some((hit.position, held));
+            return;
+        }
+        player.breaking = None;
+
+        scene.begin_stroke();
+        let mut air = hit.voxel;
+        air.id = 0;
+        air.shape = voxel_shape::CUBE;
+        if let Err(e) = scene.edit_voxel(hit.position, air) {
+            println!("[INFO] Could not break voxel at {}: {:?}", hit.position, e);
+        }
+    } else if hit.distance > 0.0 {
+        let target = hit.position + hit.face.as_vec();
+        if overlaps_player(target, pos.0, player) {
+            return;
+        }
+        // Cubes look the same either way, they're left unoriented so they still merge with generated terrain
+        let shape = if player.selected_shape == voxel_shape::CUBE {
+            voxel_shape::CUBE
+        } else {
+            let orientation = VoxelOrientation::for_placement(hit.face, hit.fraction(), look_dir);
+            player.selected_shape.oriented(orientation)
+        };
+        let voxel = VoxelData {
+            shape,
+            state: 0,
+            id: player.selected_voxel,
+        };
+        scene.begin_stroke();
+        if let Err(e) = scene.edit_voxel(target, voxel) {
+            println!("[INFO] Could not place voxel at {}: {:?}", target, e);
+        }
+    }
+}
+
+// Moves the outline onto the voxel under the crosshair, its mesh is only rebuilt when the shape's bounds change
+#[system]
+#[read_component(Player)]
+#[read_component(Rotation)]
+#[read_component(MeshRenderer)]
+#[write_component(Position)]
+#[write_component(BlockHighlight)]
+pub fn update_block_highlight(world: &mut SubWorld, #[resource] scene: &Arc<RwLock<VoxelScene>>) {
+    let eye = <(&Position, &Rotation, &Player)>::query()
+        .iter(world)
+        .next()
+        .map(|(pos, rot, player)| eye_ray(pos.0, rot.0, player));
+    let hit = eye.and_then(|(origin, look_dir)| scene.read().raycast(origin, look_dir, REACH));
+
+    let mut query = <(&mut Position, &mut BlockHighlight, &MeshRenderer)>::query();
+    for (pos, highlight, renderer) in query.iter_mut(world) {
+        let bounds = hit.as_ref().map(|hit| {
+            let (min, max) = get_voxel_bounds(hit.voxel.shape);
+            (
+                min - Vec3::splat(HIGHLIGHT_INFLATE),
+                max + Vec3::splat(HIGHLIGHT_INFLATE),
+            )
+        });
+        if let Some(hit) = &hit {
+            pos.0 = hit.position.as_vec3();
+        }
+        if highlight.bounds == bounds {
+            continue;
+        }
+        highlight.bounds = bounds;
+
+        let outline = match bounds {
+            Some((min, max)) => highlight_mesh(min, max),
+            None => Mesh::new(),
+        };
+        let mut mesh = renderer.mesh.write();
+        mesh.set_vertices(outline.get_vertices().clone());
+        mesh.set_indices(outline.get_indices().clone());
+    }
+}
+
+// A frame of thin strips along the border of each face of the box, the overlay material doesn't cull so the
+// strips show from both sides
+fn highlight_mesh(min: Vec3, max: Vec3) -> Mesh {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for side in [min[axis], max[axis]] {
+            let mut normal = [0.0; 3];
+            normal[axis] = if side == min[axis] { -1.0 } else { 1.0 };
+            let strips = [
+                ([min[u], min[v]], [min[u] + HIGHLIGHT_EDGE, max[v]]),
+                ([max[u] - HIGHLIGHT_EDGE, min[v]], [max[u], max[v]]),
+                ([min[u], min[v]], [max[u], min[v] + HIGHLIGHT_EDGE]),
+                ([min[u], max[v] - HIGHLIGHT_EDGE], [max[u], max[v]]),
+            ];
+            for (from, to) in strips {
+                let offset = vertices.len() as u32;
+                for (a, b) in [
+                    (from[0], from[1]),
+                    (to[0], from[1]),
+                    (to[0], to[1]),
+                    (from[0], to[1]),
+                ] {
+                    let mut position = [0.0; 3];
+                    position[axis] = side;
+                    position[u] = a;
+                    position[v] = b;
+                    vertices.push(Vertex {
+                        color: HIGHLIGHT_COLOR,
+                        normal,
+                        ..Vertex::new(position)
+                    });
+                }
+                indices.extend_from_slice(&[
+                    offset,
+                    offset + 1,
+                    offset + 2,
+                    offset,
+                    offset + 2,
+                    offset + 3,
+                ]);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new();
+    mesh.set_vertices(vertices);
+    mesh.set_indices(indices);
+    mesh
+}
+
+// Every shape along x and a few orientations of it along z, spaced out so each face stays visible
+fn shape_showcase(origin: IVec3, id: u16) -> Vec<(IVec3, VoxelData)> {
+    let mut voxels = Vec::new();
+    for shape in 0..8 {
+        for (row, orientation) in SHOWCASE_ORIENTATIONS.iter().enumerate() {
+            let position = origin + IVec3::new(shape as i32 * 2, 0, row as i32 * 2);
+            let voxel = VoxelData {
+                shape: VoxelShape { data: shape }.oriented(*orientation),
+                state: 0,
+                id,
+            };
+            voxels.push((position, voxel));
+        }
+    }
+    voxels
+}
+
+// Whether the right button was clicked rather than dragged. Holding it turns the camera, see update_players, so the
+// voxel is only placed once it's let go without the mouse having moved more than a few pixels
+fn placement_click(drag: &mut f32, pressed: bool, held: bool, released: bool, delta: Vec2) -> bool {
+    if pressed {
+        *drag = 0.0;
+    }
+    if held {
+        *drag += delta.length();
+    }
+    released && *drag < PLACEMENT_MAX_DRAG
+}
+
+// Rays are cast from the player's camera, which sits at eye height straight above its position
+fn eye_ray(position: Vec3, rotation: Quat, player: &Player) -> (Vec3, Vec3) {
+    (
+        position + Vec3::Y * player.eye_height,
+        player.look_rotation(rotation).mul_vec3(Vec3::Z),
+    )
+}
+
+// Rounds the player's box to voxels the same way VoxelScene::is_region_empty does
+fn overlaps_player(voxel_pos: IVec3, player_pos: Vec3, player: &Player) -> bool {
+    let (first, last) = VoxelScene::cells_in_region(
+        player_pos - player.half_extents,
+        player_pos + player.half_extents,
+    );
+    voxel_pos.cmpge(first).all() && voxel_pos.cmple(last).all()
+}
+
+#[cfg(test)]
+mod block_interaction_tests {
+    use super::*;
+
+    #[test]
+    fn placing_inside_player_is_rejected() {
+        let player = Player::new(10.0);
+        let player_pos = Vec3::new(0.2, 10.0, 0.0);
+        assert!(overlaps_player(IVec3::new(0, 10, 0), player_pos, &player));
+        assert!(overlaps_player(IVec3::new(0, 9, 0), player_pos, &player));
+        assert!(!overlaps_player(IVec3::new(0, 12, 0), player_pos, &player));
+        assert!(!overlaps_player(IVec3::new(2, 10, 0), player_pos, &player));
+        // Standing right on the edge between two voxels only overlaps the one it's standing in
+        let player_pos = Vec3::new(-0.8, 10.0, 0.0);
+        assert!(overlaps_player(IVec3::new(-1, 10, 0), player_pos, &player));
+        assert!(!overlaps_player(IVec3::new(0, 10, 0), player_pos, &player));
+    }
+
+    #[test]
+    fn looking_down_keeps_the_eye_above_the_player() {
+        let player = Player {
+            pitch: 1.2,
+            ..Player::new(10.0)
+        };
+        let rotation = Quat::from_rotation_y(0.7);
+        let (origin, look_dir) = eye_ray(Vec3::ZERO, rotation, &player);
+        assert_eq!(origin, Vec3::Y * player.eye_height);
+        assert!(look_dir.y < -0.9);
+    }
+
+    #[test]
+    fn looking_around_places_nothing() {
+        let mut drag = 0.0;
+        let mut tick = |pressed, held, released, delta: Vec2| {
+            placement_click(&mut drag, pressed, held, released, delta)
+        };
+        // Press, drag the camera to the side over a few ticks and let go
+        assert!(!tick(true, true, false, Vec2::ZERO));
+        for _ in 0..5 {
+            assert!(!tick(false, true, false, Vec2::new(12.0, -3.0)));
+        }
+        assert!(!tick(false, false, true, Vec2::ZERO));
+
+        // A click with the hand barely moving still places
+        assert!(!tick(true, true, false, Vec2::new(1.0, 0.0)));
+        assert!(!tick(false, true, false, Vec2::new(0.0, 1.0)));
+        assert!(tick(false, false, true, Vec2::ZERO));
+    }
+
+    #[test]
+    fn highlight_stays_within_its_bounds() {
+        let (min, max) = (Vec3::new(-0.5, -0.5, -0.5), Vec3::new(0.5, 0.0, 0.5));
+        let mesh = highlight_mesh(min, max);
+        // Four strips on each of the six faces
+        assert_eq!(mesh.get_vertices().len(), 6 * 4 * 4);
+        assert!(mesh.get_vertices().iter().all(|vertex| {
+            let position = Vec3::from(vertex.position);
+            position.cmpge(min).all() && position.cmple(max).all()
+        }));
+    }
+}
@@ -1,14 +1,103 @@
-use legion::system;
+use glam::{Quat, Vec2, Vec3};
+use legion::{system, Entity, EntityStore, IntoQuery, World};
+use winit::event::MouseButton;
 
-use crate::ecs::components::{
-    camera::Camera,
-    transformation_components::{Position, Rotation},
+use crate::{
+    ecs::{
+        components::{
+            camera::{Camera, CameraZoom},
+            player_components::Player,
+            transformation_components::{Parent, Position, Rotation, WorldTransform},
+        },
+        systems::{
+            player_controller::apply_mouse_look,
+            transform_systems::{interpolated_world_transform, interpolation_offsets},
+        },
+    },
+    input_manager::{get_button, get_mouse_delta_since_tick, get_scroll_delta},
+    rendering::camera::{MAX_FOV, MIN_FOV},
+    time::Time,
 };
 
+// Cameras in a hierarchy are placed where their world transform puts them
 #[system(for_each)]
-pub fn update_camera(pos: &Position, rot: &Rotation, camera: &mut Camera) {
+pub fn update_camera(
+    pos: &Position,
+    rot: &Rotation,
+    world_transform: Option<&WorldTransform>,
+    camera: &mut Camera,
+) {
+    let (rotation, position) = match world_transform {
+        Some(world_transform) => {
+            let (_, rotation, position) = world_transform.0.to_scale_rotation_translation();
+            (rotation, position)
+        }
+        None => (rot.0, pos.0),
+    };
     let mut cam_lock = camera.camera.write();
-    cam_lock.position = pos.0;
-    cam_lock.rotation = rot.0;
+    cam_lock.position = position;
+    cam_lock.rotation = rotation;
+    cam_lock.update_uniform();
+}
+
+// Runs every frame on the render thread, after the ticks placed the cameras. Cameras are moved back between the last
+// two ticks by the frame's alpha along with whatever they follow. The cameras of players looking around are turned by
+// the mouse movement the next tick hasn't picked up yet, so looking around runs at the frame rate
+pub fn interpolate_cameras(world: &World, alpha: f32) {
+    let offsets = interpolation_offsets(world, alpha);
+    let mouse_delta = if get_button(MouseButton::Right) {
+        get_mouse_delta_since_tick()
+    } else {
+        Vec2::ZERO
+    };
+    let mut query = <(Entity, &Camera, &Position, &Rotation, Option<&Parent>)>::query();
+    for (entity, camera, pos, rot, parent) in query.iter(world) {
+        let (position, mut rotation) = match parent {
+            Some(_) => {
+                let (_, rotation, position) = interpolated_world_transform(world, *entity, alpha)
+                    .to_scale_rotation_translation();
+                (position, rotation)
+            }
+            None => (
+                pos.0 + offsets.get(entity).copied().unwrap_or(Vec3::ZERO),
+                rot.0,
+            ),
+        };
+        let player = parent.and_then(|parent| {
+            let entry = world.entry_ref(parent.0).ok()?;
+            let player = *entry.get_component::<Player>().ok()?;
+            Some((entry.get_component::<Rotation>().ok()?.0, player))
+        });
+        if let Some((player_rotation, player)) = player {
+            if mouse_delta != Vec2::ZERO {
+                let (player_rotation, pitch) =
+                    apply_mouse_look(player_rotation, player.pitch, mouse_delta);
+                rotation = player_rotation * Quat::from_rotation_x(pitch);
+            }
+        }
+        let mut cam_lock = camera.camera.write();
+        if cam_lock.position != position || cam_lock.rotation != rotation {
+            cam_lock.position = position;
+            cam_lock.rotation = rotation;
+            cam_lock.update_uniform();
+        }
+    }
+}
+
+#[system(for_each)]
+pub fn update_camera_zoom(camera: &Camera, zoom: &mut CameraZoom, #[resource] time: &Time) {
+    let scroll = get_scroll_delta();
+    if scroll != 0.0 {
+        zoom.target_fov = (zoom.target_fov - scroll * zoom.scroll_step).clamp(MIN_FOV, MAX_FOV);
+    }
+
+    let mut cam_lock = camera.camera.write();
+    if (cam_lock.fovy - zoom.target_fov).abs() < 0.01 {
+        return;
+    }
+    // Frame rate independent exponential smoothing, keeps working while paused
+    let t = 1.0 - (-zoom.smoothing * time.unscaled_delta as f32).exp();
+    let fovy = cam_lock.fovy + (zoom.target_fov - cam_lock.fovy) * t;
+    cam_lock.set_fov(fovy);
     cam_lock.update_uniform();
 }
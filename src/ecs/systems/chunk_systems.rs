@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use legion::system;
+use parking_lot::RwLock;
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    ecs::components::{player_components::Player, transformation_components::Position},
+    input_manager::get_key_down,
+    voxels::{
+        biome_profile::reload_biomes, chunk_streamer::ChunkStreamer, structures::reload_structures,
+        voxel_registry::reload_voxels, voxel_scene::VoxelScene,
+    },
+};
+
+// Streams chunks in around the player, with several players the last one wins
+#[system(for_each)]
+pub fn stream_chunks(
+    pos: &Position,
+    _player: &Player,
+    #[resource] streamer: &mut ChunkStreamer,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+) {
+    streamer.update(&scene.read(), pos.0);
+}
+
+// F5 re-reads the voxel and biome profiles and the structures and regenerates the loaded chunks with them
+#[system]
+pub fn reload_profiles(#[resource] scene: &Arc<RwLock<VoxelScene>>) {
+    if !get_key_down(VirtualKeyCode::F5) {
+        return;
+    }
+    println!("[INFO] Reloading voxel and biome profiles and structures");
+    reload_voxels();
+    reload_biomes();
+    reload_structures();
+    scene.read().regenerate_loaded_chunks();
+}
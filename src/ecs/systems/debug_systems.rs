@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use legion::system;
+use parking_lot::RwLock;
+
+use crate::{
+    debug_stats::DebugStats,
+    physics::physics_scene::{lock_physics, PhysicsScene},
+    time::Time,
+    voxels::voxel_scene::VoxelScene,
+};
+
+// Summing up the voxel memory walks every chunk, so it's only done this often (in seconds)
+pub const VOXEL_MEMORY_INTERVAL: f64 = 1.0;
+
+// Nobody looks at the counters while the HUD is hidden, so they aren't collected either.
+// The state is the time since the voxel memory was last summed up
+#[system]
+pub fn collect_debug_stats(
+    #[state] since_voxel_memory: &mut f64,
+    #[resource] stats: &Arc<DebugStats>,
+    #[resource] time: &Time,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
+) {
+    if !stats.is_visible() {
+        return;
+    }
+    stats.record_scene(&scene.read());
+    {
+        let physics = lock_physics(physics);
+        stats.record_rigidbodies(physics.rigidbody_count());
+        stats.record_colliders(physics.collider_count());
+    }
+    *since_voxel_memory += time.unscaled_delta;
+    if *since_voxel_memory >= VOXEL_MEMORY_INTERVAL {
+        *since_voxel_memory = 0.0;
+        stats.record_voxel_bytes(scene.read().voxel_bytes());
+    }
+}
@@ -1,3 +1,11 @@
+pub mod audio_systems;
+pub mod block_interaction;
 pub mod camera_systems;
+pub mod chunk_systems;
+pub mod debug_systems;
+pub mod physics_systems;
 pub mod player_controller;
 pub mod render_systems;
+pub mod spawn_systems;
+pub mod time_systems;
+pub mod transform_systems;
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+use legion::{system, world::SubWorld, EntityStore, IntoQuery};
+use parking_lot::RwLock;
+
+use crate::{
+    ecs::{
+        chunk_entity_map::ChunkEntityMap,
+        components::{
+            physics_components::{
+                DynamicBody, KinematicBody, KinematicCharacterBody, MeshCollider,
+            },
+            transformation_components::{Position, Rotation},
+        },
+    },
+    physics::{
+        collider_streamer::ColliderStreamer,
+        physics_scene::{lock_physics, PhysicsScene},
+    },
+    time::Time,
+    voxels::voxel_scene::collision_friction,
+};
+
+// Activates the colliders of chunks that came within the physics distance of a dynamic body or a character and
+// deactivates the ones that went out of it. Runs before the step, so bodies never reach a chunk without a collider
+#[system]
+#[read_component(Position)]
+#[read_component(DynamicBody)]
+#[read_component(KinematicCharacterBody)]
+#[write_component(MeshCollider)]
+pub fn stream_colliders(
+    world: &mut SubWorld,
+    #[resource] streamer: &Arc<ColliderStreamer>,
+    #[resource] chunk_entities: &Arc<ChunkEntityMap>,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
+) {
+    let mut bodies: Vec<Vec3> = <(&Position, &DynamicBody)>::query()
+        .iter(world)
+        .map(|(position, _)| position.0)
+        .collect();
+    bodies.extend(
+        <(&Position, &KinematicCharacterBody)>::query()
+            .iter(world)
+            .map(|(position, _)| position.0),
+    );
+    let (entered, left) = streamer.update(bodies);
+    if entered.is_empty() && left.is_empty() {
+        return;
+    }
+
+    let mut physics = lock_physics(physics);
+    for (chunks, active) in [(entered, true), (left, false)] {
+        for chunk_pos in chunks {
+            // Not loaded yet or nothing solid means no collision meshes, their colliders are spawned in range
+            let meshes = chunk_entities.get(chunk_pos).meshes;
+            let colliders = meshes
+                .iter()
+                .filter(|(name, _)| collision_friction(name).is_some());
+            for (_, entity) in colliders {
+                if let Ok(mut entry) = world.entry_mut(*entity) {
+                    if let Ok(collider) = entry.get_component_mut::<MeshCollider>() {
+                        collider.set_active(&mut physics, active);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Kinematic bodies follow their entity into the step, dynamic bodies are copied back to their entity after it.
+// Mesh colliders whose mesh changed are rebuilt once before the step, no matter how often it changed
+#[system]
+#[write_component(MeshCollider)]
+#[read_component(KinematicBody)]
+#[read_component(DynamicBody)]
+#[write_component(Position)]
+#[write_component(Rotation)]
+pub fn step_physics(
+    world: &mut SubWorld,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
+    #[resource] time: &Time,
+) {
+    let mut physics = lock_physics(physics);
+
+    for mesh_collider in <&mut MeshCollider>::query().iter_mut(world) {
+        mesh_collider.update(&mut physics);
+    }
+
+    let mut kinematic_query = <(&KinematicBody, &Position, &Rotation)>::query();
+    for (body, position, rotation) in kinematic_query.iter(world) {
+        physics.set_kinematic_target(body.rigidbody_handle, position.0, rotation.0);
+    }
+
+    if physics.update(time.delta_time) == 0 {
+        return;
+    }
+
+    let mut dynamic_query = <(&DynamicBody, &mut Position, &mut Rotation)>::query();
+    for (body, position, rotation) in dynamic_query.iter_mut(world) {
+        if let Some((body_position, body_rotation)) = body.get_transform(&physics) {
+            position.0 = body_position;
+            rotation.0 = body_rotation;
+        }
+    }
+}
@@ -1,55 +1,238 @@
-use glam::{Quat, Vec3};
-use legion::system;
+use std::{collections::HashMap, sync::Arc};
+
+use glam::{Quat, Vec2, Vec3};
+use legion::{system, world::SubWorld, Entity, IntoQuery};
+use parking_lot::RwLock;
 use winit::event::{MouseButton, VirtualKeyCode};
 
 use crate::{
-    components::{
-        player_components::Player,
-        transformation_components::{Position, Rotation},
+    ecs::components::{
+        camera::Camera,
+        physics_components::KinematicCharacterBody,
+        player_components::{MovementMode, Player},
+        transformation_components::{Parent, Position, PreviousPosition, Rotation},
     },
     input_manager::{self, get_mouse_delta},
+    physics::physics_scene::{lock_physics, PhysicsScene},
     time::Time,
 };
 
+// Just short of straight up or down, so the forward direction never flips over
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+// Radians turned per pixel the mouse moves
+const LOOK_SENSITIVITY: f32 = 0.003;
+
+// The player's rotation around Y and its pitch once turned by the mouse movement
+pub fn apply_mouse_look(rotation: Quat, pitch: f32, mouse_delta: Vec2) -> (Quat, f32) {
+    let delta = mouse_delta * LOOK_SENSITIVITY;
+    (
+        Quat::from_axis_angle(Vec3::Y, delta.x) * rotation,
+        (pitch + delta.y).clamp(-MAX_PITCH, MAX_PITCH),
+    )
+}
+
+// Sends the player to the position, dropping whatever speed its character had so nothing carries through. The
+// previous position moves along, otherwise the player is drawn sliding there over the rest of the tick. The
+// physics scene has to be locked after the world, see PhysicsScene
+pub fn teleport_player(
+    pos: &mut Position,
+    previous: Option<&mut PreviousPosition>,
+    character: Option<&mut KinematicCharacterBody>,
+    physics: &mut PhysicsScene,
+    position: Vec3,
+) {
+    pos.0 = position;
+    if let Some(previous) = previous {
+        previous.0 = position;
+    }
+    if let Some(character) = character {
+        character.set_position(physics, position);
+    }
+}
+
+// Players with a character body walk and jump, players without one fly freely. F4 switches a character between
+// walking and flying, flying characters stop falling and pass through terrain unless noclip is turned off
 #[system(for_each)]
 pub fn update_players(
     pos: &mut Position,
     rot: &mut Rotation,
-    player: &Player,
+    player: &mut Player,
+    mut character: Option<&mut KinematicCharacterBody>,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
     #[resource] time: &Time,
 ) {
     let mut forward: Vec3 = rot.0.mul_vec3(Vec3::Z).into();
     forward = (forward * Vec3::new(1.0, 0.0, 1.0)).normalize();
     let right: Vec3 = rot.0.mul_vec3(Vec3::X).into();
     let up: Vec3 = Vec3::Y;
+    let delta_time = time.delta_time as f32;
 
+    let mut movement = Vec3::ZERO;
     if input_manager::get_key(VirtualKeyCode::W) {
-        pos.0 += forward * time.delta_time as f32 * player.fly_speed;
+        movement += forward;
     }
 
     if input_manager::get_key(VirtualKeyCode::S) {
-        pos.0 -= forward * time.delta_time as f32 * player.fly_speed;
+        movement -= forward;
     }
 
     if input_manager::get_key(VirtualKeyCode::D) {
-        pos.0 += right * time.delta_time as f32 * player.fly_speed;
+        movement += right;
     }
 
     if input_manager::get_key(VirtualKeyCode::A) {
-        pos.0 -= right * time.delta_time as f32 * player.fly_speed;
+        movement -= right;
     }
 
-    if input_manager::get_key(VirtualKeyCode::Space) {
-        pos.0 += up * time.delta_time as f32 * player.fly_speed;
+    if input_manager::get_key_down(VirtualKeyCode::F4) {
+        if let Some(character) = character.as_deref_mut() {
+            player.movement_mode = match player.movement_mode {
+                MovementMode::Walk => MovementMode::Fly,
+                MovementMode::Fly => MovementMode::Walk,
+            };
+            let flying = player.movement_mode == MovementMode::Fly;
+            // Whatever speed the character had is dropped, so toggling mid-fall stops it in place
+            character.horizontal_velocity = Vec3::ZERO;
+            character.vertical_velocity = 0.0;
+            character.grounded = false;
+            character.set_sensor(&mut lock_physics(physics), flying && player.noclip);
+            println!("[INFO] Movement mode {:?}", player.movement_mode);
+        }
     }
 
-    if input_manager::get_key(VirtualKeyCode::LShift) {
-        pos.0 -= up * time.delta_time as f32 * player.fly_speed;
+    match character {
+        Some(character) if player.movement_mode == MovementMode::Walk => {
+            let mut physics = lock_physics(physics);
+            // The position was changed from outside, like when a save is loaded
+            if pos.0 != character.get_position() {
+                character.set_position(&mut physics, pos.0);
+            }
+            if input_manager::get_key(VirtualKeyCode::Space) && character.grounded {
+                character.vertical_velocity = character.jump_speed;
+            }
+            character.vertical_velocity = (character.vertical_velocity
+                + physics.get_gravity().y * delta_time)
+                .max(-character.max_fall_speed);
+
+            // Only the heading matters, looking up or down doesn't slow the player
+            let wish_direction = (movement * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+            character.horizontal_velocity = walk_velocity(
+                character.horizontal_velocity,
+                wish_direction,
+                player,
+                delta_time,
+            );
+            let translation = character.horizontal_velocity * delta_time
+                + up * character.vertical_velocity * delta_time;
+            pos.0 = character.move_and_slide(&mut physics, translation);
+            if character.grounded {
+                character.vertical_velocity = 0.0;
+            }
+        }
+        character => {
+            if input_manager::get_key(VirtualKeyCode::Space) {
+                movement += up;
+            }
+
+            if input_manager::get_key(VirtualKeyCode::LShift) {
+                movement -= up;
+            }
+            let mut speed = player.fly_speed;
+            if input_manager::get_key(VirtualKeyCode::LControl) {
+                speed *= player.sprint_multiplier;
+            }
+            let translation = movement * delta_time * speed;
+            match character {
+                Some(character) if !player.noclip => {
+                    pos.0 = character.move_and_slide(&mut lock_physics(physics), translation);
+                }
+                Some(character) => {
+                    pos.0 += translation;
+                    character.set_position(&mut lock_physics(physics), pos.0);
+                }
+                None => pos.0 += translation,
+            }
+        }
     }
 
+    // The player only turns around Y, looking up and down tilts its camera, see aim_player_cameras
     if input_manager::get_button(MouseButton::Right) {
-        let delta = get_mouse_delta() * 0.003;
-        rot.0 = Quat::from_axis_angle(right, delta.y) * rot.0;
-        rot.0 = Quat::from_axis_angle(up, delta.x) * rot.0;
+        (rot.0, player.pitch) = apply_mouse_look(rot.0, player.pitch, get_mouse_delta());
+    }
+}
+
+// Tilts the cameras parented to players by their pitch, so the eye height offset stays straight up
+#[system]
+#[read_component(Player)]
+#[read_component(Parent)]
+#[read_component(Camera)]
+#[write_component(Rotation)]
+pub fn aim_player_cameras(world: &mut SubWorld) {
+    let pitches: HashMap<Entity, f32> = <(Entity, &Player)>::query()
+        .iter(world)
+        .map(|(entity, player)| (*entity, player.pitch))
+        .collect();
+    for (parent, rot, _) in <(&Parent, &mut Rotation, &Camera)>::query().iter_mut(world) {
+        if let Some(pitch) = pitches.get(&parent.0) {
+            rot.0 = Quat::from_rotation_x(*pitch);
+        }
+    }
+}
+
+// Moves the velocity towards the wished for direction at move speed. Speeding up and stopping take a fixed time,
+// so the player moves the same way at any tick rate
+fn walk_velocity(velocity: Vec3, wish_direction: Vec3, player: &Player, delta_time: f32) -> Vec3 {
+    let target = wish_direction * player.move_speed;
+    let change_time = if target == Vec3::ZERO {
+        player.stop_time
+    } else {
+        player.speed_up_time
+    };
+    let max_change = player.move_speed / change_time.max(f32::EPSILON) * delta_time;
+    let difference = target - velocity;
+    let velocity = if difference.length() <= max_change {
+        target
+    } else {
+        velocity + difference.normalize() * max_change
+    };
+    velocity.clamp_length_max(player.move_speed)
+}
+
+#[cfg(test)]
+mod player_controller_tests {
+    use super::*;
+
+    // Ticks until the velocity reaches the target, starting from the given velocity
+    fn ticks_to_reach(
+        velocity: Vec3,
+        wish_direction: Vec3,
+        player: &Player,
+        tick_rate: f32,
+    ) -> u32 {
+        let mut velocity = velocity;
+        let mut ticks = 0;
+        while velocity != wish_direction * player.move_speed {
+            velocity = walk_velocity(velocity, wish_direction, player, 1.0 / tick_rate);
+            ticks += 1;
+            assert!(velocity.length() <= player.move_speed + 1e-4);
+        }
+        ticks
+    }
+
+    #[test]
+    fn speeding_up_and_stopping_take_the_same_time_at_any_tick_rate() {
+        let player = Player {
+            stop_time: 0.25,
+            ..Player::new(10.0)
+        };
+        for tick_rate in [30.0, 60.0, 144.0] {
+            let full_speed = Vec3::Z * player.move_speed;
+            let stop_ticks = ticks_to_reach(full_speed, Vec3::ZERO, &player, tick_rate);
+            assert!((stop_ticks as f32 / tick_rate - player.stop_time).abs() <= 1.0 / tick_rate);
+            let start_ticks = ticks_to_reach(Vec3::ZERO, Vec3::Z, &player, tick_rate);
+            assert!(
+                (start_ticks as f32 / tick_rate - player.speed_up_time).abs() <= 1.0 / tick_rate
+            );
+        }
     }
 }
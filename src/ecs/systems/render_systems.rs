@@ -1,41 +1,260 @@
-use std::sync::{atomic::Ordering, Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+};
 
 use glam::{Mat4, Quat, Vec3};
-use legion::{IntoQuery, World};
+use legion::{system, Entity, IntoQuery, World};
+use parking_lot::RwLock;
 
 use crate::{
-    ecs::components::{rendering_components::MeshRenderer, transformation_components::Position},
-    rendering::render_pass_data::render_layers,
+    ecs::{
+        components::{
+            camera::Camera,
+            rendering_components::{MeshRenderer, SunLight},
+            transformation_components::{
+                Parent, Position, PreviousRotation, Rotation, Scale, WorldTransform,
+            },
+        },
+        systems::transform_systems::{interpolated_world_transform, interpolation_offsets},
+    },
+    rendering::{
+        light::LightUniform, material::Material, render_pass_data::RenderPassData,
+        render_settings::Fog, vertex::Vertex,
+    },
     state::State,
+    time::Time,
+    voxels::{chunk_streamer::ChunkStreamer, voxel_scene::CHUNK_SIZE},
 };
 
-pub fn construct_buffers(state: &State, world: &World) {
-    // Loop through all mesh renderers and append their data to the pass buffers if their data is dirty
-    let mut query = <(&MeshRenderer, &Position)>::query();
-    query.iter(world).for_each(|(renderer, position)| {
-        if !renderer.dirty.load(Ordering::Relaxed) {
-            return;
+// Keeps the fog ending where the loaded chunks do whenever the view distance changes, unless the fog is turned off
+#[system]
+pub fn fit_fog_to_view_distance(
+    #[resource] streamer: &ChunkStreamer,
+    #[resource] state: &Arc<RwLock<State>>,
+    #[state] fitted: &mut Option<u32>, // The view distance the fog was last fitted to
+) {
+    if *fitted == Some(streamer.view_distance) {
+        return;
+    }
+    // The window thread can hold the state while it waits for the world, so a busy state is left for the next tick
+    let mut state = match state.try_write() {
+        Some(state) => state,
+        None => return,
+    };
+    *fitted = Some(streamer.view_distance);
+    if state.render_settings.fog.is_some() {
+        state.render_settings.fog = Some(Fog::for_view_distance(streamer.view_distance));
+    }
+}
+
+// Turns the sun around the east-west axis, which gives a day and night cycle
+#[system(for_each)]
+pub fn animate_sun(sun: &mut SunLight, #[resource] time: &Time) {
+    let angle = sun.rotation_speed * time.delta_time as f32;
+    sun.direction = Quat::from_rotation_x(angle) * sun.direction;
+}
+
+// Copies the sun into the light uniform, it's written to the GPU at the start of the next frame.
+// The shadow map follows the camera
+pub fn update_light(state: &mut State, world: &World) {
+    if let Some(sun) = <&SunLight>::query().iter(world).next() {
+        state.light.uniform = LightUniform::new(sun.direction, sun.color, sun.ambient);
+    }
+    if let Some(camera) = <&Camera>::query().iter(world).next() {
+        let position = camera.camera.read().position;
+        state.light.uniform.set_shadow_focus(position);
+    }
+}
+
+pub fn construct_buffers(state: &State, world: &World, alpha: f32) {
+    // Renderer id -> (layer, material id) of every renderer that should currently be drawn
+    let mut live_renderers: HashMap<u64, (String, u64)> = HashMap::new();
+
+    let camera_positions: Vec<Vec3> = <&Camera>::query()
+        .iter(world)
+        .map(|camera| camera.camera.read().position)
+        .collect();
+    // Distance to the closest camera, infinite without one
+    let camera_distance = |position: Vec3| {
+        camera_positions
+            .iter()
+            .map(|camera| position.distance(*camera))
+            .fold(f32::INFINITY, f32::min)
+    };
+    // Renderers beyond the render distance of every camera are treated as if they were despawned until they come
+    // back in range
+    let max_distance = state
+        .render_settings
+        .render_distance
+        .filter(|_| !camera_positions.is_empty())
+        .map(|distance| (distance * CHUNK_SIZE) as f32);
+    // Dirty meshes with geometry, with their distance to the camera and size in bytes
+    let mut uploads: Vec<(f32, usize, &MeshRenderer, Mat4)> = Vec::new();
+
+    // Renderers that move with the ticks are drawn between the last two of them, see interpolation_offsets
+    let offsets = interpolation_offsets(world, alpha);
+    // Loop through all mesh renderers and queue the ones whose data is dirty for upload,
+    // renderers that only moved just get their transform rewritten
+    let mut query = <(
+        Entity,
+        &MeshRenderer,
+        &Position,
+        Option<&Rotation>,
+        Option<&PreviousRotation>,
+        Option<&Scale>,
+        Option<&WorldTransform>,
+        Option<&Parent>,
+    )>::query();
+    query.iter(world).for_each(
+        |(
+            entity,
+            renderer,
+            position,
+            rotation,
+            previous_rotation,
+            scale,
+            world_transform,
+            parent,
+        )| {
+            let offset = offsets.get(entity).copied().unwrap_or(Vec3::ZERO);
+            let transform = match (parent, world_transform) {
+                (Some(_), _) => interpolated_world_transform(world, *entity, alpha),
+                (None, Some(world_transform)) => Mat4::from_translation(offset) * world_transform.0,
+                (None, None) => {
+                    let rotation = rotation.map_or(Quat::IDENTITY, |rotation| rotation.0);
+                    Mat4::from_scale_rotation_translation(
+                        scale.map_or(Vec3::ONE, |scale| scale.0),
+                        previous_rotation
+                            .map_or(rotation, |previous| previous.0.slerp(rotation, alpha)),
+                        position.0 + offset,
+                    )
+                }
+            };
+            let position = transform.transform_point3(Vec3::ZERO);
+            renderer.poll_changes();
+            if renderer.destroyed.load(Ordering::Relaxed) {
+                return; // Removed from its pass below
+            }
+            if let Some(distance) = max_distance {
+                if camera_distance(position) > distance {
+                    renderer.culled.store(true, Ordering::Relaxed);
+                    return; // Removed from its pass below
+                }
+            }
+            if renderer.culled.swap(false, Ordering::Relaxed) {
+                renderer.dirty.store(true, Ordering::Relaxed); // Back in range, upload it again
+            }
+            let material_id = renderer.material.read().get_id();
+            live_renderers.insert(
+                renderer.get_id(),
+                (renderer.render_layer.to_string(), material_id),
+            );
+
+            let dirty = renderer.dirty.load(Ordering::Relaxed);
+            let moved = *renderer.uploaded_transform.lock() != Some(transform);
+            if !dirty && !moved {
+                return;
+            }
+
+            let (vertex_count, index_count) = {
+                let mesh = renderer.mesh.read();
+                (mesh.vertex_count as usize, mesh.index_count as usize)
+            };
+            if dirty && vertex_count > 0 {
+                let distance = camera_distance(position);
+                let size = vertex_count * std::mem::size_of::<Vertex>()
+                    + index_count * std::mem::size_of::<u32>();
+                uploads.push((distance, size, renderer, transform));
+                return;
+            }
+
+            let pass = match get_pass(state, renderer) {
+                Some(pass) => pass,
+                None => return,
+            };
+            if dirty {
+                pass.write().remove_mesh(renderer.get_id());
+            } else {
+                pass.write()
+                    .set_transform(state, renderer.get_id(), &transform);
+            }
+            *renderer.uploaded_transform.lock() = Some(transform);
+            renderer.dirty.store(false, Ordering::Relaxed);
+        },
+    );
+
+    // A burst of new meshes is spread over several frames, the ones left over stay dirty until the next frame
+    uploads.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let budget = state.settings.upload_budget;
+    let count = uploads_within_budget(uploads.iter().map(|(_, size, _, _)| *size), budget);
+    for (_, _, renderer, transform) in uploads.into_iter().take(count) {
+        if let Some(pass) = get_pass(state, renderer) {
+            pass.write().insert_mesh(
+                state,
+                renderer.get_id(),
+                Arc::clone(&renderer.mesh),
+                &transform,
+            );
+            *renderer.uploaded_transform.lock() = Some(transform);
+            renderer.dirty.store(false, Ordering::Relaxed);
         }
+    }
 
-        let mesh_lock = renderer.mesh.read();
-        if mesh_lock.vertex_count == 0 {
-            return;
+    // Drop the geometry of renderers that were destroyed, despawned or moved to another pass
+    for layer in state.render_layers.all() {
+        let layer_lock = layer.read();
+        for (material_id, pass) in &layer_lock.passes {
+            let mut pass_lock = pass.write();
+            for id in pass_lock.buffer.mesh_ids() {
+                let is_live = live_renderers
+                    .get(&id)
+                    .map_or(false, |(layer_name, material)| {
+                        *layer_name == layer_lock.name && material == material_id
+                    });
+                if !is_live {
+                    pass_lock.remove_mesh(id);
+                }
+            }
+            pass_lock.buffer.flush(state);
         }
+    }
+}
 
-        let layer = render_layers::get_layer_by_name(renderer.render_layer.to_string());
-        let layer = match layer {
-            Some(layer) => layer,
-            None => return,
-        };
+fn get_pass(
+    state: &State,
+    renderer: &MeshRenderer,
+) -> Option<Arc<RwLock<RenderPassData<dyn Material>>>> {
+    let layer = state
+        .render_layers
+        .get_layer_by_name(&renderer.render_layer)?;
+    let mut layer_lock = layer.write();
+    Some(layer_lock.get_or_create_pass(state, Arc::clone(&renderer.material)))
+}
 
-        let mut layer_lock = layer.write();
-        let pass = layer_lock.get_or_create_pass(state, Arc::clone(&renderer.material));
-        let transform =
-            Mat4::from_scale_rotation_translation(Vec3::ONE, Quat::IDENTITY, position.0);
+// How many uploads, nearest first, fit in the budget. The nearest one always goes, so a mesh bigger than the whole
+// budget still gets through
+fn uploads_within_budget(sizes: impl Iterator<Item = usize>, budget: usize) -> usize {
+    let mut total = 0;
+    let mut count = 0;
+    for size in sizes {
+        total += size;
+        if count > 0 && total > budget {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
 
-        pass.write()
-            .insert_mesh(&state, Arc::clone(&renderer.mesh), &transform);
+#[cfg(test)]
+mod render_system_tests {
+    use super::*;
 
-        renderer.dirty.store(false, Ordering::Relaxed);
-    });
+    #[test]
+    fn uploads_stop_at_the_budget() {
+        assert_eq!(uploads_within_budget([40, 40, 40].into_iter(), 100), 2);
+        assert_eq!(uploads_within_budget([500, 10].into_iter(), 100), 1);
+        assert_eq!(uploads_within_budget(std::iter::empty(), 100), 0);
+    }
 }
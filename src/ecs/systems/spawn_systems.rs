@@ -0,0 +1,361 @@
+use std::{collections::HashSet, sync::Arc};
+
+use glam::{EulerRot, IVec2, IVec3, Mat4, Quat, Vec3};
+use legion::{system, systems::CommandBuffer, Entity};
+use parking_lot::RwLock;
+
+use crate::{
+    asset_types::mesh::Mesh,
+    ecs::{
+        components::{
+            audio_components::{AudioListener, AudioSource},
+            camera::{Camera, CameraZoom},
+            physics_components::{ColliderComponent, KinematicCharacterBody},
+            player_components::Player,
+            rendering_components::{BlockHighlight, MeshRenderer},
+            transformation_components::{
+                Parent, Position, PreviousPosition, Rotation, WorldTransform,
+            },
+        },
+        events::{ChunkLoaded, EventReader, Events, PlayerSpawned},
+        systems::player_controller::teleport_player,
+    },
+    physics::physics_scene::{lock_physics, PhysicsScene},
+    rendering::{self, material::MaterialOverlay},
+    voxels::{
+        chunk_streamer::ChunkStreamer,
+        voxel_scene::{RegionQuery, VoxelScene},
+    },
+};
+
+// How far above the surface players are placed, so they never start inside the ground
+pub const SPAWN_HEIGHT: f32 = 2.0;
+// How far below the bottom of the world players fall before they're sent back to their spawn point
+pub const RESPAWN_DEPTH: f32 = 64.0;
+
+// Players waiting for the chunks under their spawn point, see Engine::spawn_player_on_surface
+#[derive(Default)]
+pub struct PendingSpawns {
+    pub spawns: Vec<PendingSpawn>,
+}
+
+pub struct PendingSpawn {
+    pub column: IVec2, // The x and z of the voxel column the player lands on
+    pub camera: Arc<RwLock<rendering::camera::Camera>>,
+}
+
+// A player carrying the camera and listener, with a character body in the physics scene, wind blowing around it and
+// an outline around the voxel it looks at. Returns the player, its camera is a child of it
+pub fn spawn_player_entities(
+    cmd: &mut CommandBuffer,
+    physics: &mut PhysicsScene,
+    camera: &Arc<RwLock<rendering::camera::Camera>>,
+    position: Vec3,
+) -> Entity {
+    let character = KinematicCharacterBody::new(physics, position, 0.6, 0.3);
+    cmd.push((
+        Position(position),
+        Rotation(Quat::IDENTITY),
+        MeshRenderer::new(
+            Arc::new(RwLock::new(Mesh::new())),
+            Arc::new(RwLock::new(MaterialOverlay::new())),
+            "Overlay".to_string(),
+        ),
+        BlockHighlight::default(),
+    ));
+    let player = Player {
+        spawn_point: position,
+        ..Player::new(50.0)
+    };
+    let eye_height = player.eye_height;
+    // Only the position is interpolated, the rotation follows the mouse right away
+    let entity = cmd.push((
+        Position(position),
+        PreviousPosition(position),
+        Rotation(Quat::from_euler(
+            EulerRot::XYZ,
+            0.0,
+            (45.0 as f32).to_radians(),
+            0.0,
+        )),
+        player,
+        character,
+        AudioSource::new("wind.wav", true, 0.25, false),
+    ));
+    // The entity is only reserved until the buffer is flushed, but its id is already final
+    physics.attach_entity(character.get_collider_handle(), entity);
+    // The camera follows the player around as its child, at eye height instead of the middle of its body
+    cmd.push((
+        Position(Vec3::Y * eye_height),
+        Rotation(Quat::IDENTITY),
+        Parent(entity),
+        WorldTransform(Mat4::IDENTITY),
+        AudioListener,
+        Camera {
+            camera: Arc::clone(camera),
+        },
+        CameraZoom::default(),
+    ));
+    entity
+}
+
+// Spawns the pending players once the chunk their surface is in reached the world, so they land on its collider.
+// Chunks are streamed in around the spawn column until then
+#[system]
+pub fn place_pending_spawns(
+    cmd: &mut CommandBuffer,
+    #[resource] pending: &mut PendingSpawns,
+    #[resource] streamer: &mut ChunkStreamer,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
+    #[resource] chunk_loaded: &Arc<Events<ChunkLoaded>>,
+    #[resource] player_spawned: &Arc<Events<PlayerSpawned>>,
+    #[state] reader: &mut EventReader<ChunkLoaded>,
+    #[state] loaded: &mut HashSet<IVec3>, // Chunks of the pending columns that reached the world
+) {
+    let events = chunk_loaded.read(reader);
+    if pending.spawns.is_empty() {
+        loaded.clear();
+        return;
+    }
+    let columns: Vec<IVec3> = pending
+        .spawns
+        .iter()
+        .map(|spawn| VoxelScene::chunk_at(&IVec3::new(spawn.column.x, 0, spawn.column.y)))
+        .collect();
+    loaded.extend(events.iter().map(|event| event.pos).filter(|pos| {
+        columns
+            .iter()
+            .any(|column| column.x == pos.x && column.z == pos.z)
+    }));
+
+    let scene = scene.read();
+    let half_extents = Player::new(0.0).half_extents;
+    streamer.update(
+        &scene,
+        Vec3::new(
+            pending.spawns[0].column.x as f32,
+            0.0,
+            pending.spawns[0].column.y as f32,
+        ),
+    );
+    pending.spawns.retain(|spawn| {
+        let height = match pending_spawn_height(&scene, loaded, spawn.column, half_extents) {
+            Some(height) => height,
+            None => return true,
+        };
+        let position = Vec3::new(spawn.column.x as f32, height, spawn.column.y as f32);
+        let entity =
+            spawn_player_entities(cmd, &mut lock_physics(physics), &spawn.camera, position);
+        println!(
+            "[INFO] Spawned the player at {}, {}, {}",
+            position.x, position.y, position.z
+        );
+        player_spawned.send(PlayerSpawned { entity });
+        false
+    });
+}
+
+// Players that fell out of the world are sent back to their spawn point, on top of whatever is there now
+#[system(for_each)]
+pub fn respawn_fallen_players(
+    pos: &mut Position,
+    previous: Option<&mut PreviousPosition>,
+    player: &mut Player,
+    character: Option<&mut KinematicCharacterBody>,
+    #[resource] scene: &Arc<RwLock<VoxelScene>>,
+    #[resource] physics: &Arc<RwLock<PhysicsScene>>,
+) {
+    let scene = scene.read();
+    if pos.0.y >= scene.config.min_height as f32 - RESPAWN_DEPTH {
+        return;
+    }
+    let spawn = player.spawn_point.round().as_ivec3();
+    let column = IVec2::new(spawn.x, spawn.z);
+    if let Some(height) = scene
+        .surface_height_at(spawn.x, spawn.z)
+        .and_then(|surface| clear_spawn_height(&scene, column, surface, player.half_extents))
+    {
+        player.spawn_point.y = height;
+    }
+    teleport_player(
+        pos,
+        previous,
+        character,
+        &mut lock_physics(physics),
+        player.spawn_point,
+    );
+}
+
+// Where a pending spawn lands, None until the chunks it needs reached the world. A column that's all air has nothing
+// to land on, players start SPAWN_HEIGHT above the top of the world there once every chunk of it is loaded
+fn pending_spawn_height(
+    scene: &VoxelScene,
+    loaded: &HashSet<IVec3>,
+    column: IVec2,
+    half_extents: Vec3,
+) -> Option<f32> {
+    let column_chunk = VoxelScene::chunk_at(&IVec3::new(column.x, 0, column.y));
+    let surface = match scene.surface_height_at(column.x, column.y) {
+        Some(surface) => surface,
+        None => {
+            let config = &scene.config;
+            let column_loaded = (config.min_chunk_y()..=config.max_chunk_y())
+                .all(|y| loaded.contains(&IVec3::new(column_chunk.x, y, column_chunk.z)));
+            return column_loaded.then(|| config.max_height as f32 + SPAWN_HEIGHT);
+        }
+    };
+    let surface_chunk = VoxelScene::chunk_at(&IVec3::new(column.x, surface, column.y));
+    if !loaded.contains(&surface_chunk) {
+        return None;
+    }
+    clear_spawn_height(scene, column, surface, half_extents)
+}
+
+// The height SPAWN_HEIGHT above the surface, or above whatever solid voxels a player's box would be stuck in there.
+// None while the box reaches into chunks that aren't loaded yet
+pub fn clear_spawn_height(
+    scene: &VoxelScene,
+    column: IVec2,
+    surface: i32,
+    half_extents: Vec3,
+) -> Option<f32> {
+    let mut height = surface as f32 + SPAWN_HEIGHT;
+    loop {
+        let center = Vec3::new(column.x as f32, height, column.y as f32);
+        // There are no voxels above the top of the world, the chunks there are never loaded
+        let mut max = center + half_extents;
+        max.y = max.y.min(scene.config.max_height as f32 - 0.5);
+        match scene.is_region_empty(center - half_extents, max) {
+            RegionQuery::Empty => return Some(height),
+            RegionQuery::Unloaded(_) => return None,
+            RegionQuery::Blocked(voxels) => {
+                // Tall boxes are lifted clear of the top voxel, so every step moves past it and the loop ends
+                let top = voxels.iter().map(|voxel| voxel.y).max().unwrap() as f32;
+                height = (top + SPAWN_HEIGHT).max(top + 0.5 + half_extents.y + 0.01);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod spawn_tests {
+    use legion::IntoQuery;
+
+    use super::*;
+    use crate::ecs::systems::transform_systems::interpolation_offsets;
+    use crate::voxels::{
+        voxel_registry::get_voxel_by_name,
+        voxel_scene::{VoxelChunk, WorldConfig, CHUNK_SIZE},
+    };
+
+    #[test]
+    fn spawns_rise_above_overhangs() {
+        let scene = VoxelScene::new();
+        let column = IVec2::new(-3, -20);
+        let chunk_pos = VoxelScene::chunk_at(&IVec3::new(column.x, 0, column.y));
+        for y in 0..2 {
+            let chunk_pos = chunk_pos + IVec3::Y * y;
+            scene.chunks.insert(chunk_pos, VoxelChunk::new(chunk_pos));
+        }
+        let half_extents = Player::new(0.0).half_extents;
+        assert_eq!(
+            clear_spawn_height(&scene, column, 4, half_extents),
+            Some(4.0 + SPAWN_HEIGHT)
+        );
+
+        // A voxel hanging right where the player's head would be
+        let stone = get_voxel_by_name("stone".to_string()).unwrap().id;
+        {
+            let position = IVec3::new(column.x, 7, column.y);
+            let mut chunk = scene.chunks.get_mut(&chunk_pos).unwrap();
+            chunk.voxel_scenespace_at_mut(&position).unwrap().id = stone;
+            chunk.is_empty = false;
+        }
+        assert_eq!(
+            clear_spawn_height(&scene, column, 4, half_extents),
+            Some(7.0 + SPAWN_HEIGHT)
+        );
+        // Taller than the spawn height, the box is lifted over the voxel rather than stuck on it forever
+        let height = clear_spawn_height(&scene, column, 4, Vec3::new(0.3, 3.0, 0.3)).unwrap();
+        assert!(height > 10.5 && height < 10.6);
+
+        // Right below the chunks that aren't loaded, unlike the ones above the top of the world
+        let top = 2 * CHUNK_SIZE as i32 - 1;
+        assert_eq!(clear_spawn_height(&scene, column, top, half_extents), None);
+        let scene = VoxelScene::new_with_config(WorldConfig {
+            max_height: 2 * CHUNK_SIZE as i32,
+            ..Default::default()
+        });
+        for y in 0..2 {
+            let chunk_pos = chunk_pos + IVec3::Y * y;
+            scene.chunks.insert(chunk_pos, VoxelChunk::new(chunk_pos));
+        }
+        assert_eq!(
+            clear_spawn_height(&scene, column, top, half_extents),
+            Some(top as f32 + SPAWN_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn spawns_over_empty_columns_fall_back_to_the_top_of_the_world() {
+        let scene = VoxelScene::new_with_config(WorldConfig {
+            max_height: 2 * CHUNK_SIZE as i32,
+            ..Default::default()
+        });
+        let column = IVec2::new(5, 5);
+        let half_extents = Player::new(0.0).half_extents;
+        let mut loaded = HashSet::new();
+        for y in 0..2 {
+            let chunk_pos = IVec3::new(0, y, 0);
+            scene.chunks.insert(chunk_pos, VoxelChunk::new(chunk_pos));
+            assert_eq!(
+                pending_spawn_height(&scene, &loaded, column, half_extents),
+                None
+            );
+            loaded.insert(chunk_pos);
+        }
+        assert_eq!(
+            pending_spawn_height(&scene, &loaded, column, half_extents),
+            Some(2.0 * CHUNK_SIZE as f32 + SPAWN_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn respawned_players_jump_straight_to_the_spawn() {
+        let scene = VoxelScene::new();
+        let mut physics = PhysicsScene::new(60);
+        let fallen = Vec3::new(
+            0.0,
+            scene.config.min_height as f32 - RESPAWN_DEPTH - 1.0,
+            0.0,
+        );
+        let spawn_point = Vec3::new(5.0, 20.0, 5.0);
+        let character = KinematicCharacterBody::new(&mut physics, fallen, 0.6, 0.3);
+        let mut world = legion::World::default();
+        world.push((
+            Position(fallen),
+            PreviousPosition(fallen),
+            Player {
+                spawn_point,
+                ..Player::new(0.0)
+            },
+            character,
+        ));
+
+        let mut resources = legion::Resources::default();
+        resources.insert(Arc::new(RwLock::new(scene)));
+        resources.insert(Arc::new(RwLock::new(physics)));
+        legion::Schedule::builder()
+            .add_system(respawn_fallen_players_system())
+            .build()
+            .execute(&mut world, &mut resources);
+
+        let mut query = <(&Position, &PreviousPosition, &KinematicCharacterBody)>::query();
+        let (position, previous, character) = query.iter(&world).next().unwrap();
+        assert_eq!(position.0, spawn_point);
+        assert_eq!(previous.0, spawn_point);
+        assert_eq!(character.get_position(), spawn_point);
+        assert!(interpolation_offsets(&world, 0.5).is_empty());
+    }
+}
@@ -0,0 +1,22 @@
+use legion::system;
+use winit::event::VirtualKeyCode;
+
+use crate::{input_manager::get_key_down, time::Time};
+
+pub const PAUSE_KEY: VirtualKeyCode = VirtualKeyCode::P;
+
+// Takes effect from the next tick, this one has already been advanced
+#[system]
+pub fn toggle_pause(#[resource] time: &mut Time) {
+    if get_key_down(PAUSE_KEY) {
+        time.toggle_pause();
+        println!(
+            "[INFO] {}",
+            if time.is_paused() {
+                "Paused"
+            } else {
+                "Resumed"
+            }
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use glam::{Mat4, Quat, Vec3};
+use legion::{
+    system, systems::CommandBuffer, world::SubWorld, Entity, EntityStore, IntoQuery, World,
+};
+
+use crate::{
+    ecs::components::transformation_components::{
+        Parent, Position, PreviousPosition, PreviousRotation, Rotation, Scale, Spin, WorldTransform,
+    },
+    time::Time,
+};
+
+#[system(for_each)]
+pub fn spin(rotation: &mut Rotation, spin: &Spin, #[resource] time: &Time) {
+    let angle = spin.0.length() * time.delta_time as f32;
+    if angle == 0.0 {
+        return;
+    }
+    rotation.0 = (Quat::from_axis_angle(spin.0.normalize(), angle) * rotation.0).normalize();
+}
+
+// Runs first every tick, so the previous transforms hold where the entities were at the end of the last one
+#[system(for_each)]
+pub fn record_previous_positions(position: &Position, previous: &mut PreviousPosition) {
+    previous.0 = position.0;
+}
+
+#[system(for_each)]
+pub fn record_previous_rotations(rotation: &Rotation, previous: &mut PreviousRotation) {
+    previous.0 = rotation.0;
+}
+
+// How far each entity with a PreviousPosition is drawn from its Position, alpha of the way from the previous one.
+// Only meaningful for entities without a parent, where the local position is the world position
+pub fn interpolation_offsets(world: &World, alpha: f32) -> HashMap<Entity, Vec3> {
+    <(Entity, &Position, &PreviousPosition)>::query()
+        .iter(world)
+        .map(|(entity, position, previous)| {
+            (*entity, previous.0.lerp(position.0, alpha) - position.0)
+        })
+        .filter(|(_, offset)| *offset != Vec3::ZERO)
+        .collect()
+}
+
+// The world transform of an entity in a hierarchy alpha of the way from the last tick to the current one. Every
+// entity in the chain is moved back along its PreviousPosition and turned back along its PreviousRotation, so children
+// swing around rotating parents instead of only following their translation
+pub fn interpolated_world_transform(world: &World, entity: Entity, alpha: f32) -> Mat4 {
+    let mut chain = Vec::new();
+    let mut current = Some(entity);
+    while let Some(entity) = current {
+        if chain.contains(&entity) {
+            break; // A parent cycle, propagate_transforms already complains about it
+        }
+        chain.push(entity);
+        current = world
+            .entry_ref(entity)
+            .ok()
+            .and_then(|entry| entry.get_component::<Parent>().ok().map(|parent| parent.0));
+    }
+    chain.iter().rev().fold(Mat4::IDENTITY, |parent, entity| {
+        let entry = match world.entry_ref(*entity) {
+            Ok(entry) => entry,
+            Err(_) => return parent,
+        };
+        let position = entry
+            .get_component::<Position>()
+            .map_or(Vec3::ZERO, |position| position.0);
+        let rotation = entry
+            .get_component::<Rotation>()
+            .map_or(Quat::IDENTITY, |rotation| rotation.0);
+        let local = Mat4::from_scale_rotation_translation(
+            entry
+                .get_component::<Scale>()
+                .map_or(Vec3::ONE, |scale| scale.0),
+            entry
+                .get_component::<PreviousRotation>()
+                .map_or(rotation, |previous| previous.0.slerp(rotation, alpha)),
+            entry
+                .get_component::<PreviousPosition>()
+                .map_or(position, |previous| previous.0.lerp(position, alpha)),
+        );
+        parent * local
+    })
+}
+
+// Entities that don't exist anymore have no transform, their children end up relative to the origin
+fn local_transform(world: &SubWorld, entity: Entity) -> Mat4 {
+    let entry = match world.entry_ref(entity) {
+        Ok(entry) => entry,
+        Err(_) => return Mat4::IDENTITY,
+    };
+    Mat4::from_scale_rotation_translation(
+        entry
+            .get_component::<Scale>()
+            .map_or(Vec3::ONE, |scale| scale.0),
+        entry
+            .get_component::<Rotation>()
+            .map_or(Quat::IDENTITY, |rotation| rotation.0),
+        entry
+            .get_component::<Position>()
+            .map_or(Vec3::ZERO, |position| position.0),
+    )
+}
+
+fn parent_of(world: &SubWorld, entity: Entity) -> Option<Entity> {
+    world
+        .entry_ref(entity)
+        .ok()
+        .and_then(|entry| entry.get_component::<Parent>().ok().map(|parent| parent.0))
+}
+
+// Composes the local transforms down every parent chain into world transforms. Every chain is walked up until an
+// entity that was already resolved this tick, then resolved from the top down, so each entity is only computed once.
+// A chain that loops back onto itself is logged and its entities keep their local transforms
+#[system]
+#[read_component(Position)]
+#[read_component(Rotation)]
+#[read_component(Scale)]
+#[read_component(Parent)]
+#[write_component(WorldTransform)]
+pub fn propagate_transforms(world: &mut SubWorld, commands: &mut CommandBuffer) {
+    let children: Vec<Entity> = <(Entity, &Parent)>::query()
+        .iter(world)
+        .map(|(entity, _)| *entity)
+        .collect();
+    let mut resolved: HashMap<Entity, Mat4> = HashMap::new();
+    for child in children {
+        let mut chain = Vec::new();
+        let mut parent_transform = Mat4::IDENTITY;
+        let mut current = Some(child);
+        while let Some(entity) = current {
+            if let Some(transform) = resolved.get(&entity) {
+                parent_transform = *transform;
+                break;
+            }
+            if let Some(start) = chain.iter().position(|member| *member == entity) {
+                println!("[INFO] Entity {entity:?} is its own ancestor, ignoring the parents in its cycle");
+                for member in chain.drain(start..) {
+                    resolved.insert(member, local_transform(world, member));
+                }
+                parent_transform = resolved[&entity];
+                break;
+            }
+            chain.push(entity);
+            current = parent_of(world, entity);
+        }
+        for entity in chain.into_iter().rev() {
+            parent_transform = parent_transform * local_transform(world, entity);
+            resolved.insert(entity, parent_transform);
+        }
+    }
+
+    // Entities whose parent was removed are back to their local transform
+    let orphans: Vec<(Entity, Mat4)> = <(Entity, &WorldTransform)>::query()
+        .iter(world)
+        .filter(|(entity, _)| !resolved.contains_key(*entity))
+        .map(|(entity, _)| (*entity, local_transform(world, *entity)))
+        .collect();
+    resolved.extend(orphans);
+
+    for (entity, world_transform) in <(Entity, &mut WorldTransform)>::query().iter_mut(world) {
+        if let Some(transform) = resolved.remove(entity) {
+            world_transform.0 = transform;
+        }
+    }
+    // Whatever is left doesn't have a world transform yet, it can be read from the next tick on
+    for (entity, transform) in resolved {
+        commands.add_component(entity, WorldTransform(transform));
+    }
+}
+
+#[cfg(test)]
+mod transform_tests {
+    use legion::{Resources, Schedule, World};
+
+    use super::*;
+
+    fn tick(world: &mut World) {
+        let mut resources = Resources::default();
+        let mut schedule = Schedule::builder()
+            .add_system(propagate_transforms_system())
+            .build();
+        schedule.execute(world, &mut resources);
+    }
+
+    fn world_transform(world: &World, entity: Entity) -> Mat4 {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<WorldTransform>()
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn hierarchies_propagate_and_follow_reparenting() {
+        let mut world = World::default();
+        let root_local = Mat4::from_rotation_translation(
+            Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            Vec3::new(10.0, 0.0, 0.0),
+        );
+        let root = world.push((
+            Position(Vec3::new(10.0, 0.0, 0.0)),
+            Rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)),
+            WorldTransform(Mat4::IDENTITY),
+        ));
+        let child = world.push((
+            Position(Vec3::new(0.0, 0.0, 2.0)),
+            Scale(Vec3::splat(2.0)),
+            Parent(root),
+            WorldTransform(Mat4::IDENTITY),
+        ));
+        let grandchild = world.push((
+            Position(Vec3::new(0.0, 1.0, 0.0)),
+            Parent(child),
+            WorldTransform(Mat4::IDENTITY),
+        ));
+        tick(&mut world);
+
+        // The child's offset along z is turned onto x by the root, the grandchild's is doubled by the child
+        let position = world_transform(&world, grandchild).transform_point3(Vec3::ZERO);
+        assert!(position.abs_diff_eq(Vec3::new(12.0, 2.0, 0.0), 1e-4));
+        assert!(world_transform(&world, root).abs_diff_eq(root_local, 1e-5));
+
+        world.entry(grandchild).unwrap().add_component(Parent(root));
+        tick(&mut world);
+        let position = world_transform(&world, grandchild).transform_point3(Vec3::ZERO);
+        assert!(position.abs_diff_eq(Vec3::new(10.0, 1.0, 0.0), 1e-4));
+
+        // A cycle doesn't hang, its entities fall back to their local transforms
+        world.entry(root).unwrap().add_component(Parent(grandchild));
+        tick(&mut world);
+        assert!(world_transform(&world, root).abs_diff_eq(root_local, 1e-5));
+    }
+
+    #[test]
+    fn children_are_drawn_between_the_last_two_ticks() {
+        let mut world = World::default();
+        let root = world.push((Position(Vec3::ZERO), PreviousPosition(Vec3::ONE)));
+        let child = world.push((Position(Vec3::Y), Parent(root)));
+        let still = world.push((Position(Vec3::X),));
+        let mut schedule = Schedule::builder()
+            .add_system(record_previous_positions_system())
+            .build();
+        schedule.execute(&mut world, &mut Resources::default());
+        assert_eq!(
+            world
+                .entry_ref(root)
+                .unwrap()
+                .get_component::<PreviousPosition>()
+                .unwrap()
+                .0,
+            Vec3::ZERO
+        );
+
+        // The tick moves the root 10 along x
+        world
+            .entry(root)
+            .unwrap()
+            .add_component(Position(Vec3::new(10.0, 0.0, 0.0)));
+        let offsets = interpolation_offsets(&world, 0.25);
+        assert_eq!(offsets[&root], Vec3::new(-7.5, 0.0, 0.0));
+        let position = |entity| {
+            interpolated_world_transform(&world, entity, 0.25).transform_point3(Vec3::ZERO)
+        };
+        assert!(position(root).abs_diff_eq(Vec3::new(2.5, 0.0, 0.0), 1e-5));
+        assert!(position(child).abs_diff_eq(Vec3::new(2.5, 1.0, 0.0), 1e-5));
+        assert_eq!(position(still), Vec3::X);
+        assert!(interpolation_offsets(&world, 1.0).is_empty());
+    }
+
+    #[test]
+    fn children_swing_around_rotating_parents() {
+        let mut world = World::default();
+        let root = world.push((
+            Position(Vec3::ZERO),
+            Rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)),
+            PreviousRotation(Quat::IDENTITY),
+        ));
+        let child = world.push((Position(Vec3::Z), Parent(root)));
+        // Halfway through the quarter turn, the child is halfway between +z and +x
+        let position =
+            interpolated_world_transform(&world, child, 0.5).transform_point3(Vec3::ZERO);
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(position.abs_diff_eq(Vec3::new(diagonal, 0.0, diagonal), 1e-5));
+    }
+}
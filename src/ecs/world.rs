@@ -1,3 +1,272 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use glam::{Quat, Vec3};
+use legion::{Entity, IntoQuery};
+use parking_lot::{RwLock, RwLockWriteGuard};
+
+use crate::ecs::components::physics_components::{
+    ColliderComponent, ConvexHullCollider, DynamicBody, KinematicBody, KinematicCharacterBody,
+    MeshCollider, TriggerCollider,
+};
+use crate::ecs::components::player_components::Player;
+use crate::ecs::components::transformation_components::{Position, PreviousPosition, Rotation};
+use crate::physics::physics_scene::{holds_physics_lock, PhysicsScene};
+use crate::voxels::chunk_storage::ChunkStorage;
+use crate::voxels::voxel_scene::{MeshingMode, VoxelScene, WorldConfig};
+
+pub const SAVE_VERSION: u64 = 1;
+
 pub struct World {
     pub legion_world: legion::World,
 }
+
+impl World {
+    // The world has to be locked before the physics scene, see PhysicsScene
+    pub fn lock(world: &RwLock<World>) -> RwLockWriteGuard<'_, World> {
+        debug_assert!(
+            !holds_physics_lock(),
+            "The world was locked while holding the physics scene, lock the world first"
+        );
+        world.write()
+    }
+
+    // Removes the entity along with its bodies and colliders, returns false if it didn't exist.
+    // Takes the physics scene rather than locking it, so it can be called from within the physics step.
+    // Renderers need no cleanup, the render systems drop the geometry of renderers that are gone
+    pub fn despawn(&mut self, entity: Entity, physics: &mut PhysicsScene) -> bool {
+        let mut entry = match self.legion_world.entry(entity) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Ok(body) = entry.get_component::<DynamicBody>() {
+            physics.remove_rigidbody(body.rigidbody_handle);
+        }
+        if let Ok(body) = entry.get_component::<KinematicBody>() {
+            physics.remove_rigidbody(body.rigidbody_handle);
+        }
+        if let Ok(body) = entry.get_component::<KinematicCharacterBody>() {
+            physics.remove_rigidbody(body.rigidbody_handle);
+        }
+        if let Ok(collider) = entry.get_component::<ConvexHullCollider>() {
+            physics.remove_collider(collider.get_collider_handle());
+        }
+        if let Ok(collider) = entry.get_component::<TriggerCollider>() {
+            physics.remove_collider(collider.get_collider_handle());
+        }
+        if let Ok(collider) = entry.get_component_mut::<MeshCollider>() {
+            collider.remove(physics);
+        }
+        self.legion_world.remove(entity)
+    }
+
+    // Lets the events of the entity's colliders report it, for entities pushed after their colliders were created
+    pub fn attach_colliders(&mut self, entity: Entity, physics: &mut PhysicsScene) {
+        let mut entry = match self.legion_world.entry(entity) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if let Ok(body) = entry.get_component::<DynamicBody>() {
+            physics.attach_entity(body.get_collider_handle(), entity);
+        }
+        if let Ok(body) = entry.get_component::<KinematicBody>() {
+            physics.attach_entity(body.get_collider_handle(), entity);
+        }
+        if let Ok(body) = entry.get_component::<KinematicCharacterBody>() {
+            physics.attach_entity(body.get_collider_handle(), entity);
+        }
+        if let Ok(collider) = entry.get_component::<ConvexHullCollider>() {
+            physics.attach_entity(collider.get_collider_handle(), entity);
+        }
+        if let Ok(collider) = entry.get_component::<TriggerCollider>() {
+            physics.attach_entity(collider.get_collider_handle(), entity);
+        }
+        if let Ok(collider) = entry.get_component_mut::<MeshCollider>() {
+            collider.attach_entity(physics, entity);
+        }
+    }
+
+    // Writes a manifest and every loaded chunk into the given folder, existing chunk files are overwritten
+    pub fn save(&self, path: &str, scene: &VoxelScene) -> std::io::Result<()> {
+        fs::create_dir_all(path)?;
+
+        let mut player = serde_json::Value::Null;
+        let mut query = <(&Position, &Rotation, &Player)>::query();
+        if let Some((position, rotation, state)) = query.iter(&self.legion_world).next() {
+            player = serde_json::json!({
+                "position": position.0.to_array(),
+                "rotation": rotation.0.to_array(),
+                "pitch": state.pitch,
+            });
+        }
+
+        let config = scene.config;
+        let manifest = serde_json::json!({
+            "version": SAVE_VERSION,
+            "seed": config.seed,
+            "min_height": config.min_height,
+            "max_height": config.max_height,
+            "meshing": match config.meshing {
+                MeshingMode::Blocky => "blocky",
+                MeshingMode::Greedy => "greedy",
+                MeshingMode::Smooth => "smooth",
+            },
+            "player": player,
+        });
+        fs::write(
+            Path::new(path).join("manifest.json"),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        let storage = ChunkStorage::new(chunk_folder(path));
+        for chunk in scene.chunks.iter() {
+            storage.save_chunk(chunk.position, &chunk)?;
+        }
+        println!("[INFO] Saved {} chunks to {}", scene.chunks.len(), path);
+        Ok(())
+    }
+
+    // Restores the player transform and builds a voxel scene with the saved settings.
+    // The chunks themselves are read from the chunk storage as they are requested
+    pub fn load(&mut self, path: &str) -> std::io::Result<VoxelScene> {
+        let manifest_data = fs::read_to_string(Path::new(path).join("manifest.json"))?;
+        let manifest: serde_json::Value = serde_json::from_str(&manifest_data)?;
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let version = manifest["version"]
+            .as_u64()
+            .ok_or_else(|| invalid("Save manifest has no version"))?;
+        if version != SAVE_VERSION {
+            return Err(invalid(&format!("Unsupported save version {version}")));
+        }
+
+        let default = WorldConfig::default();
+        let config = WorldConfig {
+            seed: manifest["seed"].as_u64().unwrap_or(default.seed),
+            min_height: manifest["min_height"]
+                .as_i64()
+                .map_or(default.min_height, |height| height as i32),
+            max_height: manifest["max_height"]
+                .as_i64()
+                .map_or(default.max_height, |height| height as i32),
+            meshing: match manifest["meshing"].as_str() {
+                Some("greedy") => MeshingMode::Greedy,
+                Some("smooth") => MeshingMode::Smooth,
+                _ => MeshingMode::Blocky,
+            },
+        };
+        let scene = VoxelScene::new_with_config(config);
+
+        let player = &manifest["player"];
+        let position = read_floats::<3>(&player["position"]).map(Vec3::from);
+        let rotation = read_floats::<4>(&player["rotation"]).map(Quat::from_array);
+        let pitch = player["pitch"].as_f64().map(|pitch| pitch as f32);
+        let mut query = <(
+            &mut Position,
+            Option<&mut PreviousPosition>,
+            &mut Rotation,
+            &mut Player,
+        )>::query();
+        for (player_position, previous, player_rotation, state) in
+            query.iter_mut(&mut self.legion_world)
+        {
+            if let Some(position) = position {
+                player_position.0 = position;
+                if let Some(previous) = previous {
+                    previous.0 = position;
+                }
+            }
+            if let Some(rotation) = rotation {
+                player_rotation.0 = rotation;
+            }
+            if let Some(pitch) = pitch {
+                state.pitch = pitch;
+            }
+        }
+
+        Ok(scene)
+    }
+}
+
+// Where the chunk storage of a world folder keeps its chunks
+pub fn chunk_folder(path: &str) -> PathBuf {
+    Path::new(path).join("chunks")
+}
+
+fn read_floats<const N: usize>(value: &serde_json::Value) -> Option<[f32; N]> {
+    let values = value.as_array()?;
+    if values.len() != N {
+        return None;
+    }
+    let mut floats = [0.0; N];
+    for (float, value) in floats.iter_mut().zip(values) {
+        *float = value.as_f64()? as f32;
+    }
+    Some(floats)
+}
+
+#[cfg(test)]
+mod save_tests {
+    use super::*;
+
+    #[test]
+    fn floats_need_the_exact_length() {
+        let value = serde_json::json!([1.0, 2.5, -3.0]);
+        assert_eq!(read_floats::<3>(&value), Some([1.0, 2.5, -3.0]));
+        assert_eq!(read_floats::<4>(&value), None);
+        assert_eq!(read_floats::<3>(&serde_json::json!([1.0, "2", 3.0])), None);
+    }
+}
+
+#[cfg(test)]
+mod physics_tests {
+    use rapier3d::prelude::ColliderBuilder;
+
+    use super::*;
+    use crate::physics::physics_scene::lock_physics;
+
+    #[test]
+    fn despawned_bodies_leave_the_physics_scene() {
+        let mut physics = PhysicsScene::new(60);
+        let mut world = World {
+            legion_world: legion::World::default(),
+        };
+        let entities: Vec<Entity> = (0..100)
+            .map(|i| {
+                let position = Vec3::new(i as f32 * 2.0, 5.0, 0.0);
+                let body =
+                    DynamicBody::new(&mut physics, position, ColliderBuilder::ball(0.5).build());
+                world.legion_world.push((Position(position), body))
+            })
+            .collect();
+        physics.update(1.0 / 60.0);
+        assert_eq!(physics.rigidbody_count(), 100);
+
+        for entity in &entities {
+            assert!(world.despawn(*entity, &mut physics));
+        }
+        assert!(!world.despawn(entities[0], &mut physics));
+        assert_eq!(physics.rigidbody_count(), 0);
+        assert_eq!(physics.collider_count(), 0);
+        assert_eq!(world.legion_world.len(), 0);
+        physics.update(1.0 / 60.0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lock the world first")]
+    fn locking_the_world_inside_the_physics_scene_panics() {
+        let world = RwLock::new(World {
+            legion_world: legion::World::default(),
+        });
+        let physics = RwLock::new(PhysicsScene::new(60));
+        {
+            // The right order
+            let _world = World::lock(&world);
+            let _physics = lock_physics(&physics);
+        }
+        let _physics = lock_physics(&physics);
+        World::lock(&world);
+    }
+}
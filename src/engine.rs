@@ -0,0 +1,841 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use glam::{IVec2, Quat, Vec3};
+use legion::{
+    systems::{Builder, CommandBuffer, ParallelRunnable, Resource},
+    Entity, IntoQuery, Resources, Schedule,
+};
+use parking_lot::{Mutex, RwLock};
+use pollster::block_on;
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+use crate::{
+    asset_types::mesh::Mesh,
+    audio::audio_engine::{AudioEngine, AudioOutput},
+    console::{commands::register_builtin_commands, draw_console, run_console_commands, Console},
+    debug_stats::DebugStats,
+    ecs::{
+        chunk_entity_map::ChunkEntityMap,
+        components::{
+            camera::Camera,
+            physics_components::MeshCollider,
+            rendering_components::{MeshRenderer, SunLight},
+            transformation_components::{Position, Rotation},
+        },
+        events::{
+            update_events, ChunkLoaded, ChunkUnloaded, EventReader, PlayerSpawned, WorldEvents,
+        },
+        systems::{
+            audio_systems::{play_block_sounds_system, update_audio_system},
+            block_interaction::{update_block_highlight_system, update_block_interaction_system},
+            camera_systems::{
+                interpolate_cameras, update_camera_system, update_camera_zoom_system,
+            },
+            chunk_systems::{reload_profiles_system, stream_chunks_system},
+            debug_systems::{collect_debug_stats_system, VOXEL_MEMORY_INTERVAL},
+            physics_systems::{step_physics_system, stream_colliders_system},
+            player_controller::{aim_player_cameras_system, update_players_system},
+            render_systems::{
+                animate_sun_system, construct_buffers, fit_fog_to_view_distance_system,
+                update_light,
+            },
+            spawn_systems::{
+                place_pending_spawns_system, respawn_fallen_players_system, spawn_player_entities,
+                PendingSpawn, PendingSpawns,
+            },
+            time_systems::toggle_pause_system,
+            transform_systems::{
+                propagate_transforms_system, record_previous_positions_system,
+                record_previous_rotations_system, spin_system,
+            },
+        },
+        world::{chunk_folder, World},
+    },
+    engine_config::{EngineConfig, TerrainNoise},
+    input_manager::update_inputs,
+    noise::{
+        cpu_simplex::CpuSimplex3D,
+        simplex::{NoiseSettings, Simplex3D},
+    },
+    physics::{
+        collider_streamer::ColliderStreamer,
+        physics_scene::{lock_physics, PhysicsScene},
+    },
+    rendering::{
+        self,
+        material::MaterialVoxelAtlas,
+        material_registry::{get_material, register_material},
+        render_pass_data::PassSorting,
+        render_settings::cycle_debug_mode,
+        screenshot::screenshot_path,
+        text::draw_text,
+    },
+    state::State,
+    time::{TickClock, Time},
+    voxels::{
+        chunk_storage::ChunkStorage,
+        chunk_streamer::ChunkStreamer,
+        voxel_registry::{DEFAULT_MATERIAL, FOLIAGE_MATERIAL, TRANSPARENT_MATERIAL},
+        voxel_scene::{collision_friction, VoxelScene, CHUNK_SIZE},
+    },
+};
+
+// Schedule and resource setup is deferred to the simulation thread, legion's builder and resources can't be sent
+type ScheduleStep = Box<dyn FnOnce(&mut Builder) + Send>;
+type ResourceStep = Box<dyn FnOnce(&mut Resources) + Send>;
+
+// Owns the window and the shared state of a running game. Systems and resources are registered before run,
+// which starts the world generation and the simulation thread and then hands the thread over to winit
+pub struct Engine {
+    config: EngineConfig,
+    window: Window,
+    state: Arc<RwLock<State>>,
+    world: Arc<RwLock<World>>,
+    physics: Arc<RwLock<PhysicsScene>>,
+    audio: Arc<AudioEngine>,
+    audio_output: Option<AudioOutput>, // Sounds only play while it's alive
+    scene: Arc<RwLock<VoxelScene>>,
+    camera: Arc<RwLock<rendering::camera::Camera>>,
+    debug_stats: Arc<DebugStats>,
+    console: Arc<RwLock<Console>>,
+    chunk_entities: Arc<ChunkEntityMap>,
+    events: WorldEvents,
+    spawn_position: Vec3, // Where the last player was spawned, the center of the pregenerated area
+    pending_spawns: PendingSpawns,
+    startup: Vec<ScheduleStep>,
+    systems: Vec<ScheduleStep>,
+    resources: Vec<ResourceStep>,
+}
+
+impl Engine {
+    pub fn new(config: EngineConfig, event_loop: &EventLoop<()>) -> Self {
+        // Tells WGPU to inform us of errors, rather than failing silently
+        let _ = env_logger::try_init();
+
+        let window = WindowBuilder::new()
+            .with_title(&config.title)
+            .with_maximized(true)
+            .build(event_loop)
+            .unwrap();
+        let state = Arc::new(RwLock::new(block_on(State::new(&window, config.graphics))));
+        let world = Arc::new(RwLock::new(World {
+            legion_world: legion::World::default(),
+        }));
+
+        let state_lock = state.write();
+        let camera = Arc::new(RwLock::new(rendering::camera::Camera::new(&state_lock)));
+        // Chunks are textured per voxel from the voxel profiles, which pick one of these materials by name
+        let voxel_materials = [
+            (
+                DEFAULT_MATERIAL,
+                MaterialVoxelAtlas::new(&state_lock),
+                "Default",
+            ),
+            (
+                TRANSPARENT_MATERIAL,
+                MaterialVoxelAtlas::new_transparent(&state_lock),
+                "Transparent",
+            ),
+            (
+                FOLIAGE_MATERIAL,
+                MaterialVoxelAtlas::new_cutout(&state_lock),
+                "Default",
+            ),
+        ];
+        for (name, material, layer) in voxel_materials {
+            let material = material.expect("Failed to build the voxel textures");
+            register_material(name, Arc::new(RwLock::new(material)), layer);
+        }
+
+        // Create the default render layer
+        let layers = &state_lock.render_layers;
+        layers.create_layer("Default".to_string(), 0);
+        // Blended geometry has to come after everything opaque it can be seen in front of
+        layers.create_sorted_layer("Transparent".to_string(), 2, PassSorting::BackToFront);
+        // Outlines and markers drawn over the finished scene
+        layers.create_layer("Overlay".to_string(), 3);
+
+        let mut camera_lock = camera.write();
+        camera_lock.add_render_layer(&state_lock, "Default".to_string());
+        camera_lock.add_render_layer(&state_lock, "Transparent".to_string());
+        camera_lock.add_render_layer(&state_lock, "Overlay".to_string());
+        drop(camera_lock);
+        drop(state_lock);
+
+        let physics = Arc::new(RwLock::new(PhysicsScene::new(config.tick_rate)));
+        let (audio, audio_output) = AudioEngine::new();
+
+        let mut scene = match &config.save_path {
+            Some(path) if Path::new(path).join("manifest.json").exists() => {
+                World::lock(&world).load(path).unwrap_or_else(|e| {
+                    println!("[INFO] Failed to load world {path}, regenerating: {e}");
+                    VoxelScene::new_with_config(config.world)
+                })
+            }
+            _ => VoxelScene::new_with_config(config.world),
+        };
+        if let Some(path) = &config.save_path {
+            scene.set_storage(ChunkStorage::new(chunk_folder(path)));
+        }
+        scene.set_processor_threads(config.processor_threads);
+        let settings = NoiseSettings::default().with_seed(scene.config.seed);
+        match config.terrain_noise {
+            TerrainNoise::Biomes => {}
+            TerrainNoise::Gpu => scene.set_noise(Arc::new(Simplex3D::new(&state.read(), settings))),
+            TerrainNoise::Cpu => scene.set_noise(Arc::new(CpuSimplex3D::new(settings))),
+        }
+        let events = WorldEvents::default();
+        scene.set_block_events(Arc::clone(&events.block_changed));
+        let mut console = Console::new();
+        register_builtin_commands(&mut console);
+
+        let mut engine = Self {
+            config,
+            window,
+            state,
+            world,
+            physics,
+            audio: Arc::new(audio),
+            audio_output,
+            scene: Arc::new(RwLock::new(scene)),
+            camera,
+            debug_stats: Arc::new(DebugStats::default()),
+            console: Arc::new(RwLock::new(console)),
+            chunk_entities: Arc::new(ChunkEntityMap::new()),
+            events,
+            spawn_position: Vec3::ZERO,
+            pending_spawns: PendingSpawns::default(),
+            startup: Vec::new(),
+            systems: Vec::new(),
+            resources: Vec::new(),
+        };
+        engine
+            // Before anything moves, so the renderer can draw between this tick and the last
+            .add_system(record_previous_positions_system())
+            .add_system(record_previous_rotations_system())
+            .add_system(toggle_pause_system())
+            .add_system(place_pending_spawns_system(
+                EventReader::default(),
+                HashSet::new(),
+            ))
+            .add_system(update_players_system())
+            .add_system(aim_player_cameras_system())
+            .add_system(respawn_fallen_players_system())
+            .add_system(update_block_interaction_system())
+            .add_system(update_block_highlight_system())
+            .add_system(spin_system())
+            .add_system(propagate_transforms_system())
+            .add_system(update_camera_zoom_system())
+            .add_system(update_camera_system())
+            .add_system(update_audio_system())
+            .add_system(play_block_sounds_system(EventReader::default()))
+            .add_system(stream_chunks_system())
+            .add_system(reload_profiles_system())
+            .add_system(animate_sun_system())
+            .add_system(fit_fog_to_view_distance_system(None))
+            .add_system(stream_colliders_system())
+            .add_system(step_physics_system())
+            .add_system(collect_debug_stats_system(VOXEL_MEMORY_INTERVAL)); // Summed up on the first tick
+        engine
+    }
+
+    // Systems run every tick in the order they were added, after the engine's own systems
+    pub fn add_system<T: ParallelRunnable + 'static>(&mut self, system: T) -> &mut Self {
+        self.systems.push(Box::new(move |builder: &mut Builder| {
+            builder.add_system(system);
+        }));
+        self
+    }
+
+    // Startup systems run once on the simulation thread, before the first tick
+    pub fn add_startup<T: ParallelRunnable + 'static>(&mut self, system: T) -> &mut Self {
+        self.startup.push(Box::new(move |builder: &mut Builder| {
+            builder.add_system(system);
+        }));
+        self
+    }
+
+    pub fn add_resource<T: Resource + Send>(&mut self, resource: T) -> &mut Self {
+        self.resources
+            .push(Box::new(move |resources: &mut Resources| {
+                resources.insert(resource)
+            }));
+        self
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn state(&self) -> &Arc<RwLock<State>> {
+        &self.state
+    }
+
+    pub fn world(&self) -> &Arc<RwLock<World>> {
+        &self.world
+    }
+
+    pub fn physics(&self) -> &Arc<RwLock<PhysicsScene>> {
+        &self.physics
+    }
+
+    pub fn audio(&self) -> &Arc<AudioEngine> {
+        &self.audio
+    }
+
+    pub fn scene(&self) -> &Arc<RwLock<VoxelScene>> {
+        &self.scene
+    }
+
+    // The camera the player looks through, drawn over the whole window unless its viewport is changed
+    pub fn camera(&self) -> &Arc<RwLock<rendering::camera::Camera>> {
+        &self.camera
+    }
+
+    pub fn debug_stats(&self) -> &Arc<DebugStats> {
+        &self.debug_stats
+    }
+
+    // Opened with the grave key, games can register commands of their own
+    pub fn console(&self) -> &Arc<RwLock<Console>> {
+        &self.console
+    }
+
+    pub fn chunk_entities(&self) -> &Arc<ChunkEntityMap> {
+        &self.chunk_entities
+    }
+
+    // Every channel is also a resource, systems read them with an EventReader as their state
+    pub fn events(&self) -> &WorldEvents {
+        &self.events
+    }
+
+    // A player carrying the main camera at the position, see spawn_player_entities
+    pub fn spawn_player(&mut self, position: Vec3) -> Entity {
+        self.spawn_position = position;
+        let mut world = World::lock(&self.world);
+        let mut cmd = CommandBuffer::new(&world.legion_world);
+        let entity = spawn_player_entities(
+            &mut cmd,
+            &mut lock_physics(&self.physics),
+            &self.camera,
+            position,
+        );
+        cmd.flush(&mut world.legion_world, &mut Resources::default());
+        self.events.player_spawned.send(PlayerSpawned { entity });
+        entity
+    }
+
+    // A player carrying the main camera, a couple of voxels above the surface of the column. It's spawned once the
+    // chunks under it are loaded, PlayerSpawned tells when
+    pub fn spawn_player_on_surface(&mut self, x: i32, z: i32) {
+        self.spawn_position = Vec3::new(x as f32, 0.0, z as f32);
+        self.pending_spawns.spawns.push(PendingSpawn {
+            column: IVec2::new(x, z),
+            camera: Arc::clone(&self.camera),
+        });
+    }
+
+    // A slow day and night cycle, a full turn takes ten minutes
+    pub fn spawn_sun(&self) -> Entity {
+        World::lock(&self.world).legion_world.push((SunLight {
+            rotation_speed: std::f32::consts::TAU / 600.0,
+            ..Default::default()
+        },))
+    }
+
+    // Never returns, the process exits once the window is closed
+    pub fn run(self, event_loop: EventLoop<()>) -> ! {
+        let Engine {
+            config,
+            window,
+            state,
+            world,
+            physics,
+            audio,
+            audio_output: _audio_output,
+            scene,
+            debug_stats,
+            console,
+            chunk_entities,
+            events,
+            spawn_position,
+            pending_spawns,
+            startup,
+            systems,
+            resources: resource_steps,
+            ..
+        } = self;
+
+        let collider_streamer = Arc::new(ColliderStreamer::new(config.physics_distance));
+        generate_world(
+            Arc::clone(&scene),
+            Arc::clone(&world),
+            Arc::clone(&physics),
+            Arc::clone(&chunk_entities),
+            Arc::clone(&collider_streamer),
+            events.clone(),
+            config
+                .pregenerate_radius
+                .map(|radius| (spawn_position, radius)),
+        );
+        scene.read().start_autosave(Duration::from_secs(30));
+
+        let world_clone = Arc::clone(&world);
+        let scene_clone = Arc::clone(&scene);
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = Arc::clone(&running);
+        let debug_stats_clone = Arc::clone(&debug_stats);
+        let console_clone = Arc::clone(&console);
+        // When the last tick finished, so frames can tell how far they are between two ticks
+        let last_tick = Arc::new(Mutex::new(Instant::now()));
+        let last_tick_clone = Arc::clone(&last_tick);
+        let simulation_config = config.clone();
+        let state_clone = Arc::clone(&state);
+        let simulation = std::thread::spawn(move || {
+            let mut resources = Resources::default(); // Resources are accessible to all systems that use them
+            resources.insert(ChunkStreamer::new(simulation_config.view_distance));
+            resources.insert(Time::new(simulation_config.tick_interval()));
+            resources.insert(simulation_config);
+            resources.insert(scene_clone);
+            resources.insert(physics);
+            resources.insert(audio);
+            resources.insert(Arc::clone(&debug_stats_clone));
+            resources.insert(chunk_entities);
+            resources.insert(collider_streamer);
+            resources.insert(pending_spawns);
+            resources.insert(console_clone);
+            resources.insert(state_clone);
+            events.insert_into(&mut resources);
+            for step in resource_steps {
+                step(&mut resources);
+            }
+
+            if !startup.is_empty() {
+                let mut builder = Schedule::builder();
+                for step in startup {
+                    step(&mut builder);
+                }
+                run_tick(&mut builder.build(), &world_clone, &mut resources, 0.0);
+            }
+
+            let mut builder = Schedule::builder();
+            for step in systems {
+                step(&mut builder);
+            }
+            let mut schedule = builder.build();
+
+            let mut clock = TickClock::new();
+            let mut loop_time = Instant::now();
+            let mut tick_overrun = 0.0;
+            while running_clone.load(Ordering::Relaxed) {
+                // Read every update so the tick rate can be changed while running
+                let tick_interval = resources.get::<EngineConfig>().unwrap().tick_interval();
+                let elapsed = loop_time.elapsed().as_secs_f64();
+                loop_time = Instant::now();
+
+                let ticks = clock.advance(elapsed, tick_interval);
+                for _ in 0..ticks {
+                    let tick_start = Instant::now();
+                    if let Some(mut time) = resources.get_mut::<Time>() {
+                        time.fixed_delta = tick_interval;
+                        time.tick_overrun = tick_overrun;
+                        time.alpha = clock.alpha(tick_interval);
+                    }
+                    run_tick(&mut schedule, &world_clone, &mut resources, tick_interval);
+                    debug_stats_clone.record_tick(tick_start.elapsed());
+                    tick_overrun = (tick_start.elapsed().as_secs_f64() - tick_interval).max(0.0);
+                }
+                if ticks > 0 {
+                    *last_tick_clone.lock() = Instant::now();
+                }
+
+                // Sleep until the next tick instead of spinning, leaving the world lock to the renderer
+                std::thread::sleep(Duration::from_secs_f64(
+                    clock.until_next_tick(tick_interval),
+                ));
+            }
+        });
+        let mut simulation = Some(simulation);
+
+        let mut frame_time = Time::new(config.tick_interval());
+        let mut last_frame = Instant::now();
+        let mut smoothed_delta = 0.0;
+        event_loop.run(move |event, _, control_flow| {
+            match event {
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == window.id() => {
+                    let mut state_lock = state.write();
+                    // F1 toggles the debug HUD, F2 takes a screenshot, F3 cycles the debug views, F7 toggles vsync
+                    // and F8 toggles 4x MSAA.
+                    // Applied on release so key repeats don't flicker
+                    if let WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Released,
+                                virtual_keycode:
+                                    Some(
+                                        key @ (VirtualKeyCode::F1
+                                        | VirtualKeyCode::F2
+                                        | VirtualKeyCode::F3
+                                        | VirtualKeyCode::F7
+                                        | VirtualKeyCode::F8),
+                                    ),
+                                ..
+                            },
+                        ..
+                    } = event
+                    {
+                        let mut settings = state_lock.settings;
+                        if *key == VirtualKeyCode::F1 {
+                            debug_stats.toggle_visible();
+                        } else if *key == VirtualKeyCode::F2 {
+                            state_lock.capture_screenshot(&screenshot_path());
+                        } else if *key == VirtualKeyCode::F3 {
+                            cycle_debug_mode(state_lock.supports_wireframe);
+                        } else if *key == VirtualKeyCode::F7 {
+                            settings.vsync = match settings.vsync {
+                                wgpu::PresentMode::Fifo => wgpu::PresentMode::Immediate,
+                                _ => wgpu::PresentMode::Fifo,
+                            };
+                        } else {
+                            settings.msaa_samples = if settings.msaa_samples > 1 { 1 } else { 4 };
+                        }
+                        if settings != state_lock.settings {
+                            state_lock.apply_settings(settings);
+                        }
+                    }
+                    if !state_lock.input(event) {
+                        match event {
+                            WindowEvent::CloseRequested => {
+                                if let Some(path) = &config.save_path {
+                                    if let Err(e) = world.read().save(path, &scene.read()) {
+                                        println!("[INFO] Failed to save world {path}: {e}");
+                                    }
+                                }
+                                *control_flow = ControlFlow::Exit
+                            }
+                            WindowEvent::Resized(physical_size) => {
+                                state_lock.resize(*physical_size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                state_lock.resize(**new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                Event::RedrawRequested(window_id) if window_id == window.id() => {
+                    let world_lock = world.read();
+                    let mut query = <&Camera>::query();
+
+                    let cameras: Vec<Arc<RwLock<rendering::camera::Camera>>> = query
+                        .iter(&world_lock.legion_world)
+                        .map(|cam| Arc::clone(&cam.camera))
+                        .collect();
+
+                    frame_time.advance(last_frame.elapsed().as_secs_f64());
+                    frame_time.fixed_delta = config.tick_interval();
+                    frame_time.alpha = (last_tick.lock().elapsed().as_secs_f64()
+                        / frame_time.fixed_delta)
+                        .min(1.0);
+                    last_frame = Instant::now();
+                    draw_debug_hud(&frame_time, &mut smoothed_delta, &debug_stats);
+
+                    let mut state_lock = state.write();
+                    draw_console(&console.read(), state_lock.size.height as f32);
+                    interpolate_cameras(&world_lock.legion_world, frame_time.alpha as f32);
+                    construct_buffers(
+                        &state_lock,
+                        &world_lock.legion_world,
+                        frame_time.alpha as f32,
+                    );
+                    update_light(&mut state_lock, &world_lock.legion_world);
+
+                    match state_lock.render(cameras) {
+                        Ok(_) => debug_stats.record_frame(
+                            Duration::from_secs_f64(smoothed_delta),
+                            state_lock.rendered_vertices,
+                        ),
+                        // Reconfigure the surface if lost
+                        Err(wgpu::SurfaceError::Lost) => {
+                            let size = state_lock.size;
+                            state_lock.resize(size);
+                        }
+                        // The system is out of memory, we should probably quit
+                        Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                        // All other errors (Outdated, Timeout) should be resolved by the next frame
+                        Err(e) => eprintln!("{:?}", e),
+                    }
+                }
+                Event::MainEventsCleared => {
+                    // RedrawRequested will only trigger once, unless we manually
+                    // request it.
+                    window.request_redraw();
+                }
+                // Stop the simulation before the world generation so no system requests chunks from a stopped scene
+                Event::LoopDestroyed => {
+                    running.store(false, Ordering::Relaxed);
+                    if let Some(simulation) = simulation.take() {
+                        if simulation.join().is_err() {
+                            println!("[INFO] The simulation thread panicked");
+                        }
+                    }
+                    scene.write().shutdown();
+                }
+                _ => {}
+            }
+        })
+    }
+}
+
+pub fn generate_world(
+    scene: Arc<RwLock<VoxelScene>>,
+    world: Arc<RwLock<World>>,
+    physics: Arc<RwLock<PhysicsScene>>,
+    chunk_entities: Arc<ChunkEntityMap>,
+    collider_streamer: Arc<ColliderStreamer>,
+    events: WorldEvents,
+    pregenerate: Option<(Vec3, u32)>, // Blocks until the chunks within the radius around the position are meshed
+) {
+    // Chunks are requested and unloaded around the player by the chunk streaming system
+    let (tx, rx) = flume::unbounded();
+    let (unload_tx, unload_rx) = flume::unbounded();
+    scene.write().setup_chunk_processors(tx, unload_tx);
+    rayon::spawn(move || {
+        let mut loaded = HashSet::new(); // Chunks whose ChunkLoaded was sent
+        let mut unknown_materials = HashSet::new(); // Logged once each, not for every chunk using them
+        loop {
+            // The meshes of a newly generated or remeshed chunk, or None when the chunk was unloaded.
+            // Either channel disconnecting means the scene shut down
+            let message = flume::Selector::new()
+                .recv(&rx, |message| {
+                    message
+                        .ok()
+                        .map(|(chunk_pos, meshes)| (chunk_pos, Some(meshes)))
+                })
+                .recv(&unload_rx, |message| {
+                    message.ok().map(|chunk_pos| (chunk_pos, None))
+                })
+                .wait();
+            let (chunk_pos, meshes) = match message {
+                Some(message) => message,
+                None => break,
+            };
+
+            let mut world_lock = World::lock(&world);
+            let mut physics_lock = lock_physics(&physics);
+            let mut entities = chunk_entities.get(chunk_pos);
+            match meshes {
+                Some(meshes) => {
+                    let position = chunk_pos.as_vec3() * CHUNK_SIZE as f32;
+                    // One entity per material and one for each collision mesh, all of them at the chunk's position
+                    for (material_name, mesh) in meshes {
+                        let mesh_use = if let Some(friction) = collision_friction(&material_name) {
+                            ChunkMeshUse::Collision { friction }
+                        } else {
+                            let registered = get_material(&material_name).or_else(|| {
+                                if unknown_materials.insert(material_name.clone()) {
+                                    println!(
+                                        "[INFO] Unknown voxel material {material_name}, drawing it with the default one"
+                                    );
+                                }
+                                get_material(DEFAULT_MATERIAL)
+                            });
+                            match registered {
+                                Some(registered) => ChunkMeshUse::Drawn(MeshRenderer::new(
+                                    Arc::clone(&mesh),
+                                    registered.material,
+                                    registered.layer,
+                                )),
+                                None => continue,
+                            }
+                        };
+                        let entity = update_chunk_entity(
+                            &mut world_lock,
+                            &mut physics_lock,
+                            entities.meshes.remove(&material_name),
+                            position,
+                            mesh,
+                            mesh_use,
+                            collider_streamer.in_range(chunk_pos),
+                        );
+                        if let Some(entity) = entity {
+                            entities.meshes.insert(material_name, entity);
+                        }
+                    }
+                    chunk_entities.set(chunk_pos, entities);
+                    if loaded.insert(chunk_pos) {
+                        events.chunk_loaded.send(ChunkLoaded { pos: chunk_pos });
+                    }
+                }
+                None => {
+                    for entity in entities.meshes.into_values() {
+                        world_lock.despawn(entity, &mut physics_lock);
+                    }
+                    chunk_entities.remove(chunk_pos);
+                    if loaded.remove(&chunk_pos) {
+                        events.chunk_unloaded.send(ChunkUnloaded { pos: chunk_pos });
+                    }
+                }
+            }
+        }
+    });
+
+    if let Some((position, radius)) = pregenerate {
+        pregenerate_area(&scene.read(), position, radius);
+    }
+}
+
+// What the entity of a chunk mesh is built with
+enum ChunkMeshUse {
+    Drawn(MeshRenderer),
+    Collision { friction: f32 },
+}
+
+// Spawns, updates or despawns the entity of one of a chunk's meshes, returns the entity that's left if any. The
+// collision mesh gets an entity with a collider and no renderer, the others a renderer and no collider. The collider
+// starts out inactive when no body is close enough, stream_colliders activates it later.
+// An existing entity keeps its mesh, the new geometry is written into it
+fn update_chunk_entity(
+    world: &mut World,
+    physics: &mut PhysicsScene,
+    entity: Option<Entity>,
+    position: Vec3,
+    mesh: Arc<RwLock<Mesh>>,
+    mesh_use: ChunkMeshUse,
+    collider_in_range: bool,
+) -> Option<Entity> {
+    // Empty meshes have nothing to draw, and rapier can't build a trimesh without triangles
+    if mesh.read().index_count == 0 {
+        if let Some(entity) = entity {
+            world.despawn(entity, physics);
+        }
+        return None;
+    }
+    if let Some(mut entry) = entity.and_then(|entity| world.legion_world.entry(entity)) {
+        let existing = match entry.get_component::<MeshRenderer>() {
+            Ok(existing) => Some(Arc::clone(&existing.mesh)),
+            Err(_) => entry
+                .get_component::<MeshCollider>()
+                .ok()
+                .map(|collider| Arc::clone(collider.mesh())),
+        };
+        if let Some(existing) = existing.filter(|existing| !Arc::ptr_eq(existing, &mesh)) {
+            let source = mesh.read();
+            let mut target = existing.write();
+            target.set_vertices(source.get_vertices().clone());
+            target.set_indices(source.get_indices().clone());
+        }
+        // The collider follows the mesh it was built from, rebuilt right away rather than on the next physics tick
+        if let Ok(collider) = entry.get_component_mut::<MeshCollider>() {
+            collider.update(physics);
+        }
+        return entity;
+    }
+
+    let entity = match mesh_use {
+        ChunkMeshUse::Drawn(renderer) => {
+            world
+                .legion_world
+                .push((Position(position), Rotation(Quat::IDENTITY), renderer))
+        }
+        ChunkMeshUse::Collision { friction } => {
+            let mut collider = MeshCollider::new_inactive(mesh, position).with_friction(friction);
+            collider.set_active(physics, collider_in_range);
+            let entity =
+                world
+                    .legion_world
+                    .push((Position(position), Rotation(Quat::IDENTITY), collider));
+            world.attach_colliders(entity, physics);
+            entity
+        }
+    };
+    Some(entity)
+}
+
+// Streams in the area like the player would and waits for the pipeline to drain, reporting the progress meanwhile
+fn pregenerate_area(scene: &VoxelScene, position: Vec3, radius: u32) {
+    let idle = scene.subscribe_idle();
+    ChunkStreamer::new(radius).update(scene, position);
+    let start = Instant::now();
+    // The pipeline can drain while the streamer is still queuing, so an idle event alone doesn't mean it's done
+    loop {
+        let (completed, queued) = scene.generation_progress();
+        if completed >= queued {
+            break;
+        }
+        if idle.recv_timeout(Duration::from_secs(1)).is_err() {
+            println!("[INFO] Pregenerating the spawn area, {completed} of {queued} chunks done");
+        }
+    }
+    println!(
+        "[INFO] Pregenerated the spawn area in {:.1}s",
+        start.elapsed().as_secs_f64()
+    );
+}
+
+// Runs the systems once, the world is only locked while they execute. Events older than two ticks are dropped after
+pub fn run_tick(
+    schedule: &mut Schedule,
+    world: &RwLock<World>,
+    resources: &mut Resources,
+    real_dt: f64,
+) {
+    update_inputs(); // Update the inputs before sending firing the systems
+    if let Some(console) = resources.get::<Arc<RwLock<Console>>>() {
+        console.write().update_input(); // Before the systems, so they never see what's typed
+    }
+    if let Some(mut time) = resources.get_mut::<Time>() {
+        time.advance(real_dt);
+    }
+    let mut world_lock = World::lock(world);
+    schedule.execute(&mut world_lock.legion_world, resources);
+    drop(world_lock);
+    run_console_commands(world, resources);
+    update_events(resources);
+}
+
+// The frame rate is always shown, the rest of the counters only after pressing F1
+fn draw_debug_hud(time: &Time, smoothed_delta: &mut f64, stats: &DebugStats) {
+    // Smooth the frame time a little so the counter is readable
+    if *smoothed_delta == 0.0 {
+        *smoothed_delta = time.delta_time;
+    }
+    *smoothed_delta = *smoothed_delta * 0.95 + time.delta_time * 0.05;
+    let mut snapshot = stats.snapshot();
+    snapshot.frame_ms = *smoothed_delta * 1000.0;
+
+    let mut lines = snapshot.hud_lines();
+    if !stats.is_visible() {
+        lines.truncate(1);
+    }
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            line,
+            10.0,
+            10.0 + i as f32 * 20.0,
+            16.0,
+            [1.0, 1.0, 1.0, 1.0],
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{
+    rendering::render_settings::GraphicsSettings,
+    voxels::{processor_threads::ProcessorThreads, voxel_scene::WorldConfig},
+};
+
+// Where the terrain density comes from, the biomes pick the voxels either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerrainNoise {
+    Biomes, // Each biome's density formula
+    Gpu,    // Simplex noise in a compute shader
+    Cpu,    // Simplex noise with simdnoise, for machines and tests without a GPU
+}
+
+// Engine wide settings, inserted as a resource so systems and tests can read them
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub tick_rate: u32, // Simulation ticks per second
+    pub title: String,
+    pub graphics: GraphicsSettings,
+    pub world: WorldConfig,
+    pub view_distance: u32,        // In chunks, around the player
+    pub save_path: Option<String>, // World folder to load from and save to
+    pub terrain_noise: TerrainNoise,
+    pub pregenerate_radius: Option<u32>, // In chunks, generated around the spawn before the first frame
+    pub processor_threads: ProcessorThreads, // Workers of the chunk pipeline, sized to the machine by default
+    pub physics_distance: u32, // In chunks, chunks further than this from every body have no collider
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60,
+            title: "Assemblage".to_string(),
+            graphics: GraphicsSettings::default(),
+            world: WorldConfig::default(),
+            view_distance: 8,
+            save_path: None,
+            terrain_noise: TerrainNoise::Biomes,
+            pregenerate_radius: None,
+            processor_threads: ProcessorThreads::default(),
+            physics_distance: 4,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn tick_interval(&self) -> f64 {
+        1.0 / self.tick_rate.max(1) as f64
+    }
+}
@@ -1,14 +1,37 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
 
-use dashmap::DashMap;
+use flume::{Receiver, Sender};
 use glam::Vec2;
 use parking_lot::RwLock;
 use winit::{
     dpi::PhysicalPosition,
-    event::{MouseButton, VirtualKeyCode},
+    event::{MouseButton, MouseScrollDelta, VirtualKeyCode},
 };
 
-#[derive(PartialEq, Eq, Copy, Clone)]
+#[derive(Clone, Copy, Debug)]
+pub struct InputSettings {
+    pub double_click_time: Duration,
+    pub double_click_distance: f64, // In pixels
+    pub key_repeat_delay: Duration,
+    pub key_repeat_interval: Duration,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            double_click_time: Duration::from_millis(300),
+            double_click_distance: 4.0,
+            key_repeat_delay: Duration::from_millis(400),
+            key_repeat_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum PressState {
     None,
     Pressed,
@@ -16,145 +39,569 @@ pub enum PressState {
     Released,
 }
 
+// What the window reports, queued until the next tick picks it up
+#[derive(Clone, Copy, Debug)]
+pub enum InputEvent {
+    Key(VirtualKeyCode, PressState),
+    Button(MouseButton, PressState),
+    MouseMoved(PhysicalPosition<f64>),
+    Scroll(f32),     // In lines
+    Character(char), // Text typed on the keyboard, after the keyboard layout is applied
+}
+
 lazy_static! {
-    static ref PREVIOUS_INPUT_MAP: Arc<DashMap<VirtualKeyCode, PressState>> =
-        Arc::new(DashMap::default());
-    static ref PREVIOUS_MOUSE_MAP: Arc<DashMap<MouseButton, PressState>> =
-        Arc::new(DashMap::default());
-    static ref INPUT_MAP: Arc<DashMap<VirtualKeyCode, PressState>> = Arc::new(DashMap::default());
-    static ref MOUSE_MAP: Arc<DashMap<MouseButton, PressState>> = Arc::new(DashMap::default());
-    static ref MOUSE_DELTA: Arc<RwLock<PhysicalPosition<f64>>> =
-        Arc::new(RwLock::new(PhysicalPosition::new(0.0, 0.0)));
-    static ref PREVIOUS_MOUSE_POS: Arc<RwLock<PhysicalPosition<f64>>> =
-        Arc::new(RwLock::new(PhysicalPosition::new(0.0, 0.0)));
-    static ref MOUSE_POS: Arc<RwLock<PhysicalPosition<f64>>> =
-        Arc::new(RwLock::new(PhysicalPosition::new(0.0, 0.0)));
-}
-
-pub fn update_inputs() {
-    // Pressed -> Held
-    INPUT_MAP.iter_mut().for_each(|mut key| {
-        if *key.value() == PressState::Pressed
-            && PREVIOUS_INPUT_MAP
-                .get(key.key())
-                .map_or(false, |previous| *previous.value() == PressState::Pressed)
-        {
-            *key.value_mut() = PressState::Held;
+    static ref INPUT_EVENTS: (Sender<InputEvent>, Receiver<InputEvent>) = flume::unbounded();
+    static ref INPUT: RwLock<InputState> = RwLock::new(InputState::default());
+    static ref SETTINGS: RwLock<InputSettings> = RwLock::new(InputSettings::default());
+    // Where the window last saw the mouse, ahead of the ticks, for the render thread
+    static ref LATEST_MOUSE_POS: RwLock<Option<PhysicalPosition<f64>>> = RwLock::new(None);
+}
+
+// The press state of every key or button, with the transitions that are still waiting for a tick. Each transition
+// carries what was known when it arrived, e.g. where the mouse was for a click
+struct PressStates<K, D = ()> {
+    states: HashMap<K, PressState>,
+    queued: HashMap<K, VecDeque<(PressState, D)>>,
+}
+
+impl<K, D> Default for PressStates<K, D> {
+    fn default() -> Self {
+        Self {
+            states: HashMap::new(),
+            queued: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Copy, D: Copy> PressStates<K, D> {
+    fn queue(&mut self, key: K, event: PressState, data: D) {
+        self.queued.entry(key).or_default().push_back((event, data));
+    }
+
+    fn get(&self, key: K) -> PressState {
+        self.states.get(&key).copied().unwrap_or(PressState::None)
+    }
+
+    // Pressed becomes Held and Released becomes None, then at most one queued transition per key is consumed.
+    // Returns the keys that were pressed this tick, with the data their press arrived with
+    fn step(&mut self) -> Vec<(K, D)> {
+        for state in self.states.values_mut() {
+            *state = match *state {
+                PressState::Pressed | PressState::Held => PressState::Held,
+                PressState::Released | PressState::None => PressState::None,
+            };
+        }
+
+        let mut pressed = Vec::new();
+        for (key, queue) in self.queued.iter_mut() {
+            while let Some((event, data)) = queue.pop_front() {
+                let is_down = self.states.get(key) == Some(&PressState::Held);
+                match event {
+                    PressState::Pressed if is_down => continue, // Key repeat
+                    PressState::Released if !is_down => continue, // Already up
+                    _ => {
+                        self.states.insert(*key, event);
+                        if event == PressState::Pressed {
+                            pressed.push((*key, data));
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        self.queued.retain(|_, queue| !queue.is_empty());
+        pressed
+    }
+}
+
+// Everything systems can ask about the input, as of the start of the current tick.
+// Every tick consumes the events that arrived since the last one, at most one transition per key:
+// - Pressed is reported for exactly the tick the press is consumed (get_key_down)
+// - Held is reported from the following tick until a release is consumed (get_key_held)
+// - Released is reported for exactly the tick the release is consumed (get_key_up)
+// - None is reported from the following tick on
+// A press and release arriving between two ticks therefore gives get_key_down on the first
+// tick and get_key_up on the next one. Repeated presses from OS key repeat are ignored while held.
+// Double clicks are reported on the tick of the second press. Key repeats fire on the press tick,
+// then once after key_repeat_delay and every key_repeat_interval after that while the key is held.
+// While text input is on, e.g. while the console is open, keys, buttons and scrolling read as untouched so gameplay
+// doesn't react to typing. Only get_typed_text and get_text_key see them then
+pub struct InputState {
+    keys: PressStates<VirtualKeyCode>,
+    buttons: PressStates<MouseButton, PhysicalPosition<f64>>, // With where the mouse was
+    mouse_pos: PhysicalPosition<f64>,
+    previous_mouse_pos: PhysicalPosition<f64>,
+    mouse_delta: PhysicalPosition<f64>,
+    scroll_delta: f32,
+    last_clicks: HashMap<MouseButton, (Instant, PhysicalPosition<f64>)>,
+    double_clicks: HashSet<MouseButton>,
+    next_key_repeat: HashMap<VirtualKeyCode, Instant>,
+    key_repeats: HashSet<VirtualKeyCode>,
+    typed: String, // Characters typed since the previous tick
+    text_input: bool,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        Self {
+            keys: PressStates::default(),
+            buttons: PressStates::default(),
+            mouse_pos: PhysicalPosition::new(0.0, 0.0),
+            previous_mouse_pos: PhysicalPosition::new(0.0, 0.0),
+            mouse_delta: PhysicalPosition::new(0.0, 0.0),
+            scroll_delta: 0.0,
+            last_clicks: HashMap::new(),
+            double_clicks: HashSet::new(),
+            next_key_repeat: HashMap::new(),
+            key_repeats: HashSet::new(),
+            typed: String::new(),
+            text_input: false,
+        }
+    }
+}
+
+impl InputState {
+    pub fn tick(
+        &mut self,
+        events: impl IntoIterator<Item = InputEvent>,
+        now: Instant,
+        settings: &InputSettings,
+    ) {
+        // Scroll events are summed between ticks
+        self.scroll_delta = 0.0;
+        self.typed.clear();
+        for event in events {
+            match event {
+                InputEvent::Key(key, state) => self.keys.queue(key, state, ()),
+                InputEvent::Button(button, state) => {
+                    self.buttons.queue(button, state, self.mouse_pos)
+                }
+                InputEvent::MouseMoved(pos) => self.mouse_pos = pos,
+                InputEvent::Scroll(lines) => self.scroll_delta += lines,
+                InputEvent::Character(character) => self.typed.push(character),
+            }
+        }
+
+        let pressed_keys = self.keys.step();
+        let pressed_buttons = self.buttons.step();
+
+        // Key repeat
+        self.key_repeats.clear();
+        let keys = &self.keys;
+        self.next_key_repeat
+            .retain(|key, _| keys.get(*key) == PressState::Held);
+        for (key, next) in self.next_key_repeat.iter_mut() {
+            if now >= *next {
+                self.key_repeats.insert(*key);
+                *next = now + settings.key_repeat_interval;
+            }
+        }
+        for (key, _) in pressed_keys {
+            self.key_repeats.insert(key);
+            self.next_key_repeat
+                .insert(key, now + settings.key_repeat_delay);
         }
-    });
-
-    MOUSE_MAP.iter_mut().for_each(|mut button| {
-        if *button.value() == PressState::Pressed
-            && PREVIOUS_MOUSE_MAP
-                .get(button.key())
-                .map_or(false, |previous| *previous.value() == PressState::Pressed)
-        {
-            *button.value_mut() = PressState::Held;
+
+        // Double clicks
+        self.double_clicks.clear();
+        for (button, click_pos) in pressed_buttons {
+            let is_double_click = self.last_clicks.get(&button).map_or(false, |(time, pos)| {
+                let distance =
+                    ((pos.x - click_pos.x).powi(2) + (pos.y - click_pos.y).powi(2)).sqrt();
+                now.duration_since(*time) <= settings.double_click_time
+                    && distance <= settings.double_click_distance
+            });
+            if is_double_click {
+                // A third click starts a new double click instead of completing another one
+                self.last_clicks.remove(&button);
+                self.double_clicks.insert(button);
+            } else {
+                self.last_clicks.insert(button, (now, click_pos));
+            }
         }
-    });
-
-    // Released -> None
-    INPUT_MAP.iter_mut().for_each(|mut key| {
-        if *key.value() == PressState::Released
-            && PREVIOUS_INPUT_MAP
-                .get(key.key())
-                .map_or(false, |previous| *previous.value() == PressState::Released)
-        {
-            *key.value_mut() = PressState::None;
+
+        self.mouse_delta = PhysicalPosition::new(
+            self.mouse_pos.x - self.previous_mouse_pos.x,
+            self.mouse_pos.y - self.previous_mouse_pos.y,
+        );
+        self.previous_mouse_pos = self.mouse_pos;
+    }
+
+    pub fn key(&self, key: VirtualKeyCode) -> PressState {
+        if self.text_input {
+            return PressState::None;
         }
-    });
-
-    MOUSE_MAP.iter_mut().for_each(|mut button| {
-        if *button.value() == PressState::Released
-            && PREVIOUS_MOUSE_MAP
-                .get(button.key())
-                .map_or(false, |previous| *previous.value() == PressState::Released)
-        {
-            *button.value_mut() = PressState::None;
+        self.keys.get(key)
+    }
+
+    pub fn button(&self, button: MouseButton) -> PressState {
+        if self.text_input {
+            return PressState::None;
         }
-    });
+        self.buttons.get(button)
+    }
 
-    // Update previous map
-    INPUT_MAP.iter().for_each(|key| {
-        PREVIOUS_INPUT_MAP.insert(*key.key(), *key.value());
-    });
+    pub fn set_text_input(&mut self, enabled: bool) {
+        self.text_input = enabled;
+    }
 
-    MOUSE_MAP.iter().for_each(|button| {
-        PREVIOUS_MOUSE_MAP.insert(*button.key(), *button.value());
-    });
+    // Pressed this tick or repeating, whether text input is on or not
+    pub fn text_key(&self, key: VirtualKeyCode) -> bool {
+        self.key_repeats.contains(&key)
+    }
 
-    let mut mouse_delta_lock = MOUSE_DELTA.write();
-    let mouse_pos_lock = MOUSE_POS.read();
-    let mut previous_mouse_pos_lock = PREVIOUS_MOUSE_POS.write();
-    (mouse_delta_lock.x, mouse_delta_lock.y) = (
-        mouse_pos_lock.x - previous_mouse_pos_lock.x,
-        mouse_pos_lock.y - previous_mouse_pos_lock.y,
-    );
-    (previous_mouse_pos_lock.x, previous_mouse_pos_lock.y) = (mouse_pos_lock.x, mouse_pos_lock.y);
+    pub fn typed_text(&self) -> &str {
+        &self.typed
+    }
+}
+
+fn is_down(state: PressState) -> bool {
+    state == PressState::Pressed || state == PressState::Held
+}
+
+// Called once at the start of every simulation tick. The events the window sent since the last tick are drained
+// at once and applied under one lock, systems see the same input for the whole tick
+pub(crate) fn update_inputs() {
+    let events: Vec<InputEvent> = INPUT_EVENTS.1.drain().collect();
+    let settings = *SETTINGS.read();
+    INPUT.write().tick(events, Instant::now(), &settings);
+}
+
+fn send_event(event: InputEvent) {
+    // The receiver lives as long as the process, sending can't fail
+    let _ = INPUT_EVENTS.0.send(event);
 }
 
 pub fn set_key(key: VirtualKeyCode, state: PressState) {
-    INPUT_MAP.insert(key, state);
+    send_event(InputEvent::Key(key, state));
 }
 
 pub fn get_key_down(key: VirtualKeyCode) -> bool {
-    INPUT_MAP
-        .get(&key)
-        .map_or(false, |state| *state.value() == PressState::Pressed)
+    INPUT.read().key(key) == PressState::Pressed
 }
 
 pub fn get_key_held(key: VirtualKeyCode) -> bool {
-    INPUT_MAP
-        .get(&key)
-        .map_or(false, |state| *state.value() == PressState::Held)
+    INPUT.read().key(key) == PressState::Held
 }
 
 pub fn get_key(key: VirtualKeyCode) -> bool {
-    INPUT_MAP.get(&key).map_or(false, |state| {
-        *state.value() == PressState::Held || *state.value() == PressState::Pressed
-    })
+    is_down(INPUT.read().key(key))
 }
 
 pub fn get_key_up(key: VirtualKeyCode) -> bool {
-    INPUT_MAP
-        .get(&key)
-        .map_or(false, |state| *state.value() == PressState::Released)
+    INPUT.read().key(key) == PressState::Released
 }
 
 pub fn get_button_down(button: MouseButton) -> bool {
-    MOUSE_MAP
-        .get(&button)
-        .map_or(false, |state| *state.value() == PressState::Pressed)
+    INPUT.read().button(button) == PressState::Pressed
 }
 
 pub fn get_button_held(button: MouseButton) -> bool {
-    MOUSE_MAP
-        .get(&button)
-        .map_or(false, |state| *state.value() == PressState::Held)
+    INPUT.read().button(button) == PressState::Held
 }
 
 pub fn get_button(button: MouseButton) -> bool {
-    MOUSE_MAP.get(&button).map_or(false, |state| {
-        *state.value() == PressState::Held || *state.value() == PressState::Pressed
-    })
+    is_down(INPUT.read().button(button))
 }
 
 pub fn get_button_up(button: MouseButton) -> bool {
-    MOUSE_MAP
-        .get(&button)
-        .map_or(false, |state| *state.value() == PressState::Released)
+    INPUT.read().button(button) == PressState::Released
+}
+
+pub fn get_key_repeat(key: VirtualKeyCode) -> bool {
+    let input = INPUT.read();
+    !input.text_input && input.key_repeats.contains(&key)
+}
+
+pub fn get_double_click(button: MouseButton) -> bool {
+    let input = INPUT.read();
+    !input.text_input && input.double_clicks.contains(&button)
+}
+
+// Text input takes effect right away, systems that run later in the same tick already see the keys as untouched
+pub fn set_text_input(enabled: bool) {
+    INPUT.write().set_text_input(enabled);
+}
+
+pub fn is_text_input() -> bool {
+    INPUT.read().text_input
+}
+
+// For editing keys like Back and Return while typing, see InputState::text_key
+pub fn get_text_key(key: VirtualKeyCode) -> bool {
+    INPUT.read().text_key(key)
+}
+
+// Everything typed since the previous tick, including control characters like backspace
+pub fn get_typed_text() -> String {
+    INPUT.read().typed.clone()
+}
+
+pub fn send_character(character: char) {
+    send_event(InputEvent::Character(character));
+}
+
+pub fn get_input_settings() -> InputSettings {
+    *SETTINGS.read()
+}
+
+pub fn set_input_settings(settings: InputSettings) {
+    *SETTINGS.write() = settings;
 }
 
 pub fn get_mouse_delta() -> Vec2 {
-    let lock = MOUSE_DELTA.read();
-    Vec2::new(lock.x as f32, lock.y as f32)
+    let delta = INPUT.read().mouse_delta;
+    Vec2::new(delta.x as f32, delta.y as f32)
 }
 
 pub fn set_mouse_button(button: &MouseButton, state: PressState) {
-    MOUSE_MAP.insert(*button, state);
+    send_event(InputEvent::Button(*button, state));
+}
+
+// Positive when scrolling away from the user, measured in lines
+pub fn get_scroll_delta() -> f32 {
+    let input = INPUT.read();
+    if input.text_input {
+        return 0.0;
+    }
+    input.scroll_delta
+}
+
+pub fn set_mouse_scroll(delta: &MouseScrollDelta) {
+    let lines = match delta {
+        MouseScrollDelta::LineDelta(_, y) => *y,
+        MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0, // Roughly one line per 20 pixels
+    };
+    send_event(InputEvent::Scroll(lines));
 }
 
 pub fn set_mouse_pos(pos: &PhysicalPosition<f64>) {
-    let mut lock = MOUSE_POS.write();
-    (lock.x, lock.y) = (pos.x, pos.y);
+    *LATEST_MOUSE_POS.write() = Some(*pos);
+    send_event(InputEvent::MouseMoved(*pos));
+}
+
+// How far the mouse moved since the last tick picked up its position, the next tick's get_mouse_delta will include it.
+// For the render thread, which runs ahead of the ticks
+pub fn get_mouse_delta_since_tick() -> Vec2 {
+    let latest = match *LATEST_MOUSE_POS.read() {
+        Some(latest) => latest,
+        None => return Vec2::ZERO,
+    };
+    let ticked = INPUT.read().mouse_pos;
+    Vec2::new((latest.x - ticked.x) as f32, (latest.y - ticked.y) as f32)
+}
+
+#[cfg(test)]
+mod input_tests {
+    use winit::event::VirtualKeyCode;
+
+    use super::*;
+
+    fn tick(input: &mut InputState, events: &[InputEvent]) {
+        input.tick(
+            events.iter().copied(),
+            Instant::now(),
+            &InputSettings::default(),
+        );
+    }
+
+    #[test]
+    fn tap_within_one_frame() {
+        let key = VirtualKeyCode::F12;
+        let mut input = InputState::default();
+        tick(
+            &mut input,
+            &[
+                InputEvent::Key(key, PressState::Pressed),
+                InputEvent::Key(key, PressState::Released),
+            ],
+        );
+        assert_eq!(input.key(key), PressState::Pressed);
+
+        tick(&mut input, &[]);
+        assert_eq!(input.key(key), PressState::Released);
+
+        tick(&mut input, &[]);
+        assert_eq!(input.key(key), PressState::None);
+    }
+
+    #[test]
+    fn held_key_ignores_repeats() {
+        let key = VirtualKeyCode::F11;
+        let mut input = InputState::default();
+        tick(&mut input, &[InputEvent::Key(key, PressState::Pressed)]);
+        assert_eq!(input.key(key), PressState::Pressed);
+
+        tick(&mut input, &[InputEvent::Key(key, PressState::Pressed)]);
+        assert_eq!(input.key(key), PressState::Held);
+    }
+
+    #[test]
+    fn press_hold_and_release_fire_on_their_ticks() {
+        let key = VirtualKeyCode::F10;
+        let mut input = InputState::default();
+        let mut states = Vec::new();
+        // The key goes down, stays down for three ticks without any events, then comes up
+        tick(&mut input, &[InputEvent::Key(key, PressState::Pressed)]);
+        states.push(input.key(key));
+        for _ in 0..3 {
+            tick(&mut input, &[]);
+            states.push(input.key(key));
+        }
+        tick(&mut input, &[InputEvent::Key(key, PressState::Released)]);
+        states.push(input.key(key));
+        tick(&mut input, &[]);
+        states.push(input.key(key));
+
+        let down: Vec<bool> = states
+            .iter()
+            .map(|state| *state == PressState::Pressed)
+            .collect();
+        let held: Vec<bool> = states.iter().map(|state| is_down(*state)).collect();
+        let up: Vec<bool> = states
+            .iter()
+            .map(|state| *state == PressState::Released)
+            .collect();
+        assert_eq!(down, [true, false, false, false, false, false]);
+        assert_eq!(held, [true, true, true, true, false, false]);
+        assert_eq!(up, [false, false, false, false, true, false]);
+    }
+
+    #[test]
+    fn text_input_hides_keys_from_gameplay() {
+        let key = VirtualKeyCode::W;
+        let mut input = InputState::default();
+        input.set_text_input(true);
+        tick(
+            &mut input,
+            &[
+                InputEvent::Key(key, PressState::Pressed),
+                InputEvent::Character('w'),
+            ],
+        );
+        assert_eq!(input.key(key), PressState::None);
+        assert!(input.text_key(key));
+        assert_eq!(input.typed_text(), "w");
+
+        input.set_text_input(false);
+        tick(&mut input, &[]);
+        assert_eq!(input.key(key), PressState::Held);
+        assert_eq!(input.typed_text(), "");
+    }
+
+    fn tick_at(input: &mut InputState, events: &[InputEvent], start: Instant, millis: u64) {
+        input.tick(
+            events.iter().copied(),
+            start + Duration::from_millis(millis),
+            &InputSettings::default(),
+        );
+    }
+
+    fn moved(x: f64, y: f64) -> InputEvent {
+        InputEvent::MouseMoved(PhysicalPosition::new(x, y))
+    }
+
+    #[test]
+    fn double_click_needs_two_close_presses_in_time() {
+        let button = MouseButton::Left;
+        let press = InputEvent::Button(button, PressState::Pressed);
+        let release = InputEvent::Button(button, PressState::Released);
+        let start = Instant::now();
+
+        let mut input = InputState::default();
+        tick_at(&mut input, &[moved(10.0, 10.0), press], start, 0);
+        assert!(!input.double_clicks.contains(&button));
+        tick_at(&mut input, &[release], start, 50);
+        // Moving away after the second press doesn't matter, it's where the press happened
+        tick_at(&mut input, &[press, moved(30.0, 30.0)], start, 100);
+        assert!(input.double_clicks.contains(&button));
+
+        // Moving away right after the first press in the same tick does
+        let mut input = InputState::default();
+        tick_at(
+            &mut input,
+            &[moved(10.0, 10.0), press, moved(30.0, 30.0)],
+            start,
+            0,
+        );
+        tick_at(&mut input, &[release], start, 50);
+        tick_at(&mut input, &[press], start, 100);
+        assert!(!input.double_clicks.contains(&button));
+
+        // Too late
+        let mut input = InputState::default();
+        tick_at(&mut input, &[press], start, 0);
+        tick_at(&mut input, &[release], start, 50);
+        tick_at(&mut input, &[press], start, 400);
+        assert!(!input.double_clicks.contains(&button));
+    }
+
+    #[test]
+    fn third_click_starts_a_new_double_click() {
+        let button = MouseButton::Right;
+        let press = InputEvent::Button(button, PressState::Pressed);
+        let release = InputEvent::Button(button, PressState::Released);
+        let start = Instant::now();
+        let mut input = InputState::default();
+        let mut double_clicks = Vec::new();
+        for millis in [0, 50, 100, 150, 200, 250, 300] {
+            let event = if millis % 100 == 0 { press } else { release };
+            tick_at(&mut input, &[event], start, millis);
+            if millis % 100 == 0 {
+                double_clicks.push(input.double_clicks.contains(&button));
+            }
+        }
+        assert_eq!(double_clicks, [false, true, false, true]);
+    }
+
+    #[test]
+    fn key_repeats_after_the_delay_then_every_interval() {
+        let key = VirtualKeyCode::Back;
+        let start = Instant::now();
+        let mut input = InputState::default();
+        tick_at(
+            &mut input,
+            &[InputEvent::Key(key, PressState::Pressed)],
+            start,
+            0,
+        );
+        let mut repeats = vec![input.key_repeats.contains(&key)];
+        // Default delay of 400ms and interval of 50ms
+        for millis in [100, 399, 400, 420, 450, 500] {
+            tick_at(&mut input, &[], start, millis);
+            repeats.push(input.key_repeats.contains(&key));
+        }
+        assert_eq!(repeats, [true, false, false, true, false, true, true]);
+
+        tick_at(
+            &mut input,
+            &[InputEvent::Key(key, PressState::Released)],
+            start,
+            550,
+        );
+        tick_at(&mut input, &[], start, 600);
+        assert!(!input.key_repeats.contains(&key));
+    }
+
+    #[test]
+    fn input_settings_change_the_timing() {
+        let button = MouseButton::Left;
+        let press = InputEvent::Button(button, PressState::Pressed);
+        let release = InputEvent::Button(button, PressState::Released);
+        let settings = InputSettings {
+            double_click_time: Duration::from_millis(500),
+            double_click_distance: 50.0,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut input = InputState::default();
+        for (events, millis) in [
+            (vec![moved(0.0, 0.0), press], 0),
+            (vec![release], 50),
+            (vec![moved(30.0, 0.0), press], 400),
+        ] {
+            input.tick(events, start + Duration::from_millis(millis), &settings);
+        }
+        assert!(input.double_clicks.contains(&button));
+    }
 }
@@ -0,0 +1,31 @@
+#![feature(int_roundings)]
+
+pub mod asset_types;
+pub mod audio;
+pub mod console;
+pub mod debug_stats;
+pub mod ecs;
+pub mod engine;
+pub mod engine_config;
+pub mod input_manager;
+pub mod noise;
+pub mod physics;
+pub mod rendering;
+pub mod state;
+pub mod time;
+pub mod voxels;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[macro_use]
+extern crate lazy_static;
+extern crate nalgebra as na;
+
+lazy_static! {
+    static ref CURRENT_ID: AtomicU64 = AtomicU64::new(0);
+}
+
+fn next_id() -> u64 {
+    CURRENT_ID.fetch_add(1, Ordering::Relaxed);
+    CURRENT_ID.load(Ordering::Relaxed)
+}
@@ -1,269 +1,262 @@
-#![feature(int_roundings)]
-
-mod asset_types;
-mod ecs;
-mod input_manager;
-mod noise;
-mod physics;
-mod rendering;
-mod state;
-mod time;
-mod voxels;
-
-use crate::noise::simplex::Simplex1D;
-
-use ecs::{
-    components::{
-        self,
+use graphics_test::{
+    asset_types::{mesh::Mesh, vertex::Vertex},
+    ecs::components::{
         camera::Camera,
-        player_components::Player,
+        physics_components::DynamicBody,
         rendering_components::MeshRenderer,
-        transformation_components::{Position, Rotation},
+        transformation_components::{Position, PreviousPosition, PreviousRotation, Rotation, Spin},
     },
-    systems::{
-        camera_systems::update_camera_system, player_controller::update_players_system,
-        render_systems::construct_buffers,
+    ecs::world::World,
+    engine::Engine,
+    engine_config::{EngineConfig, TerrainNoise},
+    physics::physics_scene::lock_physics,
+    rendering::{
+        self,
+        camera::Viewport,
+        material::{Material, MaterialDiffuseTexture, MaterialOverlay},
+        render_pass_data::PassSorting,
+        texture_cache::TextureCache,
     },
-    world::World,
+    voxels::{voxel_mesh::get_voxel_mesh, voxel_shapes::voxel_shape},
 };
-use input_manager::update_inputs;
-use legion::IntoQuery;
-use legion::{Resources, Schedule};
 use mimalloc::MiMalloc;
 use parking_lot::RwLock;
-use pollster::block_on;
-use rendering::{
-    material::{Material, MaterialDiffuseTexture},
-    render_pass_data::render_layers,
-    texture::Texture,
-};
-use state::*;
-use std::{
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
-    time::Instant,
-};
-use time::Time;
-use voxels::voxel_scene::CHUNK_SIZE;
+use rapier3d::prelude::ColliderBuilder;
+use std::sync::Arc;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-#[macro_use]
-extern crate lazy_static;
-extern crate nalgebra as na;
-
-use glam::{EulerRot, IVec3, Quat, UVec3, Vec3};
-use winit::{
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
-use crate::rendering::mesh::Mesh;
-
-use crate::voxels::voxel_scene::VoxelScene;
+use glam::{EulerRot, Quat, Vec3};
+use winit::event_loop::EventLoop;
 
-fn main() -> Result<(), ()> {
-    env_logger::init(); // Tells WGPU to inform us of errors, rather than failing silently
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = EngineConfig {
+        // The first argument is an optional world folder to load from and save to
+        save_path: args.iter().find(|arg| !arg.starts_with("--")).cloned(),
+        // With --gpu-noise or --cpu-noise the terrain density comes from simplex noise on the GPU or the CPU, voxel
+        // types still come from the biomes
+        terrain_noise: if args.iter().any(|arg| arg == "--gpu-noise") {
+            TerrainNoise::Gpu
+        } else if args.iter().any(|arg| arg == "--cpu-noise") {
+            TerrainNoise::Cpu
+        } else {
+            TerrainNoise::Biomes
+        },
+        // With --pregenerate the terrain around the spawn is ready before the first frame instead of popping in
+        pregenerate_radius: args.iter().any(|arg| arg == "--pregenerate").then_some(4),
+        ..Default::default()
+    };
+    let top = config.world.max_height as f32;
 
     let event_loop = EventLoop::new();
-    // Create a window
-    let window = WindowBuilder::new()
-        .with_maximized(true)
-        .build(&event_loop)
-        .unwrap();
-
-    let state = Arc::new(RwLock::new(State::new(&window).await));
-
-    // Setup entity world
-    let state_clone = Arc::clone(&state);
-    // Create a Legion world (ECS)
-    let world = Arc::new(RwLock::new(World {
-        legion_world: legion::World::default(),
-    }));
-
-    let state_lock = state_clone.write();
-    let camera = Arc::new(RwLock::new(rendering::camera::Camera::new(&state_lock)));
-
-    let diffuse_bytes = include_bytes!("textures/lapis_block.png");
-    let texture = Arc::new(
-        Texture::from_bytes(
-            &state_lock.device,
-            &state_lock.queue,
-            diffuse_bytes,
-            "lapis",
-        )
-        .unwrap(),
+    let mut engine = Engine::new(config, &event_loop);
+    engine.spawn_player_on_surface(0, 0);
+    engine.spawn_sun();
+    spawn_demo_objects(&engine, top);
+    // With --unsorted-transparency the glass panes are drawn in material order instead of back to front, so the
+    // further panes cover the nearer ones wherever they overlap
+    spawn_glass_panes(
+        &engine,
+        top,
+        args.iter().any(|arg| arg == "--unsorted-transparency"),
     );
+    // With --split-screen the main camera shares the window with a second camera looking at the spawn from the side
+    if args.iter().any(|arg| arg == "--split-screen") {
+        spawn_side_camera(&engine, top);
+    }
+    engine.run(event_loop);
+}
 
+// A spinning cube and a crate above the spawn, next to a panel showing what a camera looking down on them sees
+fn spawn_demo_objects(engine: &Engine, top: f32) {
+    let state_lock = engine.state().write();
+    let texture = TextureCache::load_or_fallback(&state_lock, "textures/lapis_block.png");
     let material: Arc<RwLock<dyn Material>> = Arc::new(RwLock::new(MaterialDiffuseTexture::new(
         &state_lock,
         texture,
     )));
-
+    // A second camera looking down on the spawn draws into a texture, which is shown on a panel in the main view
+    let mut overview_camera = rendering::camera::Camera::new(&state_lock);
+    let overview_texture = overview_camera.render_to_texture(&state_lock, 512, 512);
+    overview_camera.add_render_layer(&state_lock, "Default".to_string());
+    overview_camera.add_render_layer(&state_lock, "Transparent".to_string());
+    let overview_material: Arc<RwLock<dyn Material>> = Arc::new(RwLock::new(
+        MaterialDiffuseTexture::new(&state_lock, overview_texture),
+    ));
+    // Only the main camera draws the overview panel, a camera can't sample the texture it draws into
+    state_lock
+        .render_layers
+        .create_layer("Display".to_string(), 1);
+    engine
+        .camera()
+        .write()
+        .add_render_layer(&state_lock, "Display".to_string());
     drop(state_lock);
 
-    // Create the default render layer
-    render_layers::create_layer("Default".to_string());
-
-    let mut camera_lock = camera.write();
-    camera_lock.add_render_layer("Default".to_string());
-    drop(camera_lock);
-
-    let mut world_lock = world.write();
+    let mut world_lock = World::lock(engine.world());
+    // The cube only ever rewrites its transform and never re-uploads its mesh
     world_lock.legion_world.push((
-        Position(Vec3::new(0.0, 80.0, 0.0)), // Middle of world
-        Rotation(Quat::from_euler(
-            EulerRot::XYZ,
-            0.0,
-            (45.0 as f32).to_radians(),
-            0.0,
-        )),
-        Player { fly_speed: 50.0 },
-        components::camera::Camera { camera },
+        Position(Vec3::new(0.0, top + 5.0, 10.0)),
+        Rotation(Quat::IDENTITY),
+        Spin(Vec3::new(0.3, 1.0, 0.0)),
+        MeshRenderer::new(
+            Arc::new(RwLock::new(cube_mesh())),
+            Arc::clone(&material),
+            "Default".to_string(),
+        ),
     ));
-    drop(world_lock);
-
-    let world_clone = Arc::clone(&world);
-    rayon::spawn(move || {
-        // Add systems
-        let mut schedule = Schedule::builder()
-            .add_system(update_players_system())
-            .add_system(update_camera_system())
-            .build();
-        let start = Instant::now();
-        let mut loop_time = Instant::now();
-        let mut resources = Resources::default(); // Resources are accessible to all systems that use them
-        loop {
-            update_inputs(); // Update the inputs before sending firing the systems
-            resources.insert(Time {
-                time: start.elapsed().as_secs_f64(),
-                delta_time: loop_time.elapsed().as_secs_f64(),
-            });
-            loop_time = Instant::now();
 
-            let mut world_lock = world_clone.write();
-            schedule.execute(&mut world_lock.legion_world, &mut resources);
-        }
-    });
-
-    // Setup voxel scene
-    let scene = Arc::new(RwLock::new(VoxelScene::new()));
-    generate_world(
-        Arc::clone(&scene),
-        Arc::clone(&world),
-        Arc::clone(&material),
-        UVec3::new(50, 5, 50),
+    // The crate comes to rest on the terrain
+    let crate_position = Vec3::new(3.0, top + 5.0, 10.0);
+    let mut physics = lock_physics(engine.physics());
+    let crate_body = DynamicBody::new(
+        &mut physics,
+        crate_position,
+        ColliderBuilder::cuboid(0.5, 0.5, 0.5).build(),
     );
+    let crate_entity = world_lock.legion_world.push((
+        Position(crate_position),
+        Rotation(Quat::IDENTITY),
+        PreviousPosition(crate_position),
+        PreviousRotation(Quat::IDENTITY),
+        crate_body,
+        MeshRenderer::new(
+            Arc::new(RwLock::new(cube_mesh())),
+            Arc::clone(&material),
+            "Default".to_string(),
+        ),
+    ));
+    world_lock.attach_colliders(crate_entity, &mut physics);
+    drop(physics);
 
-    let state_clone = Arc::clone(&state);
-    rayon::spawn(move || {
-        let noise = block_on(Simplex1D::build_noise(
-            &state_clone.read(),
-            &UVec3::new(16, 16, 16),
-        ));
-        //noise.iter().for_each(|v| println!("{v}"));
-    });
-
-    event_loop.run(move |event, _, control_flow| {
-        match event {
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == window.id() => {
-                let mut state_lock = state.write();
-                if !state_lock.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state_lock.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state_lock.resize(**new_inner_size);
-                        }
-                        _ => {}
-                    }
-                }
-            }
-
-            Event::RedrawRequested(window_id) if window_id == window.id() => {
-                let world_lock = world.read();
-                let mut query = <&Camera>::query();
-
-                let cameras: Vec<Arc<RwLock<rendering::camera::Camera>>> = query
-                    .iter(&world_lock.legion_world)
-                    .map(|cam| Arc::clone(&cam.camera))
-                    .collect();
+    world_lock.legion_world.push((
+        Position(Vec3::new(0.0, top + 40.0, 0.0)),
+        Rotation(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2)), // Looking straight down
+        Camera {
+            camera: Arc::new(RwLock::new(overview_camera)),
+        },
+    ));
+    world_lock.legion_world.push((
+        Position(Vec3::new(-4.0, top + 6.0, 10.0)),
+        Rotation(Quat::IDENTITY),
+        MeshRenderer::new(
+            Arc::new(RwLock::new(panel_mesh(3.0))),
+            overview_material,
+            "Display".to_string(),
+        ),
+    ));
+}
 
-                let mut state_lock = state.write();
-                construct_buffers(&state_lock, &world_lock.legion_world);
+// Three overlapping tinted panes in front of the spawn. They blend without writing depth, so they only look right
+// when the furthest is drawn first
+fn spawn_glass_panes(engine: &Engine, top: f32, unsorted: bool) {
+    let layer = if unsorted {
+        let state_lock = engine.state().write();
+        state_lock.render_layers.create_sorted_layer(
+            "Unsorted Transparent".to_string(),
+            2,
+            PassSorting::Material,
+        );
+        engine
+            .camera()
+            .write()
+            .add_render_layer(&state_lock, "Unsorted Transparent".to_string());
+        "Unsorted Transparent"
+    } else {
+        "Transparent"
+    };
 
-                match state_lock.render(cameras) {
-                    Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => {
-                        let size = state_lock.size;
-                        state_lock.resize(size);
-                    }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
-                }
-            }
-            Event::MainEventsCleared => {
-                // RedrawRequested will only trigger once, unless we manually
-                // request it.
-                window.request_redraw();
-            }
-            _ => {}
-        }
-    });
+    let mut world_lock = World::lock(engine.world());
+    for (index, color) in [
+        [1.0, 0.2, 0.2, 0.5],
+        [0.2, 1.0, 0.2, 0.5],
+        [0.2, 0.2, 1.0, 0.5],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let offset = index as f32;
+        let mut mesh = panel_mesh(2.0);
+        let vertices = mesh
+            .get_vertices()
+            .iter()
+            .map(|vertex| Vertex { color, ..*vertex })
+            .collect();
+        mesh.set_vertices(vertices);
+        // Every pane has its own material so it gets its own pass, passes are what the layer sorts
+        world_lock.legion_world.push((
+            Position(Vec3::new(
+                8.0 + offset * 0.7,
+                top + 5.0 + offset * 0.5,
+                14.0 + offset * 1.5,
+            )),
+            Rotation(Quat::IDENTITY),
+            MeshRenderer::new(
+                Arc::new(RwLock::new(mesh)),
+                Arc::new(RwLock::new(MaterialOverlay::new())),
+                layer.to_string(),
+            ),
+        ));
+    }
 }
 
-pub fn generate_world(
-    scene: Arc<RwLock<VoxelScene>>,
-    world: Arc<RwLock<World>>,
-    material: Arc<RwLock<dyn Material>>,
-    size: UVec3,
-) {
-    for x in 0..size.x {
-        for y in 0..size.y {
-            for z in 0..size.z {
-                scene
-                    .write()
-                    .initialize_and_generate_chunk(IVec3::new(x as i32, y as i32, z as i32));
-            }
-        }
-    }
+fn spawn_side_camera(engine: &Engine, top: f32) {
+    let state_lock = engine.state().write();
+    let mut side_camera = rendering::camera::Camera::new(&state_lock);
+    side_camera.set_viewport(&state_lock, Viewport::new(0.5, 0.0, 0.5, 1.0));
+    side_camera.add_render_layer(&state_lock, "Default".to_string());
+    side_camera.add_render_layer(&state_lock, "Transparent".to_string());
+    engine
+        .camera()
+        .write()
+        .set_viewport(&state_lock, Viewport::new(0.0, 0.0, 0.5, 1.0));
+    drop(state_lock);
 
-    let (tx, rx) = flume::unbounded();
-    scene.write().setup_chunk_processors(tx);
-    rayon::spawn(move || {
-        //let mut saved_meshes = HashMap::new();
-        loop {
-            let (mesh_pos, mesh) = rx.recv().unwrap();
-            let mut world_lock = world.write();
-            world_lock.legion_world.push((
-                Position(mesh_pos.as_vec3() * CHUNK_SIZE as f32),
-                Rotation(Quat::IDENTITY),
-                MeshRenderer::new(
-                    Arc::new(RwLock::new(mesh)),
-                    Arc::clone(&material),
-                    "Default".to_string(),
-                ),
-            ));
-        }
-    });
+    World::lock(engine.world()).legion_world.push((
+        Position(Vec3::new(-20.0, top + 10.0, 10.0)),
+        Rotation(Quat::from_euler(
+            EulerRot::YXZ,
+            std::f32::consts::FRAC_PI_2, // Facing the spawn along +X, tilted down a little
+            0.3,
+            0.0,
+        )),
+        Camera {
+            camera: Arc::new(RwLock::new(side_camera)),
+        },
+    ));
 }
 
-lazy_static! {
-    static ref CURRENT_ID: AtomicU64 = AtomicU64::new(0);
+// A square facing south with the whole texture on it, upright when looked at from the south
+fn panel_mesh(size: f32) -> Mesh {
+    let half = size / 2.0;
+    Mesh::new().append_quad(
+        [
+            [-half, half, 0.0],
+            [half, half, 0.0],
+            [-half, -half, 0.0],
+            [half, -half, 0.0],
+        ],
+        [0.0, 0.0, -1.0],
+    )
 }
 
-fn next_id() -> u64 {
-    CURRENT_ID.fetch_add(1, Ordering::Relaxed);
-    CURRENT_ID.load(Ordering::Relaxed)
+// A unit cube centered on the origin, built from the faces of the cube voxel
+fn cube_mesh() -> Mesh {
+    let cube = get_voxel_mesh(voxel_shape::CUBE);
+    let mut mesh = Mesh::new();
+    for face in [
+        &cube.north,
+        &cube.south,
+        &cube.east,
+        &cube.west,
+        &cube.top,
+        &cube.bottom,
+    ] {
+        let offset = mesh.get_vertices().len() as u32;
+        mesh.append_indices_with_offset(&mut face.get_indices().clone(), offset);
+        mesh.append_vertices(&mut face.get_vertices().clone());
+    }
+    mesh
 }
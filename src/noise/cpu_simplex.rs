@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, UVec3};
+use simdnoise::NoiseBuilder;
+
+use super::{simplex::NoiseSettings, NoiseField};
+use crate::voxels::voxel_scene::{pos_to_index, CHUNK_SIZE};
+
+// Chunks within the same region of this many chunks along each axis share one noise call
+const REGION_CHUNKS: i32 = 4;
+
+// Computes the density fields of chunks with simdnoise's fractal simplex noise, the CPU counterpart of Simplex3D for
+// machines and tests without a GPU. The noise itself differs from the shader's, so the two don't produce the same
+// terrain
+pub struct CpuSimplex3D {
+    pub settings: NoiseSettings,
+}
+
+impl CpuSimplex3D {
+    pub fn new(settings: NoiseSettings) -> Self {
+        Self { settings }
+    }
+
+    // The density of every voxel of the chunk, in the same order as the chunk's voxels
+    pub fn build_noise(&self, chunk_pos: IVec3) -> Vec<f32> {
+        self.build_noise_batch(&[chunk_pos]).pop().unwrap()
+    }
+
+    // Chunks close to each other are computed with a single noise call over the box around them, which keeps the SIMD
+    // lanes busy for longer rows and sets up the octaves once. Returned in the same order as the positions
+    pub fn build_noise_batch(&self, chunk_positions: &[IVec3]) -> Vec<Vec<f32>> {
+        let mut regions: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (index, chunk_pos) in chunk_positions.iter().enumerate() {
+            let region = IVec3::new(
+                chunk_pos.x.div_euclid(REGION_CHUNKS),
+                chunk_pos.y.div_euclid(REGION_CHUNKS),
+                chunk_pos.z.div_euclid(REGION_CHUNKS),
+            );
+            regions.entry(region).or_default().push(index);
+        }
+
+        let mut fields = vec![Vec::new(); chunk_positions.len()];
+        for indices in regions.values() {
+            let (min, max) = indices.iter().fold(
+                (IVec3::splat(i32::MAX), IVec3::splat(i32::MIN)),
+                |(min, max), index| {
+                    (
+                        min.min(chunk_positions[*index]),
+                        max.max(chunk_positions[*index]),
+                    )
+                },
+            );
+            // A box that's mostly chunks nobody asked for costs more than sampling the few that were one by one
+            let box_chunks = (max - min + IVec3::ONE).as_uvec3();
+            if (box_chunks.x * box_chunks.y * box_chunks.z) as usize > indices.len() * 2 {
+                for index in indices {
+                    let chunk_pos = chunk_positions[*index];
+                    fields[*index] = self
+                        .sample_domain(chunk_pos, chunk_pos)
+                        .slice_chunk(chunk_pos);
+                }
+                continue;
+            }
+            let domain = self.sample_domain(min, max);
+            for index in indices {
+                fields[*index] = domain.slice_chunk(chunk_positions[*index]);
+            }
+        }
+        fields
+    }
+
+    // Densities of every voxel in the chunks from min to max, an isolated chunk is a domain of its own
+    fn sample_domain(&self, min: IVec3, max: IVec3) -> NoiseDomain {
+        let origin = min * CHUNK_SIZE as i32;
+        let size = ((max - min + IVec3::ONE) * CHUNK_SIZE as i32).as_uvec3();
+        let start = origin.as_vec3() + self.settings.offset;
+        let (values, _, _) = NoiseBuilder::fbm_3d_offset(
+            start.x,
+            size.x as usize,
+            start.y,
+            size.y as usize,
+            start.z,
+            size.z as usize,
+        )
+        .with_freq(1.0 / self.settings.wavelength)
+        .with_octaves(self.settings.octaves as u8)
+        .generate();
+        NoiseDomain {
+            origin,
+            size,
+            values,
+            amplitude: self.settings.amplitude,
+            height: self.settings.height,
+        }
+    }
+}
+
+impl NoiseField for CpuSimplex3D {
+    fn build_noise_batch(&self, chunk_positions: &[IVec3]) -> Vec<Vec<f32>> {
+        CpuSimplex3D::build_noise_batch(self, chunk_positions)
+    }
+}
+
+// Noise over a box of voxels, simdnoise lays it out with x changing fastest and z slowest
+struct NoiseDomain {
+    origin: IVec3,
+    size: UVec3,
+    values: Vec<f32>,
+    amplitude: f32,
+    height: f32,
+}
+
+impl NoiseDomain {
+    // Chunk fields are laid out by pos_to_index, which has z changing fastest, so every value is looked up by position
+    fn slice_chunk(&self, chunk_pos: IVec3) -> Vec<f32> {
+        let chunk_origin = chunk_pos * CHUNK_SIZE as i32;
+        let offset = (chunk_origin - self.origin).as_uvec3();
+        let mut field = vec![0.0; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize];
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let local = UVec3::new(x, y, z);
+                    let p = offset + local;
+                    let index = (p.z * self.size.y + p.y) * self.size.x + p.x;
+                    let world_y = chunk_origin.y + y as i32;
+                    field[pos_to_index(&local) as usize] =
+                        self.values[index as usize] * self.amplitude + self.height - world_y as f32;
+                }
+            }
+        }
+        field
+    }
+}
+
+#[cfg(test)]
+mod cpu_simplex_tests {
+    use super::*;
+    use crate::time::fastest_of;
+
+    #[test]
+    fn batches_match_single_chunks() {
+        let noise = CpuSimplex3D::new(NoiseSettings::default().with_seed(7));
+        // A block sharing a region, one on its own and one across the region border at negative coordinates
+        let mut chunks: Vec<IVec3> = (0..8)
+            .map(|i| IVec3::new(i % 2, i / 4, i / 2 % 2))
+            .collect();
+        chunks.push(IVec3::new(9, 1, -5));
+        chunks.push(IVec3::new(-1, 0, 0));
+        // Far enough into the block's region that the box around them is mostly unrequested
+        chunks.push(IVec3::new(3, 3, 3));
+
+        let fields = noise.build_noise_batch(&chunks);
+        assert_eq!(fields.len(), chunks.len());
+        for (chunk_pos, field) in chunks.iter().zip(&fields) {
+            let single = noise.build_noise(*chunk_pos);
+            assert_eq!(field.len(), single.len());
+            let largest_difference = field
+                .iter()
+                .zip(&single)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(
+                largest_difference < 1e-3,
+                "{chunk_pos}: {largest_difference}"
+            );
+        }
+    }
+
+    #[test]
+    fn fields_follow_chunk_voxel_order() {
+        // Without noise only the height gradient is left, which gives away where every value came from
+        let noise = CpuSimplex3D::new(NoiseSettings {
+            amplitude: 0.0,
+            ..Default::default()
+        });
+        let chunks = [IVec3::new(0, 1, 0), IVec3::new(1, 2, 0)];
+        for (chunk_pos, field) in chunks.iter().zip(noise.build_noise_batch(&chunks)) {
+            let local = UVec3::new(3, 5, 7);
+            let y = chunk_pos.y * CHUNK_SIZE as i32 + local.y as i32;
+            assert_eq!(
+                field[pos_to_index(&local) as usize],
+                noise.settings.height - y as f32
+            );
+        }
+    }
+
+    #[test]
+    #[ignore] // Timing comparison, run with --ignored --nocapture
+    fn per_chunk_and_batched_noise_benchmark() {
+        let noise = CpuSimplex3D::new(NoiseSettings::default());
+        let chunks: Vec<IVec3> = (0..125)
+            .map(|i| IVec3::new(i % 5, i / 25, i / 5 % 5))
+            .collect();
+
+        let per_chunk = fastest_of(3, || {
+            for chunk_pos in &chunks {
+                noise.build_noise(*chunk_pos);
+            }
+        });
+        let batched = fastest_of(3, || {
+            noise.build_noise_batch(&chunks);
+        });
+
+        println!(
+            "125 chunks, per chunk: {per_chunk:?}, batched: {batched:?} ({:.2}x)",
+            per_chunk.as_secs_f64() / batched.as_secs_f64()
+        );
+        assert!(
+            batched < per_chunk,
+            "sampling regions was slower than sampling chunk by chunk, {batched:?} against {per_chunk:?}"
+        );
+    }
+}
@@ -1 +1,11 @@
+use glam::IVec3;
+
+pub mod cpu_simplex;
+pub mod noise_cache;
 pub mod simplex;
+
+// Terrain density over whole chunks, in the same order as a chunk's voxels. Computed many chunks at a time, the
+// chunks are returned in the same order as the positions
+pub trait NoiseField: Send + Sync {
+    fn build_noise_batch(&self, chunk_positions: &[IVec3]) -> Vec<Vec<f32>>;
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::Arc};
+
+use dashmap::DashMap;
+use glam::IVec3;
+
+use super::NoiseField;
+
+// Fields past this many are computed on request only, so chunks that never ask for theirs can't fill up memory
+const MAX_CACHED_FIELDS: usize = 256;
+
+// Holds on to the fields of chunks that are computed ahead of time, so chunks waiting in the queue can share one
+// noise call with the chunk being generated. Fields are taken out once their chunk is generated
+pub struct CachedNoise {
+    noise: Arc<dyn NoiseField>,
+    fields: DashMap<IVec3, Vec<f32>, ahash::RandomState>,
+}
+
+impl CachedNoise {
+    pub fn new(noise: Arc<dyn NoiseField>) -> Self {
+        Self {
+            noise,
+            fields: DashMap::default(),
+        }
+    }
+
+    // Computes the fields that aren't cached yet in a single batch. Once the cache is full only the first position is
+    // computed, the one about to be generated
+    pub fn prefetch(&self, chunk_positions: &[IVec3]) {
+        let room = MAX_CACHED_FIELDS.saturating_sub(self.fields.len()).max(1);
+        let mut missing: Vec<IVec3> = Vec::new();
+        for chunk_pos in chunk_positions {
+            if missing.len() < room
+                && !self.fields.contains_key(chunk_pos)
+                && !missing.contains(chunk_pos)
+            {
+                missing.push(*chunk_pos);
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+        for (chunk_pos, field) in missing.iter().zip(self.noise.build_noise_batch(&missing)) {
+            self.fields.insert(*chunk_pos, field);
+        }
+    }
+
+    // The chunk is generated, its field isn't needed anymore
+    pub fn forget(&self, chunk_pos: IVec3) {
+        self.fields.remove(&chunk_pos);
+    }
+
+    pub fn retain(&self, keep: impl Fn(&IVec3) -> bool) {
+        self.fields.retain(|chunk_pos, _| keep(chunk_pos));
+    }
+
+    pub fn cached_count(&self) -> usize {
+        self.fields.len()
+    }
+}
+
+impl NoiseField for CachedNoise {
+    fn build_noise_batch(&self, chunk_positions: &[IVec3]) -> Vec<Vec<f32>> {
+        // Copied out first, another thread may forget a field in the meantime
+        let cached: Vec<Option<Vec<f32>>> = chunk_positions
+            .iter()
+            .map(|chunk_pos| self.fields.get(chunk_pos).map(|field| field.clone()))
+            .collect();
+        let mut missing: Vec<IVec3> = Vec::new();
+        for (chunk_pos, field) in chunk_positions.iter().zip(&cached) {
+            if field.is_none() && !missing.contains(chunk_pos) {
+                missing.push(*chunk_pos);
+            }
+        }
+        let computed: HashMap<IVec3, Vec<f32>> = if missing.is_empty() {
+            HashMap::new()
+        } else {
+            missing
+                .iter()
+                .copied()
+                .zip(self.noise.build_noise_batch(&missing))
+                .collect()
+        };
+        chunk_positions
+            .iter()
+            .zip(cached)
+            .map(|(chunk_pos, field)| field.unwrap_or_else(|| computed[chunk_pos].clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod noise_cache_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // Every field is filled with its chunk's x, and the number of calls is counted
+    #[derive(Default)]
+    struct CountingNoise {
+        calls: AtomicUsize,
+    }
+
+    impl NoiseField for CountingNoise {
+        fn build_noise_batch(&self, chunk_positions: &[IVec3]) -> Vec<Vec<f32>> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            chunk_positions
+                .iter()
+                .map(|chunk_pos| vec![chunk_pos.x as f32; 4])
+                .collect()
+        }
+    }
+
+    #[test]
+    fn prefetched_fields_are_shared() {
+        let counting = Arc::new(CountingNoise::default());
+        let noise = CachedNoise::new(counting.clone());
+        let positions: Vec<IVec3> = (0..8).map(|x| IVec3::new(x, 0, 0)).collect();
+        noise.prefetch(&positions);
+        assert_eq!(noise.cached_count(), 8);
+
+        // A cached chunk and one that isn't, only the missing one is computed
+        let fields = noise.build_noise_batch(&[IVec3::new(3, 0, 0), IVec3::new(9, 0, 0)]);
+        assert_eq!(fields, vec![vec![3.0; 4], vec![9.0; 4]]);
+        assert_eq!(counting.calls.load(Ordering::Relaxed), 2);
+
+        noise.forget(IVec3::new(3, 0, 0));
+        noise.retain(|chunk_pos| chunk_pos.x < 4);
+        assert_eq!(noise.cached_count(), 3);
+    }
+}
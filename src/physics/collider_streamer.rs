@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use glam::{IVec3, Vec3};
+use parking_lot::{Mutex, RwLock};
+
+use crate::voxels::voxel_scene::VoxelScene;
+
+// Decides which chunks keep their colliders. Trimesh colliders are expensive in the broad phase, so only the chunks
+// within the physics distance of a body have one, while their render meshes stay for the whole view distance.
+// Shared between the stream_colliders system and the mesh consumer in generate_world, which spawns colliders of new
+// chunks inactive when they're out of range
+pub struct ColliderStreamer {
+    pub physics_distance: u32, // In chunks, along every axis
+    body_chunks: Mutex<Vec<IVec3>>,
+    in_range: RwLock<HashSet<IVec3>>,
+}
+
+impl ColliderStreamer {
+    pub fn new(physics_distance: u32) -> Self {
+        Self {
+            physics_distance,
+            body_chunks: Mutex::new(Vec::new()),
+            in_range: RwLock::new(HashSet::new()),
+        }
+    }
+
+    // Moves the range to the chunks around the bodies and returns the chunks that came into range and the ones that
+    // left it. Only does work when a body moved into another chunk
+    pub fn update(
+        &self,
+        body_positions: impl IntoIterator<Item = Vec3>,
+    ) -> (Vec<IVec3>, Vec<IVec3>) {
+        let mut body_chunks: Vec<IVec3> = body_positions
+            .into_iter()
+            .map(|position| VoxelScene::chunk_at(&position.floor().as_ivec3()))
+            .collect();
+        body_chunks.sort_by_key(|chunk_pos| chunk_pos.to_array());
+        body_chunks.dedup();
+        {
+            let mut last = self.body_chunks.lock();
+            if *last == body_chunks {
+                return (Vec::new(), Vec::new());
+            }
+            *last = body_chunks.clone();
+        }
+
+        let distance = self.physics_distance as i32;
+        let mut in_range = HashSet::new();
+        for center in body_chunks {
+            for x in -distance..=distance {
+                for y in -distance..=distance {
+                    for z in -distance..=distance {
+                        in_range.insert(center + IVec3::new(x, y, z));
+                    }
+                }
+            }
+        }
+
+        let mut current = self.in_range.write();
+        let entered = in_range.difference(&current).copied().collect();
+        let left = current.difference(&in_range).copied().collect();
+        *current = in_range;
+        (entered, left)
+    }
+
+    pub fn in_range(&self, chunk_pos: IVec3) -> bool {
+        self.in_range.read().contains(&chunk_pos)
+    }
+}
+
+#[cfg(test)]
+mod collider_streamer_tests {
+    use super::*;
+
+    #[test]
+    fn range_follows_the_bodies() {
+        let streamer = ColliderStreamer::new(1);
+        let (entered, left) = streamer.update([Vec3::new(8.0, 8.0, 8.0)]);
+        assert_eq!(entered.len(), 27);
+        assert!(left.is_empty());
+        assert!(streamer.in_range(IVec3::new(1, -1, 1)));
+        assert!(!streamer.in_range(IVec3::new(2, 0, 0)));
+
+        // Staying within the same chunk changes nothing
+        let (entered, left) = streamer.update([Vec3::new(9.0, 3.0, 15.0)]);
+        assert!(entered.is_empty() && left.is_empty());
+
+        // One chunk east, the western slice leaves and a new eastern one enters
+        let (entered, left) = streamer.update([Vec3::new(24.0, 8.0, 8.0)]);
+        assert_eq!(entered.len(), 9);
+        assert_eq!(left.len(), 9);
+        assert!(entered.iter().all(|chunk_pos| chunk_pos.x == 2));
+        assert!(left.iter().all(|chunk_pos| chunk_pos.x == -1));
+
+        let (entered, left) = streamer.update([]);
+        assert!(entered.is_empty());
+        assert_eq!(left.len(), 27);
+        assert!(!streamer.in_range(IVec3::new(1, 0, 0)));
+    }
+}
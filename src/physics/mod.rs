@@ -1 +1,2 @@
+pub mod collider_streamer;
 pub mod physics_scene;
@@ -1,6 +1,33 @@
-use glam::Vec3;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+
+use glam::{Quat, Vec3};
+use legion::Entity;
+use parking_lot::{RwLock, RwLockWriteGuard};
+use rapier3d::crossbeam::channel::{unbounded, Receiver};
 use rapier3d::prelude::*;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerEventKind {
+    Enter,
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerEvent {
+    pub kind: TriggerEventKind,
+    pub trigger: ColliderHandle,
+    pub other: ColliderHandle,
+    pub trigger_entity: Option<Entity>,
+    pub other_entity: Option<Entity>,
+}
+
+// Shared as Arc<RwLock<PhysicsScene>>. Whoever needs both the world and the physics scene locks the world first, like
+// systems do since the world is locked while they run. Locking the world while holding the physics scene can
+// deadlock against the chunk thread, which locks them in that order too. The helpers that move bodies take the
+// locked scene rather than the lock, so a caller only ever holds it once.
+// lock_physics and World::lock keep to the order, debug builds panic when the world is locked after the scene
 pub struct PhysicsScene {
     rigidbodies: RigidBodySet,
     colliders: ColliderSet,
@@ -13,54 +40,410 @@ pub struct PhysicsScene {
     narrow_phase: NarrowPhase,
     joint_set: JointSet,
     ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
     physics_hooks: (),
-    event_handler: (),
+    event_handler: ChannelEventCollector,
+    intersection_events: Receiver<IntersectionEvent>,
+    contact_events: Receiver<ContactEvent>,
+
+    triggers: HashSet<ColliderHandle>,
+    collider_entities: HashMap<ColliderHandle, Entity>,
+    // What the steps of the last update reported, replaced by the next update so nothing piles up unread
+    trigger_events: Vec<TriggerEvent>,
+    contacts: Vec<ContactEvent>,
+    accumulator: f64, // Time that has passed but hasn't been simulated yet
 }
 
+// Caps the steps taken by a single update so a long frame can't make the simulation fall further and further behind
+const MAX_STEPS_PER_UPDATE: u32 = 8;
+
 impl PhysicsScene {
     pub fn new(update_rate: u32) -> PhysicsScene {
+        let (intersection_sender, intersection_events) = unbounded();
+        let (contact_sender, contact_events) = unbounded();
         PhysicsScene {
             rigidbodies: RigidBodySet::new(),
             colliders: ColliderSet::new(),
             gravity: Vec3::new(0.0, -9.81, 0.0),
-            integration_parameters: IntegrationParameters::default(),
+            integration_parameters: IntegrationParameters {
+                dt: 1.0 / update_rate.max(1) as f32,
+                ..Default::default()
+            },
             physics_pipeline: PhysicsPipeline::new(),
             island_manager: IslandManager::new(),
             broad_phase: BroadPhase::new(),
             narrow_phase: NarrowPhase::new(),
             joint_set: JointSet::new(),
             ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
             physics_hooks: (),
-            event_handler: (),
+            event_handler: ChannelEventCollector::new(intersection_sender, contact_sender),
+            intersection_events,
+            contact_events,
+            triggers: HashSet::new(),
+            collider_entities: HashMap::new(),
+            trigger_events: Vec::new(),
+            contacts: Vec::new(),
+            accumulator: 0.0,
         }
     }
 
-    fn step_scene(&mut self) {
-        for _ in 0..200 {
-            self.physics_pipeline.step(
-                &vector![self.gravity.x, self.gravity.y, self.gravity.z],
-                &self.integration_parameters,
-                &mut self.island_manager,
-                &mut self.broad_phase,
-                &mut self.narrow_phase,
-                &mut self.rigidbodies,
-                &mut self.colliders,
-                &mut self.joint_set,
-                &mut self.ccd_solver,
-                &self.physics_hooks,
-                &self.event_handler,
-            );
+    pub fn set_gravity(&mut self, gravity: Vec3) {
+        self.gravity = gravity;
+    }
+
+    pub fn get_gravity(&self) -> Vec3 {
+        self.gravity
+    }
+
+    // Length of a single physics step in seconds
+    pub fn set_timestep(&mut self, timestep: f32) {
+        self.integration_parameters.dt = timestep;
+    }
+
+    pub fn get_timestep(&self) -> f32 {
+        self.integration_parameters.dt
+    }
+
+    // Direct access for tuning the solver (iterations, ccd substeps, erp, etc.)
+    pub fn integration_parameters(&self) -> &IntegrationParameters {
+        &self.integration_parameters
+    }
+
+    pub fn integration_parameters_mut(&mut self) -> &mut IntegrationParameters {
+        &mut self.integration_parameters
+    }
+
+    pub fn register_rigidbody(&mut self, rigidbody: RigidBody) -> RigidBodyHandle {
+        self.rigidbodies.insert(rigidbody)
+    }
+
+    // Also removes every collider attached to the body
+    pub fn remove_rigidbody(&mut self, handle: RigidBodyHandle) {
+        let colliders = self
+            .rigidbodies
+            .get(handle)
+            .map_or_else(Vec::new, |rigidbody| rigidbody.colliders().to_vec());
+        self.rigidbodies.remove(
+            handle,
+            &mut self.island_manager,
+            &mut self.colliders,
+            &mut self.joint_set,
+        );
+        for collider in colliders {
+            self.triggers.remove(&collider);
+            self.collider_entities.remove(&collider);
         }
     }
 
-    fn physics_scene_processor() {
-        println!("Started physics scene processor");
-        loop {
-            // Fixed update loop
+    pub fn register_collider(&mut self, collider: Collider) -> ColliderHandle {
+        self.colliders.insert(collider)
+    }
+
+    pub fn register_collider_with_parent(
+        &mut self,
+        collider: Collider,
+        parent: RigidBodyHandle,
+    ) -> ColliderHandle {
+        self.colliders
+            .insert_with_parent(collider, parent, &mut self.rigidbodies)
+    }
+
+    pub fn remove_collider(&mut self, handle: ColliderHandle) {
+        self.colliders.remove(
+            handle,
+            &mut self.island_manager,
+            &mut self.rigidbodies,
+            true,
+        );
+        self.triggers.remove(&handle);
+        self.collider_entities.remove(&handle);
+    }
+
+    // Sensors don't generate contacts, only enter/exit events that can be read with trigger_events
+    pub fn register_trigger(&mut self, collider: Collider) -> ColliderHandle {
+        let handle = self.colliders.insert(collider);
+        self.triggers.insert(handle);
+        handle
+    }
+
+    // Associates a collider with the entity that owns it so events can be reported per entity
+    pub fn attach_entity(&mut self, collider: ColliderHandle, entity: Entity) {
+        self.collider_entities.insert(collider, entity);
+    }
+
+    pub fn get_collider_entity(&self, collider: ColliderHandle) -> Option<Entity> {
+        self.collider_entities.get(&collider).copied()
+    }
+
+    // The enter/exit events of the triggers during the last update, with the entities of both colliders
+    pub fn trigger_events(&self) -> &[TriggerEvent] {
+        &self.trigger_events
+    }
+
+    // The contacts that started or stopped during the last update
+    pub fn contact_events(&self) -> &[ContactEvent] {
+        &self.contacts
+    }
+
+    // Empties the channels after every step, events between colliders that aren't triggers are dropped
+    fn collect_events(&mut self) {
+        let trigger_events = self.intersection_events.try_iter().filter_map(|event| {
+            let (trigger, other) = if self.triggers.contains(&event.collider1) {
+                (event.collider1, event.collider2)
+            } else if self.triggers.contains(&event.collider2) {
+                (event.collider2, event.collider1)
+            } else {
+                return None;
+            };
+            Some(TriggerEvent {
+                kind: if event.intersecting {
+                    TriggerEventKind::Enter
+                } else {
+                    TriggerEventKind::Exit
+                },
+                trigger,
+                other,
+                trigger_entity: self.collider_entities.get(&trigger).copied(),
+                other_entity: self.collider_entities.get(&other).copied(),
+            })
+        });
+        self.trigger_events.extend(trigger_events);
+        self.contacts.extend(self.contact_events.try_iter());
+    }
+
+    pub fn rigidbody_count(&self) -> usize {
+        self.rigidbodies.len()
+    }
+
+    pub fn collider_count(&self) -> usize {
+        self.colliders.len()
+    }
+
+    pub fn get_rigidbody(&self, handle: RigidBodyHandle) -> Option<&RigidBody> {
+        self.rigidbodies.get(handle)
+    }
+
+    pub fn get_rigidbody_mut(&mut self, handle: RigidBodyHandle) -> Option<&mut RigidBody> {
+        self.rigidbodies.get_mut(handle)
+    }
+
+    pub fn get_collider(&self, handle: ColliderHandle) -> Option<&Collider> {
+        self.colliders.get(handle)
+    }
+
+    // Sensors still report intersections but nothing collides with them anymore
+    pub fn set_sensor(&mut self, handle: ColliderHandle, is_sensor: bool) {
+        if let Some(collider) = self.colliders.get_mut(handle) {
+            collider.set_sensor(is_sensor);
+        }
+    }
+
+    // Moves a kinematic body, it reaches the target during the next step and pushes dynamic bodies out of the way
+    pub fn set_kinematic_target(
+        &mut self,
+        handle: RigidBodyHandle,
+        position: Vec3,
+        rotation: Quat,
+    ) {
+        if let Some(rigidbody) = self.rigidbodies.get_mut(handle) {
+            rigidbody.set_next_kinematic_position(to_isometry(position, rotation));
+        }
+    }
+
+    // Sweeps a shape from a position and returns the distance and surface normal of the first collider it hits.
+    // Triggers and the excluded collider are ignored
+    pub fn cast_shape(
+        &self,
+        shape: &dyn Shape,
+        position: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        exclude: ColliderHandle,
+    ) -> Option<(f32, Vec3)> {
+        let filter = |handle: ColliderHandle| handle != exclude && !self.triggers.contains(&handle);
+        self.query_pipeline
+            .cast_shape(
+                &self.colliders,
+                &to_isometry(position, Quat::IDENTITY),
+                &vector![direction.x, direction.y, direction.z],
+                shape,
+                max_distance,
+                InteractionGroups::all(),
+                Some(&filter),
+            )
+            .map(|(_, toi)| {
+                // normal2 points out of the cast shape, which is never rotated
+                let normal = -Vec3::new(toi.normal2.x, toi.normal2.y, toi.normal2.z);
+                (toi.toi, normal)
+            })
+    }
+
+    // Advances the simulation in fixed steps of the timestep, time that doesn't fill a whole step carries over to the
+    // next update. Returns the number of steps taken
+    pub fn update(&mut self, delta_time: f64) -> u32 {
+        let timestep = self.integration_parameters.dt as f64;
+        self.accumulator += delta_time;
+        self.trigger_events.clear();
+        self.contacts.clear();
+        let mut steps = 0;
+        while self.accumulator >= timestep && steps < MAX_STEPS_PER_UPDATE {
+            self.step_scene();
+            self.collect_events();
+            self.accumulator -= timestep;
+            steps += 1;
+        }
+        if steps == MAX_STEPS_PER_UPDATE {
+            self.accumulator = self.accumulator.min(timestep);
+        }
+        steps
+    }
+
+    fn step_scene(&mut self) {
+        self.physics_pipeline.step(
+            &vector![self.gravity.x, self.gravity.y, self.gravity.z],
+            &self.integration_parameters,
+            &mut self.island_manager,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.rigidbodies,
+            &mut self.colliders,
+            &mut self.joint_set,
+            &mut self.ccd_solver,
+            &self.physics_hooks,
+            &self.event_handler,
+        );
+        self.query_pipeline
+            .update(&self.island_manager, &self.rigidbodies, &self.colliders);
+    }
+}
+
+thread_local! {
+    // How many physics scenes the thread has locked through lock_physics
+    static PHYSICS_LOCKS: Cell<u32> = Cell::new(0);
+}
+
+// The locked physics scene, counted for the thread while it's held
+pub struct PhysicsLock<'a> {
+    guard: RwLockWriteGuard<'a, PhysicsScene>,
+}
+
+impl Deref for PhysicsLock<'_> {
+    type Target = PhysicsScene;
+
+    fn deref(&self) -> &PhysicsScene {
+        &self.guard
+    }
+}
+
+impl DerefMut for PhysicsLock<'_> {
+    fn deref_mut(&mut self) -> &mut PhysicsScene {
+        &mut self.guard
+    }
+}
+
+impl Drop for PhysicsLock<'_> {
+    fn drop(&mut self) {
+        PHYSICS_LOCKS.with(|locks| locks.set(locks.get() - 1));
+    }
+}
+
+pub fn lock_physics(physics: &RwLock<PhysicsScene>) -> PhysicsLock<'_> {
+    let guard = physics.write();
+    PHYSICS_LOCKS.with(|locks| locks.set(locks.get() + 1));
+    PhysicsLock { guard }
+}
+
+// Whether the thread holds a physics scene it locked through lock_physics
+pub fn holds_physics_lock() -> bool {
+    PHYSICS_LOCKS.with(|locks| locks.get() > 0)
+}
+
+pub fn to_isometry(position: Vec3, rotation: Quat) -> Isometry<Real> {
+    Isometry::from_parts(
+        Translation::new(position.x, position.y, position.z),
+        Rotation::from_quaternion(rapier3d::na::Quaternion::new(
+            rotation.w, rotation.x, rotation.y, rotation.z,
+        )),
+    )
+}
+
+pub fn from_isometry(isometry: &Isometry<Real>) -> (Vec3, Quat) {
+    let translation = isometry.translation.vector;
+    let rotation = isometry.rotation.coords; // Stored as i, j, k, w
+    (
+        Vec3::new(translation.x, translation.y, translation.z),
+        Quat::from_xyzw(rotation.x, rotation.y, rotation.z, rotation.w),
+    )
+}
+
+#[cfg(test)]
+mod physics_scene_tests {
+    use super::*;
+
+    #[test]
+    fn steps_follow_the_timestep() {
+        let mut scene = PhysicsScene::new(60);
+        assert_eq!(scene.update(1.0 / 120.0), 0);
+        assert_eq!(scene.update(1.0 / 120.0), 1);
+        assert_eq!(scene.update(0.5), MAX_STEPS_PER_UPDATE);
+    }
+
+    #[test]
+    fn ball_comes_to_rest_on_the_ground() {
+        let mut scene = PhysicsScene::new(60);
+        scene.register_collider(ColliderBuilder::cuboid(10.0, 0.5, 10.0).build());
+        let ball = scene.register_rigidbody(
+            RigidBodyBuilder::new_dynamic()
+                .translation(vector![0.0, 5.0, 0.0])
+                .build(),
+        );
+        scene.register_collider_with_parent(ColliderBuilder::ball(0.5).build(), ball);
+
+        for _ in 0..300 {
+            scene.update(1.0 / 60.0);
         }
-        /* Create the bounding ball. */
-        // let rigid_body = RigidBodyBuilder::new_dynamic()
-        //     .translation(vector![0.0, 10.0, 0.0])
-        //     .build();
+        let (position, _) = from_isometry(scene.get_rigidbody(ball).unwrap().position());
+        assert!(
+            (position.y - 1.0).abs() < 0.05,
+            "ball ended up at {position}"
+        );
+    }
+
+    #[test]
+    fn trigger_events_carry_the_entities() {
+        let mut world = legion::World::default();
+        let (sensor, ball) = (world.push((0u32,)), world.push((1u32,)));
+        let mut scene = PhysicsScene::new(60);
+        scene.set_gravity(Vec3::ZERO);
+        let trigger = scene.register_trigger(
+            ColliderBuilder::cuboid(2.0, 2.0, 2.0)
+                .sensor(true)
+                .active_events(ActiveEvents::INTERSECTION_EVENTS)
+                .build(),
+        );
+        scene.attach_entity(trigger, sensor);
+        let body = scene.register_rigidbody(RigidBodyBuilder::new_dynamic().build());
+        let collider =
+            scene.register_collider_with_parent(ColliderBuilder::ball(0.5).build(), body);
+        scene.attach_entity(collider, ball);
+
+        assert_eq!(scene.update(1.0 / 60.0), 1);
+        let events = scene.trigger_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, TriggerEventKind::Enter);
+        assert_eq!(events[0].trigger_entity, Some(sensor));
+        assert_eq!(events[0].other_entity, Some(ball));
+        // Nothing new happened, the events of the previous update are gone
+        scene.update(1.0 / 60.0);
+        assert!(scene.trigger_events().is_empty());
+    }
+
+    #[test]
+    fn isometry_round_trips() {
+        let rotation = Quat::from_rotation_y(1.0) * Quat::from_rotation_x(0.5);
+        let (position, result) = from_isometry(&to_isometry(Vec3::new(1.0, 2.0, 3.0), rotation));
+        assert_eq!(position, Vec3::new(1.0, 2.0, 3.0));
+        assert!(result.abs_diff_eq(rotation, 1e-6));
     }
 }
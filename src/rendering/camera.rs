@@ -1,7 +1,110 @@
 use crate::state::State;
 use glam::{Mat4, Quat, Vec3};
+use std::sync::Arc;
 use wgpu::{util::DeviceExt, BindGroup, Buffer};
 
+use super::texture::Texture;
+
+pub const MIN_FOV: f32 = 20.0;
+pub const MAX_FOV: f32 = 110.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic { height: f32 }, // Height of the visible area in world units
+}
+
+// Where a camera draws to. Texture targets keep their own depth buffer, are never multisampled and can be
+// sampled by materials once drawn
+#[derive(Debug)]
+pub enum RenderTarget {
+    Surface,
+    Texture {
+        color: Arc<Texture>,
+        depth: Texture,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl RenderTarget {
+    // Uses the output format, so the same pipelines can draw into it
+    pub fn texture(state: &State, width: u32, height: u32) -> Self {
+        let config = wgpu::SurfaceConfiguration {
+            width: width.max(1),
+            height: height.max(1),
+            ..state.config.clone()
+        };
+        RenderTarget::Texture {
+            color: Arc::new(Texture::create_render_texture(
+                &state.device,
+                &config,
+                "camera_color_texture",
+            )),
+            depth: Texture::create_depth_texture(&state.device, &config, 1, "camera_depth_texture"),
+            width: config.width,
+            height: config.height,
+        }
+    }
+
+    pub fn size(&self, state: &State) -> (u32, u32) {
+        match self {
+            RenderTarget::Surface => (state.size.width, state.size.height),
+            RenderTarget::Texture { width, height, .. } => (*width, *height),
+        }
+    }
+
+    pub fn color_texture(&self) -> Option<Arc<Texture>> {
+        match self {
+            RenderTarget::Surface => None,
+            RenderTarget::Texture { color, .. } => Some(Arc::clone(color)),
+        }
+    }
+}
+
+// The part of the target a camera draws to, in fractions of the target's size from the top left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub const FULL: Viewport = Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // x, y, width and height in pixels, as set_viewport expects them
+    pub fn to_pixels(&self, target_width: u32, target_height: u32) -> [f32; 4] {
+        let (target_width, target_height) = (target_width as f32, target_height as f32);
+        [
+            self.x * target_width,
+            self.y * target_height,
+            (self.width * target_width).max(1.0),
+            (self.height * target_height).max(1.0),
+        ]
+    }
+
+    pub fn aspect(&self, target_width: u32, target_height: u32) -> f32 {
+        let [_, _, width, height] = self.to_pixels(target_width, target_height);
+        width / height
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub position: Vec3,
@@ -9,11 +112,14 @@ pub struct Camera {
     pub uniform: CameraUniform,
     pub buffer: Buffer,
     pub bind_group: BindGroup,
-    pub render_layers: Vec<String>,
+    render_layers: Vec<String>, // Only added through add_render_layer
+    pub projection: Projection,
     pub aspect: f32,
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    pub target: RenderTarget,
+    pub viewport: Viewport,
 }
 
 impl Camera {
@@ -23,7 +129,23 @@ impl Camera {
     }
 
     pub fn build_projection_matrix(&self) -> Mat4 {
-        let proj = Mat4::perspective_lh(self.fovy.to_radians(), self.aspect, self.znear, self.zfar);
+        let proj = match self.projection {
+            Projection::Perspective => {
+                Mat4::perspective_lh(self.fovy.to_radians(), self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                Mat4::orthographic_lh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
         proj
     }
 
@@ -52,8 +174,6 @@ impl Camera {
             label: Some("camera_bind_group"),
         });
 
-        let render_passes = Vec::new();
-
         let aspect = state.size.width as f32 / state.size.height as f32;
         let fovy = 50.0;
         let znear = 0.01;
@@ -68,19 +188,66 @@ impl Camera {
             uniform,
             buffer,
             bind_group,
-            render_layers: render_passes,
+            render_layers: Vec::new(),
+            projection: Projection::Perspective,
             aspect,
             fovy,
             znear,
             zfar,
+            target: RenderTarget::Surface,
+            viewport: Viewport::FULL,
         };
         cam.update_uniform();
         cam
     }
 
-    pub fn add_render_layer(&mut self, layer_name: String) {
+    // Draws into a new texture of the given size, the aspect ratio follows the texture instead of the window
+    pub fn render_to_texture(&mut self, state: &State, width: u32, height: u32) -> Arc<Texture> {
+        self.target = RenderTarget::texture(state, width, height);
+        self.fit_to_target(state);
+        self.update_uniform();
+        self.target.color_texture().unwrap()
+    }
+
+    // Restricts drawing to part of the target, e.g. one half of the window for split-screen
+    pub fn set_viewport(&mut self, state: &State, viewport: Viewport) {
+        self.viewport = viewport;
+        self.fit_to_target(state);
+        self.update_uniform();
+    }
+
+    // Matches the aspect ratio to the viewport's size on the target, which changes with the window for cameras
+    // drawing to it. Returns whether it changed, the uniform has to be updated then
+    pub fn fit_to_target(&mut self, state: &State) -> bool {
+        let (width, height) = self.target.size(state);
+        let aspect = self.viewport.aspect(width, height);
+        if aspect == self.aspect {
+            return false;
+        }
+        self.aspect = aspect;
+        true
+    }
+
+    // Vertical field of view in degrees, clamped to a sane range. Call update_uniform to apply it
+    pub fn set_fov(&mut self, fovy: f32) {
+        self.fovy = fovy.clamp(MIN_FOV, MAX_FOV);
+    }
+
+    // Layers are only drawn once. They're looked up by name in State::render_layers every frame, so layers that
+    // don't exist yet are drawn once they're created, with a warning in case the name is a typo
+    pub fn add_render_layer(&mut self, state: &State, layer_name: String) {
+        if self.render_layers.contains(&layer_name) {
+            return;
+        }
+        if state.render_layers.get_layer_by_name(&layer_name).is_none() {
+            println!("[WARN] The render layer {layer_name} doesn't exist yet, it isn't drawn until it's created");
+        }
         self.render_layers.push(layer_name);
     }
+
+    pub fn render_layers(&self) -> &[String] {
+        &self.render_layers
+    }
 }
 
 // We need this for Rust to store our data correctly for the shaders
@@ -100,3 +267,55 @@ impl CameraUniform {
         }
     }
 }
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+
+    #[test]
+    fn viewports_scale_to_the_target() {
+        let right_half = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        assert_eq!(right_half.to_pixels(800, 600), [400.0, 0.0, 400.0, 600.0]);
+        assert_eq!(right_half.aspect(800, 600), 400.0 / 600.0);
+        assert_eq!(Viewport::FULL.aspect(450, 1600), 450.0 / 1600.0);
+        assert_eq!(Viewport::FULL.to_pixels(0, 0), [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn cameras_fit_their_viewport_on_the_target() {
+        let state = match crate::state::headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut camera = Camera::new(&state);
+        camera.render_to_texture(&state, 400, 100);
+        assert_eq!(camera.aspect, 4.0);
+        // Nothing changed since, so the uniform doesn't need an update
+        assert!(!camera.fit_to_target(&state));
+
+        camera.viewport = Viewport::new(0.0, 0.0, 0.5, 1.0);
+        assert!(camera.fit_to_target(&state));
+        assert_eq!(camera.aspect, 2.0);
+    }
+
+    #[test]
+    fn render_layers_are_only_added_once() {
+        let state = match crate::state::headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        state.render_layers.create_layer("Default".to_string(), 0);
+        let mut camera = Camera::new(&state);
+        camera.add_render_layer(&state, "Default".to_string());
+        camera.add_render_layer(&state, "Default".to_string());
+        // Unknown layers are kept, they're drawn once they exist
+        camera.add_render_layer(&state, "Later".to_string());
+        assert_eq!(camera.render_layers(), ["Default", "Later"]);
+    }
+}
@@ -0,0 +1,134 @@
+use wgpu::util::DeviceExt;
+
+// Size of the crosshair in pixels, it stays the same size whatever the resolution
+const ARM_LENGTH: f32 = 10.0;
+const THICKNESS: f32 = 2.0;
+
+// A plus in the middle of the screen, drawn in its own pass without depth after every camera
+pub struct CrosshairRenderer {
+    pipeline: wgpu::RenderPipeline,
+    pub visible: bool,
+}
+
+impl CrosshairRenderer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Crosshair Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/crosshair.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Crosshair Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Crosshair Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &[wgpu::VertexAttribute {
+                        offset: 0,
+                        shader_location: 0,
+                        format: wgpu::VertexFormat::Float32x2,
+                    }],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            visible: true,
+        }
+    }
+
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        screen_size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        if !self.visible || screen_size.width == 0 || screen_size.height == 0 {
+            return;
+        }
+        let vertices = crosshair_vertices(screen_size.width, screen_size.height);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Crosshair Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Crosshair Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+}
+
+// Two bars crossing in the centre, in normalized device coordinates
+fn crosshair_vertices(width: u32, height: u32) -> Vec<[f32; 2]> {
+    let pixel = [2.0 / width as f32, 2.0 / height as f32];
+    let mut vertices = Vec::with_capacity(12);
+    for (half_width, half_height) in [(ARM_LENGTH, THICKNESS / 2.0), (THICKNESS / 2.0, ARM_LENGTH)]
+    {
+        let (x, y) = (half_width * pixel[0], half_height * pixel[1]);
+        vertices.extend_from_slice(&[[-x, -y], [x, -y], [-x, y], [x, -y], [x, y], [-x, y]]);
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod crosshair_tests {
+    use super::*;
+
+    #[test]
+    fn crosshair_keeps_its_pixel_size() {
+        let vertices = crosshair_vertices(800, 400);
+        assert_eq!(vertices.len(), 12);
+        // The horizontal bar is 20 pixels wide and 2 high
+        let max_x = vertices[..6].iter().map(|v| v[0]).fold(0.0, f32::max);
+        let max_y = vertices[..6].iter().map(|v| v[1]).fold(0.0, f32::max);
+        assert_eq!(max_x * 800.0 / 2.0, ARM_LENGTH);
+        assert_eq!(max_y * 400.0 / 2.0, THICKNESS / 2.0);
+    }
+}
@@ -0,0 +1,185 @@
+use glam::Vec3;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer};
+
+use super::{
+    render_settings::{Fog, GraphicsSettings, SKY_COLOR},
+    shadow::{light_view_projection, ShadowMap},
+};
+
+// The global light every material is shaded with, bound to group 2 next to the camera together with its shadow map
+// and the fog
+pub struct Light {
+    pub uniform: LightUniform,
+    pub buffer: Buffer,
+    pub fog: FogUniform,
+    pub fog_buffer: Buffer,
+    pub bind_group: BindGroup,
+    pub shadow_map: ShadowMap,
+}
+
+impl Light {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &BindGroupLayout,
+        settings: &GraphicsSettings,
+    ) -> Self {
+        let uniform = LightUniform::new(Vec3::new(-0.5, 0.6, -0.3), Vec3::ONE, 0.3);
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let fog = FogUniform::new(None);
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[fog]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shadow_map = ShadowMap::new(device, &buffer, settings);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: fog_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_bind_group"),
+        });
+
+        Self {
+            uniform,
+            buffer,
+            fog,
+            fog_buffer,
+            bind_group,
+            shadow_map,
+        }
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_bind_group_layout"),
+        })
+    }
+}
+
+// Laid out like the WGSL struct, a vec3 followed by a scalar packs into 16 bytes
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct LightUniform {
+    direction: [f32; 3], // Points towards the light
+    ambient: f32,
+    color: [f32; 3],
+    _padding: f32,
+    view_projection: [[f32; 4]; 4], // Of the shadow map
+}
+
+impl LightUniform {
+    // The shadow map covers the area around the origin until it's given a focus
+    pub fn new(direction: Vec3, color: Vec3, ambient: f32) -> Self {
+        let direction = direction.normalize_or_zero();
+        Self {
+            direction: direction.to_array(),
+            ambient,
+            color: color.to_array(),
+            _padding: 0.0,
+            view_projection: light_view_projection(direction, Vec3::ZERO).to_cols_array_2d(),
+        }
+    }
+
+    // Moves the shadow map along with the player, shadows are only drawn close to the focus
+    pub fn set_shadow_focus(&mut self, focus: Vec3) {
+        self.view_projection =
+            light_view_projection(Vec3::from(self.direction), focus).to_cols_array_2d();
+    }
+}
+
+// Laid out like the WGSL struct, which is padded to a multiple of 16 bytes
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct FogUniform {
+    color: [f32; 3],
+    start: f32,
+    end: f32,
+    _padding: [f32; 3],
+}
+
+impl FogUniform {
+    // Without fog nothing is ever far enough away to be fogged
+    pub fn new(fog: Option<Fog>) -> Self {
+        let (start, end) = fog.map_or((f32::MAX / 2.0, f32::MAX), |fog| (fog.start, fog.end));
+        Self {
+            color: SKY_COLOR,
+            start,
+            end,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[cfg(test)]
+mod light_tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_wgsl_layout() {
+        assert_eq!(std::mem::size_of::<LightUniform>(), 96);
+        let uniform = LightUniform::new(Vec3::new(0.0, 2.0, 0.0), Vec3::ONE, 0.25);
+        assert_eq!(uniform.direction, [0.0, 1.0, 0.0]);
+        assert_eq!(std::mem::size_of::<FogUniform>(), 32);
+    }
+}
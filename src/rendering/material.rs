@@ -1,25 +1,40 @@
+use parking_lot::RwLock;
 use std::{fmt::Debug, sync::Arc};
 use wgpu::{BindGroup, BindGroupLayout, PrimitiveTopology, RenderPipeline, ShaderModule};
 
-use crate::{next_id, state::State};
+use crate::{next_id, state::State, voxels::voxel_registry};
 
 use super::{
-    texture::{self, Texture},
-    vertex::Vertex,
+    pipeline_cache::{BlendMode, PipelineKey},
+    render_settings::{get_debug_mode, RenderDebugMode},
+    texture::{self, SamplerConfig, Texture},
+    texture_cache::TextureCache,
+    vertex::{TransformInstance, Vertex, VertexFormat},
 };
 
 pub trait Material: Debug + Sync + Send {
-    fn get_pipeline(&self, state: &State) -> Arc<RenderPipeline>;
+    // Texture targets are never multisampled, so the sample count depends on what is being drawn to
+    fn get_pipeline(&self, state: &State, sample_count: u32) -> Arc<RenderPipeline>;
     fn get_texture_bind_group(&self, state: &State) -> Arc<BindGroup>;
     fn get_texture_bind_group_layout(&self, state: &State) -> Arc<BindGroupLayout>;
     fn get_shader(&self, state: &State) -> Arc<ShaderModule>;
     fn get_id(&self) -> u64;
+    // Whether its passes are drawn into the sun's shadow map
+    fn casts_shadows(&self) -> bool {
+        true
+    }
+    // Meshes drawn with the material are converted to this layout when they're added to its passes
+    fn vertex_format(&self) -> VertexFormat {
+        VertexFormat::Standard
+    }
 }
 
 // Structs for the various kinds of materials
 #[derive(Debug)]
 pub struct MaterialDiffuseTexture {
     pub diffuse_texture: Arc<Texture>,
+    pipeline: RwLock<Option<(PipelineKey, Arc<RenderPipeline>)>>, // Rebuilt when the output format changes
+    bind_group: RwLock<Option<Arc<BindGroup>>>,
     id: u64,
 }
 
@@ -27,42 +42,352 @@ impl MaterialDiffuseTexture {
     pub fn new(state: &State, diffuse_texture: Arc<Texture>) -> MaterialDiffuseTexture {
         MaterialDiffuseTexture {
             diffuse_texture,
+            pipeline: RwLock::new(None),
+            bind_group: RwLock::new(None),
             id: next_id(),
         }
     }
 }
 
-// TODO: Cache the pipeline
 impl Material for MaterialDiffuseTexture {
-    fn get_pipeline(&self, state: &State) -> Arc<RenderPipeline> {
-        Arc::new(create_pipeline(
+    fn get_pipeline(&self, state: &State, sample_count: u32) -> Arc<RenderPipeline> {
+        get_cached_pipeline(
             state,
-            self.get_texture_bind_group_layout(state),
-            self.get_shader(state),
-        ))
+            &self.pipeline,
+            "shader.wgsl",
+            VertexFormat::Standard,
+            sample_count,
+            BlendMode::Opaque,
+            |debug_mode| {
+                create_pipeline(
+                    state,
+                    self.get_texture_bind_group_layout(state),
+                    self.get_shader(state),
+                    VertexFormat::Standard,
+                    sample_count,
+                    debug_mode,
+                    BlendMode::Opaque,
+                )
+            },
+        )
     }
 
-    // TODO: Cache this too!
     fn get_texture_bind_group(&self, state: &State) -> Arc<BindGroup> {
-        Arc::new(state.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.get_texture_bind_group_layout(state),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        }))
+        get_cached_bind_group(&self.bind_group, || {
+            create_texture_bind_group(
+                state,
+                &self.get_texture_bind_group_layout(state),
+                &self.diffuse_texture,
+            )
+        })
     }
 
-    // TODO: And this!
     fn get_texture_bind_group_layout(&self, state: &State) -> Arc<BindGroupLayout> {
-        Arc::new(
+        get_texture_bind_group_layout(state, wgpu::TextureViewDimension::D2)
+    }
+
+    fn get_shader(&self, state: &State) -> Arc<ShaderModule> {
+        state
+            .pipeline_cache
+            .get_or_create_shader("shader.wgsl", || {
+                state
+                    .device
+                    .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                        label: Some("Shader"),
+                        source: wgpu::ShaderSource::Wgsl(
+                            include_str!("../shaders/shader.wgsl").into(),
+                        ),
+                    })
+            })
+    }
+
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+}
+
+// Textures voxel faces with the texture array built from the voxel profiles, vertices pick their layer
+#[derive(Debug)]
+pub struct MaterialVoxelAtlas {
+    texture_array: RwLock<(u64, Arc<Texture>)>, // Registry version it was built from
+    pipeline: RwLock<Option<(PipelineKey, Arc<RenderPipeline>)>>,
+    bind_group: RwLock<Option<Arc<BindGroup>>>,
+    blend: BlendMode,
+    id: u64,
+}
+
+impl MaterialVoxelAtlas {
+    pub fn new(state: &State) -> anyhow::Result<MaterialVoxelAtlas> {
+        Self::with_blend(state, BlendMode::Opaque)
+    }
+
+    // For the transparent chunk meshes, blends with what was drawn before it and doesn't write depth.
+    // Belongs on a layer drawn after the opaque ones
+    pub fn new_transparent(state: &State) -> anyhow::Result<MaterialVoxelAtlas> {
+        Self::with_blend(state, BlendMode::Transparent)
+    }
+
+    // For foliage, the see-through parts of the texture are cut out instead of blended
+    pub fn new_cutout(state: &State) -> anyhow::Result<MaterialVoxelAtlas> {
+        Self::with_blend(state, BlendMode::Cutout)
+    }
+
+    fn with_blend(state: &State, blend: BlendMode) -> anyhow::Result<MaterialVoxelAtlas> {
+        let version = voxel_registry::registry_version();
+        let texture_array = Self::build_texture_array(state)?;
+        Ok(MaterialVoxelAtlas {
+            texture_array: RwLock::new((version, Arc::new(texture_array))),
+            pipeline: RwLock::new(None),
+            bind_group: RwLock::new(None),
+            blend,
+            id: next_id(),
+        })
+    }
+
+    fn build_texture_array(state: &State) -> anyhow::Result<Texture> {
+        // Greedy meshing stretches faces over many voxels and relies on the texture repeating
+        let sampler_config = state.sampler_config(SamplerConfig {
+            address_mode: wgpu::AddressMode::Repeat,
+            ..Default::default()
+        });
+        Texture::from_layers_with_sampler(
+            &state.device,
+            &state.queue,
+            &voxel_registry::load_voxel_texture_layers(),
+            Some("voxel_textures"),
+            &sampler_config,
+        )
+    }
+
+    // Rebuilds the texture array once the voxel profiles were reloaded. A failed rebuild keeps the old textures
+    fn refresh_texture_array(&self, state: &State) {
+        let version = voxel_registry::registry_version();
+        if self.texture_array.read().0 == version {
+            return;
+        }
+        match Self::build_texture_array(state) {
+            Ok(texture_array) => *self.texture_array.write() = (version, Arc::new(texture_array)),
+            Err(e) => {
+                println!("[INFO] Failed to rebuild the voxel textures: {e}");
+                self.texture_array.write().0 = version;
+            }
+        }
+        *self.bind_group.write() = None;
+    }
+}
+
+impl Material for MaterialVoxelAtlas {
+    fn get_pipeline(&self, state: &State, sample_count: u32) -> Arc<RenderPipeline> {
+        get_cached_pipeline(
+            state,
+            &self.pipeline,
+            "voxel.wgsl",
+            self.vertex_format(),
+            sample_count,
+            self.blend,
+            |debug_mode| {
+                create_pipeline(
+                    state,
+                    self.get_texture_bind_group_layout(state),
+                    self.get_shader(state),
+                    self.vertex_format(),
+                    sample_count,
+                    debug_mode,
+                    self.blend,
+                )
+            },
+        )
+    }
+
+    fn get_texture_bind_group(&self, state: &State) -> Arc<BindGroup> {
+        self.refresh_texture_array(state);
+        get_cached_bind_group(&self.bind_group, || {
+            create_texture_bind_group(
+                state,
+                &self.get_texture_bind_group_layout(state),
+                &self.texture_array.read().1,
+            )
+        })
+    }
+
+    fn get_texture_bind_group_layout(&self, state: &State) -> Arc<BindGroupLayout> {
+        get_texture_bind_group_layout(state, wgpu::TextureViewDimension::D2Array)
+    }
+
+    fn get_shader(&self, state: &State) -> Arc<ShaderModule> {
+        state.pipeline_cache.get_or_create_shader("voxel.wgsl", || {
+            state
+                .device
+                .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                    label: Some("Voxel Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        concat!(
+                            include_str!("../shaders/packed_vertex.wgsl"),
+                            include_str!("../shaders/voxel.wgsl")
+                        )
+                        .into(),
+                    ),
+                })
+        })
+    }
+
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    // Light goes through water and glass
+    fn casts_shadows(&self) -> bool {
+        self.blend != BlendMode::Transparent
+    }
+
+    fn vertex_format(&self) -> VertexFormat {
+        VertexFormat::Packed
+    }
+}
+
+// Unlit vertex colours drawn on top of the geometry they outline, like the highlight around the targeted voxel.
+// Belongs on a layer drawn after everything it's supposed to show up on
+#[derive(Debug)]
+pub struct MaterialOverlay {
+    pipeline: RwLock<Option<(PipelineKey, Arc<RenderPipeline>)>>,
+    bind_group: RwLock<Option<Arc<BindGroup>>>,
+    id: u64,
+}
+
+impl MaterialOverlay {
+    pub fn new() -> MaterialOverlay {
+        MaterialOverlay {
+            pipeline: RwLock::new(None),
+            bind_group: RwLock::new(None),
+            id: next_id(),
+        }
+    }
+}
+
+impl Default for MaterialOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Material for MaterialOverlay {
+    fn get_pipeline(&self, state: &State, sample_count: u32) -> Arc<RenderPipeline> {
+        // The debug views are about the scene, overlays look the same in all of them
+        get_cached_pipeline(
+            state,
+            &self.pipeline,
+            "overlay.wgsl",
+            VertexFormat::Standard,
+            sample_count,
+            BlendMode::Transparent,
+            |_| {
+                create_overlay_pipeline(
+                    state,
+                    self.get_texture_bind_group_layout(state),
+                    self.get_shader(state),
+                    sample_count,
+                )
+            },
+        )
+    }
+
+    // Nothing is sampled, the texture only fills the slot every material pipeline has
+    fn get_texture_bind_group(&self, state: &State) -> Arc<BindGroup> {
+        get_cached_bind_group(&self.bind_group, || {
+            create_texture_bind_group(
+                state,
+                &self.get_texture_bind_group_layout(state),
+                &TextureCache::fallback(state),
+            )
+        })
+    }
+
+    fn get_texture_bind_group_layout(&self, state: &State) -> Arc<BindGroupLayout> {
+        get_texture_bind_group_layout(state, wgpu::TextureViewDimension::D2)
+    }
+
+    fn get_shader(&self, state: &State) -> Arc<ShaderModule> {
+        state
+            .pipeline_cache
+            .get_or_create_shader("overlay.wgsl", || {
+                state
+                    .device
+                    .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                        label: Some("Overlay Shader"),
+                        source: wgpu::ShaderSource::Wgsl(
+                            include_str!("../shaders/overlay.wgsl").into(),
+                        ),
+                    })
+            })
+    }
+
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    fn casts_shadows(&self) -> bool {
+        false
+    }
+}
+
+// Returns the material's pipeline, it's looked up again when the output format, sample count or debug mode changed
+fn get_cached_pipeline(
+    state: &State,
+    cached: &RwLock<Option<(PipelineKey, Arc<RenderPipeline>)>>,
+    shader: &'static str,
+    vertex_format: VertexFormat,
+    sample_count: u32,
+    blend: BlendMode,
+    create: impl FnOnce(RenderDebugMode) -> RenderPipeline,
+) -> Arc<RenderPipeline> {
+    let debug_mode = match get_debug_mode() {
+        RenderDebugMode::Wireframe if !state.supports_wireframe => RenderDebugMode::Normal,
+        mode => mode,
+    };
+    let key = PipelineKey {
+        shader,
+        vertex_layout: vertex_format.layout_name(),
+        format: state.config.format,
+        sample_count,
+        debug_mode,
+        blend,
+    };
+    if let Some((cached_key, pipeline)) = &*cached.read() {
+        if *cached_key == key {
+            return Arc::clone(pipeline);
+        }
+    }
+
+    let pipeline = state
+        .pipeline_cache
+        .get_or_create_pipeline(key, || create(debug_mode));
+    *cached.write() = Some((key, Arc::clone(&pipeline)));
+    pipeline
+}
+
+fn get_cached_bind_group(
+    cached: &RwLock<Option<Arc<BindGroup>>>,
+    create: impl FnOnce() -> BindGroup,
+) -> Arc<BindGroup> {
+    if let Some(bind_group) = &*cached.read() {
+        return Arc::clone(bind_group);
+    }
+    let bind_group = Arc::new(create());
+    *cached.write() = Some(Arc::clone(&bind_group));
+    bind_group
+}
+
+fn get_texture_bind_group_layout(
+    state: &State,
+    view_dimension: wgpu::TextureViewDimension,
+) -> Arc<BindGroupLayout> {
+    let name = match view_dimension {
+        wgpu::TextureViewDimension::D2Array => "texture_array",
+        _ => "diffuse_texture",
+    };
+    state
+        .pipeline_cache
+        .get_or_create_bind_group_layout(name, || {
             state
                 .device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -72,7 +397,7 @@ impl Material for MaterialDiffuseTexture {
                             visibility: wgpu::ShaderStages::FRAGMENT,
                             ty: wgpu::BindingType::Texture {
                                 multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
+                                view_dimension,
                                 sample_type: wgpu::TextureSampleType::Float { filterable: true },
                             },
                             count: None,
@@ -85,41 +410,79 @@ impl Material for MaterialDiffuseTexture {
                         },
                     ],
                     label: Some("texture_bind_group_layout"),
-                }),
-        )
-    }
+                })
+        })
+}
 
-    // TODO: And also finally this!
-    fn get_shader(&self, state: &State) -> Arc<ShaderModule> {
-        Arc::new(
-            state
-                .device
-                .create_shader_module(&wgpu::ShaderModuleDescriptor {
-                    label: Some("Shader"),
-                    source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
-                }),
-        )
-    }
+fn create_texture_bind_group(
+    state: &State,
+    layout: &BindGroupLayout,
+    texture: &Texture,
+) -> BindGroup {
+    state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
 
-    fn get_id(&self) -> u64 {
-        self.id
-    }
+fn get_debug_shader(state: &State) -> Arc<ShaderModule> {
+    state.pipeline_cache.get_or_create_shader("debug.wgsl", || {
+        state
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Debug Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("../shaders/packed_vertex.wgsl"),
+                        include_str!("../shaders/debug.wgsl")
+                    )
+                    .into(),
+                ),
+            })
+    })
 }
 
-// Create a render pipeline
+// Create a render pipeline. The debug views swap in their own shader but keep the material's layout and vertex format.
+// Transparent pipelines still test against the depth of opaque geometry but leave it untouched
 pub fn create_pipeline(
     state: &State,
     texture_bind_group_layout: Arc<BindGroupLayout>,
     shader: Arc<ShaderModule>,
+    vertex_format: VertexFormat,
+    sample_count: u32,
+    debug_mode: RenderDebugMode,
+    blend: BlendMode,
 ) -> RenderPipeline {
-    let render_pipeline_layout =
-        state
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &state.camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+    let (shader, fragment_entry_point) = match debug_mode {
+        RenderDebugMode::Normal | RenderDebugMode::Wireframe if blend == BlendMode::Cutout => {
+            (shader, "fs_cutout")
+        }
+        RenderDebugMode::Normal | RenderDebugMode::Wireframe => (shader, "fs_main"),
+        RenderDebugMode::Normals => (get_debug_shader(state), "fs_normals"),
+        RenderDebugMode::Depth => (get_debug_shader(state), "fs_depth"),
+    };
+    let polygon_mode = match debug_mode {
+        RenderDebugMode::Wireframe => wgpu::PolygonMode::Line,
+        _ => wgpu::PolygonMode::Fill,
+    };
+    let transparent = blend == BlendMode::Transparent;
+    let blend = if transparent {
+        wgpu::BlendState::ALPHA_BLENDING
+    } else {
+        wgpu::BlendState::REPLACE
+    };
+
+    let render_pipeline_layout = create_pipeline_layout(state, &texture_bind_group_layout);
 
     state
         .device
@@ -128,15 +491,15 @@ pub fn create_pipeline(
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
+                entry_point: vertex_format.entry_point(),
+                buffers: &[vertex_format.desc(), TransformInstance::desc()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
+                entry_point: fragment_entry_point,
                 targets: &[wgpu::ColorTargetState {
                     format: state.config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 }],
             }),
@@ -145,22 +508,138 @@ pub fn create_pipeline(
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw, // <- Polygons are wound counter-clockwise
                 cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
+                polygon_mode,
                 unclipped_depth: false,
                 conservative: false,
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled: !transparent,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
         })
 }
+
+// Overlays are blended over everything and pulled towards the camera by the depth bias, so they win against the
+// faces they lie on without showing through whatever is in front of them. Both sides are drawn
+fn create_overlay_pipeline(
+    state: &State,
+    texture_bind_group_layout: Arc<BindGroupLayout>,
+    shader: Arc<ShaderModule>,
+    sample_count: u32,
+) -> RenderPipeline {
+    let render_pipeline_layout = create_pipeline_layout(state, &texture_bind_group_layout);
+    state
+        .device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), TransformInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: state.config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: -4,
+                    slope_scale: -1.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+}
+
+// Every material pipeline binds its textures, the camera and the light in the same slots
+fn create_pipeline_layout(
+    state: &State,
+    texture_bind_group_layout: &BindGroupLayout,
+) -> wgpu::PipelineLayout {
+    state
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[
+                texture_bind_group_layout,
+                &state.camera_bind_group_layout,
+                &state.light_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        })
+}
+
+#[cfg(test)]
+mod material_tests {
+    use super::*;
+    use crate::state::headless_state;
+
+    #[test]
+    fn pipelines_are_cached_and_shared() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let texture = Arc::new(
+            Texture::from_bytes(
+                &state.device,
+                &state.queue,
+                include_bytes!("../textures/lapis_block.png"),
+                "lapis",
+            )
+            .unwrap(),
+        );
+        let first = MaterialDiffuseTexture::new(&state, Arc::clone(&texture));
+        let second = MaterialDiffuseTexture::new(&state, texture);
+
+        let pipeline = first.get_pipeline(&state, 1);
+        assert!(Arc::ptr_eq(&pipeline, &first.get_pipeline(&state, 1)));
+        assert!(Arc::ptr_eq(&pipeline, &second.get_pipeline(&state, 1)));
+        assert!(Arc::ptr_eq(
+            &first.get_texture_bind_group(&state),
+            &first.get_texture_bind_group(&state)
+        ));
+        assert_eq!(state.pipeline_cache.pipeline_count(), 1);
+
+        // Multisampled pipelines are separate and rebuilt after the cache was cleared
+        let multisampled = first.get_pipeline(&state, 4);
+        assert!(!Arc::ptr_eq(&pipeline, &multisampled));
+        assert_eq!(state.pipeline_cache.pipeline_count(), 2);
+        state.pipeline_cache.clear_pipelines();
+        assert!(!Arc::ptr_eq(&multisampled, &second.get_pipeline(&state, 4)));
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use parking_lot::RwLock;
+
+use super::material::Material;
+
+// A material voxel profiles can refer to by name, with the layer its meshes are drawn on
+#[derive(Debug, Clone)]
+pub struct RegisteredMaterial {
+    pub material: Arc<RwLock<dyn Material>>,
+    pub layer: String,
+}
+
+lazy_static! {
+    // Filled in by the engine at startup, see Engine::new
+    static ref MATERIALS: DashMap<String, RegisteredMaterial> = DashMap::default();
+}
+
+// Replaces whatever was registered under the name before
+pub fn register_material(name: &str, material: Arc<RwLock<dyn Material>>, layer: &str) {
+    MATERIALS.insert(
+        name.to_string(),
+        RegisteredMaterial {
+            material,
+            layer: layer.to_string(),
+        },
+    );
+}
+
+pub fn get_material(name: &str) -> Option<RegisteredMaterial> {
+    MATERIALS.get(name).map(|entry| entry.value().clone())
+}
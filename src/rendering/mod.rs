@@ -1,5 +1,14 @@
 pub mod camera;
+pub mod crosshair;
+pub mod light;
 pub mod material;
+pub mod material_registry;
+pub mod pipeline_cache;
 pub mod render_pass_data;
+pub mod render_settings;
+pub mod screenshot;
+pub mod shadow;
+pub mod text;
 pub mod texture;
+pub mod texture_cache;
 pub mod vertex;
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use wgpu::{BindGroupLayout, RenderPipeline, ShaderModule, TextureFormat};
+
+use super::render_settings::RenderDebugMode;
+
+// How a pipeline's output is combined with what's already in the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    Cutout, // Opaque, but fragments below half alpha are discarded, e.g. the holes in leaves
+    Transparent, // Alpha blended without writing depth
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shader: &'static str,
+    pub vertex_layout: &'static str,
+    pub format: TextureFormat,
+    pub sample_count: u32,
+    pub debug_mode: RenderDebugMode, // Every debug view is its own variant, so switching between them is instant
+    pub blend: BlendMode,
+}
+
+// Shaders, layouts and pipelines shared between materials. Everything in here belongs to a single device,
+// so every State owns its own cache
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: DashMap<&'static str, Arc<ShaderModule>>,
+    bind_group_layouts: DashMap<&'static str, Arc<BindGroupLayout>>,
+    pipelines: DashMap<PipelineKey, Arc<RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn get_or_create_shader(
+        &self,
+        name: &'static str,
+        create: impl FnOnce() -> ShaderModule,
+    ) -> Arc<ShaderModule> {
+        Arc::clone(
+            self.shaders
+                .entry(name)
+                .or_insert_with(|| Arc::new(create()))
+                .value(),
+        )
+    }
+
+    pub fn get_or_create_bind_group_layout(
+        &self,
+        name: &'static str,
+        create: impl FnOnce() -> BindGroupLayout,
+    ) -> Arc<BindGroupLayout> {
+        Arc::clone(
+            self.bind_group_layouts
+                .entry(name)
+                .or_insert_with(|| Arc::new(create()))
+                .value(),
+        )
+    }
+
+    pub fn get_or_create_pipeline(
+        &self,
+        key: PipelineKey,
+        create: impl FnOnce() -> RenderPipeline,
+    ) -> Arc<RenderPipeline> {
+        Arc::clone(
+            self.pipelines
+                .entry(key)
+                .or_insert_with(|| Arc::new(create()))
+                .value(),
+        )
+    }
+
+    // Pipelines are rebuilt on their next use, e.g. after the graphics settings changed
+    pub fn clear_pipelines(&self) {
+        self.pipelines.clear();
+    }
+
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+}
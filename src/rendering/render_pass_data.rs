@@ -1,41 +1,66 @@
-use std::sync::Arc;
+use std::{collections::HashMap, ops::Range, sync::Arc};
 
 use crate::{asset_types::mesh::Mesh, state::State};
 
 use wgpu::{BufferDescriptor, BufferUsages};
 
-use super::material::Material;
-use glam::Mat4;
+use super::{
+    material::Material,
+    vertex::{PackedVertex, TransformInstance, Vertex, VertexFormat},
+};
+use glam::{Mat4, Vec3};
 use parking_lot::RwLock;
 
 // Render layers are a convenient way to filter what a camera renders
-// They also make for a convenient location to store render passes
+// They also make for a convenient location to store render passes. Each State owns its own, see State::render_layers
 pub mod render_layers {
-    use super::{create_render_pass, RenderPassData};
+    use super::{create_render_pass, PassSorting, RenderPassData};
     use crate::{rendering::material::Material, state::State};
     use dashmap::DashMap;
+    use glam::Vec3;
     use parking_lot::RwLock;
     use std::{collections::HashMap, sync::Arc};
 
-    lazy_static! {
-        pub static ref RENDER_LAYERS: DashMap<String, Arc<RwLock<RenderLayer>>> =
-            DashMap::default();
-    }
-
     #[derive(Debug)]
     pub struct RenderLayer {
         pub name: String,
+        pub order: i32, // Layers are drawn from lowest to highest order
+        pub sorting: PassSorting,
         pub passes: HashMap<u64, Arc<RwLock<RenderPassData<dyn Material>>>>,
     }
 
     impl RenderLayer {
-        pub fn new(name: String) -> Self {
+        pub fn new(name: String, order: i32) -> Self {
             Self {
                 name,
+                order,
+                sorting: PassSorting::Material,
                 passes: HashMap::new(),
             }
         }
 
+        // The passes in the order they're drawn in. Sorted back to front, a pass goes by its furthest mesh
+        pub fn sorted_passes(
+            &self,
+            camera_position: Vec3,
+        ) -> Vec<Arc<RwLock<RenderPassData<dyn Material>>>> {
+            let mut passes: Vec<(f32, u64, Arc<RwLock<RenderPassData<dyn Material>>>)> = self
+                .passes
+                .iter()
+                .map(|(id, pass)| {
+                    let distance = match self.sorting {
+                        PassSorting::Material => 0.0,
+                        PassSorting::BackToFront => {
+                            pass.read().buffer.furthest_distance(camera_position)
+                        }
+                    };
+                    (distance, *id, Arc::clone(pass))
+                })
+                .collect();
+            passes.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            passes.into_iter().map(|(_, _, pass)| pass).collect()
+        }
+
         pub fn add_pass(&mut self, pass: Arc<RwLock<RenderPassData<dyn Material>>>) {
             self.passes.insert(pass.read().id, Arc::clone(&pass));
         }
@@ -66,89 +91,490 @@ pub mod render_layers {
         }
     }
 
-    pub fn get_layer_by_name(name: String) -> Option<Arc<RwLock<RenderLayer>>> {
-        RENDER_LAYERS
-            .get(&name)
-            .map(|layer| Arc::clone(layer.value()))
+    // The layers of one State, along with the passes and GPU buffers they hold. Dropped with the State, so nothing
+    // drawn by one engine outlives it or shows up in another
+    #[derive(Default)]
+    pub struct RenderLayers {
+        layers: DashMap<String, Arc<RwLock<RenderLayer>>>,
     }
 
-    pub fn create_layer(name: String) {
-        RENDER_LAYERS.insert(name.clone(), Arc::new(RwLock::new(RenderLayer::new(name))));
+    impl RenderLayers {
+        pub fn get_layer_by_name(&self, name: &str) -> Option<Arc<RwLock<RenderLayer>>> {
+            self.layers.get(name).map(|layer| Arc::clone(layer.value()))
+        }
+
+        pub fn create_layer(&self, name: String, order: i32) {
+            self.create_sorted_layer(name, order, PassSorting::Material);
+        }
+
+        // Replaces the layer with the same name, passes drawn on it are dropped
+        pub fn create_sorted_layer(&self, name: String, order: i32, sorting: PassSorting) {
+            let mut layer = RenderLayer::new(name.clone(), order);
+            layer.sorting = sorting;
+            self.layers.insert(name, Arc::new(RwLock::new(layer)));
+        }
+
+        // Cameras keep the name and draw the layer again once it's created anew
+        pub fn remove_layer(&self, name: &str) -> Option<Arc<RwLock<RenderLayer>>> {
+            self.layers.remove(name).map(|(_, layer)| layer)
+        }
+
+        pub fn clear(&self) {
+            self.layers.clear();
+        }
+
+        // Every layer, in no particular order
+        pub fn all(&self) -> Vec<Arc<RwLock<RenderLayer>>> {
+            self.layers
+                .iter()
+                .map(|layer| Arc::clone(layer.value()))
+                .collect()
+        }
+
+        // Resolves the named layers and sorts them into draw order. Unknown names are skipped,
+        // layers with the same order are drawn by name so the result is always deterministic
+        pub fn get_sorted_layers(&self, names: &[String]) -> Vec<Arc<RwLock<RenderLayer>>> {
+            let mut layers: Vec<(i32, String, Arc<RwLock<RenderLayer>>)> = names
+                .iter()
+                .filter_map(|name| self.get_layer_by_name(name))
+                .map(|layer| {
+                    let (order, name) = {
+                        let layer_lock = layer.read();
+                        (layer_lock.order, layer_lock.name.clone())
+                    };
+                    (order, name, layer)
+                })
+                .collect();
+            layers.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+            layers.dedup_by(|a, b| a.1 == b.1);
+            layers.into_iter().map(|(_, _, layer)| layer).collect()
+        }
     }
 }
 
-#[derive(Debug)]
+// How the passes of a layer and the meshes in each pass are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassSorting {
+    Material,    // By material id, the same pipelines come up in the same order every frame
+    BackToFront, // Furthest from the camera first, blended geometry needs what's behind it drawn already
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct MeshBufferEntry {
     pub vertex_start: usize,
     pub vertex_length: usize,
+    pub vertex_capacity: usize,
     pub index_start: usize,
     pub index_length: usize,
+    pub index_capacity: usize,
 }
 
+// Vertices are stored in mesh space and already in the buffer's format, indices are local to the mesh
+#[derive(Debug)]
+struct MeshBufferData {
+    vertices: MeshVertices,
+    indices: Vec<u32>,
+    center: Vec3, // Of the mesh's bounds, in mesh space
+}
+
+#[derive(Debug)]
+enum MeshVertices {
+    Standard(Vec<Vertex>),
+    Packed(Vec<PackedVertex>),
+}
+
+impl MeshVertices {
+    fn new(vertices: &[Vertex], format: VertexFormat) -> Self {
+        match format {
+            VertexFormat::Standard => MeshVertices::Standard(vertices.to_vec()),
+            VertexFormat::Packed => {
+                MeshVertices::Packed(vertices.iter().map(PackedVertex::pack).collect())
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            MeshVertices::Standard(vertices) => vertices.len(),
+            MeshVertices::Packed(vertices) => vertices.len(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            MeshVertices::Standard(vertices) => bytemuck::cast_slice(vertices),
+            MeshVertices::Packed(vertices) => bytemuck::cast_slice(vertices),
+        }
+    }
+}
+
+// Once this fraction of the index buffer is made up of freed ranges the buffer is repacked
+const MAX_WASTED_RATIO: f32 = 0.25;
+// Vertex and index buffers start out this big and double whenever they run out of room
+const INITIAL_BUFFER_SIZE: u64 = 1 << 20;
+// Renderers that can have a transform in a single pass
+const MAX_INSTANCES: u32 = 65_536;
+
+// All meshes sharing a material and layer are accumulated into one buffer, every renderer owns a range of the buffer
+// so edits can be written in place. Each range is drawn on its own with the renderer's transform as its instance,
+// so moving a renderer never touches its geometry
 #[derive(Debug)]
 pub struct MeshBuffer {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
-    pub vertex_offset: u64,
-    pub index_offset: u64,
+    pub instance_buffer: wgpu::Buffer,
     pub vertex_count: u32,
     pub index_count: u32,
+    pub vertex_format: VertexFormat,
+    vertex_buffer_size: u64, // In bytes
+    index_buffer_size: u64,
+    entries: HashMap<u64, MeshBufferEntry>, // Keyed by renderer id
+    data: HashMap<u64, MeshBufferData>,
+    wasted_indices: usize,
+    needs_rebuild: bool,
+    instance_slots: HashMap<u64, u32>, // Renderer id -> transform slot in the instance buffer
+    transforms: HashMap<u64, Mat4>,
+    free_instances: Vec<u32>,
+    instance_count: u32,
 }
 
 impl MeshBuffer {
     pub fn new(device: &wgpu::Device) -> Self {
-        let vertex_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Vertex Buffer"),
-            size: 500_000_000, // 500mb (Maybe too much!)
+        Self::with_format(device, VertexFormat::Standard)
+    }
+
+    pub fn with_format(device: &wgpu::Device, vertex_format: VertexFormat) -> Self {
+        let vertex_buffer = create_mesh_buffer(device, VERTEX_BUFFER, INITIAL_BUFFER_SIZE);
+        let index_buffer = create_mesh_buffer(device, INDEX_BUFFER, INITIAL_BUFFER_SIZE);
+        let instance_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: MAX_INSTANCES as u64 * std::mem::size_of::<TransformInstance>() as u64,
             usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
             mapped_at_creation: false,
         });
-        let index_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Index Buffer"),
-            size: 500_000_000, // 500mb (Maybe too much!)
-            usage: BufferUsages::COPY_DST | BufferUsages::INDEX,
-            mapped_at_creation: false,
-        });
         MeshBuffer {
             vertex_buffer,
             index_buffer,
-            vertex_offset: 0,
-            index_offset: 0,
+            instance_buffer,
             vertex_count: 0,
             index_count: 0,
+            vertex_format,
+            vertex_buffer_size: INITIAL_BUFFER_SIZE,
+            index_buffer_size: INITIAL_BUFFER_SIZE,
+            entries: HashMap::new(),
+            data: HashMap::new(),
+            wasted_indices: 0,
+            needs_rebuild: false,
+            instance_slots: HashMap::new(),
+            transforms: HashMap::new(),
+            free_instances: Vec::new(),
+            instance_count: 0,
         }
     }
 
-    pub fn insert_mesh(&mut self, state: &State, mesh: Arc<RwLock<Mesh>>, transform: &Mat4) {
+    // Inserts or replaces the geometry contributed by a renderer.
+    // Meshes that still fit in their range are updated in place, anything else is appended to the end
+    // of the buffer and its old range is freed
+    pub fn insert_mesh(
+        &mut self,
+        state: &State,
+        id: u64,
+        mesh: Arc<RwLock<Mesh>>,
+        transform: &Mat4,
+    ) {
+        if !self.set_transform(state, id, transform) {
+            println!("[INFO] Render pass is out of transform slots, skipping mesh {id}");
+            return;
+        }
+
         let mesh_lock = mesh.read();
-        // Prepare data
-        let mut new_vertices = mesh_lock.get_vertices().clone();
-        new_vertices.iter_mut().for_each(|vertex| {
-            vertex.position = transform.transform_point3(vertex.position.into()).into();
-            // Transforming the normal is not always required, perhaps find a way to avoid doing this in those cases
-            vertex.normal = transform.transform_vector3(vertex.normal.into()).into();
-        });
-        let vertex_data = bytemuck::cast_slice(&new_vertices);
+        let data = MeshBufferData {
+            vertices: MeshVertices::new(mesh_lock.get_vertices(), self.vertex_format),
+            indices: mesh_lock.get_indices().clone(),
+            center: bounds_center(mesh_lock.get_vertices()),
+        };
+        drop(mesh_lock);
+
+        if !self.needs_rebuild {
+            match self.entries.get(&id).copied() {
+                Some(entry)
+                    if data.vertices.len() <= entry.vertex_capacity
+                        && data.indices.len() <= entry.index_capacity =>
+                {
+                    let entry = MeshBufferEntry {
+                        vertex_length: data.vertices.len(),
+                        index_length: data.indices.len(),
+                        ..entry
+                    };
+                    self.write_entry(state, &entry, &data);
+                    self.entries.insert(id, entry);
+                }
+                existing => {
+                    if let Some(entry) = existing {
+                        self.free_entry(&entry);
+                    }
+                    self.append_entry(state, id, &data);
+                }
+            }
+        }
+        self.data.insert(id, data);
+    }
+
+    pub fn remove_mesh(&mut self, id: u64) {
+        if self.data.remove(&id).is_some() {
+            if let Some(entry) = self.entries.remove(&id) {
+                self.free_entry(&entry);
+            }
+        }
+        if let Some(slot) = self.instance_slots.remove(&id) {
+            self.free_instances.push(slot);
+        }
+        self.transforms.remove(&id);
+    }
+
+    // Writes the transform of a renderer into its instance slot, a slot is assigned on first use.
+    // Returns false when every slot is taken
+    pub fn set_transform(&mut self, state: &State, id: u64, transform: &Mat4) -> bool {
+        let slot = match self.instance_slots.get(&id) {
+            Some(slot) => *slot,
+            None => {
+                let slot = match self.free_instances.pop() {
+                    Some(slot) => slot,
+                    None if self.instance_count < MAX_INSTANCES => {
+                        self.instance_count += 1;
+                        self.instance_count - 1
+                    }
+                    None => return false,
+                };
+                self.instance_slots.insert(id, slot);
+                slot
+            }
+        };
+        state.queue.write_buffer(
+            &self.instance_buffer,
+            slot as u64 * std::mem::size_of::<TransformInstance>() as u64,
+            bytemuck::bytes_of(&TransformInstance::new(transform)),
+        );
+        self.transforms.insert(id, *transform);
+        true
+    }
+
+    // The index range and instance slot of every mesh in the buffer, one draw call each
+    pub fn draws(&self) -> impl Iterator<Item = (Range<u32>, u32)> + '_ {
+        self.entries.iter().filter_map(|(id, entry)| {
+            let slot = *self.instance_slots.get(id)?;
+            let start = entry.index_start as u32;
+            Some((start..start + entry.index_length as u32, slot))
+        })
+    }
+
+    // Same as draws, but ordered by how far the center of each mesh is from the camera, furthest first
+    pub fn draws_back_to_front(&self, camera_position: Vec3) -> Vec<(Range<u32>, u32)> {
+        let mut draws: Vec<(f32, u64, Range<u32>, u32)> = self
+            .entries
+            .iter()
+            .filter_map(|(id, entry)| {
+                let slot = *self.instance_slots.get(id)?;
+                let start = entry.index_start as u32;
+                let distance = self.distance_to(*id, camera_position)?;
+                Some((
+                    distance,
+                    *id,
+                    start..start + entry.index_length as u32,
+                    slot,
+                ))
+            })
+            .collect();
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        draws
+            .into_iter()
+            .map(|(_, _, indices, slot)| (indices, slot))
+            .collect()
+    }
+
+    // How far the furthest mesh in the buffer is from the camera, 0 when it's empty
+    pub fn furthest_distance(&self, camera_position: Vec3) -> f32 {
+        self.entries
+            .keys()
+            .filter_map(|id| self.distance_to(*id, camera_position))
+            .fold(0.0, f32::max)
+    }
+
+    fn distance_to(&self, id: u64, camera_position: Vec3) -> Option<f32> {
+        let center = self.data.get(&id)?.center;
+        let transform = self.transforms.get(&id)?;
+        Some(transform.transform_point3(center).distance(camera_position))
+    }
+
+    pub fn contains_mesh(&self, id: u64) -> bool {
+        self.data.contains_key(&id)
+    }
 
-        let mut new_indices = mesh_lock.get_indices().clone();
-        new_indices
-            .iter_mut()
-            .for_each(|index| *index += self.vertex_count);
-        let index_data = bytemuck::cast_slice(&new_indices);
+    pub fn mesh_ids(&self) -> Vec<u64> {
+        self.data.keys().copied().collect()
+    }
+
+    pub fn get_entry(&self, id: u64) -> Option<&MeshBufferEntry> {
+        self.entries.get(&id)
+    }
+
+    // Repacks every mesh if too much of the buffer went to waste
+    pub fn flush(&mut self, state: &State) {
+        if self.data.is_empty() {
+            self.entries.clear();
+            self.wasted_indices = 0;
+            self.vertex_count = 0;
+            self.index_count = 0;
+            self.needs_rebuild = false;
+            return;
+        }
+
+        if self.wasted_indices as f32 > self.index_count as f32 * MAX_WASTED_RATIO {
+            self.needs_rebuild = true;
+        }
+        if !self.needs_rebuild {
+            return;
+        }
+
+        self.needs_rebuild = false;
+        self.entries.clear();
+        self.wasted_indices = 0;
+        self.vertex_count = 0;
+        self.index_count = 0;
+
+        let mut ids: Vec<u64> = self.data.keys().copied().collect();
+        ids.sort_unstable(); // Keep the layout stable between rebuilds
+        let data = std::mem::take(&mut self.data);
+        for id in ids {
+            self.append_entry(state, id, data.get(&id).unwrap());
+        }
+        self.data = data;
+    }
+
+    fn append_entry(&mut self, state: &State, id: u64, data: &MeshBufferData) {
+        let entry = MeshBufferEntry {
+            vertex_start: self.vertex_count as usize,
+            vertex_length: data.vertices.len(),
+            vertex_capacity: data.vertices.len(),
+            index_start: self.index_count as usize,
+            index_length: data.indices.len(),
+            index_capacity: data.indices.len(),
+        };
+        self.reserve(
+            state,
+            (entry.vertex_start + entry.vertex_capacity) * self.vertex_format.size(),
+            (entry.index_start + entry.index_capacity) * std::mem::size_of::<u32>(),
+        );
+        self.write_entry(state, &entry, data);
+        self.vertex_count += entry.vertex_capacity as u32;
+        self.index_count += entry.index_capacity as u32;
+        self.entries.insert(id, entry);
+    }
+
+    // Grows the buffers to hold the given number of bytes, the used part of the old buffers is copied over on the GPU.
+    // Writes queued for the old buffers run at the start of the submit, before the copy
+    fn reserve(&mut self, state: &State, vertex_bytes: usize, index_bytes: usize) {
+        let grown_size = |size: u64, needed: u64| {
+            let mut size = size;
+            while size < needed {
+                size *= 2;
+            }
+            size
+        };
+        let vertex_size = grown_size(self.vertex_buffer_size, vertex_bytes as u64);
+        let index_size = grown_size(self.index_buffer_size, index_bytes as u64);
+        if vertex_size == self.vertex_buffer_size && index_size == self.index_buffer_size {
+            return;
+        }
+
+        let mut encoder = state
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mesh Buffer Grow Encoder"),
+            });
+        if vertex_size != self.vertex_buffer_size {
+            let buffer = create_mesh_buffer(&state.device, VERTEX_BUFFER, vertex_size);
+            let used = self.vertex_count as u64 * self.vertex_format.size() as u64;
+            encoder.copy_buffer_to_buffer(&self.vertex_buffer, 0, &buffer, 0, used);
+            self.vertex_buffer = buffer;
+            self.vertex_buffer_size = vertex_size;
+        }
+        if index_size != self.index_buffer_size {
+            let buffer = create_mesh_buffer(&state.device, INDEX_BUFFER, index_size);
+            let used = self.index_count as u64 * std::mem::size_of::<u32>() as u64;
+            encoder.copy_buffer_to_buffer(&self.index_buffer, 0, &buffer, 0, used);
+            self.index_buffer = buffer;
+            self.index_buffer_size = index_size;
+        }
+        state.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    pub fn buffer_sizes(&self) -> (u64, u64) {
+        (self.vertex_buffer_size, self.index_buffer_size)
+    }
+
+    fn free_entry(&mut self, entry: &MeshBufferEntry) {
+        // A range at the end of the buffer is handed out again by the next append, nothing goes to waste
+        let at_end = entry.index_start + entry.index_capacity == self.index_count as usize
+            && entry.vertex_start + entry.vertex_capacity == self.vertex_count as usize;
+        if at_end {
+            self.index_count = entry.index_start as u32;
+            self.vertex_count = entry.vertex_start as u32;
+            return;
+        }
+        // Freed ranges are never drawn, they only take up space until the next repack
+        self.wasted_indices += entry.index_capacity;
+    }
+
+    fn write_entry(&self, state: &State, entry: &MeshBufferEntry, data: &MeshBufferData) {
+        let indices: Vec<u32> = data
+            .indices
+            .iter()
+            .map(|index| index + entry.vertex_start as u32)
+            .collect();
 
         // write data into buffers
-        state
-            .queue
-            .write_buffer(&self.vertex_buffer, self.vertex_offset, vertex_data);
-        state
-            .queue
-            .write_buffer(&self.index_buffer, self.index_offset, index_data);
-
-        self.vertex_offset += vertex_data.len() as u64;
-        self.index_offset += index_data.len() as u64;
-        self.vertex_count += mesh_lock.vertex_count as u32;
-        self.index_count += mesh_lock.index_count as u32;
+        state.queue.write_buffer(
+            &self.vertex_buffer,
+            (entry.vertex_start * self.vertex_format.size()) as u64,
+            data.vertices.bytes(),
+        );
+        state.queue.write_buffer(
+            &self.index_buffer,
+            (entry.index_start * std::mem::size_of::<u32>()) as u64,
+            bytemuck::cast_slice(&indices),
+        );
+    }
+}
+
+fn bounds_center(vertices: &[Vertex]) -> Vec3 {
+    if vertices.is_empty() {
+        return Vec3::ZERO;
     }
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), vertex| {
+            let position = Vec3::from(vertex.position);
+            (min.min(position), max.max(position))
+        },
+    );
+    (min + max) / 2.0
+}
+
+const VERTEX_BUFFER: (&str, BufferUsages) = ("Vertex Buffer", BufferUsages::VERTEX);
+const INDEX_BUFFER: (&str, BufferUsages) = ("Index Buffer", BufferUsages::INDEX);
+
+fn create_mesh_buffer(
+    device: &wgpu::Device,
+    (label, usage): (&str, BufferUsages),
+    size: u64,
+) -> wgpu::Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | usage,
+        mapped_at_creation: false,
+    })
 }
 
 #[derive(Debug)]
@@ -159,8 +585,25 @@ pub struct RenderPassData<M: Material + ?Sized> {
 }
 
 impl RenderPassData<dyn Material> {
-    pub fn insert_mesh(&mut self, state: &State, mesh: Arc<RwLock<Mesh>>, transform: &Mat4) {
-        self.buffer.insert_mesh(state, mesh, transform)
+    pub fn insert_mesh(
+        &mut self,
+        state: &State,
+        id: u64,
+        mesh: Arc<RwLock<Mesh>>,
+        transform: &Mat4,
+    ) {
+        self.buffer.insert_mesh(state, id, mesh, transform)
+    }
+
+    // Only meshes already in the pass are moved, an empty mesh has nothing to draw
+    pub fn set_transform(&mut self, state: &State, id: u64, transform: &Mat4) {
+        if self.buffer.contains_mesh(id) {
+            self.buffer.set_transform(state, id, transform);
+        }
+    }
+
+    pub fn remove_mesh(&mut self, id: u64) {
+        self.buffer.remove_mesh(id)
     }
 }
 
@@ -172,6 +615,156 @@ pub fn create_render_pass(
     RenderPassData {
         material: Arc::clone(&material),
         id: pass_id,
-        buffer: MeshBuffer::new(&state.device),
+        buffer: MeshBuffer::with_format(&state.device, material.read().vertex_format()),
+    }
+}
+
+#[cfg(test)]
+mod mesh_buffer_tests {
+    use super::*;
+    use crate::state::headless_state;
+
+    fn quads(count: usize) -> Arc<RwLock<Mesh>> {
+        let mut mesh = Mesh::new();
+        for _ in 0..count {
+            mesh = mesh.append_quad([[0.0; 3]; 4], [0.0, 1.0, 0.0]);
+        }
+        Arc::new(RwLock::new(mesh))
+    }
+
+    #[test]
+    fn replaced_and_removed_meshes_leave_no_geometry_behind() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut buffer = MeshBuffer::new(&state.device);
+        buffer.insert_mesh(&state, 1, quads(1), &Mat4::IDENTITY);
+        buffer.insert_mesh(&state, 2, quads(1), &Mat4::IDENTITY);
+
+        // Growing the last mesh reuses its own range
+        buffer.insert_mesh(&state, 2, quads(3), &Mat4::IDENTITY);
+        assert_eq!(buffer.index_count, 6 * 4);
+        assert_eq!(buffer.get_entry(2).unwrap().index_start, 6);
+
+        // Growing a mesh in the middle moves it to the end and frees its old range
+        buffer.insert_mesh(&state, 1, quads(2), &Mat4::IDENTITY);
+        assert_eq!(buffer.get_entry(1).unwrap().index_start, 6 * 4);
+
+        buffer.remove_mesh(1);
+        buffer.remove_mesh(2);
+        buffer.flush(&state);
+        assert_eq!(buffer.index_count, 0);
+        assert!(buffer.mesh_ids().is_empty());
+    }
+
+    #[test]
+    fn moving_a_mesh_keeps_its_range_and_slot() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut buffer = MeshBuffer::new(&state.device);
+        buffer.insert_mesh(&state, 1, quads(1), &Mat4::IDENTITY);
+        buffer.insert_mesh(&state, 2, quads(2), &Mat4::IDENTITY);
+        let before: Vec<(Range<u32>, u32)> = buffer.draws().collect();
+
+        buffer.set_transform(&state, 2, &Mat4::from_rotation_y(1.0));
+        let after: Vec<(Range<u32>, u32)> = buffer.draws().collect();
+        assert_eq!(before, after);
+
+        // Freed slots are handed out again
+        buffer.remove_mesh(1);
+        buffer.insert_mesh(&state, 3, quads(1), &Mat4::IDENTITY);
+        assert_eq!(buffer.instance_slots[&3], 0);
+    }
+
+    #[test]
+    fn buffers_grow_to_fit_and_keep_their_layout() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut buffer = MeshBuffer::new(&state.device);
+        buffer.insert_mesh(&state, 1, quads(1), &Mat4::IDENTITY);
+        // Four vertices per quad, enough of them to outgrow the initial vertex buffer
+        let quad_bytes = 4 * std::mem::size_of::<Vertex>() as u64;
+        let count = (INITIAL_BUFFER_SIZE / quad_bytes) as usize + 1;
+        buffer.insert_mesh(&state, 2, quads(count), &Mat4::IDENTITY);
+
+        let (vertex_size, _) = buffer.buffer_sizes();
+        assert_eq!(vertex_size, INITIAL_BUFFER_SIZE * 2);
+        assert_eq!(buffer.get_entry(1).unwrap().index_start, 0);
+        assert_eq!(buffer.get_entry(2).unwrap().vertex_start, 4);
+    }
+
+    #[test]
+    fn blended_meshes_are_drawn_back_to_front() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut buffer = MeshBuffer::new(&state.device);
+        for (id, distance) in [(1, 5.0), (2, 20.0), (3, 10.0)] {
+            let transform = Mat4::from_translation(Vec3::new(0.0, 0.0, distance));
+            buffer.insert_mesh(&state, id, quads(1), &transform);
+        }
+        let slots: Vec<u32> = buffer
+            .draws_back_to_front(Vec3::ZERO)
+            .into_iter()
+            .map(|(_, slot)| slot)
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                buffer.instance_slots[&2],
+                buffer.instance_slots[&3],
+                buffer.instance_slots[&1]
+            ]
+        );
+        assert_eq!(buffer.furthest_distance(Vec3::ZERO), 20.0);
+    }
+}
+
+#[cfg(test)]
+mod render_layer_tests {
+    use super::render_layers::*;
+    use crate::state::headless_state;
+
+    #[test]
+    fn layers_are_drawn_by_order() {
+        let layers = RenderLayers::default();
+        layers.create_layer("late".to_string(), 10);
+        layers.create_layer("first".to_string(), 0);
+        layers.create_layer("middle".to_string(), 5);
+        let names: Vec<String> = ["late", "first", "missing", "middle", "first"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        let sorted: Vec<String> = layers
+            .get_sorted_layers(&names)
+            .iter()
+            .map(|layer| layer.read().name.clone())
+            .collect();
+        assert_eq!(sorted, ["first", "middle", "late"]);
+
+        assert!(layers.remove_layer("middle").is_some());
+        assert_eq!(layers.get_sorted_layers(&names).len(), 2);
+        layers.clear();
+        assert!(layers.all().is_empty());
+    }
+
+    #[test]
+    fn states_dont_share_layers() {
+        let first = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        first.render_layers.create_layer("Default".to_string(), 0);
+        drop(first);
+
+        let second = headless_state().unwrap();
+        assert!(second.render_layers.get_layer_by_name("Default").is_none());
     }
 }
@@ -0,0 +1,166 @@
+use parking_lot::RwLock;
+
+use crate::voxels::voxel_scene::CHUNK_SIZE;
+
+// The window is cleared to it and distant geometry fades into it
+pub const SKY_COLOR: [f32; 3] = [0.3, 0.4, 0.6];
+
+// Kept in State and shared by every camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    // Hard radius in chunks around the cameras, renderers further away from all of them are dropped from their passes.
+    // None draws everything
+    pub render_distance: Option<u32>,
+    // Kept fitted to the chunk streaming view distance by fit_fog_to_view_distance. None turns the fog off
+    pub fog: Option<Fog>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            render_distance: None,
+            fog: Some(Fog::for_view_distance(8)), // EngineConfig's default view distance
+        }
+    }
+}
+
+// Linear fog towards the sky color, by distance from the camera in voxels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub start: f32, // Nothing is fogged closer than this
+    pub end: f32,   // Everything is fully fogged past this
+}
+
+impl Fog {
+    // The camera can be anywhere within the center chunk, so the furthest ring of chunks can start a chunk closer than
+    // the view distance. That ring is the one streaming in and out, it's fully fogged
+    pub fn for_view_distance(view_distance: u32) -> Self {
+        let end = (view_distance.max(2) - 1) as f32 * CHUNK_SIZE as f32;
+        Self {
+            start: end * 0.5,
+            end,
+        }
+    }
+}
+
+// Settings baked into the surface and pipelines, changing them goes through State::apply_settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GraphicsSettings {
+    pub vsync: wgpu::PresentMode,
+    pub msaa_samples: u32,
+    pub upload_budget: usize, // Bytes of mesh data uploaded per frame, the meshes nearest the camera go first
+    // Pushes the shadow casters away from the sun. Too little and lit faces shadow themselves with stripes,
+    // too much and shadows come loose from whatever casts them
+    pub shadow_depth_bias: i32,
+    pub shadow_slope_bias: f32,
+    // Max anisotropy of the voxel and cached textures, 1 turns it off. Textures pick it up when they're created
+    pub anisotropy: u8,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            vsync: wgpu::PresentMode::Fifo,
+            msaa_samples: 1,
+            upload_budget: 8 * 1024 * 1024,
+            shadow_depth_bias: 2,
+            shadow_slope_bias: 2.0,
+            anisotropy: 1,
+        }
+    }
+}
+
+// WebGPU only guarantees these for render attachments and wgpu can't be asked for more
+pub const SUPPORTED_SAMPLE_COUNTS: [u32; 2] = [1, 4];
+
+// Falls back to the highest supported count below the requested one
+pub fn supported_sample_count(requested: u32) -> u32 {
+    if SUPPORTED_SAMPLE_COUNTS.contains(&requested) {
+        return requested;
+    }
+    let fallback = SUPPORTED_SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1);
+    println!("[INFO] {requested}x MSAA is not supported, using {fallback}x instead");
+    fallback
+}
+
+// How every material is drawn, for looking at meshes rather than at the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenderDebugMode {
+    Normal,
+    Wireframe, // Needs the POLYGON_MODE_LINE feature
+    Normals,   // Fragments colored by their normal
+    Depth,     // Fragments shaded by their distance to the camera
+}
+
+impl RenderDebugMode {
+    pub fn next(&self) -> Self {
+        match self {
+            RenderDebugMode::Normal => RenderDebugMode::Wireframe,
+            RenderDebugMode::Wireframe => RenderDebugMode::Normals,
+            RenderDebugMode::Normals => RenderDebugMode::Depth,
+            RenderDebugMode::Depth => RenderDebugMode::Normal,
+        }
+    }
+}
+
+lazy_static! {
+    static ref DEBUG_MODE: RwLock<RenderDebugMode> = RwLock::new(RenderDebugMode::Normal);
+}
+
+pub fn get_debug_mode() -> RenderDebugMode {
+    *DEBUG_MODE.read()
+}
+
+pub fn set_debug_mode(mode: RenderDebugMode) {
+    *DEBUG_MODE.write() = mode;
+}
+
+// Moves on to the next mode, wireframe is skipped when the adapter can't draw lines
+pub fn cycle_debug_mode(supports_wireframe: bool) -> RenderDebugMode {
+    let mut mode = get_debug_mode().next();
+    if mode == RenderDebugMode::Wireframe && !supports_wireframe {
+        println!("[INFO] Wireframe rendering isn't supported by this adapter, skipping it");
+        mode = mode.next();
+    }
+    set_debug_mode(mode);
+    println!("[INFO] Render debug mode: {:?}", mode);
+    mode
+}
+
+#[cfg(test)]
+mod render_settings_tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_sample_counts_fall_back() {
+        assert_eq!(supported_sample_count(4), 4);
+        assert_eq!(supported_sample_count(8), 4);
+        assert_eq!(supported_sample_count(2), 1);
+        assert_eq!(supported_sample_count(0), 1);
+    }
+
+    #[test]
+    fn furthest_ring_of_chunks_is_fully_fogged() {
+        for view_distance in [1, 2, 8, 32] {
+            let fog = Fog::for_view_distance(view_distance);
+            let ring_start = (view_distance.saturating_sub(1) * CHUNK_SIZE) as f32;
+            assert!(fog.end <= ring_start.max(CHUNK_SIZE as f32));
+            assert!(fog.start > 0.0 && fog.start < fog.end);
+        }
+    }
+
+    #[test]
+    fn debug_modes_cycle_around_missing_wireframe() {
+        set_debug_mode(RenderDebugMode::Normal);
+        assert_eq!(cycle_debug_mode(false), RenderDebugMode::Normals);
+        assert_eq!(cycle_debug_mode(false), RenderDebugMode::Depth);
+        assert_eq!(cycle_debug_mode(true), RenderDebugMode::Normal);
+        assert_eq!(cycle_debug_mode(true), RenderDebugMode::Wireframe);
+        set_debug_mode(RenderDebugMode::Normal);
+    }
+}
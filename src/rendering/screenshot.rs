@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SCREENSHOT_FOLDER: &str = "./screenshots/";
+
+// Named after when they were taken, so screenshots never overwrite each other
+pub fn screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    Path::new(SCREENSHOT_FOLDER).join(format!("screenshot_{millis}.png"))
+}
+
+// Rows copied out of a texture have to start at a multiple of the copy alignment
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4 + alignment - 1) / alignment * alignment
+}
+
+// Drops the row padding of a texture copy and puts the channels in RGBA order
+pub fn to_rgba(
+    data: &[u8],
+    width: u32,
+    padded_bytes_per_row: u32,
+    format: wgpu::TextureFormat,
+) -> Result<Vec<u8>, String> {
+    let bgra = match format {
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        _ => return Err(format!("Unsupported texture format {format:?}")),
+    };
+    let mut pixels: Vec<u8> = data
+        .chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..(width * 4) as usize])
+        .copied()
+        .collect();
+    if bgra {
+        pixels
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+    Ok(pixels)
+}
+
+// A texture copied into a buffer the CPU can read, once the encoder it was recorded into is submitted
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl Readback {
+    pub fn copy(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row(width) * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row(width)),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Self {
+            buffer,
+            width,
+            height,
+            format,
+        }
+    }
+
+    // Blocks until the copy is done, the pixels come back as tightly packed RGBA rows
+    pub fn read(self, device: &wgpu::Device) -> Result<Vec<u8>, String> {
+        let slice = self.buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).map_err(|e| e.to_string())?;
+
+        let data = slice.get_mapped_range();
+        let pixels = to_rgba(
+            &data,
+            self.width,
+            padded_bytes_per_row(self.width),
+            self.format,
+        );
+        drop(data);
+        self.buffer.unmap();
+        pixels
+    }
+
+    // Waits for the copy and encodes the PNG on its own thread, so the frame doesn't stall on it.
+    // Screenshots are saved opaque, blended passes can leave the alpha of the frame below one
+    pub fn save_png_in_background(self, device: Arc<wgpu::Device>, path: PathBuf) {
+        std::thread::spawn(move || {
+            let (width, height) = (self.width, self.height);
+            let result = self.read(&device).and_then(|mut pixels| {
+                pixels.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+                if let Some(folder) = path.parent() {
+                    fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+                }
+                image::save_buffer(&path, &pixels, width, height, image::ColorType::Rgba8)
+                    .map_err(|e| e.to_string())
+            });
+            match result {
+                Ok(()) => println!("[INFO] Saved screenshot {}", path.display()),
+                Err(e) => println!("[INFO] Failed to save screenshot {}: {e}", path.display()),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+
+    #[test]
+    fn padding_is_dropped_and_bgra_swizzled() {
+        let padded = padded_bytes_per_row(3);
+        assert_eq!(padded, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        // Two rows of three pixels, blue, green and red in BGRA order followed by the padding
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            data.extend_from_slice(&[255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255]);
+            data.resize(data.len() + padded as usize - 12, 7);
+        }
+
+        let pixels = to_rgba(&data, 3, padded, wgpu::TextureFormat::Bgra8UnormSrgb).unwrap();
+        assert_eq!(pixels.len(), 2 * 3 * 4);
+        assert_eq!(
+            &pixels[..12],
+            &[0, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 255]
+        );
+        let unchanged = to_rgba(&data, 3, padded, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        assert_eq!(&unchanged[..12], &data[..12]);
+        assert!(to_rgba(&data, 3, padded, wgpu::TextureFormat::Rgba16Float).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use glam::{Mat4, Vec3};
+use wgpu::{BindGroup, Buffer, RenderPipeline};
+
+use super::{
+    render_settings::GraphicsSettings,
+    texture::Texture,
+    vertex::{TransformInstance, VertexFormat},
+};
+
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+const SHADOW_EXTENT: f32 = 64.0; // Half the width of the square around the focus that casts and receives shadows
+const SHADOW_DEPTH: f32 = 256.0; // How far above the focus the light camera sits, and twice that is its far plane
+
+// The orthographic camera the sun's shadows are rendered from, centered on the focus. The focus is snapped to the
+// texels of the shadow map, so the shadow edges don't crawl while the player walks around
+pub fn light_view_projection(direction: Vec3, focus: Vec3) -> Mat4 {
+    let direction = direction.normalize_or_zero();
+    // Looking straight down, the usual up vector would be parallel to the view direction
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    let texel = SHADOW_EXTENT * 2.0 / SHADOW_MAP_SIZE as f32;
+    let view = Mat4::look_at_lh(Vec3::ZERO, -direction, up);
+    let focus = view.transform_point3(focus);
+    let focus = Vec3::new(
+        (focus.x / texel).floor() * texel,
+        (focus.y / texel).floor() * texel,
+        focus.z,
+    );
+    let view = Mat4::from_translation(-focus) * view;
+    Mat4::orthographic_lh(
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        -SHADOW_DEPTH,
+        SHADOW_DEPTH,
+    ) * view
+}
+
+// A single shadow cascade for the sun. Everything that casts shadows is drawn into its depth texture with one shared
+// depth-only pipeline, materials then sample it through the light bind group
+pub struct ShadowMap {
+    pub texture: Texture,
+    pub pipeline: RenderPipeline,
+    pub packed_pipeline: RenderPipeline, // For the passes of materials with packed vertices
+    pub bind_group: BindGroup, // Only the light uniform, the shadow map can't be sampled while it's drawn to
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, light_buffer: &Buffer, settings: &GraphicsSettings) -> Self {
+        let texture = Texture::create_shadow_map(device, SHADOW_MAP_SIZE, "shadow_map");
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_bind_group"),
+        });
+        let pipeline =
+            Self::create_pipeline(device, &bind_group_layout, settings, VertexFormat::Standard);
+        let packed_pipeline =
+            Self::create_pipeline(device, &bind_group_layout, settings, VertexFormat::Packed);
+
+        Self {
+            texture,
+            pipeline,
+            packed_pipeline,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    // The depth bias is baked into the pipeline
+    pub fn apply_settings(&mut self, device: &wgpu::Device, settings: &GraphicsSettings) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.bind_group_layout,
+            settings,
+            VertexFormat::Standard,
+        );
+        self.packed_pipeline = Self::create_pipeline(
+            device,
+            &self.bind_group_layout,
+            settings,
+            VertexFormat::Packed,
+        );
+    }
+
+    pub fn pipeline_for(&self, vertex_format: VertexFormat) -> &RenderPipeline {
+        match vertex_format {
+            VertexFormat::Standard => &self.pipeline,
+            VertexFormat::Packed => &self.packed_pipeline,
+        }
+    }
+
+    // Faces are drawn from both sides, thin geometry like leaves still casts a shadow when seen from behind
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        settings: &GraphicsSettings,
+        vertex_format: VertexFormat,
+    ) -> RenderPipeline {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("../shaders/packed_vertex.wgsl"),
+                    include_str!("../shaders/shadow.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: vertex_format.entry_point(),
+                buffers: &[vertex_format.desc(), TransformInstance::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: settings.shadow_depth_bias,
+                    slope_scale: settings.shadow_slope_bias,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod shadow_tests {
+    use super::*;
+
+    #[test]
+    fn focus_ends_up_in_the_middle_of_the_map() {
+        let direction = Vec3::new(-0.5, 0.6, -0.3);
+        let focus = Vec3::new(100.3, 40.0, -20.7);
+        let projection = light_view_projection(direction, focus);
+
+        let center = projection.project_point3(focus);
+        let texel = 2.0 / SHADOW_MAP_SIZE as f32;
+        assert!(center.x.abs() <= texel * 2.0 && center.y.abs() <= texel * 2.0);
+        assert!((0.0..1.0).contains(&center.z));
+
+        // Further towards the sun is closer to the light camera
+        let above = projection.project_point3(focus + direction.normalize() * 10.0);
+        assert!(above.z < center.z);
+    }
+}
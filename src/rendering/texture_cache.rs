@@ -0,0 +1,347 @@
+use std::{
+    fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::*;
+use dashmap::DashMap;
+use image::RgbaImage;
+use parking_lot::RwLock;
+
+use crate::state::State;
+
+use super::{
+    pipeline_cache::{BlendMode, PipelineKey},
+    render_settings::RenderDebugMode,
+    texture::{SamplerConfig, Texture},
+};
+
+// Asset paths are relative to this folder
+pub const ASSET_FOLDER: &str = "./src";
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Textures loaded from the asset folder, every path is only read and uploaded once.
+// Textures belong to a device, so every State owns its own cache
+pub struct TextureCache {
+    folder: PathBuf,
+    textures: DashMap<String, Arc<Texture>>,
+    fallback: RwLock<Option<Arc<Texture>>>,
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new(ASSET_FOLDER)
+    }
+}
+
+impl TextureCache {
+    pub fn new(folder: impl Into<PathBuf>) -> Self {
+        Self {
+            folder: folder.into(),
+            textures: DashMap::new(),
+            fallback: RwLock::new(None),
+        }
+    }
+
+    // Loads e.g. "textures/stone.png" with a full mip chain, repeated loads return the same texture
+    pub fn load(state: &State, path: &str) -> Result<Arc<Texture>> {
+        let cache = &state.texture_cache;
+        if let Some(texture) = cache.textures.get(path) {
+            return Ok(Arc::clone(texture.value()));
+        }
+
+        let image = read_image(&cache.folder.join(path))?;
+        let texture = Arc::new(create_mipmapped_texture(state, &image, path));
+        // Another thread may have loaded it in the meantime, everyone gets the first one
+        Ok(Arc::clone(
+            cache
+                .textures
+                .entry(path.to_string())
+                .or_insert(texture)
+                .value(),
+        ))
+    }
+
+    // Missing or broken files are reported and show the fallback texture instead
+    pub fn load_or_fallback(state: &State, path: &str) -> Arc<Texture> {
+        Self::load(state, path).unwrap_or_else(|e| {
+            println!("[INFO] {e:#}, using the fallback texture");
+            Self::fallback(state)
+        })
+    }
+
+    // A magenta and black checkerboard that is hard to miss
+    pub fn fallback(state: &State) -> Arc<Texture> {
+        let cache = &state.texture_cache;
+        if let Some(texture) = &*cache.fallback.read() {
+            return Arc::clone(texture);
+        }
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            if (x / 4 + y / 4) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        let texture = Arc::new(create_mipmapped_texture(state, &image, "fallback"));
+        *cache.fallback.write() = Some(Arc::clone(&texture));
+        texture
+    }
+}
+
+// Grayscale and other formats are expanded to RGBA8
+fn read_image(path: &Path) -> Result<RgbaImage> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Couldn't read texture {}", path.display()))?;
+    let image = image::load_from_memory(&bytes)
+        .with_context(|| format!("Couldn't decode texture {}", path.display()))?;
+    Ok(image.to_rgba8())
+}
+
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+fn create_mipmapped_texture(state: &State, image: &RgbaImage, label: &str) -> Texture {
+    let (width, height) = image.dimensions();
+    let mip_count = mip_level_count(width, height);
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = state.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: mip_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TEXTURE_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::RENDER_ATTACHMENT, // Mip levels are drawn from the level above
+    });
+
+    state.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        image,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * width),
+            rows_per_image: NonZeroU32::new(height),
+        },
+        size,
+    );
+    generate_mipmaps(state, &texture, mip_count);
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = state
+        .sampler_config(SamplerConfig::default())
+        .create_sampler(&state.device);
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+// Every level is a filtered blit of the one above it
+fn generate_mipmaps(state: &State, texture: &wgpu::Texture, mip_count: u32) {
+    if mip_count < 2 {
+        return;
+    }
+    let layout = blit_bind_group_layout(state);
+    let pipeline = blit_pipeline(state, &layout);
+    let sampler = state.device.create_sampler(&wgpu::SamplerDescriptor {
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    let views: Vec<wgpu::TextureView> = (0..mip_count)
+        .map(|level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    let mut encoder = state
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+    for level in 1..mip_count as usize {
+        let bind_group = state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[level - 1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("mipmap_bind_group"),
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &views[level],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    state.queue.submit(std::iter::once(encoder.finish()));
+}
+
+fn blit_bind_group_layout(state: &State) -> Arc<wgpu::BindGroupLayout> {
+    state
+        .pipeline_cache
+        .get_or_create_bind_group_layout("blit_source", || {
+            state
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("blit_bind_group_layout"),
+                })
+        })
+}
+
+fn blit_pipeline(state: &State, layout: &wgpu::BindGroupLayout) -> Arc<wgpu::RenderPipeline> {
+    let shader = state.pipeline_cache.get_or_create_shader("blit.wgsl", || {
+        state
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Blit Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/blit.wgsl").into()),
+            })
+    });
+    let key = PipelineKey {
+        shader: "blit.wgsl",
+        vertex_layout: "none",
+        format: TEXTURE_FORMAT,
+        sample_count: 1,
+        debug_mode: RenderDebugMode::Normal,
+        blend: BlendMode::Opaque,
+    };
+    state.pipeline_cache.get_or_create_pipeline(key, || {
+        let pipeline_layout =
+            state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Blit Pipeline Layout"),
+                    bind_group_layouts: &[layout],
+                    push_constant_ranges: &[],
+                });
+        state
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Blit Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: TEXTURE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+    })
+}
+
+#[cfg(test)]
+mod texture_cache_tests {
+    use super::*;
+    use crate::state::headless_state;
+
+    #[test]
+    fn mip_chains_go_down_to_one_pixel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(16, 16), 5);
+        assert_eq!(mip_level_count(17, 4), 5);
+    }
+
+    #[test]
+    fn repeated_loads_share_the_texture() {
+        let state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let first = TextureCache::load(&state, "textures/lapis_block.png").unwrap();
+        let second = TextureCache::load(&state, "textures/lapis_block.png").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn missing_and_corrupt_files_fall_back() {
+        let mut state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let folder = std::env::temp_dir().join(format!("texture_cache_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("corrupt.png"), b"not a png").unwrap();
+        image::GrayImage::from_pixel(8, 8, image::Luma([128]))
+            .save(folder.join("gray.png"))
+            .unwrap();
+        state.texture_cache = TextureCache::new(&folder);
+
+        assert!(TextureCache::load(&state, "missing.png").is_err());
+        assert!(TextureCache::load(&state, "corrupt.png").is_err());
+        assert!(TextureCache::load(&state, "gray.png").is_ok());
+        let fallback = TextureCache::fallback(&state);
+        assert!(Arc::ptr_eq(
+            &fallback,
+            &TextureCache::load_or_fallback(&state, "missing.png")
+        ));
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
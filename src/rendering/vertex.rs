@@ -1,22 +1,48 @@
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
-pub struct Vertex {
-    pub position: [f32; 3],
-    pub color: [f32; 4],
-    pub normal: [f32; 3],
-    pub uv: [f32; 2],
+use glam::Mat4;
+
+pub use crate::asset_types::vertex::{PackedVertex, Vertex};
+
+// How a material expects the vertices of its meshes to be laid out in the vertex buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexFormat {
+    Standard,
+    Packed, // PackedVertex, for the chunk meshes
 }
 
-impl Vertex {
-    pub fn new(position: [f32; 3]) -> Vertex {
-        Vertex {
-            position,
-            color: [1.0, 1.0, 1.0, 1.0],
-            normal: [0.0, 0.0, 1.0],
-            uv: [0.0, 0.0],
+impl VertexFormat {
+    pub fn desc<'a>(&self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            VertexFormat::Standard => Vertex::desc(),
+            VertexFormat::Packed => PackedVertex::desc(),
         }
     }
 
+    pub fn size(&self) -> usize {
+        match self {
+            VertexFormat::Standard => std::mem::size_of::<Vertex>(),
+            VertexFormat::Packed => std::mem::size_of::<PackedVertex>(),
+        }
+    }
+
+    // The vertex entry point that reads this layout, every shader drawing meshes has both
+    pub fn entry_point(&self) -> &'static str {
+        match self {
+            VertexFormat::Standard => "vs_main",
+            VertexFormat::Packed => "vs_packed",
+        }
+    }
+
+    // Part of the pipeline cache key
+    pub fn layout_name(&self) -> &'static str {
+        match self {
+            VertexFormat::Standard => "vertex+transform",
+            VertexFormat::Packed => "packed+transform",
+        }
+    }
+}
+
+// The vertex itself lives with the mesh, only how it's laid out in a vertex buffer is a rendering concern
+impl Vertex {
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -32,7 +58,7 @@ impl Vertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
                 // Normal
                 wgpu::VertexAttribute {
@@ -46,6 +72,99 @@ impl Vertex {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                // Tangent
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Texture layer
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                // Ambient occlusion, after the instance attributes
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 17]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // Block light
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+// The shaders unpack the three words themselves, only the colour is normalized by the hardware
+impl PackedVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                // Position, UV, normal and the rest
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Uint32x3,
+                },
+                // Color
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[u32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Unorm8x4,
+                },
+            ],
+        }
+    }
+}
+
+// The model matrix of a mesh, read as an instance attribute so moving an object only rewrites these 64 bytes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, PartialEq)]
+pub struct TransformInstance {
+    pub model: [[f32; 4]; 4],
+}
+
+impl TransformInstance {
+    pub fn new(transform: &Mat4) -> Self {
+        Self {
+            model: transform.to_cols_array_2d(),
+        }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TransformInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            // A mat4 takes up four attribute slots, one per column
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -0,0 +1,68 @@
+// Debug views shared by every material, see RenderDebugMode
+struct CameraUniform {
+    projection: mat4x4<f32>;
+    transform: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    [[location(0)]] position : vec3<f32>;
+    [[location(2)]] normal : vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(6)]] model_0 : vec4<f32>;
+    [[location(7)]] model_1 : vec4<f32>;
+    [[location(8)]] model_2 : vec4<f32>;
+    [[location(9)]] model_3 : vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] normal : vec3<f32>;
+    [[location(1)]] view_depth : f32;
+};
+
+// Only the position and normal of a packed vertex, see packed_vertex.wgsl
+struct PackedVertexInput {
+    [[location(0)]] data : vec3<u32>;
+};
+
+fn transform_vertex(in : VertexInput, instance : InstanceInput) -> VertexOutput {
+    var model: mat4x4<f32> = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    var view_position: vec4<f32> = camera.transform * model * vec4<f32>(in.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.projection * view_position;
+    out.normal = normalize((model * vec4<f32>(in.normal, 0.0)).xyz);
+    out.view_depth = view_position.z;
+    return out;
+}
+
+[[stage(vertex)]]
+fn vs_main(in : VertexInput, instance : InstanceInput) -> VertexOutput {
+    return transform_vertex(in, instance);
+}
+
+[[stage(vertex)]]
+fn vs_packed(in : PackedVertexInput, instance : InstanceInput) -> VertexOutput {
+    var unpacked: VertexInput;
+    unpacked.position = unpack_position(in.data.x);
+    unpacked.normal = unpack_normal(in.data.z);
+    return transform_vertex(unpacked, instance);
+}
+
+// World space normals mapped from -1..1 to 0..1
+[[stage(fragment)]]
+fn fs_normals(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(normalize(in.normal) * 0.5 + vec3<f32>(0.5), 1.0);
+}
+
+// White up close, fading to black over the first 200 units
+[[stage(fragment)]]
+fn fs_depth(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var brightness: f32 = 1.0 - clamp(in.view_depth / 200.0, 0.0, 1.0);
+    return vec4<f32>(vec3<f32>(brightness), 1.0);
+}
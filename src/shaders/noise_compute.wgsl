@@ -1,11 +1,141 @@
-struct PrimeIndices {
-    data: [[stride(4)]] array<f32>;
+// Density fields for whole chunks, one invocation per voxel. Chunks are stacked along z, so a batch of chunks
+// is a single dispatch. The voxel order matches pos_to_index: x * 256 + y * 16 + z
+struct NoiseParams {
+    offset: vec3<f32>; // Added to every position, moves the noise domain
+    chunk_count: u32;
+    frequency: f32;
+    amplitude: f32;
+    octaves: u32;
+    height: f32; // The density is the noise plus height - y, so the surface lies around this height
+};
+
+struct ChunkOrigins {
+    origins: array<vec4<i32>>; // Scenespace position of every chunk's first voxel, w is unused
+};
+
+struct NoiseOutput {
+    values: array<f32>;
 };
 
 [[group(0), binding(0)]]
-var<storage, write> noise_output: [[stride(4)]] PrimeIndices;
+var<uniform> params: NoiseParams;
+[[group(0), binding(1)]]
+var<storage, read> chunks: ChunkOrigins;
+[[group(0), binding(2)]]
+var<storage, read_write> noise_output: NoiseOutput;
+
+let CHUNK_SIZE: u32 = 16u;
+
+fn mod289_3(x: vec3<f32>) -> vec3<f32> {
+    return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn mod289_4(x: vec4<f32>) -> vec4<f32> {
+    return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn permute(x: vec4<f32>) -> vec4<f32> {
+    return mod289_4((x * 34.0 + vec4<f32>(1.0)) * x);
+}
+
+fn taylor_inv_sqrt(r: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(1.79284291400159) - r * 0.85373472095314;
+}
+
+// 3D simplex noise after Stefan Gustavson and Ashima Arts, roughly in -1..1
+fn simplex(v: vec3<f32>) -> f32 {
+    let c = vec2<f32>(1.0 / 6.0, 1.0 / 3.0);
 
-[[stage(compute), workgroup_size(1)]]
+    // First corner
+    var i: vec3<f32> = floor(v + vec3<f32>(dot(v, c.yyy)));
+    let x0 = v - i + vec3<f32>(dot(i, c.xxx));
+
+    // Other corners
+    let g = step(x0.yzx, x0.xyz);
+    let l = vec3<f32>(1.0) - g;
+    let i1 = min(g.xyz, l.zxy);
+    let i2 = max(g.xyz, l.zxy);
+    let x1 = x0 - i1 + c.xxx;
+    let x2 = x0 - i2 + c.yyy;
+    let x3 = x0 - vec3<f32>(0.5);
+
+    // Permutations
+    i = mod289_3(i);
+    let p = permute(
+        permute(
+            permute(vec4<f32>(i.z) + vec4<f32>(0.0, i1.z, i2.z, 1.0))
+            + vec4<f32>(i.y) + vec4<f32>(0.0, i1.y, i2.y, 1.0)
+        )
+        + vec4<f32>(i.x) + vec4<f32>(0.0, i1.x, i2.x, 1.0)
+    );
+
+    // Gradients from 7x7 points over a square, mapped onto an octahedron
+    let ns = vec3<f32>(2.0 / 7.0, -13.0 / 14.0, 1.0 / 7.0);
+    let j = p - floor(p * ns.z * ns.z) * 49.0;
+    let x_ = floor(j * ns.z);
+    let y_ = floor(j - x_ * 7.0);
+    let x = x_ * ns.x + vec4<f32>(ns.y);
+    let y = y_ * ns.x + vec4<f32>(ns.y);
+    let h = vec4<f32>(1.0) - abs(x) - abs(y);
+
+    let b0 = vec4<f32>(x.xy, y.xy);
+    let b1 = vec4<f32>(x.zw, y.zw);
+    let s0 = floor(b0) * 2.0 + vec4<f32>(1.0);
+    let s1 = floor(b1) * 2.0 + vec4<f32>(1.0);
+    let sh = -step(h, vec4<f32>(0.0));
+    let a0 = b0.xzyw + s0.xzyw * sh.xxyy;
+    let a1 = b1.xzyw + s1.xzyw * sh.zzww;
+
+    let norm = taylor_inv_sqrt(vec4<f32>(
+        dot(vec3<f32>(a0.xy, h.x), vec3<f32>(a0.xy, h.x)),
+        dot(vec3<f32>(a0.zw, h.y), vec3<f32>(a0.zw, h.y)),
+        dot(vec3<f32>(a1.xy, h.z), vec3<f32>(a1.xy, h.z)),
+        dot(vec3<f32>(a1.zw, h.w), vec3<f32>(a1.zw, h.w))
+    ));
+    let p0 = vec3<f32>(a0.xy, h.x) * norm.x;
+    let p1 = vec3<f32>(a0.zw, h.y) * norm.y;
+    let p2 = vec3<f32>(a1.xy, h.z) * norm.z;
+    let p3 = vec3<f32>(a1.zw, h.w) * norm.w;
+
+    // Mix the contributions of the four corners
+    var m: vec4<f32> = max(
+        vec4<f32>(0.6) - vec4<f32>(dot(x0, x0), dot(x1, x1), dot(x2, x2), dot(x3, x3)),
+        vec4<f32>(0.0)
+    );
+    m = m * m;
+    return 42.0 * dot(m * m, vec4<f32>(dot(p0, x0), dot(p1, x1), dot(p2, x2), dot(p3, x3)));
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
 fn main([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
-    noise_output.data[global_id.x + (global_id.y * u32(16)) + (global_id.z * u32(16) * u32(16))] = 1.0; 
-}
\ No newline at end of file
+    let chunk = global_id.z / CHUNK_SIZE;
+    if (chunk >= params.chunk_count) {
+        return;
+    }
+    let local = vec3<u32>(global_id.x, global_id.y, global_id.z % CHUNK_SIZE);
+    let origin = chunks.origins[chunk];
+    let position = vec3<f32>(
+        f32(origin.x + i32(local.x)),
+        f32(origin.y + i32(local.y)),
+        f32(origin.z + i32(local.z))
+    );
+
+    // Every octave doubles the frequency and halves the amplitude
+    var value: f32 = 0.0;
+    var frequency: f32 = params.frequency;
+    var amplitude: f32 = params.amplitude;
+    var octave: u32 = 0u;
+    loop {
+        if (octave >= params.octaves) {
+            break;
+        }
+        value = value + simplex((position + params.offset) * frequency) * amplitude;
+        frequency = frequency * 2.0;
+        amplitude = amplitude * 0.5;
+        octave = octave + 1u;
+    }
+
+    let index = chunk * CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE
+        + local.x * CHUNK_SIZE * CHUNK_SIZE + local.y * CHUNK_SIZE + local.z;
+    noise_output.values[index] = value + params.height - position.y;
+}
@@ -0,0 +1,41 @@
+// Vertex shader
+struct CameraUniform {
+    projection: mat4x4<f32>;
+    transform: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    [[location(0)]] position : vec3<f32>;
+    [[location(1)]] color : vec4<f32>;
+};
+
+struct InstanceInput {
+    [[location(6)]] model_0 : vec4<f32>;
+    [[location(7)]] model_1 : vec4<f32>;
+    [[location(8)]] model_2 : vec4<f32>;
+    [[location(9)]] model_3 : vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color : vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(in : VertexInput, instance : InstanceInput) -> VertexOutput {
+    var model: mat4x4<f32> = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+
+    var out: VertexOutput;
+    out.clip_position = camera.projection * camera.transform * model * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+ // Fragment shader, unlit
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
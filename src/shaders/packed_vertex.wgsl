@@ -0,0 +1,17 @@
+// Unpacks the fields of a PackedVertex, put in front of every shader that draws chunk meshes
+
+// Sixteenths of a voxel from half a voxel below the chunk's corner, 10 bits per axis
+fn unpack_position(word: u32) -> vec3<f32> {
+    return vec3<f32>(f32(word & 1023u), f32((word >> 10u) & 1023u), f32((word >> 20u) & 1023u)) / 16.0 - vec3<f32>(0.5);
+}
+
+// Octahedral encoding, 9 bits per component
+fn unpack_normal(word: u32) -> vec3<f32> {
+    var encoded: vec2<f32> = vec2<f32>(f32(word & 511u), f32((word >> 9u) & 511u)) / 255.0 - vec2<f32>(1.0);
+    var normal: vec3<f32> = vec3<f32>(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    var fold: f32 = max(-normal.z, 0.0);
+    normal.x = normal.x + select(fold, -fold, normal.x >= 0.0);
+    normal.y = normal.y + select(fold, -fold, normal.y >= 0.0);
+    return normalize(normal);
+}
+
@@ -7,11 +7,42 @@ struct CameraUniform {
 [[group(1), binding(0)]]
 var<uniform> camera: CameraUniform;
 
+struct LightUniform {
+    direction: vec3<f32>; // Towards the light
+    ambient: f32;
+    color: vec3<f32>;
+    view_projection: mat4x4<f32>; // Into the space of the shadow map
+};
+
+[[group(2), binding(0)]]
+var<uniform> light: LightUniform;
+[[group(2), binding(1)]]
+var t_shadow: texture_depth_2d;
+[[group(2), binding(2)]]
+var s_shadow: sampler_comparison;
+
+struct FogUniform {
+    color: vec3<f32>; // The sky color
+    start: f32;
+    end: f32;
+};
+
+[[group(2), binding(3)]]
+var<uniform> fog: FogUniform;
+
 struct VertexInput {
     [[location(0)]] position : vec3<f32>;
-    [[location(1)]] color : vec3<f32>;
+    [[location(1)]] color : vec4<f32>;
     [[location(2)]] normal : vec3<f32>;
     [[location(3)]] uv : vec2<f32>;
+    [[location(10)]] ao : f32;
+};
+
+struct InstanceInput {
+    [[location(6)]] model_0 : vec4<f32>;
+    [[location(7)]] model_1 : vec4<f32>;
+    [[location(8)]] model_2 : vec4<f32>;
+    [[location(9)]] model_3 : vec4<f32>;
 };
 
 struct VertexOutput {
@@ -20,18 +51,23 @@ struct VertexOutput {
     [[location(1)]] color : vec3<f32>;
     [[location(2)]] normal : vec3<f32>;
     [[location(3)]] uv : vec2<f32>;
-    [[location(4)]] camera_position : vec3<f32>;
+    [[location(4)]] view_position : vec3<f32>;
+    [[location(6)]] ao : f32;
 };
 
 [[stage(vertex)]]
-fn vs_main(in : VertexInput) -> VertexOutput {
+fn vs_main(in : VertexInput, instance : InstanceInput) -> VertexOutput {
+    var model: mat4x4<f32> = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    var world_position: vec4<f32> = model * vec4<f32>(in.position, 1.0);
+
     var out: VertexOutput;
-    out.clip_position = camera.projection * camera.transform * vec4<f32>(in.position, 1.0);
-    out.position = in.position;
-    out.color = in.color;
-    out.normal = in.normal;
+    out.clip_position = camera.projection * camera.transform * world_position;
+    out.position = world_position.xyz;
+    out.color = in.color.xyz;
+    out.normal = normalize((model * vec4<f32>(in.normal, 0.0)).xyz);
     out.uv = in.uv;
-    out.camera_position = (camera.transform * vec4<f32>(0.0, 0.0, 0.0, 0.0)).xyz;
+    out.ao = in.ao;
+    out.view_position = (camera.transform * world_position).xyz;
     return out;
 }
 
@@ -47,19 +83,46 @@ fn lerp4(a: vec4<f32>, b: vec4<f32>, t: f32) -> vec4<f32>{
     return vec4<f32>(lerp(a.x, b.x, t), lerp(a.y, b.y, t), lerp(a.z, b.z, t), lerp(a.w, b.w, t));
 }
 
+// How much of the sun reaches the position, averaged over the 3x3 texels around it to soften the edges.
+// Anything outside of the shadow map is lit
+fn sun_visibility(position: vec3<f32>) -> f32 {
+    var light_position: vec4<f32> = light.view_projection * vec4<f32>(position, 1.0);
+    var projected: vec3<f32> = light_position.xyz / light_position.w;
+    if (projected.x < -1.0 || projected.x > 1.0 || projected.y < -1.0 || projected.y > 1.0 || projected.z > 1.0) {
+        return 1.0;
+    }
+    var uv: vec2<f32> = projected.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    var texel: vec2<f32> = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+
+    var visibility: f32 = 0.0;
+    for (var x: i32 = -1; x <= 1; x = x + 1) {
+        for (var y: i32 = -1; y <= 1; y = y + 1) {
+            var offset: vec2<f32> = vec2<f32>(f32(x), f32(y)) * texel;
+            visibility = visibility + textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, projected.z);
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Fades into the sky with the distance to the camera, so chunks at the edge of the loaded area don't pop in
+fn apply_fog(color: vec3<f32>, view_position: vec3<f32>) -> vec3<f32> {
+    var amount: f32 = clamp((length(view_position) - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    return mix(color, fog.color, amount);
+}
+
  // Fragment shader
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
     var col: vec4<f32> = vec4<f32>(in.color, 1.0); //textureSample(t_diffuse, s_diffuse, in.uv) * in.color;
 
-    var light_dir: vec3<f32> = normalize(vec3<f32>(-0.5, 0.6, -0.3));
-    var ambient_light: f32 = 0.3;
-    var light_dot: f32 = clamp(dot(in.normal, light_dir), 0.0, 1.0);
+    // Faces turned away from the light only get the ambient term
+    var light_dot: f32 = clamp(dot(normalize(in.normal), light.direction), 0.0, 1.0);
+    var shading: vec3<f32> = light.color * light_dot * sun_visibility(in.position) + vec3<f32>(light.ambient);
 
-    var shading: f32 = light_dot;
+    // Fully occluded corners keep some light so caves don't turn pitch black
+    var occlusion: f32 = lerp(0.35, 1.0, in.ao);
 
-    col = vec4<f32>(col.xyz * (shading + ambient_light), 1.0);
-    col = col;
+    col = vec4<f32>(apply_fog(col.xyz * shading * occlusion, in.view_position), 1.0);
 
     return col;
 }
\ No newline at end of file
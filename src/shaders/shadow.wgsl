@@ -0,0 +1,42 @@
+// Depth only, every mesh that casts shadows is drawn with this from the sun's point of view
+struct LightUniform {
+    direction: vec3<f32>;
+    ambient: f32;
+    color: vec3<f32>;
+    view_projection: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> light: LightUniform;
+
+struct VertexInput {
+    [[location(0)]] position : vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(6)]] model_0 : vec4<f32>;
+    [[location(7)]] model_1 : vec4<f32>;
+    [[location(8)]] model_2 : vec4<f32>;
+    [[location(9)]] model_3 : vec4<f32>;
+};
+
+// Only the position of a packed vertex, see packed_vertex.wgsl
+struct PackedVertexInput {
+    [[location(0)]] data : vec3<u32>;
+};
+
+fn transform_position(position: vec3<f32>, instance : InstanceInput) -> vec4<f32> {
+    var model: mat4x4<f32> = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return light.view_projection * model * vec4<f32>(position, 1.0);
+}
+
+[[stage(vertex)]]
+fn vs_main(in : VertexInput, instance : InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    return transform_position(in.position, instance);
+}
+
+[[stage(vertex)]]
+fn vs_packed(in : PackedVertexInput, instance : InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    var position: vec3<f32> = unpack_position(in.data.x);
+    return transform_position(position, instance);
+}
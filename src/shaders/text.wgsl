@@ -0,0 +1,40 @@
+// Vertex shader
+struct ScreenUniform {
+    projection: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> screen: ScreenUniform;
+
+struct VertexInput {
+    [[location(0)]] position : vec2<f32>;
+    [[location(1)]] uv : vec2<f32>;
+    [[location(2)]] color : vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv : vec2<f32>;
+    [[location(1)]] color : vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(in : VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = screen.projection * vec4<f32>(in.position, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+[[group(0), binding(0)]]
+var t_font: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_font: sampler;
+
+ // Fragment shader
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var coverage: f32 = textureSample(t_font, s_font, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
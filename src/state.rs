@@ -1,12 +1,26 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::input_manager::send_character;
 use crate::input_manager::set_key;
 use crate::input_manager::set_mouse_button;
 use crate::input_manager::set_mouse_pos;
+use crate::input_manager::set_mouse_scroll;
 use crate::input_manager::PressState;
-use crate::rendering::camera::Camera;
-use crate::rendering::render_pass_data::render_layers;
+use crate::rendering::camera::{Camera, RenderTarget};
+use crate::rendering::crosshair::CrosshairRenderer;
+use crate::rendering::light::{FogUniform, Light};
+use crate::rendering::pipeline_cache::PipelineCache;
+use crate::rendering::render_pass_data::{render_layers::RenderLayers, PassSorting};
+use crate::rendering::render_settings::{
+    supported_sample_count, GraphicsSettings, RenderSettings, SKY_COLOR,
+};
+use crate::rendering::screenshot::Readback;
+use crate::rendering::text;
+use crate::rendering::text::TextRenderer;
 use crate::rendering::texture;
+use crate::rendering::texture_cache::TextureCache;
 use parking_lot::RwLock;
 use wgpu::BindGroupLayout;
 use wgpu::RenderPassDepthStencilAttachment;
@@ -15,19 +29,49 @@ use winit::event::KeyboardInput;
 use winit::event::WindowEvent;
 use winit::window::Window;
 
+// Where finished frames end up, a window surface or a texture when running headless
+pub enum OutputTarget {
+    Surface(wgpu::Surface),
+    Offscreen(texture::Texture),
+}
+
+// The attachments a camera's passes draw into
+struct PassTarget<'a> {
+    view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    depth_view: &'a wgpu::TextureView,
+    sample_count: u32,
+    size: (u32, u32),
+}
+
 pub struct State {
-    pub surface: wgpu::Surface,
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    pub output: OutputTarget,
+    pub device: Arc<wgpu::Device>, // Shared with workers that run compute work, like the GPU noise
+    pub queue: Arc<wgpu::Queue>,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub depth_texture: texture::Texture,
+    pub msaa_texture: Option<texture::Texture>, // Drawn to instead of the output when MSAA is on, then resolved
+    pub settings: GraphicsSettings,
+    pub render_settings: RenderSettings, // Read every frame, unlike the graphics settings they can change freely
+    pub supports_wireframe: bool,        // Whether the adapter can draw RenderDebugMode::Wireframe
+    pub supports_anisotropy: bool, // Without it samplers are created without anisotropic filtering
     pub camera_bind_group_layout: BindGroupLayout,
+    pub light_bind_group_layout: BindGroupLayout,
+    pub light: Light,
+    pub text_renderer: TextRenderer,
+    pub crosshair_renderer: CrosshairRenderer,
+    pub pipeline_cache: PipelineCache,
+    pub texture_cache: TextureCache,
+    pub render_layers: RenderLayers, // Cameras look their layers up here by name
+    pub rendered_vertices: usize,    // Indices drawn during the last frame, over every camera
+    pending_screenshot: Option<PathBuf>,
+    capture_texture: Option<texture::Texture>, // Drawn to instead of the window while a screenshot is taken
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, settings: GraphicsSettings) -> Self {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -44,33 +88,112 @@ impl State {
             .next()
             .unwrap(); // Finds a suitable adapter
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let supports_anisotropy = Self::supports_anisotropy(&adapter);
+        let (device, queue) = Self::request_device(&adapter).await;
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface.get_preferred_format(&adapter).unwrap(),
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo, // Fifo effectively acts like VSync, I don't know why.
+            present_mode: settings.vsync, // Fifo waits for vblank, Immediate tears but never blocks
         };
         surface.configure(&device, &config);
 
-        // Load surface texture
-        surface.configure(&device, &config);
+        Self::from_parts(
+            OutputTarget::Surface(surface),
+            device,
+            queue,
+            config,
+            size,
+            settings,
+            supports_anisotropy,
+        )
+    }
+
+    // Renders into an offscreen texture instead of a window, returns None when no adapter is available
+    pub async fn new_headless(width: u32, height: u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        let supports_anisotropy = Self::supports_anisotropy(&adapter);
+        let (device, queue) = Self::request_device(&adapter).await;
+
+        let size = winit::dpi::PhysicalSize::new(width.max(1), height.max(1));
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo, // Unused without a surface
+        };
+        let output = texture::Texture::create_render_texture(&device, &config, "output_texture");
+
+        Some(Self::from_parts(
+            OutputTarget::Offscreen(output),
+            device,
+            queue,
+            config,
+            size,
+            GraphicsSettings::default(),
+            supports_anisotropy,
+        ))
+    }
 
-        // Depth texture
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+    // Anisotropic filtering is a downlevel capability rather than a feature, samplers silently drop it when missing
+    fn supports_anisotropy(adapter: &wgpu::Adapter) -> bool {
+        let supported = adapter
+            .get_downlevel_properties()
+            .flags
+            .contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING);
+        println!("[INFO] Anisotropic filtering supported: {supported}");
+        supported
+    }
+
+    // Line rendering is only used by the wireframe debug view, so it's requested but not required
+    async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+                    limits: wgpu::Limits::default(),
+                    label: None,
+                },
+                None, // Trace path
+            )
+            .await
+            .unwrap()
+    }
+
+    fn from_parts(
+        output: OutputTarget,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: GraphicsSettings,
+        supports_anisotropy: bool,
+    ) -> Self {
+        let settings = GraphicsSettings {
+            msaa_samples: supported_sample_count(settings.msaa_samples),
+            ..settings
+        };
+        let depth_texture = texture::Texture::create_depth_texture(
+            &device,
+            &config,
+            settings.msaa_samples,
+            "depth_texture",
+        );
+        let msaa_texture = Self::create_msaa_texture(&device, &config, settings.msaa_samples);
+        let supports_wireframe = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        println!("[INFO] Wireframe rendering supported: {supports_wireframe}");
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -87,14 +210,35 @@ impl State {
                 label: Some("camera_bind_group_layout"),
             });
 
+        let light_bind_group_layout = Light::create_bind_group_layout(&device);
+        let light = Light::new(&device, &light_bind_group_layout, &settings);
+
+        let text_renderer = TextRenderer::new(&device, &queue, config.format);
+        let crosshair_renderer = CrosshairRenderer::new(&device, config.format);
+
         Self {
-            surface,
-            device,
-            queue,
+            output,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
             config,
             size,
             depth_texture,
+            msaa_texture,
+            settings,
+            render_settings: RenderSettings::default(),
+            supports_wireframe,
+            supports_anisotropy,
             camera_bind_group_layout,
+            light_bind_group_layout,
+            light,
+            text_renderer,
+            crosshair_renderer,
+            pipeline_cache: PipelineCache::default(),
+            texture_cache: TextureCache::default(),
+            render_layers: RenderLayers::default(),
+            rendered_vertices: 0,
+            pending_screenshot: None,
+            capture_texture: None,
         }
     }
 
@@ -104,14 +248,78 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            //self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+            self.recreate_targets();
+        }
+    }
+
+    // Takes effect from the next frame on, no restart needed
+    pub fn apply_settings(&mut self, settings: GraphicsSettings) {
+        let settings = GraphicsSettings {
+            msaa_samples: supported_sample_count(settings.msaa_samples),
+            ..settings
+        };
+        if settings.msaa_samples != self.settings.msaa_samples {
+            self.pipeline_cache.clear_pipelines();
+        }
+        if settings.shadow_depth_bias != self.settings.shadow_depth_bias
+            || settings.shadow_slope_bias != self.settings.shadow_slope_bias
+        {
+            self.light
+                .shadow_map
+                .apply_settings(&self.device, &settings);
+        }
+        self.settings = settings;
+        self.config.present_mode = settings.vsync;
+        self.recreate_targets();
+        println!(
+            "[INFO] Graphics settings: {:?}, {}x MSAA",
+            settings.vsync, settings.msaa_samples
+        );
+    }
 
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+    // The sampler with the anisotropy level of the settings, left off when the adapter can't filter anisotropically
+    pub fn sampler_config(&self, config: texture::SamplerConfig) -> texture::SamplerConfig {
+        let anisotropy = self.settings.anisotropy;
+        texture::SamplerConfig {
+            anisotropy_clamp: (self.supports_anisotropy && anisotropy > 1).then_some(anisotropy),
+            ..config
         }
     }
 
+    // Everything sized like the output or depending on the settings
+    fn recreate_targets(&mut self) {
+        match &mut self.output {
+            OutputTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+            OutputTarget::Offscreen(texture) => {
+                *texture = texture::Texture::create_render_texture(
+                    &self.device,
+                    &self.config,
+                    "output_texture",
+                )
+            }
+        }
+        // Cameras drawing to the window pick up its new aspect ratio in render
+
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.settings.msaa_samples,
+            "depth_texture",
+        );
+        self.msaa_texture =
+            Self::create_msaa_texture(&self.device, &self.config, self.settings.msaa_samples);
+    }
+
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<texture::Texture> {
+        (sample_count > 1).then(|| {
+            texture::Texture::create_msaa_texture(device, config, sample_count, "msaa_texture")
+        })
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -134,10 +342,18 @@ impl State {
                 );
                 true
             }
+            WindowEvent::ReceivedCharacter(character) => {
+                send_character(*character);
+                true
+            }
             WindowEvent::CursorMoved { position, .. } => {
                 set_mouse_pos(position);
                 true
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                set_mouse_scroll(delta);
+                true
+            }
             WindowEvent::MouseInput { state, button, .. } => {
                 set_mouse_button(
                     button,
@@ -153,8 +369,59 @@ impl State {
         }
     }
 
+    // The next frame is saved as a PNG at the path
+    pub fn capture_screenshot(&mut self, path: &Path) {
+        self.pending_screenshot = Some(path.to_path_buf());
+    }
+
     pub fn render(&mut self, cameras: Vec<Arc<RwLock<Camera>>>) -> Result<(), wgpu::SurfaceError> {
+        let overlay_text = text::take_queued_text();
+        // Surface textures can't be copied from, so a frame that's captured is drawn into a texture instead.
+        // That frame isn't presented, the window keeps showing the one before it
+        let screenshot = self.pending_screenshot.take();
+        if screenshot.is_some() && matches!(self.output, OutputTarget::Surface(_)) {
+            self.capture_texture = Some(texture::Texture::create_render_texture(
+                &self.device,
+                &self.config,
+                "capture_texture",
+            ));
+        }
+        self.queue.write_buffer(
+            &self.light.buffer,
+            0,
+            bytemuck::cast_slice(&[self.light.uniform]),
+        );
+        self.light.fog = FogUniform::new(self.render_settings.fog);
+        self.queue.write_buffer(
+            &self.light.fog_buffer,
+            0,
+            bytemuck::cast_slice(&[self.light.fog]),
+        );
+
+        // Texture targets are drawn first, so materials sampling them show this frame's image
+        let mut cameras = cameras;
+        cameras.sort_by_key(|camera| matches!(camera.read().target, RenderTarget::Surface));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            }); // The encoder is responsible for sending commands to the GPU via a command buffer.
+
+        // Every camera samples the same shadow map, so it's drawn once up front
+        self.draw_shadows(&mut encoder);
+
+        // The output is acquired by the first camera drawing to it, and presented once everything is drawn
+        let mut frame = None;
+        let mut rendered_vertices = 0;
         for camera in &cameras {
+            // Resizing the window changes the aspect ratio of the cameras drawing to it
+            {
+                let mut camera_lock = camera.write();
+                if camera_lock.fit_to_target(self) {
+                    camera_lock.update_uniform();
+                }
+            }
             // Write the camera uniform into the buffer
             let camera_lock = camera.read();
             self.queue.write_buffer(
@@ -163,124 +430,378 @@ impl State {
                 bytemuck::cast_slice(&[camera_lock.uniform]),
             );
 
-            let output = self.surface.get_current_texture()?;
-            let view = output
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
-
-            // Create a clear pass
-            let mut encoder = self
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                }); // The encoder is responsible for sending commands to the GPU via a command buffer.
-            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what [[location(0)]] in the fragment shader targets
-                    wgpu::RenderPassColorAttachment {
-                        view: &view,
+            let target = match &camera_lock.target {
+                RenderTarget::Surface => {
+                    // Cleared once per frame, so cameras sharing the surface don't wipe each other's viewports
+                    if frame.is_none() {
+                        frame = Some(self.acquire_frame()?);
+                        self.clear(
+                            &mut encoder,
+                            &self.surface_pass_target(frame.as_ref().unwrap()),
+                        );
+                    }
+                    self.surface_pass_target(frame.as_ref().unwrap())
+                }
+                RenderTarget::Texture {
+                    color,
+                    depth,
+                    width,
+                    height,
+                } => {
+                    let target = PassTarget {
+                        view: &color.view,
                         resolve_target: None,
+                        depth_view: &depth.view,
+                        sample_count: 1,
+                        size: (*width, *height),
+                    };
+                    self.clear(&mut encoder, &target);
+                    target
+                }
+            };
+            rendered_vertices += self.draw_camera(&mut encoder, &camera_lock, &target);
+        }
+        self.rendered_vertices = rendered_vertices;
+
+        // The crosshair and text overlay belong to the window rather than to a camera, they're drawn once on top of
+        // everything. Without a camera drawing to the window they're drawn over the sky
+        let (output, view) = match frame {
+            Some(frame) => frame,
+            None => {
+                let frame = self.acquire_frame()?;
+                self.clear(&mut encoder, &self.surface_pass_target(&frame));
+                frame
+            }
+        };
+        self.crosshair_renderer
+            .render(&self.device, &mut encoder, &view, self.size);
+        self.text_renderer.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            self.size,
+            &overlay_text,
+        );
+
+        // The finished frame is copied out with the rest of the frame's work and saved once the copy is done
+        let readback = screenshot.and_then(|path| {
+            let texture = self.capture_source()?;
+            Some((
+                Readback::copy(
+                    &self.device,
+                    &mut encoder,
+                    texture,
+                    (self.size.width, self.size.height),
+                    self.config.format,
+                ),
+                path,
+            ))
+        });
+
+        // submit will accept anything that implements IntoIter
+        self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(output) = output {
+            output.present();
+        }
+        if let Some((readback, path)) = readback {
+            readback.save_png_in_background(Arc::clone(&self.device), path);
+        }
+        self.capture_texture = None;
+
+        Ok(())
+    }
+
+    // With MSAA on the camera draws into the multisampled texture, which is resolved into the frame after each pass
+    fn surface_pass_target<'a>(
+        &'a self,
+        frame: &'a (Option<wgpu::SurfaceTexture>, wgpu::TextureView),
+    ) -> PassTarget<'a> {
+        let (_, frame_view) = frame;
+        let (view, resolve_target) = match &self.msaa_texture {
+            Some(msaa_texture) => (&msaa_texture.view, Some(frame_view)),
+            None => (frame_view, None),
+        };
+        PassTarget {
+            view,
+            resolve_target,
+            depth_view: &self.depth_texture.view,
+            sample_count: self.settings.msaa_samples,
+            size: (self.size.width, self.size.height),
+        }
+    }
+
+    fn clear(&self, encoder: &mut wgpu::CommandEncoder, target: &PassTarget) {
+        // The pass does nothing but clear, it ends as soon as it is dropped
+        let _clear_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[
+                // This is what [[location(0)]] in the fragment shader targets
+                wgpu::RenderPassColorAttachment {
+                    view: target.view,
+                    resolve_target: target.resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: SKY_COLOR[0] as f64,
+                            g: SKY_COLOR[1] as f64,
+                            b: SKY_COLOR[2] as f64,
+                            a: 1.0,
+                        }),
+                        store: true,
+                    },
+                },
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: target.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+    }
+
+    fn draw_camera(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        target: &PassTarget,
+    ) -> usize {
+        let viewport = camera.viewport.to_pixels(target.size.0, target.size.1);
+        // Draw the camera's passes in layer order
+        let mut rendered_vertices = 0;
+        let layers = self.render_layers.get_sorted_layers(camera.render_layers());
+        for layer in layers {
+            let layer_lock = layer.read();
+
+            // Do a pass
+            for pass_data in layer_lock.sorted_passes(camera.position) {
+                // Prepare data
+                let pass_lock = pass_data.write();
+                let material_lock = pass_lock.material.read();
+                let pipeline = Arc::clone(&material_lock.get_pipeline(self, target.sample_count));
+                let texture_bind_group = Arc::clone(&material_lock.get_texture_bind_group(self));
+
+                // Create the pass
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: target.view,
+                        resolve_target: target.resolve_target,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.3,
-                                g: 0.4,
-                                b: 0.6,
-                                a: 1.0,
-                            }),
+                            load: wgpu::LoadOp::Load,
                             store: true,
                         },
-                    },
-                ],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
+                    }],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: target.depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
                     }),
-                    stencil_ops: None,
-                }),
-            });
-
-            // Check if the camera has anything to draw before trying to draw
-            if camera_lock.render_layers.len() == 0 {
-                self.queue.submit(std::iter::once(encoder.finish()));
-                output.present();
-                continue;
-            }
-            let mut has_passes = true;
-            for layer in render_layers::RENDER_LAYERS.iter() {
-                let layer_lock = layer.read();
-                if layer_lock.passes.len() == 0 {
-                    has_passes = false;
-                    break;
+                });
+                let [x, y, width, height] = viewport;
+                render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                render_pass.set_pipeline(&pipeline);
+                render_pass.set_bind_group(0, &texture_bind_group, &[]);
+                render_pass.set_bind_group(1, &camera.bind_group, &[]);
+                render_pass.set_bind_group(2, &self.light.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, pass_lock.buffer.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, pass_lock.buffer.instance_buffer.slice(..));
+                render_pass.set_index_buffer(
+                    pass_lock.buffer.index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                let draws: Vec<(Range<u32>, u32)> = match layer_lock.sorting {
+                    PassSorting::Material => pass_lock.buffer.draws().collect(),
+                    PassSorting::BackToFront => {
+                        pass_lock.buffer.draws_back_to_front(camera.position)
+                    }
+                };
+                for (indices, instance) in draws {
+                    rendered_vertices += indices.len();
+                    render_pass.draw_indexed(indices, 0, instance..instance + 1);
                 }
+                drop(render_pass); // Required to release the borrow of encoder
             }
-            if !has_passes {
-                self.queue.submit(std::iter::once(encoder.finish()));
-                output.present();
-                continue;
-            }
+        }
+        rendered_vertices
+    }
 
-            // Camera has passes, draw them
-            for layer in &camera_lock.render_layers {
-                let layer = render_layers::get_layer_by_name(layer.to_string());
-                let layer = match layer {
-                    Some(l) => l,
-                    None => continue,
-                };
-                let layer_lock = layer.read();
-
-                // Do a pass
-                for (_pass_id, pass_data) in &layer_lock.passes {
-                    // Prepare data
-                    let pass_lock = pass_data.write();
-                    let material_lock = pass_lock.material.read();
-                    let pipeline = Arc::clone(&material_lock.get_pipeline(self));
-                    let texture_bind_group =
-                        Arc::clone(&material_lock.get_texture_bind_group(self));
-
-                    // Create the pass
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Render Pass"),
-                        color_attachments: &[wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: true,
-                            },
-                        }],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: &self.depth_texture.view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Load,
-                                store: true,
-                            }),
-                            stencil_ops: None,
-                        }),
-                    });
-                    render_pass.set_pipeline(&pipeline);
-                    render_pass.set_bind_group(0, &texture_bind_group, &[]);
-                    render_pass.set_bind_group(1, &camera_lock.bind_group, &[]);
-                    render_pass.set_vertex_buffer(0, pass_lock.buffer.vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(
-                        pass_lock.buffer.index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    // println!("Drawing mesh");
-                    // println!("{} vertices", pass_lock.buffer.vertex_count);
-                    // println!("{} indices", pass_lock.buffer.index_count);
-                    render_pass.draw_indexed(0..pass_lock.buffer.index_count, 0, 0..1);
-                    drop(render_pass); // Required to release the borrow of encoder
+    // Draws every pass whose material casts shadows into the shadow map, from the sun's point of view.
+    // Layers don't matter here, anything in the sun's view that casts shadows is drawn
+    fn draw_shadows(&self, encoder: &mut wgpu::CommandEncoder) {
+        let shadow_map = &self.light.shadow_map;
+        let depth_attachment = |load| {
+            Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &shadow_map.texture.view,
+                depth_ops: Some(wgpu::Operations { load, store: true }),
+                stencil_ops: None,
+            })
+        };
+        // Cleared even when nothing casts shadows, so no shadows of despawned meshes are left behind
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Clear Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: depth_attachment(wgpu::LoadOp::Clear(1.0)),
+        });
+
+        for layer in self.render_layers.all() {
+            let layer_lock = layer.read();
+            for pass_data in layer_lock.passes.values() {
+                let pass_lock = pass_data.read();
+                if !pass_lock.material.read().casts_shadows() {
+                    continue;
                 }
+                let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Shadow Pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: depth_attachment(wgpu::LoadOp::Load),
+                });
+                shadow_pass.set_pipeline(shadow_map.pipeline_for(pass_lock.buffer.vertex_format));
+                shadow_pass.set_bind_group(0, &shadow_map.bind_group, &[]);
+                shadow_pass.set_vertex_buffer(0, pass_lock.buffer.vertex_buffer.slice(..));
+                shadow_pass.set_vertex_buffer(1, pass_lock.buffer.instance_buffer.slice(..));
+                shadow_pass.set_index_buffer(
+                    pass_lock.buffer.index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                for (indices, instance) in pass_lock.buffer.draws() {
+                    shadow_pass.draw_indexed(indices, 0, instance..instance + 1);
+                }
+                drop(shadow_pass); // Required to release the borrow of encoder
             }
+        }
+    }
 
-            // submit will accept anything that implements IntoIter
-            self.queue.submit(std::iter::once(encoder.finish()));
-            output.present();
+    // Surface textures have to be presented once drawn, offscreen and captured frames are simply kept in the texture
+    fn acquire_frame(
+        &self,
+    ) -> Result<(Option<wgpu::SurfaceTexture>, wgpu::TextureView), wgpu::SurfaceError> {
+        if let Some(capture) = &self.capture_texture {
+            return Ok((
+                None,
+                capture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            ));
         }
+        match &self.output {
+            OutputTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Ok((Some(output), view))
+            }
+            OutputTarget::Offscreen(texture) => Ok((
+                None,
+                texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            )),
+        }
+    }
 
-        Ok(())
+    // The texture holding the frame being drawn, if it can be copied from
+    fn capture_source(&self) -> Option<&wgpu::Texture> {
+        match (&self.capture_texture, &self.output) {
+            (Some(capture), _) => Some(&capture.texture),
+            (None, OutputTarget::Offscreen(texture)) => Some(&texture.texture),
+            (None, OutputTarget::Surface(_)) => None,
+        }
+    }
+
+    // Copies the last offscreen frame back to the CPU as tightly packed RGBA rows.
+    // Surface textures can't be copied from, so this is None when rendering to a window
+    pub fn read_output_pixels(&self) -> Option<Vec<u8>> {
+        let texture = match &self.output {
+            OutputTarget::Offscreen(texture) => texture,
+            OutputTarget::Surface(_) => return None,
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        let readback = Readback::copy(
+            &self.device,
+            &mut encoder,
+            &texture.texture,
+            (self.size.width, self.size.height),
+            self.config.format,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        readback.read(&self.device).ok()
+    }
+}
+
+// For tests that need a GPU, they're skipped on machines without an adapter
+#[cfg(test)]
+pub(crate) fn headless_state() -> Option<State> {
+    headless_state_with_size(4, 4)
+}
+
+#[cfg(test)]
+pub(crate) fn headless_state_with_size(width: u32, height: u32) -> Option<State> {
+    let state = pollster::block_on(State::new_headless(width, height));
+    if state.is_none() {
+        println!("[INFO] No adapter available, skipping the test");
+    }
+    state
+}
+
+#[cfg(test)]
+mod headless_tests {
+    use super::*;
+
+    #[test]
+    fn headless_frame_is_cleared() {
+        let mut state = match headless_state_with_size(64, 32) {
+            Some(state) => state,
+            None => return,
+        };
+        let camera = Arc::new(RwLock::new(Camera::new(&state)));
+        state.render(vec![camera]).unwrap();
+
+        let pixels = state.read_output_pixels().unwrap();
+        assert_eq!(pixels.len(), 64 * 32 * 4);
+        // Every pixel holds the opaque sky clear color
+        assert!(pixels
+            .chunks(4)
+            .all(|pixel| pixel[3] == 255 && pixel[2] > pixel[0]));
+    }
+
+    #[test]
+    fn window_is_drawn_without_cameras() {
+        let mut state = match headless_state_with_size(64, 32) {
+            Some(state) => state,
+            None => return,
+        };
+        state.render(Vec::new()).unwrap();
+
+        // Cleared for the overlay even though no camera draws to it, the corners are away from the crosshair
+        let pixels = state.read_output_pixels().unwrap();
+        assert_eq!(pixels[3], 255);
+        assert!(pixels[2] > pixels[0]);
+    }
+
+    #[test]
+    fn samplers_take_the_anisotropy_setting() {
+        let mut state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let config = texture::SamplerConfig::default();
+        assert_eq!(state.sampler_config(config).anisotropy_clamp, None);
+
+        state.settings.anisotropy = 8;
+        let expected = state.supports_anisotropy.then_some(8);
+        assert_eq!(state.sampler_config(config).anisotropy_clamp, expected);
+        state.sampler_config(config).create_sampler(&state.device);
     }
 }
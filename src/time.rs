@@ -1,4 +1,148 @@
+// Kept for the whole run and advanced every tick, gameplay systems use the scaled delta_time so they stop while
+// paused. Anything that has to keep moving, like the camera, uses unscaled_delta
 pub struct Time {
-    pub time: f64,
-    pub delta_time: f64,
+    pub time: f64,           // Scaled time since the start
+    pub delta_time: f64,     // Scaled by time_scale, 0 while paused
+    pub unscaled_delta: f64, // Real time since the previous tick or frame
+    pub fixed_delta: f64,    // The tick interval
+    pub frame_index: u64,    // How many times the time was advanced
+    pub alpha: f64, // How far along the next tick the simulation is, for interpolating between the last two steps
+    pub time_scale: f64,
+    pub tick_overrun: f64, // How much longer than the tick interval the previous tick took, 0 when it kept up
+    resume_scale: f64,     // The time scale to go back to after a pause
+}
+
+impl Time {
+    pub fn new(fixed_delta: f64) -> Self {
+        Self {
+            time: 0.0,
+            delta_time: 0.0,
+            unscaled_delta: 0.0,
+            fixed_delta,
+            frame_index: 0,
+            alpha: 0.0,
+            time_scale: 1.0,
+            tick_overrun: 0.0,
+            resume_scale: 1.0,
+        }
+    }
+
+    pub fn advance(&mut self, real_dt: f64) {
+        self.frame_index += 1;
+        self.unscaled_delta = real_dt;
+        self.delta_time = real_dt * self.time_scale;
+        self.time += self.delta_time;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.time_scale == 0.0
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.is_paused() {
+            self.time_scale = self.resume_scale;
+        } else {
+            self.resume_scale = self.time_scale;
+            self.time_scale = 0.0;
+        }
+    }
+}
+
+// Caps the ticks run to catch up at once, the rest of the backlog is dropped instead of snowballing
+pub const MAX_TICKS_PER_UPDATE: u32 = 4;
+
+// Turns wall clock time into a fixed number of ticks, so systems always see the same delta time
+pub struct TickClock {
+    accumulator: f64, // Time that has passed but hasn't been ticked yet
+    pub time: f64,    // Simulated time, advanced by the tick interval every tick
+}
+
+impl TickClock {
+    pub fn new() -> Self {
+        Self {
+            accumulator: 0.0,
+            time: 0.0,
+        }
+    }
+
+    // Returns how many ticks are due after the elapsed time
+    pub fn advance(&mut self, elapsed: f64, tick_interval: f64) -> u32 {
+        self.accumulator += elapsed;
+        let due = (self.accumulator / tick_interval).floor() as u32;
+        let ticks = due.min(MAX_TICKS_PER_UPDATE);
+        self.accumulator = if due > ticks {
+            0.0
+        } else {
+            self.accumulator - ticks as f64 * tick_interval
+        };
+        self.time += ticks as f64 * tick_interval;
+        ticks
+    }
+
+    // How far the time that hasn't been ticked yet is into the next tick, between 0 and 1
+    pub fn alpha(&self, tick_interval: f64) -> f64 {
+        (self.accumulator / tick_interval).min(1.0)
+    }
+
+    // How long to wait until the next tick is due
+    pub fn until_next_tick(&self, tick_interval: f64) -> f64 {
+        (tick_interval - self.accumulator).max(0.0)
+    }
+}
+
+impl Default for TickClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The shortest of several runs, for the timing comparisons in the ignored benchmark tests. The fastest run is the one
+// least disturbed by whatever else the machine was doing
+#[cfg(test)]
+pub(crate) fn fastest_of(runs: usize, mut run: impl FnMut()) -> std::time::Duration {
+    (0..runs)
+        .map(|_| {
+            let start = std::time::Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tick_clock_tests {
+    use super::*;
+
+    #[test]
+    fn pausing_stops_scaled_time_only() {
+        let mut time = Time::new(0.02);
+        time.time_scale = 0.5;
+        time.advance(0.02);
+        assert_eq!(time.delta_time, 0.01);
+
+        time.toggle_pause();
+        time.advance(0.02);
+        assert_eq!(time.delta_time, 0.0);
+        assert_eq!(time.unscaled_delta, 0.02);
+        assert_eq!(time.time, 0.01);
+
+        // Resumes at the speed it had before
+        time.toggle_pause();
+        assert_eq!(time.time_scale, 0.5);
+        assert_eq!(time.frame_index, 2);
+    }
+
+    #[test]
+    fn ticks_accumulate_and_cap() {
+        let mut clock = TickClock::new();
+        assert_eq!(clock.advance(0.01, 0.02), 0);
+        assert_eq!(clock.advance(0.015, 0.02), 1);
+        assert!((clock.until_next_tick(0.02) - 0.015).abs() < 1e-9);
+
+        // A long stall only runs the capped number of ticks and forgets the rest
+        assert_eq!(clock.advance(1.0, 0.02), MAX_TICKS_PER_UPDATE);
+        assert_eq!(clock.until_next_tick(0.02), 0.02);
+        assert!((clock.time - 0.02 * (1 + MAX_TICKS_PER_UPDATE) as f64).abs() < 1e-9);
+    }
 }
@@ -1,6 +1,14 @@
-use std::{collections::HashMap, fs, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use glam::{IVec3, Vec3};
+use noise::{NoiseFn, Perlin};
 use parking_lot::RwLock;
 
 use crate::voxels::biome_profile::instructions::{
@@ -15,106 +23,353 @@ use self::instructions::{
 };
 
 use super::{
-    voxel_data::VoxelData,
+    voxel_data::{voxel_state, VoxelData},
     voxel_registry::get_voxel_by_name,
     voxel_shapes::{voxel_shape, VoxelShape},
 };
 
+const BIOME_PROFILE_FOLDER: &str = "./src/resources/biome_profiles/";
+
 lazy_static! {
-    static ref BIOMES: RwLock<HashMap<String, Arc<BiomeProfile>>> = RwLock::new(load_biomes());
+    static ref BIOMES: RwLock<HashMap<String, Arc<BiomeProfile>>> =
+        RwLock::new(load_biomes().map_or_else(|_| HashMap::new(), |(biomes, _)| biomes));
+    // The maps shared by chunk generation by seed, with the biome version they were built from
+    static ref BIOME_MAPS: RwLock<HashMap<u64, (u64, Arc<BiomeMap>)>> = RwLock::new(HashMap::new());
 }
 
-fn load_biomes() -> HashMap<String, Arc<BiomeProfile>> {
-    let paths = fs::read_dir("./src/resources/biome_profiles/").unwrap();
+// Bumped by every reload, so the shared biome map is built again
+static BIOME_VERSION: AtomicU64 = AtomicU64::new(0);
+
+// Biomes that fail to parse are logged and left out, the rest still load. Fails if the folder can't be read
+fn load_biomes(
+) -> Result<(HashMap<String, Arc<BiomeProfile>>, Vec<BiomeParseError>), BiomeParseError> {
+    let paths = fs::read_dir(BIOME_PROFILE_FOLDER).map_err(|e| {
+        let error = BiomeParseError {
+            file: BIOME_PROFILE_FOLDER.to_string(),
+            formula: String::new(),
+            offset: 0,
+            message: format!("Unable to read folder: {e}"),
+        };
+        println!("[INFO] Failed to load biomes: {error}");
+        error
+    })?;
     let mut map = HashMap::new();
-
-    for biome_file in paths.into_iter() {
-        let biome_file = biome_file.unwrap();
-
-        let name = biome_file
-            .file_name()
-            .to_string_lossy()
-            .replace(".json", "");
-
-        map.insert(
-            name.to_string(),
-            Arc::new(BiomeProfile::from_json(
-                fs::read_to_string(biome_file.path()).unwrap(),
-            )),
-        );
-
-        println!("==Created Biome Profile==");
-        println!("Name: {name}");
-        println!("");
+    let mut errors = Vec::new();
+
+    for biome_file in paths.into_iter().flatten() {
+        let file_name = biome_file.file_name().to_string_lossy().to_string();
+        let name = file_name.replace(".json", "");
+
+        let biome = fs::read_to_string(biome_file.path())
+            .map_err(|e| BiomeParseError {
+                file: file_name.clone(),
+                formula: String::new(),
+                offset: 0,
+                message: format!("Unable to read file: {e}"),
+            })
+            .and_then(|data| BiomeProfile::from_json(&file_name, &data));
+        match biome {
+            Ok(biome) => {
+                map.insert(name.to_string(), Arc::new(biome));
+
+                println!("==Created Biome Profile==");
+                println!("Name: {name}");
+                println!("");
+            }
+            Err(error) => {
+                println!("[INFO] Skipping biome {name}: {error}");
+                errors.push(error);
+            }
+        }
     }
 
-    return map;
+    return Ok((map, errors));
 }
 
-pub fn reload_biomes() {
+// Biomes that no longer parse keep their previous version, so a bad live edit doesn't remove them. All of them do
+// if the folder can't be read
+pub fn reload_biomes() -> Vec<BiomeParseError> {
+    let (biomes, errors) = match load_biomes() {
+        Ok(loaded) => loaded,
+        Err(error) => return vec![error],
+    };
     let mut lock = BIOMES.write();
-    lock.clear();
-    lock.extend(load_biomes());
+    lock.retain(|name, _| {
+        errors
+            .iter()
+            .any(|error| error.file.replace(".json", "") == *name)
+    });
+    lock.extend(biomes);
+    BIOME_VERSION.fetch_add(1, Ordering::Relaxed);
+    errors
+}
+
+// Built once per seed and shared between the chunks being generated, reloading the biomes builds them again
+pub fn shared_biome_map(seed: u64) -> Arc<BiomeMap> {
+    // Read before building, a reload while building leaves the map outdated and it's built again next time
+    let version = BIOME_VERSION.load(Ordering::Relaxed);
+    if let Some((map_version, map)) = BIOME_MAPS.read().get(&seed) {
+        if *map_version == version {
+            return Arc::clone(map);
+        }
+    }
+    let map = Arc::new(BiomeMap::new(seed));
+    BIOME_MAPS.write().insert(seed, (version, Arc::clone(&map)));
+    map
 }
 
 pub fn get_biome_by_name(name: String) -> Option<Arc<BiomeProfile>> {
     BIOMES.read().get(&name).map(|v| Arc::clone(&v))
 }
 
+// Biomes are chosen per column by the nearest climate, (moisture, temperature), both roughly within -1 to 1
+pub struct BiomeMap {
+    moisture: Perlin,
+    temperature: Perlin,
+    biomes: Vec<Arc<BiomeProfile>>,
+}
+
+// Biomes whose climate is within this distance of the nearest one get blended in
+const BLEND_WIDTH: f32 = 0.15;
+const CLIMATE_WAVELENGTH: f64 = 300.0;
+
+impl BiomeMap {
+    // Takes a snapshot of the loaded biomes, reloaded biomes are picked up by the next map
+    pub fn new(seed: u64) -> Self {
+        let biomes = BIOMES.read();
+        let mut names: Vec<&String> = biomes.keys().collect();
+        names.sort();
+        Self::from_biomes(
+            seed,
+            names
+                .iter()
+                .map(|name| Arc::clone(&biomes[*name]))
+                .collect(),
+        )
+    }
+
+    pub fn from_biomes(seed: u64, biomes: Vec<Arc<BiomeProfile>>) -> Self {
+        assert!(!biomes.is_empty(), "A biome map needs at least one biome");
+        Self {
+            moisture: instructions::perlin_for_seed(seed ^ 0x6d6f_6973_7475_7265),
+            temperature: instructions::perlin_for_seed(seed ^ 0x7465_6d70_6572_6174),
+            biomes,
+        }
+    }
+
+    pub fn climate_at(&self, x: i32, z: i32) -> (f32, f32) {
+        let sample = |perlin: &Perlin| {
+            perlin.get([x as f64 / CLIMATE_WAVELENGTH, z as f64 / CLIMATE_WAVELENGTH]) as f32
+        };
+        (sample(&self.moisture), sample(&self.temperature))
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> Arc<BiomeProfile> {
+        let (moisture, temperature) = self.climate_at(x, z);
+        let nearest = self
+            .biomes
+            .iter()
+            .min_by(|a, b| {
+                let a = a.climate_distance(moisture, temperature);
+                let b = b.climate_distance(moisture, temperature);
+                a.total_cmp(&b)
+            })
+            .unwrap();
+        Arc::clone(nearest)
+    }
+
+    // Every biome close enough in climate space to contribute, with weights adding up to 1, nearest first
+    pub fn weights_for_climate(
+        &self,
+        moisture: f32,
+        temperature: f32,
+    ) -> Vec<(Arc<BiomeProfile>, f32)> {
+        let distances: Vec<f32> = self
+            .biomes
+            .iter()
+            .map(|biome| biome.climate_distance(moisture, temperature))
+            .collect();
+        let nearest = distances.iter().cloned().fold(f32::INFINITY, f32::min);
+
+        // Weights only depend on how much further than the nearest biome a biome is, so they change smoothly
+        // when the nearest biome changes
+        let mut weights: Vec<(Arc<BiomeProfile>, f32)> = self
+            .biomes
+            .iter()
+            .zip(distances)
+            .filter_map(|(biome, distance)| {
+                let weight = (BLEND_WIDTH - (distance - nearest)).max(0.0).powi(2);
+                (weight > 0.0).then(|| (Arc::clone(biome), weight))
+            })
+            .collect();
+        let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        weights.iter_mut().for_each(|(_, weight)| *weight /= total);
+        weights.sort_by(|a, b| b.1.total_cmp(&a.1));
+        weights
+    }
+}
+
+// A structure the biome places on its surface, rate is the chance per surface column
+pub struct StructureSpawn {
+    pub structure: String,
+    pub rate: f32,
+    pub on: Option<u16>, // The surface voxel it grows on, any solid voxel when None
+}
+
 pub struct BiomeProfile {
+    moisture_range: (f32, f32),
+    temperature_range: (f32, f32),
     density_formula: Arc<Box<dyn Instruction<f32>>>,
     id_formula: Arc<Box<dyn Instruction<u16>>>,
     shape_formula: Arc<Box<dyn Instruction<VoxelShape>>>,
+    variant_formula: Option<Arc<Box<dyn Instruction<f32>>>>, // Floored and wrapped into the variant bits
+    structures: Vec<StructureSpawn>,
 }
 
 impl BiomeProfile {
-    pub fn from_json(data: String) -> Self {
-        let json: serde_json::Value = serde_json::from_str(&data).unwrap();
-        let mut fields: HashMap<&str, Arc<Box<dyn Instruction<f32>>>> = HashMap::new();
-        for field in json.get("Samplers").unwrap().as_array().unwrap() {
-            let field_type = field.get("Type").unwrap().as_str().unwrap();
-            let field_name = field.get("Name").unwrap().as_str().unwrap();
-            fields.insert(
-                field_name,
-                match field_type {
-                    "Simplex" => Arc::new(Box::new(SimplexInstruction::new(
-                        field.get("Wavelength").unwrap().as_f64().unwrap() as f32,
-                        field.get("Amplitude").unwrap().as_f64().unwrap() as f32,
-                    ))),
-                    "Formula" => build_f32_instruction(
-                        field.get("Formula").unwrap().as_str().unwrap().to_string(),
-                        &fields,
-                    ),
-                    &_ => panic!("Field type is not supported: {field_type}"),
-                },
-            );
+    pub fn from_json(file: &str, data: &str) -> Result<Self, BiomeParseError> {
+        let invalid = |message: String| BiomeParseError {
+            file: file.to_string(),
+            formula: String::new(),
+            offset: 0,
+            message,
+        };
+        let in_formula = |formula: &str, error: FormulaError| BiomeParseError {
+            file: file.to_string(),
+            formula: formula.to_string(),
+            offset: error.offset,
+            message: error.message,
+        };
+        fn text<'a>(value: &'a serde_json::Value, key: &str) -> Result<&'a str, String> {
+            value
+                .get(key)
+                .and_then(|value| value.as_str())
+                .ok_or_else(|| format!("Missing text '{key}'"))
         }
-        Self {
-            density_formula: build_f32_instruction(
-                json.get("Voxel Density")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
-            id_formula: build_voxel_type_instruction(
-                json.get("Voxel Type")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
-            shape_formula: build_voxel_shape_instruction(
-                json.get("Voxel Shape")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                &fields,
-            ),
+        fn number(value: &serde_json::Value, key: &str) -> Result<f32, String> {
+            value
+                .get(key)
+                .and_then(|value| value.as_f64())
+                .map(|value| value as f32)
+                .ok_or_else(|| format!("Missing number '{key}'"))
+        }
+
+        let json: serde_json::Value =
+            serde_json::from_str(data).map_err(|e| invalid(format!("Invalid JSON: {e}")))?;
+        let no_samplers = Vec::new();
+        let samplers = match json.get("Samplers") {
+            Some(samplers) => samplers
+                .as_array()
+                .ok_or_else(|| invalid("'Samplers' must be a list".to_string()))?,
+            None => &no_samplers,
+        };
+
+        // Samplers can only refer to the ones declared before them
+        let mut fields: Fields = HashMap::new();
+        for field in samplers {
+            let field_type = text(field, "Type").map_err(invalid)?;
+            let field_name = text(field, "Name").map_err(invalid)?;
+            let instruction: Arc<Box<dyn Instruction<f32>>> = match field_type {
+                "Simplex" => Arc::new(Box::new(SimplexInstruction::new(
+                    number(field, "Wavelength").map_err(invalid)?,
+                    number(field, "Amplitude").map_err(invalid)?,
+                ))),
+                "Formula" => {
+                    let formula = text(field, "Formula").map_err(invalid)?;
+                    parse_formula(formula)
+                        .and_then(|expression| build_f32_instruction(&expression, &fields))
+                        .map_err(|error| in_formula(formula, error))?
+                }
+                _ => {
+                    let expression = Expression {
+                        name: field_type.to_string(),
+                        offset: 0,
+                        params: None,
+                    };
+                    let error = unknown("sampler type", &expression, ["Simplex", "Formula"]);
+                    return Err(invalid(error.message));
+                }
+            };
+            fields.insert(field_name, instruction);
         }
+
+        let range = |key: &str| match json.get(key) {
+            None => Ok((-1.0, 1.0)),
+            Some(range) => match range.as_array().map(|range| &range[..]) {
+                Some([min, max]) => min.as_f64().zip(max.as_f64()),
+                _ => None,
+            }
+            .map(|(min, max)| (min as f32, max as f32))
+            .ok_or_else(|| invalid(format!("'{key}' must be a [min, max] pair"))),
+        };
+        let no_structures = Vec::new();
+        let structures = match json.get("Structures") {
+            Some(structures) => structures
+                .as_array()
+                .ok_or_else(|| invalid("'Structures' must be a list".to_string()))?,
+            None => &no_structures,
+        };
+        let structures = structures
+            .iter()
+            .map(|spawn| {
+                let on = match spawn.get("On").and_then(|on| on.as_str()) {
+                    Some(name) => Some(
+                        get_voxel_by_name(name.to_string())
+                            .ok_or_else(|| format!("Unknown voxel '{name}'"))?
+                            .id,
+                    ),
+                    None => None,
+                };
+                Ok(StructureSpawn {
+                    structure: text(spawn, "Structure")?.to_string(),
+                    rate: number(spawn, "Rate")?,
+                    on,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(invalid)?;
+
+        let density_formula = text(&json, "Voxel Density").map_err(invalid)?;
+        let id_formula = text(&json, "Voxel Type").map_err(invalid)?;
+        let shape_formula = text(&json, "Voxel Shape").map_err(invalid)?;
+        let variant_formula = match json.get("Voxel Variant") {
+            Some(_) => {
+                let formula = text(&json, "Voxel Variant").map_err(invalid)?;
+                Some(
+                    parse_formula(formula)
+                        .and_then(|expression| build_f32_instruction(&expression, &fields))
+                        .map_err(|error| in_formula(formula, error))?,
+                )
+            }
+            None => None,
+        };
+        Ok(Self {
+            moisture_range: range("Moisture Range")?,
+            temperature_range: range("Temperature Range")?,
+            density_formula: parse_formula(density_formula)
+                .and_then(|expression| build_f32_instruction(&expression, &fields))
+                .map_err(|error| in_formula(density_formula, error))?,
+            id_formula: parse_formula(id_formula)
+                .and_then(|expression| build_voxel_type_instruction(&expression, &fields))
+                .map_err(|error| in_formula(id_formula, error))?,
+            shape_formula: parse_formula(shape_formula)
+                .and_then(|expression| build_voxel_shape_instruction(&expression, &fields))
+                .map_err(|error| in_formula(shape_formula, error))?,
+            variant_formula,
+            structures,
+        })
+    }
+
+    // Distance from the center of the biome's climate ranges
+    pub fn climate_distance(&self, moisture: f32, temperature: f32) -> f32 {
+        let center = |(min, max): (f32, f32)| (min + max) / 2.0;
+        let moisture = moisture - center(self.moisture_range);
+        let temperature = temperature - center(self.temperature_range);
+        (moisture * moisture + temperature * temperature).sqrt()
+    }
+
+    pub fn structures(&self) -> &[StructureSpawn] {
+        &self.structures
     }
 
     pub fn sample_density(&self, context: &SampleContext) -> f32 {
@@ -124,10 +379,19 @@ impl BiomeProfile {
     pub fn sample_voxel(&self, context: &SampleContext) -> VoxelData {
         let id = self.id_formula.process(context);
         let shape = self.shape_formula.process(context);
-        VoxelData {
+        let voxel = VoxelData {
             shape,
             state: 0,
             id,
+        };
+        match &self.variant_formula {
+            Some(formula) => voxel.with_variant(
+                formula
+                    .process(context)
+                    .floor()
+                    .rem_euclid(voxel_state::MAX_VARIANTS as f32) as u8,
+            ),
+            None => voxel,
         }
     }
 }
@@ -135,7 +399,8 @@ impl BiomeProfile {
 mod instructions {
     use std::sync::Arc;
 
-    use noise::{NoiseFn, Perlin};
+    use dashmap::DashMap;
+    use noise::{NoiseFn, Perlin, Seedable};
 
     use super::SampleContext;
 
@@ -301,12 +566,20 @@ mod instructions {
     }
 
     lazy_static! {
-        static ref PERLIN: Perlin = Perlin::new();
+        // Building a permutation table is expensive, keep one around for every seed in use
+        static ref PERLINS: DashMap<u32, Perlin> = DashMap::new();
+    }
+
+    pub fn perlin_for_seed(seed: u64) -> Perlin {
+        let seed = (seed ^ (seed >> 32)) as u32;
+        *PERLINS
+            .entry(seed)
+            .or_insert_with(|| Perlin::new().set_seed(seed))
     }
 
     impl Instruction<f32> for SimplexInstruction {
         fn process(&self, context: &SampleContext) -> f32 {
-            PERLIN.get([
+            context.perlin.get([
                 (context.position.x as f32 * self.frequency) as f64,
                 (context.position.y as f32 * self.frequency) as f64,
                 (context.position.z as f32 * self.frequency) as f64,
@@ -360,6 +633,7 @@ mod instructions {
 }
 
 pub struct SampleContext {
+    pub perlin: Perlin, // The noise of the world's seed, looked up once rather than for every sample
     pub position: IVec3,
     pub depth: f32,
     pub slope: Vec3,
@@ -368,216 +642,559 @@ pub struct SampleContext {
     pub density: f32,
 }
 
-fn get_instruction_params(string: String) -> Vec<String> {
-    let mut params = Vec::new();
-    let mut current_param = String::new();
-    let mut scope_depth = 0;
-    for c in string.chars() {
-        if c == '(' {
-            scope_depth += 1;
-        }
-        if c == ')' {
-            scope_depth -= 1;
-        }
-        if scope_depth == -1 {
-            params.push(current_param.trim().to_string());
-            break;
-        }
-        if scope_depth == 0 && c == ',' {
-            params.push(current_param.trim().to_string());
-            current_param = String::new();
-        } else {
-            current_param.push(c);
+impl SampleContext {
+    // Everything but the position starts out at zero, callers fill in what they know about the voxel
+    pub fn new(seed: u64, position: IVec3) -> Self {
+        Self {
+            perlin: instructions::perlin_for_seed(seed),
+            position,
+            depth: 0.0,
+            slope: Vec3::ZERO,
+            moisture: 0.0,
+            temperature: 0.0,
+            density: 0.0,
         }
     }
-    params
 }
 
-fn build_bool_instruction(
-    instruction: String,
-    fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<bool>>> {
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "Less" => {
-            return Arc::new(Box::new(LessInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+// Where and why a biome file failed to parse. The offset is in characters into the formula, which is empty for
+// errors outside of formulas
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeParseError {
+    pub file: String,
+    pub formula: String,
+    pub offset: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for BiomeParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.formula.is_empty() {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}: {} at character {} of '{}'",
+                self.file, self.message, self.offset, self.formula
+            )
         }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
     }
 }
 
-fn build_f32_instruction(
-    instruction: String,
-    fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<f32>>> {
-    let number = instruction.parse();
+struct FormulaError {
+    offset: usize,
+    message: String,
+}
+
+type FormulaResult<T> = Result<T, FormulaError>;
+type Fields<'a> = HashMap<&'a str, Arc<Box<dyn Instruction<f32>>>>;
+
+// A parsed formula, plain values have no params. Every node remembers where it starts so errors can point at it
+struct Expression {
+    name: String,
+    offset: usize,
+    params: Option<Vec<Expression>>,
+}
+
+const F32_INSTRUCTIONS: [(&str, usize); 11] = [
+    ("If", 3),
+    ("Add", 2),
+    ("Sub", 2),
+    ("Mul", 2),
+    ("Div", 2),
+    ("Sin", 1),
+    ("Cos", 1),
+    ("Mod", 2),
+    ("Floor", 1),
+    ("Ceil", 1),
+    ("Round", 1),
+];
+const F32_VALUES: [&str; 7] = ["Depth", "Moisture", "Temperature", "Density", "X", "Y", "Z"];
+
+fn parse_formula(formula: &str) -> FormulaResult<Expression> {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut position = 0;
+    let expression = parse_expression(&chars, &mut position)?;
+    skip_whitespace(&chars, &mut position);
+    match chars.get(position) {
+        Some(c) => Err(FormulaError {
+            offset: position,
+            message: format!("Unexpected '{c}' after the end of the formula"),
+        }),
+        None => Ok(expression),
+    }
+}
 
-    if let Ok(number) = number {
-        return Arc::new(Box::new(ConstInstruction { val: number }));
+fn parse_expression(chars: &[char], position: &mut usize) -> FormulaResult<Expression> {
+    skip_whitespace(chars, position);
+    let offset = *position;
+    while chars
+        .get(*position)
+        .map_or(false, |c| !"(),".contains(*c) && !c.is_whitespace())
+    {
+        *position += 1;
+    }
+    let name: String = chars[offset..*position].iter().collect();
+    if name.is_empty() {
+        return Err(FormulaError {
+            offset,
+            message: match chars.get(offset) {
+                Some(c) => format!("Expected a value, found '{c}'"),
+                None => "Formula ended early, expected a value".to_string(),
+            },
+        });
     }
 
-    if fields.contains_key(&instruction[..]) {
-        return Arc::clone(&fields.get(&instruction[..]).unwrap());
+    skip_whitespace(chars, position);
+    if chars.get(*position) != Some(&'(') {
+        return Ok(Expression {
+            name,
+            offset,
+            params: None,
+        });
     }
+    *position += 1;
 
-    if !instruction.contains('(') {
-        match &instruction[..] {
-            "Depth" => {
-                return Arc::new(Box::new(DepthInstruction {}));
-            }
-            "Moisture" => {
-                return Arc::new(Box::new(MoistureInstruction {}));
-            }
-            "Temperature" => {
-                return Arc::new(Box::new(TemperatureInstruction {}));
-            }
-            "Density" => {
-                return Arc::new(Box::new(DensityInstruction {}));
-            }
-            "X" => {
-                return Arc::new(Box::new(XInstruction {}));
-            }
-            "Y" => {
-                return Arc::new(Box::new(YInstruction {}));
-            }
-            "Z" => {
-                return Arc::new(Box::new(ZInstruction {}));
+    let mut params = Vec::new();
+    skip_whitespace(chars, position);
+    if chars.get(*position) == Some(&')') {
+        *position += 1;
+    } else {
+        loop {
+            params.push(parse_expression(chars, position)?);
+            skip_whitespace(chars, position);
+            match chars.get(*position) {
+                Some(',') => *position += 1,
+                Some(')') => {
+                    *position += 1;
+                    break;
+                }
+                Some(c) => {
+                    return Err(FormulaError {
+                        offset: *position,
+                        message: format!("Expected ',' or ')', found '{c}'"),
+                    })
+                }
+                None => {
+                    return Err(FormulaError {
+                        offset: *position,
+                        message: format!("Formula ended early, '{name}' is missing a ')'"),
+                    })
+                }
             }
-            &_ => panic!(
-                "Constant variable '{}' was not found while constructing f32 instruction",
-                instruction
+        }
+    }
+    Ok(Expression {
+        name,
+        offset,
+        params: Some(params),
+    })
+}
+
+fn skip_whitespace(chars: &[char], position: &mut usize) {
+    while chars.get(*position).map_or(false, |c| c.is_whitespace()) {
+        *position += 1;
+    }
+}
+
+fn expect_params(expression: &Expression, count: usize) -> FormulaResult<&[Expression]> {
+    let params = expression.params.as_deref().unwrap_or(&[]);
+    if params.len() != count {
+        return Err(FormulaError {
+            offset: expression.offset,
+            message: format!(
+                "{} expects {} parameter{}, got {}",
+                expression.name,
+                count,
+                if count == 1 { "" } else { "s" },
+                params.len()
             ),
+        });
+    }
+    Ok(params)
+}
+
+fn f32_params<const N: usize>(
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<[Arc<Box<dyn Instruction<f32>>>; N]> {
+    let params = expect_params(expression, N)?
+        .iter()
+        .map(|param| build_f32_instruction(param, fields))
+        .collect::<FormulaResult<Vec<_>>>()?;
+    Ok(params.try_into().unwrap_or_else(|_| unreachable!()))
+}
+
+fn unknown<'a>(
+    kind: &str,
+    expression: &Expression,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> FormulaError {
+    let name = &expression.name;
+    let suggestion = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance);
+    FormulaError {
+        offset: expression.offset,
+        message: match suggestion {
+            Some((_, candidate)) => format!("Unknown {kind} '{name}', did you mean '{candidate}'?"),
+            None => format!("Unknown {kind} '{name}'"),
+        },
+    }
+}
+
+// Levenshtein distance, ignoring case
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut diagonal = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let substitution = diagonal + (a[i - 1] != b[j - 1]) as usize;
+            diagonal = row[j];
+            row[j] = substitution.min(row[j] + 1).min(row[j - 1] + 1);
         }
     }
+    row[b.len()]
+}
 
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
+fn build_bool_instruction(
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<Arc<Box<dyn Instruction<bool>>>> {
+    if expression.params.is_none() {
+        return Err(FormulaError {
+            offset: expression.offset,
+            message: format!(
+                "Expected a condition like Less(a, b), found '{}'",
+                expression.name
+            ),
+        });
+    }
+    match &expression.name[..] {
+        "Less" => {
+            let [val1, val2] = f32_params(expression, fields)?;
+            Ok(Arc::new(Box::new(LessInstruction { val1, val2 })))
+        }
+        _ => Err(unknown("condition", expression, ["Less"])),
+    }
+}
+
+fn build_f32_instruction(
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<Arc<Box<dyn Instruction<f32>>>> {
+    if expression.params.is_none() {
+        return build_f32_value(expression, fields);
+    }
+
+    let instruction: Box<dyn Instruction<f32>> = match &expression.name[..] {
         "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
+            let params = expect_params(expression, 3)?;
+            Box::new(IfInstruction {
+                condition: build_bool_instruction(&params[0], fields)?,
+                val1: build_f32_instruction(&params[1], fields)?,
+                val2: build_f32_instruction(&params[2], fields)?,
+            })
         }
         "Add" => {
-            return Arc::new(Box::new(AddInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+            let [val1, val2] = f32_params(expression, fields)?;
+            Box::new(AddInstruction { val1, val2 })
         }
         "Sub" => {
-            return Arc::new(Box::new(SubInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+            let [val1, val2] = f32_params(expression, fields)?;
+            Box::new(SubInstruction { val1, val2 })
         }
         "Mul" => {
-            return Arc::new(Box::new(MulInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+            let [val1, val2] = f32_params(expression, fields)?;
+            Box::new(MulInstruction { val1, val2 })
         }
         "Div" => {
-            return Arc::new(Box::new(DivInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+            let [val1, val2] = f32_params(expression, fields)?;
+            Box::new(DivInstruction { val1, val2 })
         }
         "Sin" => {
-            return Arc::new(Box::new(SinInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
+            let [val1] = f32_params(expression, fields)?;
+            Box::new(SinInstruction { val1 })
         }
         "Cos" => {
-            return Arc::new(Box::new(CosInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
+            let [val1] = f32_params(expression, fields)?;
+            Box::new(CosInstruction { val1 })
         }
         "Mod" => {
-            return Arc::new(Box::new(ModInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-                val2: build_f32_instruction(params.get(1).unwrap().to_string(), fields),
-            }));
+            let [val1, val2] = f32_params(expression, fields)?;
+            Box::new(ModInstruction { val1, val2 })
         }
         "Floor" => {
-            return Arc::new(Box::new(FloorInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
+            let [val1] = f32_params(expression, fields)?;
+            Box::new(FloorInstruction { val1 })
         }
         "Ceil" => {
-            return Arc::new(Box::new(CeilInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
+            let [val1] = f32_params(expression, fields)?;
+            Box::new(CeilInstruction { val1 })
         }
         "Round" => {
-            return Arc::new(Box::new(RoundInstruction {
-                val1: build_f32_instruction(params.get(0).unwrap().to_string(), fields),
-            }));
+            let [val1] = f32_params(expression, fields)?;
+            Box::new(RoundInstruction { val1 })
+        }
+        _ => {
+            let names = F32_INSTRUCTIONS.iter().map(|(name, _)| *name);
+            return Err(unknown("instruction", expression, names));
         }
-        &_ => panic!(
-            "Unable to process given instruction for type f32: {}",
-            instruction_name
-        ),
+    };
+    Ok(Arc::new(instruction))
+}
+
+// Numbers, samplers and the values of the sample context
+fn build_f32_value(
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<Arc<Box<dyn Instruction<f32>>>> {
+    let name = &expression.name[..];
+    if let Ok(number) = name.parse() {
+        return Ok(Arc::new(Box::new(ConstInstruction { val: number })));
+    }
+    if let Some(field) = fields.get(name) {
+        return Ok(Arc::clone(field));
     }
+
+    let instruction: Box<dyn Instruction<f32>> = match name {
+        "Depth" => Box::new(DepthInstruction {}),
+        "Moisture" => Box::new(MoistureInstruction {}),
+        "Temperature" => Box::new(TemperatureInstruction {}),
+        "Density" => Box::new(DensityInstruction {}),
+        "X" => Box::new(XInstruction {}),
+        "Y" => Box::new(YInstruction {}),
+        "Z" => Box::new(ZInstruction {}),
+        _ => {
+            if let Some((_, count)) = F32_INSTRUCTIONS.iter().find(|(other, _)| *other == name) {
+                expect_params(expression, *count)?;
+            }
+            let names = fields.keys().cloned().chain(F32_VALUES);
+            return Err(unknown("sampler", expression, names));
+        }
+    };
+    Ok(Arc::new(instruction))
 }
 
 fn build_voxel_type_instruction(
-    instruction: String,
-    fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<u16>>> {
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
-
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<Arc<Box<dyn Instruction<u16>>>> {
+    if expression.params.is_none() {
+        return Err(FormulaError {
+            offset: expression.offset,
+            message: format!(
+                "Expected a voxel type, did you mean 'Voxel({})'?",
+                expression.name
+            ),
+        });
+    }
+    match &expression.name[..] {
         "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_voxel_type_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_voxel_type_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
+            let params = expect_params(expression, 3)?;
+            Ok(Arc::new(Box::new(IfInstruction {
+                condition: build_bool_instruction(&params[0], fields)?,
+                val1: build_voxel_type_instruction(&params[1], fields)?,
+                val2: build_voxel_type_instruction(&params[2], fields)?,
+            })))
         }
         "Voxel" => {
-            return Arc::new(Box::new(ConstInstruction {
-                val: get_voxel_by_name(params.get(0).unwrap().to_string())
-                    .unwrap()
-                    .id,
-            }))
+            let name = &expect_params(expression, 1)?[0];
+            let voxel = get_voxel_by_name(name.name.to_string()).ok_or_else(|| FormulaError {
+                offset: name.offset,
+                message: format!("Unknown voxel '{}'", name.name),
+            })?;
+            Ok(Arc::new(Box::new(ConstInstruction { val: voxel.id })))
         }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
+        _ => Err(unknown("instruction", expression, ["If", "Voxel"])),
     }
 }
 
 fn build_voxel_shape_instruction(
-    instruction: String,
-    fields: &HashMap<&str, Arc<Box<dyn Instruction<f32>>>>,
-) -> Arc<Box<dyn Instruction<VoxelShape>>> {
-    if !instruction.contains('(') {
-        // Const value
-        return Arc::new(Box::new(ConstInstruction {
-            val: match &instruction[..] {
-                "CUBE" => voxel_shape::CUBE,
-                "SLAB" => voxel_shape::SLAB,
-                &_ => panic!("Shape '{}' does is not defined", instruction),
-            },
-        }));
+    expression: &Expression,
+    fields: &Fields,
+) -> FormulaResult<Arc<Box<dyn Instruction<VoxelShape>>>> {
+    if expression.params.is_none() {
+        let shape = match &expression.name[..] {
+            "CUBE" => voxel_shape::CUBE,
+            "SLAB" => voxel_shape::SLAB,
+            _ => return Err(unknown("shape", expression, ["CUBE", "SLAB"])),
+        };
+        return Ok(Arc::new(Box::new(ConstInstruction { val: shape })));
     }
+    match &expression.name[..] {
+        "If" => {
+            let params = expect_params(expression, 3)?;
+            Ok(Arc::new(Box::new(IfInstruction {
+                condition: build_bool_instruction(&params[0], fields)?,
+                val1: build_voxel_shape_instruction(&params[1], fields)?,
+                val2: build_voxel_shape_instruction(&params[2], fields)?,
+            })))
+        }
+        _ => Err(unknown("instruction", expression, ["If"])),
+    }
+}
 
-    let (instruction_name, instruction_data) = instruction.split_once('(').unwrap();
+#[cfg(test)]
+mod biome_map_tests {
+    use super::*;
+
+    fn biome(temperature_range: &str, density: &str) -> Arc<BiomeProfile> {
+        Arc::new(
+            BiomeProfile::from_json(
+                "test.json",
+                &format!(
+                    r#"{{
+                "Temperature Range": {temperature_range},
+                "Samplers": [],
+                "Voxel Density": "{density}",
+                "Voxel Type": "Voxel(dirt)",
+                "Voxel Shape": "CUBE"
+            }}"#
+                ),
+            )
+            .unwrap(),
+        )
+    }
 
-    let params = get_instruction_params(instruction_data.to_string());
-    match &instruction_name[..] {
-        "If" => {
-            return Arc::new(Box::new(IfInstruction {
-                condition: build_bool_instruction(params.get(0).unwrap().to_string(), fields),
-                val1: build_voxel_shape_instruction(params.get(1).unwrap().to_string(), fields),
-                val2: build_voxel_shape_instruction(params.get(2).unwrap().to_string(), fields),
-            }));
+    #[test]
+    fn density_blends_smoothly_across_climates() {
+        let map = BiomeMap::from_biomes(
+            0,
+            vec![biome("[-1.0, 0.0]", "10"), biome("[0.0, 1.0]", "20")],
+        );
+        let density_at = |temperature: f32| -> f32 {
+            let context = SampleContext {
+                temperature,
+                ..SampleContext::new(0, IVec3::ZERO)
+            };
+            map.weights_for_climate(0.0, temperature)
+                .iter()
+                .map(|(biome, weight)| biome.sample_density(&context) * weight)
+                .sum()
+        };
+
+        assert_eq!(density_at(-0.5), 10.0);
+        assert_eq!(density_at(0.5), 20.0);
+        assert!((density_at(0.0) - 15.0).abs() < 1e-4);
+        // No jumps while crossing the border
+        let mut temperature = -0.5;
+        while temperature < 0.5 {
+            assert!((density_at(temperature + 0.01) - density_at(temperature)).abs() < 1.0);
+            temperature += 0.01;
+        }
+    }
+
+    #[test]
+    fn malformed_ranges_are_reported() {
+        let result = BiomeProfile::from_json(
+            "test.json",
+            r#"{
+                "Temperature Range": [0.0, "hot"],
+                "Samplers": [],
+                "Voxel Density": "1",
+                "Voxel Type": "Voxel(dirt)",
+                "Voxel Shape": "CUBE"
+            }"#,
+        );
+        match result {
+            Ok(_) => panic!("the range should not parse"),
+            Err(error) => assert!(error
+                .message
+                .contains("'Temperature Range' must be a [min, max] pair")),
+        }
+    }
+
+    #[test]
+    fn biome_map_is_shared_until_a_reload() {
+        let map = shared_biome_map(7);
+        assert!(Arc::ptr_eq(&map, &shared_biome_map(7)));
+        assert!(!Arc::ptr_eq(&map, &shared_biome_map(8)));
+        reload_biomes();
+        assert!(!Arc::ptr_eq(&map, &shared_biome_map(7)));
+    }
+}
+
+#[cfg(test)]
+mod biome_parse_tests {
+    use super::*;
+
+    fn parse(density: &str) -> Result<BiomeProfile, BiomeParseError> {
+        BiomeProfile::from_json(
+            "broken.json",
+            &format!(
+                r#"{{
+                    "Samplers": [{{ "Type": "Simplex", "Name": "Noise1", "Wavelength": 10, "Amplitude": 1 }}],
+                    "Voxel Density": "{density}",
+                    "Voxel Type": "Voxel(dirt)",
+                    "Voxel Shape": "CUBE"
+                }}"#
+            ),
+        )
+    }
+
+    fn parse_error(density: &str) -> BiomeParseError {
+        match parse(density) {
+            Ok(_) => panic!("'{density}' should not parse"),
+            Err(error) => error,
         }
-        &_ => panic!("Unable to process given instruction: {}", instruction_name),
+    }
+
+    #[test]
+    fn valid_formulas_parse() {
+        assert!(parse("Sub(Add(Noise1, 2), Div(Y, 2))").is_ok());
+        assert!(parse("If(Less(Depth, 1), -1.5, Floor( X ))").is_ok());
+    }
+
+    #[test]
+    fn truncated_formulas_are_reported() {
+        let error = parse_error("Sub(Add(Noise1, 2), Y");
+        assert_eq!(error.file, "broken.json");
+        assert_eq!(error.formula, "Sub(Add(Noise1, 2), Y");
+        assert_eq!(error.offset, 21);
+        assert_eq!(error.message, "Formula ended early, 'Sub' is missing a ')'");
+
+        let error = parse_error("Sub(Y,");
+        assert_eq!(error.offset, 6);
+        assert_eq!(error.message, "Formula ended early, expected a value");
+
+        assert_eq!(parse_error("Add(1, 2))").offset, 9);
+    }
+
+    #[test]
+    fn wrong_arity_is_reported() {
+        let error = parse_error("Add(1, Sub(Y))");
+        assert_eq!(error.offset, 7);
+        assert_eq!(error.message, "Sub expects 2 parameters, got 1");
+        assert_eq!(
+            parse_error("Floor").message,
+            "Floor expects 1 parameter, got 0"
+        );
+    }
+
+    #[test]
+    fn unknown_names_suggest_close_matches() {
+        let error = parse_error("Addd(1, Y)");
+        assert_eq!(error.offset, 0);
+        assert_eq!(
+            error.message,
+            "Unknown instruction 'Addd', did you mean 'Add'?"
+        );
+
+        let error = parse_error("Add(Noise2, Y)");
+        assert_eq!(error.offset, 4);
+        assert_eq!(
+            error.message,
+            "Unknown sampler 'Noise2', did you mean 'Noise1'?"
+        );
+        assert_eq!(
+            parse_error("Add(Mountains, Y)").message,
+            "Unknown sampler 'Mountains'"
+        );
     }
 }
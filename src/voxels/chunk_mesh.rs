@@ -0,0 +1,460 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use glam::{IVec3, UVec3};
+use parking_lot::RwLock;
+
+use crate::asset_types::mesh::Mesh;
+
+use super::chunk_neighborhood::ChunkNeighborhood;
+use super::voxel_registry::DEFAULT_MATERIAL;
+use super::voxel_scene::{
+    MeshBucket, MeshBuckets, MeshingMode, MeshingStrategy, CHUNK_SIZE, COLLISION_MESH,
+};
+
+// Keeps a chunk's geometry split into x slices so an edit only has to regenerate the faces of the slices it touches.
+// Greedy and smooth meshes can't be split this way and are always regenerated as a whole.
+// There's one mesh per material, smooth meshes are drawn with the default material only. The collision mesh sits
+// next to them under COLLISION_MESH
+pub struct ChunkMesh {
+    pub meshes: BTreeMap<String, Arc<RwLock<Mesh>>>, // Meshes of materials the chunk no longer uses are left empty
+    mode: MeshingMode,
+    sections: Vec<MeshBuckets>, // Indices are local to the section
+}
+
+impl ChunkMesh {
+    pub fn generate(neighborhood: &ChunkNeighborhood, mode: MeshingMode) -> Self {
+        let mut chunk_mesh = Self {
+            meshes: BTreeMap::new(),
+            mode,
+            sections: vec![MeshBuckets::new(); CHUNK_SIZE as usize],
+        };
+        match mode {
+            MeshingMode::Blocky => {
+                for x in 0..CHUNK_SIZE {
+                    chunk_mesh.rebuild_section(neighborhood, x);
+                }
+                chunk_mesh.splice();
+            }
+            MeshingMode::Greedy | MeshingMode::Smooth => {
+                chunk_mesh.regenerate_whole(neighborhood);
+            }
+        }
+        chunk_mesh
+    }
+
+    // Takes over the geometry of a freshly generated chunk mesh, written into the shared meshes so renderers holding
+    // them pick up the change
+    pub fn replace_with(&mut self, generated: ChunkMesh) {
+        let buckets = generated
+            .meshes
+            .iter()
+            .map(|(material, mesh)| {
+                let mesh = mesh.read();
+                let bucket = MeshBucket {
+                    vertices: mesh.get_vertices().clone(),
+                    indices: mesh.get_indices().clone(),
+                };
+                (material.clone(), bucket)
+            })
+            .collect();
+        self.write_meshes(buckets);
+        self.mode = generated.mode;
+        self.sections = generated.sections;
+    }
+
+    // The shared mesh of every material, as sent to the main thread
+    pub fn meshes(&self) -> Vec<(String, Arc<RwLock<Mesh>>)> {
+        self.meshes
+            .iter()
+            .map(|(material, mesh)| (material.clone(), Arc::clone(mesh)))
+            .collect()
+    }
+
+    // Which of the material meshes have no triangles
+    pub fn empty_meshes(&self) -> Vec<(String, bool)> {
+        self.meshes
+            .iter()
+            .map(|(material, mesh)| (material.clone(), mesh.read().index_count == 0))
+            .collect()
+    }
+
+    fn generate_whole(neighborhood: &ChunkNeighborhood, mode: MeshingMode) -> MeshBuckets {
+        let chunk = neighborhood.center();
+        match mode {
+            MeshingMode::Blocky => chunk.generate_buckets(neighborhood, MeshingStrategy::Naive),
+            MeshingMode::Greedy => chunk.generate_buckets(neighborhood, MeshingStrategy::Greedy),
+            MeshingMode::Smooth => {
+                let mesh = chunk.generate_mesh_smooth(neighborhood);
+                let bucket = MeshBucket {
+                    vertices: mesh.get_vertices().clone(),
+                    indices: mesh.get_indices().clone(),
+                };
+                // The surface doesn't tell voxels apart, all of it collides
+                MeshBuckets::from([
+                    (COLLISION_MESH.to_string(), bucket.clone()),
+                    (DEFAULT_MATERIAL.to_string(), bucket),
+                ])
+            }
+        }
+    }
+
+    fn regenerate_whole(&mut self, neighborhood: &ChunkNeighborhood) {
+        let buckets = Self::generate_whole(neighborhood, self.mode);
+        self.write_meshes(buckets);
+    }
+
+    // Writes into the shared meshes, so renderers holding them pick up the change. Materials that weren't meshed
+    // before get a new mesh
+    fn write_meshes(&mut self, mut buckets: MeshBuckets) {
+        for (material, mesh) in &self.meshes {
+            let bucket = buckets.remove(material).unwrap_or_default();
+            let mut mesh_lock = mesh.write();
+            mesh_lock.set_vertices(bucket.vertices);
+            mesh_lock.set_indices(bucket.indices);
+        }
+        for (material, bucket) in buckets {
+            let mut mesh = Mesh::new();
+            mesh.set_vertices(bucket.vertices);
+            mesh.set_indices(bucket.indices);
+            self.meshes.insert(material, Arc::new(RwLock::new(mesh)));
+        }
+    }
+
+    // Regenerates the faces around the given scenespace positions, positions outside of the chunk are ignored
+    pub fn remesh_voxels(&mut self, neighborhood: &ChunkNeighborhood, positions: &[IVec3]) {
+        if self.mode != MeshingMode::Blocky {
+            self.regenerate_whole(neighborhood);
+            return;
+        }
+
+        let chunk = neighborhood.center();
+        let mut slices: Vec<u32> = positions
+            .iter()
+            .map(|position| *position - chunk.scenespace_pos())
+            .filter(|local| {
+                local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(CHUNK_SIZE as i32)).all()
+            })
+            .map(|local| local.x as u32)
+            .collect();
+        if slices.is_empty() {
+            return;
+        }
+        slices.sort_unstable();
+        slices.dedup();
+        for x in slices {
+            self.rebuild_section(neighborhood, x);
+        }
+        self.splice();
+    }
+
+    fn rebuild_section(&mut self, neighborhood: &ChunkNeighborhood, x: u32) {
+        let chunk = neighborhood.center();
+        let section = &mut self.sections[x as usize];
+        section.clear();
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.generate_voxel_faces(neighborhood, &UVec3::new(x, y, z), section);
+            }
+        }
+    }
+
+    // Stitches the sections of every material back into the shared meshes, no faces are generated here
+    fn splice(&mut self) {
+        let mut buckets = MeshBuckets::new();
+        for section in &self.sections {
+            for (material, section_bucket) in section {
+                let bucket = buckets.entry(material.clone()).or_default();
+                let index_offset = bucket.vertices.len() as u32;
+                bucket.vertices.extend_from_slice(&section_bucket.vertices);
+                bucket.indices.extend(
+                    section_bucket
+                        .indices
+                        .iter()
+                        .map(|index| index + index_offset),
+                );
+            }
+        }
+        self.write_meshes(buckets);
+    }
+}
+
+#[cfg(test)]
+mod chunk_mesh_tests {
+    use std::time::Instant;
+
+    use dashmap::DashMap;
+
+    use super::*;
+    use crate::asset_types::vertex::{PackedVertex, Vertex};
+    use crate::time::fastest_of;
+    use crate::voxels::voxel_registry::{get_voxel_by_name, TRANSPARENT_MATERIAL};
+    use crate::voxels::voxel_scene::{
+        collision_friction, collision_mesh_name, ChunkMap, VoxelChunk,
+    };
+    use crate::voxels::voxel_shapes::voxel_directions;
+
+    fn half_filled_chunk() -> VoxelChunk {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE / 2 {
+                for z in 0..CHUNK_SIZE {
+                    chunk.voxel_at_mut(&UVec3::new(x, y, z)).id = dirt;
+                }
+            }
+        }
+        chunk.is_empty = false;
+        chunk
+    }
+
+    fn dig(chunk: &mut VoxelChunk, chunks: &ChunkMap, position: IVec3) -> Vec<IVec3> {
+        chunk.voxel_at_mut(&position.as_uvec3()).id = 0;
+        chunks.insert(chunk.position, chunk.clone());
+        let mut affected = vec![position];
+        affected.extend(
+            voxel_directions::ALL
+                .iter()
+                .map(|direction| position + direction.as_vec()),
+        );
+        affected
+    }
+
+    #[test]
+    fn incremental_remesh_matches_full_generation() {
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = half_filled_chunk();
+        chunks.insert(chunk.position, chunk.clone());
+        let mut chunk_mesh = ChunkMesh::generate(
+            &ChunkNeighborhood::new(chunk.clone(), &chunks),
+            MeshingMode::Blocky,
+        );
+
+        let affected = dig(&mut chunk, &chunks, IVec3::new(4, 7, 4));
+        chunk_mesh.remesh_voxels(&ChunkNeighborhood::new(chunk.clone(), &chunks), &affected);
+
+        let full = chunk
+            .generate_meshes(
+                &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                MeshingStrategy::Naive,
+            )
+            .remove(DEFAULT_MATERIAL)
+            .unwrap();
+        let incremental = chunk_mesh.meshes[DEFAULT_MATERIAL].read();
+        assert_eq!(incremental.get_vertices().len(), full.get_vertices().len());
+        assert_eq!(incremental.get_indices().len(), full.get_indices().len());
+    }
+
+    #[test]
+    fn water_shows_its_surface_and_keeps_the_shore() {
+        let water = get_voxel_by_name("water".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let pool = |id: u16| {
+            let mut chunk = half_filled_chunk();
+            for x in 4..6 {
+                for y in 6..8 {
+                    for z in 4..6 {
+                        chunk.voxel_at_mut(&UVec3::new(x, y, z)).id = id;
+                    }
+                }
+            }
+            chunks.insert(chunk.position, chunk.clone());
+            ChunkMesh::generate(
+                &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                MeshingMode::Blocky,
+            )
+        };
+
+        let pit = pool(0);
+        let lake = pool(water);
+        // Only the four top faces, none between the water voxels or against the dirt
+        let transparent = lake.meshes[TRANSPARENT_MATERIAL].read();
+        assert_eq!(transparent.get_vertices().len(), 4 * 4);
+        assert!(transparent
+            .get_vertices()
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 1.0, 0.0]));
+        // The shore shows through the water just like through air
+        assert_eq!(
+            lake.meshes[DEFAULT_MATERIAL].read().get_vertices().len(),
+            pit.meshes[DEFAULT_MATERIAL].read().get_vertices().len()
+        );
+        assert!(!pit.meshes.contains_key(TRANSPARENT_MATERIAL));
+    }
+
+    #[test]
+    fn colliders_leave_out_water() {
+        let water = get_voxel_by_name("water".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = half_filled_chunk();
+        for x in 4..6 {
+            for z in 4..6 {
+                chunk.voxel_at_mut(&UVec3::new(x, 8, z)).id = water;
+            }
+        }
+        chunks.insert(chunk.position, chunk.clone());
+
+        for mode in [MeshingMode::Blocky, MeshingMode::Greedy] {
+            let chunk_mesh =
+                ChunkMesh::generate(&ChunkNeighborhood::new(chunk.clone(), &chunks), mode);
+            let collision = chunk_mesh.meshes[COLLISION_MESH].read().index_count;
+            assert!(chunk_mesh.meshes[TRANSPARENT_MATERIAL].read().index_count > 0);
+            assert_eq!(
+                collision,
+                chunk_mesh.meshes[DEFAULT_MATERIAL].read().index_count
+            );
+        }
+    }
+
+    #[test]
+    fn slippery_voxels_collide_through_their_own_mesh() {
+        let slime = get_voxel_by_name("slime".to_string()).unwrap();
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = half_filled_chunk();
+        chunk.voxel_at_mut(&UVec3::new(4, 8, 4)).id = slime.id;
+        chunks.insert(chunk.position, chunk.clone());
+
+        let chunk_mesh =
+            ChunkMesh::generate(&ChunkNeighborhood::new(chunk, &chunks), MeshingMode::Blocky);
+        let name = collision_mesh_name(slime.friction);
+        assert_eq!(collision_friction(&name), Some(slime.friction));
+        // The five faces sticking out of the ground
+        assert_eq!(chunk_mesh.meshes[&name].read().index_count, 5 * 6);
+        assert_eq!(collision_friction(DEFAULT_MATERIAL), None);
+    }
+
+    #[test]
+    #[ignore] // Timing comparison, run with --ignored --nocapture
+    fn single_voxel_edit_benchmark() {
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = half_filled_chunk();
+        chunks.insert(chunk.position, chunk.clone());
+        let mut chunk_mesh = ChunkMesh::generate(
+            &ChunkNeighborhood::new(chunk.clone(), &chunks),
+            MeshingMode::Blocky,
+        );
+        let iterations = 100;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            chunk.generate_meshes(
+                &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                MeshingStrategy::Naive,
+            );
+        }
+        let full = start.elapsed() / iterations;
+
+        let affected = dig(&mut chunk, &chunks, IVec3::new(4, 7, 4));
+        let start = Instant::now();
+        for _ in 0..iterations {
+            chunk_mesh.remesh_voxels(&ChunkNeighborhood::new(chunk.clone(), &chunks), &affected);
+        }
+        let incremental = start.elapsed() / iterations;
+
+        println!("Full remesh: {full:?}, incremental remesh: {incremental:?}");
+    }
+
+    #[test]
+    #[ignore] // Memory comparison, run with --ignored --nocapture
+    fn packed_vertex_memory_report() {
+        // A 25x5x25 chunk world with half of every chunk filled
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        for x in 0..25 {
+            for y in 0..5 {
+                for z in 0..25 {
+                    let mut chunk = half_filled_chunk();
+                    chunk.position = IVec3::new(x, y, z);
+                    chunks.insert(chunk.position, chunk);
+                }
+            }
+        }
+        let positions: Vec<IVec3> = chunks.iter().map(|chunk| *chunk.key()).collect();
+        for mode in [MeshingMode::Blocky, MeshingMode::Greedy] {
+            // The bytes of the vertex buffers the meshes upload, in both formats
+            let (mut vertices, mut full, mut packed) = (0, 0, 0);
+            for position in &positions {
+                let chunk = chunks.get(position).unwrap().clone();
+                let chunk_mesh =
+                    ChunkMesh::generate(&ChunkNeighborhood::new(chunk.clone(), &chunks), mode);
+                for mesh in chunk_mesh.meshes.values() {
+                    let mesh = mesh.read();
+                    let packed_vertices: Vec<PackedVertex> =
+                        mesh.get_vertices().iter().map(PackedVertex::pack).collect();
+                    vertices += mesh.get_vertices().len();
+                    full += bytemuck::cast_slice::<Vertex, u8>(mesh.get_vertices()).len();
+                    packed += bytemuck::cast_slice::<PackedVertex, u8>(&packed_vertices).len();
+                }
+            }
+            let ratio = full as f32 / packed as f32;
+            println!(
+                "{mode:?}: {vertices} vertices, {} KiB full, {} KiB packed, {ratio:.2}x smaller",
+                full / 1024,
+                packed / 1024,
+            );
+            assert!(vertices > 0);
+            assert!(ratio >= 4.0, "packed buffers are only {ratio:.2}x smaller");
+        }
+    }
+
+    #[test]
+    #[ignore] // Timing comparison, run with --ignored --nocapture
+    fn hundred_chunk_meshing_benchmark() {
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        for x in 0..5 {
+            for y in 0..4 {
+                for z in 0..5 {
+                    let mut chunk = half_filled_chunk();
+                    chunk.position = IVec3::new(x, y, z);
+                    chunks.insert(chunk.position, chunk);
+                }
+            }
+        }
+        let positions: Vec<IVec3> = chunks.iter().map(|chunk| *chunk.key()).collect();
+        let sides = [
+            IVec3::X,
+            -IVec3::X,
+            IVec3::Y,
+            -IVec3::Y,
+            IVec3::Z,
+            -IVec3::Z,
+        ];
+
+        // Before the neighborhood, every mesh job copied the chunk's voxels and hashed into the chunk map for each
+        // voxel sampled across a border. Both are put back on top of the same meshing for the baseline
+        let before = fastest_of(3, || {
+            for position in &positions {
+                let mut chunk = chunks.get(position).unwrap().clone();
+                chunk.voxel_at_mut(&UVec3::ZERO); // Writing to the copy copies the shared voxels
+                for side in sides {
+                    for i in 0..CHUNK_SIZE as i32 {
+                        for j in 0..CHUNK_SIZE as i32 {
+                            let sample = chunks.get(&(*position + side)).map(|neighbour| {
+                                neighbour.voxel_at(&UVec3::new(i as u32, j as u32, 0)).id
+                            });
+                            std::hint::black_box(sample);
+                        }
+                    }
+                }
+                let neighborhood = ChunkNeighborhood::new(chunk, &chunks);
+                ChunkMesh::generate(&neighborhood, MeshingMode::Blocky);
+            }
+        });
+        let after = fastest_of(3, || {
+            for position in &positions {
+                let chunk = chunks.get(position).unwrap().clone();
+                let neighborhood = ChunkNeighborhood::new(chunk, &chunks);
+                ChunkMesh::generate(&neighborhood, MeshingMode::Blocky);
+            }
+        });
+
+        println!(
+            "Meshed {} chunks, before: {before:?}, after: {after:?} ({:.2}x)",
+            positions.len(),
+            before.as_secs_f64() / after.as_secs_f64()
+        );
+        assert!(
+            after < before,
+            "meshing from the neighborhood was slower, {after:?} against {before:?}"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use glam::IVec3;
+
+use super::{
+    voxel_data::VoxelData,
+    voxel_scene::{ChunkMap, VoxelChunk, VoxelScene},
+};
+
+// A chunk and the 26 chunks around it, grabbed once per mesh job so sampling across its borders doesn't hash
+// into the chunk map for every voxel. Copying a chunk only shares its storage, nothing is copied until it's written
+pub struct ChunkNeighborhood {
+    chunks: [Option<VoxelChunk>; 27], // Indexed by the offset from the center, see neighbour_index
+}
+
+impl ChunkNeighborhood {
+    // Neighbours that aren't loaded stay missing, sampling them behaves like it did against the chunk map
+    pub fn new(center: VoxelChunk, scene_chunks: &ChunkMap) -> Self {
+        let position = center.position;
+        let mut chunks: [Option<VoxelChunk>; 27] = Default::default();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+                    if offset == IVec3::ZERO {
+                        continue;
+                    }
+                    // The guard is dropped right away, holding several at once could deadlock against a writer
+                    chunks[neighbour_index(offset)] = scene_chunks
+                        .get(&(position + offset))
+                        .map(|chunk| chunk.clone());
+                }
+            }
+        }
+        chunks[neighbour_index(IVec3::ZERO)] = Some(center);
+        Self { chunks }
+    }
+
+    pub fn center(&self) -> &VoxelChunk {
+        self.chunks[neighbour_index(IVec3::ZERO)].as_ref().unwrap()
+    }
+
+    // Anything further than one chunk from the center counts as missing
+    pub fn chunk_containing(&self, global_position: IVec3) -> Option<&VoxelChunk> {
+        let offset = VoxelScene::chunk_at(&global_position) - self.center().position;
+        if offset.abs().max_element() > 1 {
+            return None;
+        }
+        self.chunks[neighbour_index(offset)].as_ref()
+    }
+
+    pub fn voxel_at(&self, global_position: IVec3) -> Option<&VoxelData> {
+        self.chunk_containing(global_position)?
+            .voxel_scenespace_at(&global_position)
+    }
+
+    // Missing chunks are dark
+    pub fn block_light_at(&self, global_position: IVec3) -> u8 {
+        self.chunk_containing(global_position)
+            .and_then(|chunk| chunk.block_light_scenespace_at(&global_position))
+            .unwrap_or(0)
+    }
+
+    pub fn density_at(&self, global_position: IVec3) -> Option<f32> {
+        self.chunk_containing(global_position)?
+            .density_scenespace_at(&global_position)
+    }
+}
+
+fn neighbour_index(offset: IVec3) -> usize {
+    let index = offset + IVec3::ONE;
+    (index.x * 9 + index.y * 3 + index.z) as usize
+}
+
+#[cfg(test)]
+mod neighborhood_tests {
+    use std::sync::Arc;
+
+    use dashmap::DashMap;
+    use glam::UVec3;
+
+    use super::*;
+    use crate::voxels::voxel_scene::CHUNK_SIZE;
+
+    #[test]
+    fn samples_reach_across_borders_and_stop_beyond_the_neighbours() {
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut neighbour = VoxelChunk::new(IVec3::new(1, 0, -1));
+        neighbour.voxel_at_mut(&UVec3::new(0, 3, CHUNK_SIZE - 1)).id = 7;
+        chunks.insert(neighbour.position, neighbour);
+        chunks.insert(IVec3::new(2, 0, 0), VoxelChunk::new(IVec3::new(2, 0, 0)));
+
+        let neighborhood = ChunkNeighborhood::new(VoxelChunk::new(IVec3::ZERO), &chunks);
+        let size = CHUNK_SIZE as i32;
+        assert_eq!(
+            neighborhood.voxel_at(IVec3::new(size, 3, -1)).unwrap().id,
+            7
+        );
+        assert_eq!(neighborhood.voxel_at(IVec3::new(0, 0, 0)).unwrap().id, 0);
+        assert!(neighborhood.voxel_at(IVec3::new(0, -1, 0)).is_none());
+        assert!(neighborhood.voxel_at(IVec3::new(size * 2, 0, 0)).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flume::{Receiver, Sender};
+use glam::IVec3;
+use parking_lot::{Mutex, RwLock};
+
+// A work queue that always hands out the chunk closest to its focus, so chunks near the player are never starved by
+// far away ones requested earlier. Every pushed item sends a signal, so a waiting pop always finds an item
+pub struct ChunkQueue<T> {
+    items: Mutex<Vec<(IVec3, T)>>,
+    signal: (Sender<()>, Receiver<()>),
+    focus: RwLock<IVec3>,
+    closed: AtomicBool,
+}
+
+impl<T> ChunkQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(Vec::new()),
+            signal: flume::unbounded(),
+            focus: RwLock::new(IVec3::ZERO),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // Items pushed after the queue was closed are dropped
+    pub fn push(&self, chunk_pos: IVec3, item: T) {
+        if self.is_closed() {
+            return;
+        }
+        self.items.lock().push((chunk_pos, item));
+        self.signal.0.send(()).unwrap();
+    }
+
+    // Blocks until an item is available, None once the queue is closed
+    pub fn pop_nearest(&self) -> Option<(IVec3, T)> {
+        loop {
+            self.signal.1.recv().unwrap();
+            if self.is_closed() {
+                // Passed on so every other waiting pop wakes up as well
+                self.signal.0.send(()).unwrap();
+                return None;
+            }
+            if let Some(item) = self.take_nearest() {
+                return Some(item);
+            }
+        }
+    }
+
+    // Drops the queued items and wakes up everything waiting on the queue
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.items.lock().clear();
+        self.signal.0.send(()).unwrap();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn take_nearest(&self) -> Option<(IVec3, T)> {
+        let focus = *self.focus.read();
+        let mut items = self.items.lock();
+        let (index, _) = items.iter().enumerate().min_by_key(|(_, (chunk_pos, _))| {
+            let offset = *chunk_pos - focus;
+            offset.dot(offset)
+        })?;
+        Some(items.swap_remove(index))
+    }
+
+    // The queued positions closest to the chunk, without taking them off the queue
+    pub fn positions_near(&self, chunk_pos: IVec3, count: usize) -> Vec<IVec3> {
+        let mut positions: Vec<IVec3> = self.items.lock().iter().map(|(pos, _)| *pos).collect();
+        positions.sort_unstable_by_key(|pos| {
+            let offset = *pos - chunk_pos;
+            offset.dot(offset)
+        });
+        positions.truncate(count);
+        positions
+    }
+
+    pub fn set_focus(&self, chunk_pos: IVec3) {
+        *self.focus.write() = chunk_pos;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.lock().is_empty()
+    }
+}
+
+impl<T> Default for ChunkQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod chunk_queue_tests {
+    use super::*;
+
+    #[test]
+    fn nearest_chunk_is_popped_first() {
+        let queue = ChunkQueue::new();
+        queue.set_focus(IVec3::new(10, 0, 0));
+        for x in 0..4 {
+            queue.push(IVec3::new(x * 4, 0, 0), x);
+        }
+
+        let order: Vec<i32> = (0..4).map(|_| queue.pop_nearest().unwrap().1).collect();
+        assert_eq!(order, vec![2, 3, 1, 0]);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn nearby_positions_stay_queued() {
+        let queue = ChunkQueue::new();
+        for x in 0..4 {
+            queue.push(IVec3::new(x * 4, 0, 0), x);
+        }
+        assert_eq!(
+            queue.positions_near(IVec3::new(9, 0, 0), 2),
+            vec![IVec3::new(8, 0, 0), IVec3::new(12, 0, 0)]
+        );
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[test]
+    fn closing_wakes_every_waiting_pop() {
+        let queue = std::sync::Arc::new(ChunkQueue::<()>::new());
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let queue = std::sync::Arc::clone(&queue);
+                std::thread::spawn(move || queue.pop_nearest())
+            })
+            .collect();
+        queue.close();
+        for waiter in waiters {
+            assert!(waiter.join().unwrap().is_none());
+        }
+        queue.push(IVec3::ZERO, ());
+        assert_eq!(queue.len(), 0);
+    }
+}
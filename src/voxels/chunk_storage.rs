@@ -0,0 +1,364 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use glam::IVec3;
+
+use super::voxel_registry;
+use super::voxel_scene::{VoxelChunk, VOXEL_BYTES};
+
+pub const STORAGE_VERSION: u16 = 3;
+// Version 1 had no name table, its ids are read as they are
+const UNNAMED_VERSION: u16 = 1;
+// Versions before 3 stored the shape in a single byte
+const NARROW_SHAPE_VERSION: u16 = 2;
+const NARROW_VOXEL_BYTES: usize = VOXEL_BYTES - 1;
+const MAGIC: [u8; 4] = *b"ACHK";
+const HEADER_BYTES: usize = MAGIC.len() + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkDecodeError {
+    UnsupportedVersion(u16), // Written by a newer build
+    Corrupt,
+}
+
+// Chunks are stored one file per chunk, "x_y_z.chunk", in the storage folder
+pub struct ChunkStorage {
+    folder: PathBuf,
+}
+
+impl ChunkStorage {
+    pub fn new(folder: impl Into<PathBuf>) -> Self {
+        Self {
+            folder: folder.into(),
+        }
+    }
+
+    // Written to a temporary file first so a crash mid-save never leaves a half written chunk behind
+    pub fn save_chunk(&self, position: IVec3, chunk: &VoxelChunk) -> io::Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        let path = self.chunk_path(position);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, encode_chunk(chunk))?;
+        fs::rename(temporary, path)
+    }
+
+    // Missing chunks are generated as usual, unreadable ones are reported and regenerated as well
+    pub fn load_chunk(&self, position: IVec3) -> Option<VoxelChunk> {
+        let bytes = fs::read(self.chunk_path(position)).ok()?;
+        match decode_chunk(position, &bytes) {
+            Ok(chunk) => Some(chunk),
+            Err(e) => {
+                println!("[INFO] Skipping stored chunk {}: {:?}", position, e);
+                None
+            }
+        }
+    }
+
+    fn chunk_path(&self, position: IVec3) -> PathBuf {
+        self.folder.join(chunk_file_name(position))
+    }
+}
+
+pub fn chunk_file_name(position: IVec3) -> String {
+    format!("{}_{}_{}.chunk", position.x, position.y, position.z)
+}
+
+// Offset of the little endian voxel id within a voxel's bytes
+const ID_OFFSET: usize = 3;
+
+fn record_id(record: &[u8]) -> u16 {
+    u16::from_le_bytes([record[ID_OFFSET], record[ID_OFFSET + 1]])
+}
+
+// Gives records of the older versions the high byte of the shape, which they had no orientations for
+fn widen_records(narrow: &[u8]) -> Vec<u8> {
+    let mut wide = Vec::with_capacity(narrow.len() / NARROW_VOXEL_BYTES * VOXEL_BYTES);
+    for record in narrow.chunks_exact(NARROW_VOXEL_BYTES) {
+        wide.extend_from_slice(&[record[0], 0]);
+        wide.extend_from_slice(&record[1..]);
+    }
+    wide
+}
+
+// The name of every voxel id in the chunk, so the chunk still decodes to the same voxels if ids are assigned
+// differently when it's loaded. A u16 count followed by the entries, each a u16 id, a u8 length and the name.
+// Air is always 0 and ids without a profile have no name, neither is listed
+fn encode_name_table(raw: &[u8], name_of: impl Fn(u16) -> Option<String>) -> Vec<u8> {
+    let ids: BTreeSet<u16> = raw.chunks_exact(VOXEL_BYTES).map(record_id).collect();
+    let entries: Vec<(u16, String)> = ids
+        .into_iter()
+        .filter(|id| *id != 0)
+        .filter_map(|id| name_of(id).map(|name| (id, name)))
+        .filter(|(_, name)| name.len() <= u8::MAX as usize)
+        .collect();
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (id, name) in entries {
+        bytes.extend_from_slice(&id.to_le_bytes());
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+    }
+    bytes
+}
+
+// Returns the stored id -> name entries and the bytes after the table
+fn decode_name_table(bytes: &[u8]) -> Result<(Vec<(u16, String)>, &[u8]), ChunkDecodeError> {
+    fn take(bytes: &[u8], count: usize) -> Result<(&[u8], &[u8]), ChunkDecodeError> {
+        (bytes.len() >= count)
+            .then(|| bytes.split_at(count))
+            .ok_or(ChunkDecodeError::Corrupt)
+    }
+    let (count, mut rest) = take(bytes, 2)?;
+    let count = u16::from_le_bytes([count[0], count[1]]);
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (id, after_id) = take(rest, 2)?;
+        let (length, after_length) = take(after_id, 1)?;
+        let (name, after_name) = take(after_length, length[0] as usize)?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| ChunkDecodeError::Corrupt)?;
+        entries.push((u16::from_le_bytes([id[0], id[1]]), name));
+        rest = after_name;
+    }
+    Ok((entries, rest))
+}
+
+// A header with the format version and the name table, followed by runs of identical voxels, each a little endian
+// u16 count and the voxel's bytes. Most chunks are mostly air or mostly solid so this stays tiny
+pub fn encode_chunk(chunk: &VoxelChunk) -> Vec<u8> {
+    let raw = chunk.to_bytes();
+    let mut bytes = Vec::with_capacity(HEADER_BYTES + VOXEL_BYTES * 16);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&STORAGE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&encode_name_table(&raw, voxel_registry::get_voxel_name));
+
+    let mut records = raw.chunks_exact(VOXEL_BYTES).peekable();
+    while let Some(record) = records.next() {
+        let mut count: u16 = 1;
+        while count < u16::MAX && records.peek() == Some(&record) {
+            records.next();
+            count += 1;
+        }
+        bytes.extend_from_slice(&count.to_le_bytes());
+        bytes.extend_from_slice(record);
+    }
+    bytes
+}
+
+// Files without a header are the raw voxel dumps older saves wrote
+pub fn decode_chunk(position: IVec3, bytes: &[u8]) -> Result<VoxelChunk, ChunkDecodeError> {
+    decode_chunk_with(position, bytes, voxel_registry::get_voxel_id)
+}
+
+// Stored ids are mapped to the current id of the voxel with the same name. Voxels whose profile is gone become air
+fn decode_chunk_with(
+    position: IVec3,
+    bytes: &[u8],
+    id_of: impl Fn(&str) -> Option<u16>,
+) -> Result<VoxelChunk, ChunkDecodeError> {
+    if !bytes.starts_with(&MAGIC) {
+        return VoxelChunk::from_bytes(position, &widen_records(bytes))
+            .ok_or(ChunkDecodeError::Corrupt);
+    }
+    if bytes.len() < HEADER_BYTES {
+        return Err(ChunkDecodeError::Corrupt);
+    }
+    let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+    let (remap, runs) = match version {
+        UNNAMED_VERSION => (HashMap::new(), &bytes[HEADER_BYTES..]),
+        NARROW_SHAPE_VERSION | STORAGE_VERSION => {
+            let (entries, runs) = decode_name_table(&bytes[HEADER_BYTES..])?;
+            let remap: HashMap<u16, u16> = entries
+                .into_iter()
+                .map(|(id, name)| {
+                    let current = id_of(&name).unwrap_or_else(|| {
+                        println!("[INFO] Voxel {name} no longer exists, chunk {position} loads it as air");
+                        0
+                    });
+                    (id, current)
+                })
+                .collect();
+            (remap, runs)
+        }
+        _ => return Err(ChunkDecodeError::UnsupportedVersion(version)),
+    };
+
+    let record_bytes = if version == STORAGE_VERSION {
+        VOXEL_BYTES
+    } else {
+        NARROW_VOXEL_BYTES
+    };
+    if runs.len() % (record_bytes + 2) != 0 {
+        return Err(ChunkDecodeError::Corrupt);
+    }
+    let mut raw = Vec::new();
+    for run in runs.chunks_exact(record_bytes + 2) {
+        let count = u16::from_le_bytes([run[0], run[1]]) as usize;
+        let mut record = if record_bytes == VOXEL_BYTES {
+            run[2..].to_vec()
+        } else {
+            widen_records(&run[2..])
+        };
+        if let Some(id) = remap.get(&record_id(&record)) {
+            record[ID_OFFSET..ID_OFFSET + 2].copy_from_slice(&id.to_le_bytes());
+        }
+        for _ in 0..count {
+            raw.extend_from_slice(&record);
+        }
+    }
+    VoxelChunk::from_bytes(position, &raw).ok_or(ChunkDecodeError::Corrupt)
+}
+
+#[cfg(test)]
+mod chunk_storage_tests {
+    use glam::UVec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::voxels::voxel_data::VoxelData;
+    use crate::voxels::voxel_scene::CHUNK_SIZE;
+    use crate::voxels::voxel_shapes::{voxel_shape, VoxelShape, SHAPE_VALUES};
+    use crate::voxels::voxel_storage::CHUNK_VOLUME;
+
+    fn random_chunk(position: IVec3, seed: u64) -> VoxelChunk {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut chunk = VoxelChunk::new(position);
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let local_pos = UVec3::new(x, y, z);
+                    // Runs of the same voxel alongside noise, so both paths of the encoder are exercised
+                    if rng.gen_bool(0.5) {
+                        continue;
+                    }
+                    *chunk.voxel_at_mut(&local_pos) = VoxelData {
+                        shape: VoxelShape {
+                            data: rng.gen_range(0..SHAPE_VALUES),
+                        },
+                        state: rng.gen(),
+                        id: rng.gen(),
+                    };
+                    chunk.set_density(&local_pos, rng.gen_range(-4.0..4.0));
+                }
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn random_chunk_round_trips_byte_for_byte() {
+        let position = IVec3::new(-2, 1, 7);
+        let chunk = random_chunk(position, 42);
+        let decoded = decode_chunk(position, &encode_chunk(&chunk)).unwrap();
+        assert_eq!(decoded.to_bytes(), chunk.to_bytes());
+        assert_eq!(decoded.position, position);
+    }
+
+    #[test]
+    fn uniform_chunks_encode_to_a_single_run() {
+        let chunk = VoxelChunk::new(IVec3::ZERO);
+        // Air isn't named, so the name table is just its empty count
+        assert_eq!(
+            encode_chunk(&chunk).len(),
+            HEADER_BYTES + 2 + VOXEL_BYTES + 2
+        );
+    }
+
+    #[test]
+    fn stored_names_survive_id_reassignment() {
+        let grass = voxel_registry::get_voxel_id("grass").unwrap();
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        chunk.set_voxel_at(
+            &UVec3::new(1, 2, 3),
+            VoxelData {
+                shape: VoxelShape { data: 0 },
+                state: 0,
+                id: grass,
+            },
+        );
+        let encoded = encode_chunk(&chunk);
+
+        // Grass has a different id by the time the chunk is loaded
+        let decoded = decode_chunk_with(IVec3::ZERO, &encoded, |name| {
+            (name == "grass").then(|| grass + 100)
+        })
+        .unwrap();
+        assert_eq!(decoded.voxel_at(&UVec3::new(1, 2, 3)).id, grass + 100);
+        assert_eq!(decoded.voxel_at(&UVec3::ZERO).id, 0);
+        // And is gone entirely
+        let gone = decode_chunk_with(IVec3::ZERO, &encoded, |_| None).unwrap();
+        assert_eq!(gone.voxel_at(&UVec3::new(1, 2, 3)).id, 0);
+
+        // Saves from before the name table keep their ids
+        let mut unnamed = MAGIC.to_vec();
+        unnamed.extend_from_slice(&UNNAMED_VERSION.to_le_bytes());
+        unnamed.extend_from_slice(&(CHUNK_VOLUME as u16).to_le_bytes());
+        unnamed.extend_from_slice(&[0, 0, grass as u8, (grass >> 8) as u8, 0]);
+        let decoded = decode_chunk(IVec3::ZERO, &unnamed).unwrap();
+        assert_eq!(decoded.voxel_at(&UVec3::new(1, 2, 3)).id, grass);
+    }
+
+    #[test]
+    fn narrow_shapes_load_unrotated() {
+        // A version 2 chunk of upside down stairs, the shape and then the state, id and density
+        let stair = voxel_shape::STAIR.data as u8 | 0b_0001_0000;
+        let mut narrow = MAGIC.to_vec();
+        narrow.extend_from_slice(&NARROW_SHAPE_VERSION.to_le_bytes());
+        narrow.extend_from_slice(&0u16.to_le_bytes());
+        narrow.extend_from_slice(&(CHUNK_VOLUME as u16).to_le_bytes());
+        narrow.extend_from_slice(&[stair, 2, 7, 0, 100]);
+        let decoded = decode_chunk(IVec3::ZERO, &narrow).unwrap();
+        let voxel = *decoded.voxel_at(&UVec3::new(4, 5, 6));
+        let (shape, id) = (voxel.shape.data, voxel.id);
+        assert_eq!((shape, voxel.state, id), (stair as u16, 2, 7));
+        assert_eq!(
+            decoded.to_bytes(),
+            widen_records(&narrow[HEADER_BYTES + 4..]).repeat(CHUNK_VOLUME)
+        );
+    }
+
+    #[test]
+    fn legacy_and_unknown_formats_do_not_panic() {
+        let chunk = random_chunk(IVec3::ZERO, 7);
+        // Raw dumps are as old as the single byte shapes
+        let narrow: Vec<u8> = chunk
+            .to_bytes()
+            .chunks_exact(VOXEL_BYTES)
+            .flat_map(|record| [&record[..1], &record[2..]].concat())
+            .collect();
+        let legacy = decode_chunk(IVec3::ZERO, &narrow).unwrap();
+        assert_eq!(legacy.to_bytes(), widen_records(&narrow));
+
+        let mut newer = encode_chunk(&chunk);
+        newer[MAGIC.len()..HEADER_BYTES].copy_from_slice(&(STORAGE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_chunk(IVec3::ZERO, &newer).err(),
+            Some(ChunkDecodeError::UnsupportedVersion(STORAGE_VERSION + 1))
+        );
+
+        let encoded = encode_chunk(&chunk);
+        let truncated = &encoded[..encoded.len() - 3];
+        assert_eq!(
+            decode_chunk(IVec3::ZERO, truncated).err(),
+            Some(ChunkDecodeError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn saved_chunks_load_from_disk() {
+        let folder = std::env::temp_dir().join(format!("chunk_storage_{}", std::process::id()));
+        let storage = ChunkStorage::new(&folder);
+        let position = IVec3::new(3, -1, 0);
+        let chunk = random_chunk(position, 3);
+
+        assert!(storage.load_chunk(position).is_none());
+        storage.save_chunk(position, &chunk).unwrap();
+        let loaded = storage.load_chunk(position).unwrap();
+        assert_eq!(loaded.to_bytes(), chunk.to_bytes());
+        assert!(folder.join("3_-1_0.chunk").exists());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
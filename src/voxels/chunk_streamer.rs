@@ -0,0 +1,72 @@
+use glam::{IVec3, Vec3};
+
+use super::voxel_scene::VoxelScene;
+
+// Keeps the chunks around a position loaded. Chunks are requested within the view distance and only unloaded once
+// they are past the view distance plus the margin, so walking back and forth over a chunk border doesn't thrash
+pub struct ChunkStreamer {
+    pub view_distance: u32, // In chunks
+    pub unload_margin: u32, // In chunks
+    last_center: Option<IVec3>,
+}
+
+impl ChunkStreamer {
+    pub fn new(view_distance: u32) -> Self {
+        Self {
+            view_distance,
+            unload_margin: 2,
+            last_center: None,
+        }
+    }
+
+    // Only does work when the position moved into another chunk
+    pub fn update(&mut self, scene: &VoxelScene, position: Vec3) {
+        let center = VoxelScene::chunk_at(&position.floor().as_ivec3());
+        if self.last_center == Some(center) {
+            return;
+        }
+        self.last_center = Some(center);
+
+        scene.set_focus(center);
+        scene.unload_chunks_outside(center, self.view_distance + self.unload_margin);
+        for chunk_pos in self.columns_in_view(center) {
+            for y in scene.config.min_chunk_y()..=scene.config.max_chunk_y() {
+                scene.initialize_and_generate_chunk(IVec3::new(chunk_pos.x, y, chunk_pos.z));
+            }
+        }
+    }
+
+    // Chunk columns within the view distance, nearest first
+    fn columns_in_view(&self, center: IVec3) -> Vec<IVec3> {
+        let distance = self.view_distance as i32;
+        let mut columns = Vec::new();
+        for x in -distance..=distance {
+            for z in -distance..=distance {
+                columns.push(IVec3::new(center.x + x, 0, center.z + z));
+            }
+        }
+        columns.sort_by_key(|column| {
+            let offset = *column - IVec3::new(center.x, 0, center.z);
+            offset.dot(offset)
+        });
+        columns
+    }
+}
+
+#[cfg(test)]
+mod chunk_streamer_tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_sorted_nearest_first() {
+        let streamer = ChunkStreamer::new(2);
+        let columns = streamer.columns_in_view(IVec3::new(5, 3, -5));
+        assert_eq!(columns.len(), 25);
+        assert_eq!(columns[0], IVec3::new(5, 0, -5));
+        let distances: Vec<i32> = columns
+            .iter()
+            .map(|column| (*column - columns[0]).dot(*column - columns[0]))
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use glam::IVec3;
+
+use super::voxel_data::VoxelData;
+
+// Edits kept for undo across all strokes, the oldest strokes are dropped past it
+pub const EDIT_HISTORY_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct VoxelEdit {
+    pub pos: IVec3,
+    pub old: VoxelData,
+    pub new: VoxelData,
+}
+
+// The player's edits grouped into strokes, a stroke is undone or redone as a whole. Positions are in scene space,
+// so strokes stay valid while their chunks are unloaded and reloaded
+pub struct EditHistory {
+    strokes: VecDeque<VecDeque<VoxelEdit>>, // Edits leave the front of the oldest stroke once it's over capacity
+    undone: Vec<Vec<VoxelEdit>>,            // Most recently undone last
+    edits: usize,                           // Edits in strokes and undone together
+    capacity: usize,
+    new_stroke: bool, // The next edit starts a stroke of its own
+}
+
+impl EditHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            strokes: VecDeque::new(),
+            undone: Vec::new(),
+            edits: 0,
+            capacity,
+            new_stroke: true,
+        }
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.new_stroke = true;
+    }
+
+    // A new edit can't be redone over, whatever was undone is dropped
+    pub fn record(&mut self, edit: VoxelEdit) {
+        self.edits -= self
+            .undone
+            .drain(..)
+            .map(|stroke| stroke.len())
+            .sum::<usize>();
+        match self.strokes.back_mut() {
+            Some(stroke) if !self.new_stroke => stroke.push_back(edit),
+            _ => self.strokes.push_back(VecDeque::from([edit])),
+        }
+        self.new_stroke = false;
+        self.edits += 1;
+
+        // A stroke larger than the whole history loses its oldest edits
+        while self.edits > self.capacity {
+            if self.strokes.len() > 1 {
+                self.edits -= self.strokes.pop_front().unwrap().len();
+            } else {
+                self.strokes[0].pop_front();
+                self.edits -= 1;
+            }
+        }
+    }
+
+    pub fn peek_undo(&self) -> Option<&VecDeque<VoxelEdit>> {
+        self.strokes.back()
+    }
+
+    pub fn peek_redo(&self) -> Option<&[VoxelEdit]> {
+        self.undone.last().map(Vec::as_slice)
+    }
+
+    // The stroke to revert, in the order its edits were made. It can be redone afterwards
+    pub fn undo(&mut self) -> Option<Vec<VoxelEdit>> {
+        let stroke = Vec::from(self.strokes.pop_back()?);
+        self.undone.push(stroke.clone());
+        self.new_stroke = true;
+        Some(stroke)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<VoxelEdit>> {
+        let stroke = self.undone.pop()?;
+        self.strokes.push_back(VecDeque::from(stroke.clone()));
+        self.new_stroke = true;
+        Some(stroke)
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits == 0
+    }
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(EDIT_HISTORY_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod edit_history_tests {
+    use super::*;
+    use crate::voxels::voxel_shapes::voxel_shape;
+
+    fn edit(x: i32) -> VoxelEdit {
+        let voxel = |id| VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id,
+        };
+        VoxelEdit {
+            pos: IVec3::new(x, 0, 0),
+            old: voxel(0),
+            new: voxel(1),
+        }
+    }
+
+    #[test]
+    fn strokes_are_undone_whole_and_dropped_by_new_edits() {
+        let mut history = EditHistory::default();
+        history.record(edit(1));
+        history.record(edit(2));
+        history.begin_stroke();
+        history.record(edit(3));
+
+        assert_eq!(history.undo().unwrap().len(), 1);
+        assert_eq!(history.undo().unwrap().len(), 2);
+        assert!(history.undo().is_none());
+        assert_eq!(history.redo().unwrap().len(), 2);
+
+        history.record(edit(4));
+        assert!(history.redo().is_none());
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn oldest_strokes_are_dropped_past_the_capacity() {
+        let mut history = EditHistory::new(3);
+        for x in 0..2 {
+            history.begin_stroke();
+            history.record(edit(x));
+            history.record(edit(x));
+        }
+        assert_eq!(history.len(), 2);
+        assert!(history.undo().is_some());
+        assert!(history.undo().is_none());
+
+        history.begin_stroke();
+        for x in 0..5 {
+            history.record(edit(x));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.peek_undo().unwrap()[0].pos.x, 2);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Vec3};
+
+use crate::asset_types::mesh::Mesh;
+use crate::asset_types::vertex::Vertex;
+
+// Corner i of a cell sits at (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+pub fn corner_offset(corner: usize) -> IVec3 {
+    IVec3::new(
+        (corner & 1) as i32,
+        ((corner >> 1) & 1) as i32,
+        ((corner >> 2) & 1) as i32,
+    )
+}
+
+// Pairs of corners connected by each edge, grouped by axis
+pub const EDGE_CORNERS: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+lazy_static! {
+    // Triangles (as edge indices) for every combination of solid corners
+    static ref CASE_TABLE: Vec<Vec<[usize; 3]>> = (0..256).map(|case| build_case(case as u8)).collect();
+}
+
+pub fn case_triangles(case: u8) -> &'static [[usize; 3]] {
+    &CASE_TABLE[case as usize]
+}
+
+fn edge_between(a: usize, b: usize) -> usize {
+    EDGE_CORNERS
+        .iter()
+        .position(|&(c0, c1)| (c0 == a && c1 == b) || (c0 == b && c1 == a))
+        .unwrap()
+}
+
+// The corners of every cell face in winding order
+fn face_corners() -> Vec<[usize; 4]> {
+    let mut faces = Vec::new();
+    for axis in 0..3 {
+        let u = 1 << ((axis + 1) % 3);
+        let w = 1 << ((axis + 2) % 3);
+        for side in 0..2 {
+            let base = side << axis;
+            faces.push([base, base | u, base | u | w, base | w]);
+        }
+    }
+    faces
+}
+
+// Instead of a hand written table, each case is built by connecting the crossed edges around every face of the cell
+// and following the resulting loops. Ambiguous faces always keep solid corners apart, both cells sharing the face
+// make the same choice so the surface stays watertight
+fn build_case(case: u8) -> Vec<[usize; 3]> {
+    let solid = |corner: usize| case & (1 << corner) != 0;
+    let mut links: Vec<Vec<usize>> = vec![Vec::new(); 12];
+    let mut link = |a: usize, b: usize| {
+        links[a].push(b);
+        links[b].push(a);
+    };
+
+    for face in face_corners() {
+        let crossed: Vec<usize> = (0..4)
+            .filter(|&i| solid(face[i]) != solid(face[(i + 1) % 4]))
+            .map(|i| edge_between(face[i], face[(i + 1) % 4]))
+            .collect();
+        match crossed.len() {
+            2 => link(crossed[0], crossed[1]),
+            4 => {
+                for i in (0..4).filter(|&i| solid(face[i])) {
+                    let previous = face[(i + 3) % 4];
+                    let next = face[(i + 1) % 4];
+                    link(edge_between(previous, face[i]), edge_between(face[i], next));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut triangles = Vec::new();
+    let mut visited = [false; 12];
+    for start in 0..12 {
+        if visited[start] || links[start].is_empty() {
+            continue;
+        }
+        let mut polygon = vec![start];
+        visited[start] = true;
+        let mut previous = start;
+        let mut current = links[start][0];
+        while current != start {
+            polygon.push(current);
+            visited[current] = true;
+            let next = if links[current][0] == previous {
+                links[current][1]
+            } else {
+                links[current][0]
+            };
+            previous = current;
+            current = next;
+        }
+        for i in 1..polygon.len() - 1 {
+            triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+    triangles
+}
+
+// Polygonizes the cells whose minimum corner lies in [origin, origin + size).
+// Density is positive inside the surface, vertices are made relative to the origin.
+// The surface looks are sampled from the solid corner of each edge and the vertex normal, the light from the open one
+pub fn generate_mesh(
+    origin: IVec3,
+    size: IVec3,
+    density_at: impl Fn(IVec3) -> f32,
+    surface_at: impl Fn(IVec3, Vec3) -> ([f32; 4], u32),
+    light_at: impl Fn(IVec3) -> f32,
+) -> Mesh {
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut edge_vertices: HashMap<(IVec3, IVec3), u32> = HashMap::new();
+
+    let gradient_at = |position: IVec3| -> Vec3 {
+        Vec3::new(
+            density_at(position + IVec3::X) - density_at(position - IVec3::X),
+            density_at(position + IVec3::Y) - density_at(position - IVec3::Y),
+            density_at(position + IVec3::Z) - density_at(position - IVec3::Z),
+        ) * 0.5
+    };
+
+    for x in 0..size.x {
+        for y in 0..size.y {
+            for z in 0..size.z {
+                let cell = origin + IVec3::new(x, y, z);
+                let densities: [f32; 8] =
+                    std::array::from_fn(|corner| density_at(cell + corner_offset(corner)));
+                let case = densities
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |case, (i, density)| {
+                        if *density > 0.0 {
+                            case | (1 << i)
+                        } else {
+                            case
+                        }
+                    });
+                if case == 0 || case == 255 {
+                    continue;
+                }
+
+                for triangle in case_triangles(case) {
+                    let mut corners = [0u32; 3];
+                    for (i, edge) in triangle.iter().enumerate() {
+                        let (a, b) = EDGE_CORNERS[*edge];
+                        let key = (cell + corner_offset(a), cell + corner_offset(b));
+                        corners[i] = *edge_vertices.entry(key).or_insert_with(|| {
+                            let (pos_a, pos_b) = key;
+                            let t = densities[a] / (densities[a] - densities[b]);
+                            let position = pos_a.as_vec3().lerp(pos_b.as_vec3(), t);
+                            // Density decreases towards the outside of the surface
+                            let normal = -gradient_at(pos_a)
+                                .lerp(gradient_at(pos_b), t)
+                                .normalize_or_zero();
+                            let (solid_corner, open_corner) = if densities[a] > 0.0 {
+                                (pos_a, pos_b)
+                            } else {
+                                (pos_b, pos_a)
+                            };
+                            let local_position = position - origin.as_vec3();
+                            let (color, texture_layer) = surface_at(solid_corner, normal);
+                            vertices.push(Vertex {
+                                position: local_position.into(),
+                                color,
+                                normal: normal.into(),
+                                uv: [position.x, position.z],
+                                tangent: [1.0, 0.0, 0.0, 1.0],
+                                texture_layer,
+                                ao: 1.0,
+                                light: light_at(open_corner),
+                            });
+                            vertices.len() as u32 - 1
+                        });
+                    }
+                    if corners[0] == corners[1]
+                        || corners[1] == corners[2]
+                        || corners[0] == corners[2]
+                    {
+                        continue;
+                    }
+
+                    // Match the winding used by the voxel shape meshes
+                    let p0 = Vec3::from(vertices[corners[0] as usize].position);
+                    let p1 = Vec3::from(vertices[corners[1] as usize].position);
+                    let p2 = Vec3::from(vertices[corners[2] as usize].position);
+                    let normal: Vec3 = corners.iter().fold(Vec3::ZERO, |sum, &index| {
+                        sum + Vec3::from(vertices[index as usize].normal)
+                    });
+                    if (p1 - p0).cross(p2 - p0).dot(normal) > 0.0 {
+                        corners.swap(1, 2);
+                    }
+                    indices.extend_from_slice(&corners);
+                }
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new();
+    mesh.append_vertices(&mut vertices);
+    mesh.append_indices(&mut indices);
+    mesh
+}
+
+#[cfg(test)]
+mod marching_cubes_tests {
+    use super::*;
+
+    #[test]
+    fn every_crossed_edge_is_used() {
+        for case in 0..=255u8 {
+            let solid = |corner: usize| case & (1 << corner) != 0;
+            let crossed: Vec<usize> = (0..12)
+                .filter(|&edge| solid(EDGE_CORNERS[edge].0) != solid(EDGE_CORNERS[edge].1))
+                .collect();
+            let mut used: Vec<usize> = case_triangles(case).iter().flatten().copied().collect();
+            used.sort_unstable();
+            used.dedup();
+            assert_eq!(used, crossed, "case {case}");
+        }
+    }
+
+    #[test]
+    fn plane_produces_flat_surface() {
+        let mesh = generate_mesh(
+            IVec3::ZERO,
+            IVec3::new(4, 4, 4),
+            |position| 1.5 - position.y as f32,
+            |_, _| ([1.0; 4], 0),
+            |_| 0.0,
+        );
+        assert!(mesh.index_count > 0);
+        for vertex in mesh.get_vertices() {
+            assert!((vertex.position[1] - 1.5).abs() < 1e-5);
+            assert!((Vec3::from(vertex.normal) - Vec3::Y).length() < 1e-5);
+        }
+    }
+}
@@ -1,6 +1,19 @@
 pub mod biome_profile;
+pub mod chunk_mesh;
+pub mod chunk_neighborhood;
+pub mod chunk_queue;
+pub mod chunk_storage;
+pub mod chunk_streamer;
+pub mod edit_history;
+pub mod marching_cubes;
+pub mod pending_work;
+pub mod processor_threads;
+pub mod structures;
+pub mod surface_cache;
 pub mod voxel_data;
+pub mod voxel_light;
 pub mod voxel_mesh;
 pub mod voxel_registry;
 pub mod voxel_scene;
 pub mod voxel_shapes;
+pub mod voxel_storage;
@@ -0,0 +1,119 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use flume::{Receiver, Sender};
+use parking_lot::{Condvar, Mutex};
+
+// Counts the chunks somewhere in the generation pipeline. A stage adds the work for the next stage before it finishes
+// its own, so the count only drops to zero once nothing is queued or being processed.
+// Skipped work (cancelled, already loaded or empty chunks) is finished like everything else, so the totals converge
+#[derive(Default)]
+pub struct PendingWork {
+    count: Mutex<usize>,
+    idle: Condvar,
+    queued: AtomicUsize,    // Total ever added
+    completed: AtomicUsize, // Total ever finished
+    subscribers: Mutex<Vec<Sender<()>>>,
+}
+
+impl PendingWork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self) {
+        let mut count = self.count.lock();
+        *count += 1;
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        let mut count = self.count.lock();
+        *count = count.saturating_sub(1);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        if *count == 0 {
+            self.idle.notify_all();
+            // Dropped receivers unsubscribe
+            self.subscribers
+                .lock()
+                .retain(|subscriber| subscriber.send(()).is_ok());
+        }
+    }
+
+    // Receives an event every time the pending work drains to zero
+    pub fn subscribe(&self) -> Receiver<()> {
+        let (sender, receiver) = flume::unbounded();
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+
+    // How much work was finished and how much was ever added, equal whenever nothing is pending
+    pub fn progress(&self) -> (usize, usize) {
+        // Read under the lock so a stage handing work on isn't seen half way
+        let _count = self.count.lock();
+        (
+            self.completed.load(Ordering::Relaxed),
+            self.queued.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn count(&self) -> usize {
+        *self.count.lock()
+    }
+
+    // Blocks until nothing is pending, false when the timeout passed first
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock();
+        while *count > 0 {
+            if self.idle.wait_until(&mut count, deadline).timed_out() {
+                return *count == 0;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod pending_work_tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn waits_for_work_finished_on_another_thread() {
+        let pending = Arc::new(PendingWork::new());
+        pending.add();
+        pending.add();
+        assert!(!pending.wait_idle(Duration::from_millis(10)));
+
+        let worker = Arc::clone(&pending);
+        let handle = std::thread::spawn(move || {
+            worker.finish();
+            worker.finish();
+        });
+        assert!(pending.wait_idle(Duration::from_secs(5)));
+        assert_eq!(pending.count(), 0);
+        assert_eq!(pending.progress(), (2, 2));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn subscribers_hear_every_drain() {
+        let pending = PendingWork::new();
+        let idle = pending.subscribe();
+        pending.add();
+        pending.add();
+        pending.finish();
+        assert!(idle.try_recv().is_err());
+        pending.finish();
+        assert!(idle.try_recv().is_ok());
+
+        pending.add();
+        pending.finish();
+        assert!(idle.try_recv().is_ok());
+        assert_eq!(pending.progress(), (3, 3));
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How many workers each stage of the chunk pipeline gets. The workers block on their queues while there's nothing to
+// do, so they only take a core while they work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessorThreads {
+    pub initialization: usize, // Generate or load chunks
+    pub generation: usize,     // Mesh chunks
+    pub pre_processor: usize,  // Wait for a chunk's neighbours before it's meshed
+}
+
+impl Default for ProcessorThreads {
+    fn default() -> Self {
+        let cores = std::thread::available_parallelism().map_or(4, |cores| cores.get());
+        Self::for_cores(cores)
+    }
+}
+
+impl ProcessorThreads {
+    // Two cores are left to the render loop and the simulation, the rest is split between initialization and
+    // meshing. The pre-processors barely do any work
+    pub fn for_cores(cores: usize) -> Self {
+        let available = cores.saturating_sub(2).max(2);
+        Self {
+            initialization: (available * 3 / 8).max(1),
+            generation: (available * 3 / 8).max(1),
+            pre_processor: (available / 8).max(1),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.initialization + self.generation + self.pre_processor
+    }
+}
+
+// How busy the workers of one stage are, read by the debug HUD
+#[derive(Debug, Default)]
+pub struct StageUtilization {
+    workers: AtomicUsize,
+    busy: AtomicUsize,
+}
+
+impl StageUtilization {
+    pub fn set_workers(&self, workers: usize) {
+        self.workers.store(workers, Ordering::Relaxed);
+    }
+
+    // The worker counts as busy until the guard is dropped
+    pub fn start(&self) -> BusyGuard {
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        BusyGuard { stage: self }
+    }
+
+    // Busy and total workers right now
+    pub fn busy_workers(&self) -> (usize, usize) {
+        (
+            self.busy.load(Ordering::Relaxed),
+            self.workers.load(Ordering::Relaxed),
+        )
+    }
+}
+
+pub struct BusyGuard<'a> {
+    stage: &'a StageUtilization,
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.stage.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessorUtilization {
+    pub initialization: StageUtilization,
+    pub generation: StageUtilization,
+    pub pre_processor: StageUtilization,
+}
+
+#[cfg(test)]
+mod processor_threads_tests {
+    use super::*;
+
+    #[test]
+    fn every_stage_gets_a_worker_without_taking_every_core() {
+        for cores in [1, 2, 4, 8, 16, 64] {
+            let threads = ProcessorThreads::for_cores(cores);
+            assert!(threads.initialization >= 1 && threads.generation >= 1);
+            assert!(threads.pre_processor >= 1);
+            assert!(threads.total() <= cores.max(3));
+        }
+        assert_eq!(ProcessorThreads::for_cores(16).total(), 11);
+    }
+
+    #[test]
+    fn busy_workers_are_counted_until_they_finish() {
+        let stage = StageUtilization::default();
+        stage.set_workers(2);
+        let guard = stage.start();
+        assert_eq!(stage.busy_workers(), (1, 2));
+        drop(guard);
+        assert_eq!(stage.busy_workers(), (0, 2));
+    }
+}
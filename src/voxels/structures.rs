@@ -0,0 +1,413 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    sync::Arc,
+};
+
+use glam::{IVec3, UVec3};
+use parking_lot::{Mutex, RwLock};
+
+use super::{
+    biome_profile::BiomeMap,
+    surface_cache::ColumnHeights,
+    voxel_data::VoxelData,
+    voxel_registry::get_voxel_by_name,
+    voxel_scene::{ChunkMap, VoxelChunk, VoxelScene, WorldConfig, CHUNK_SIZE},
+    voxel_shapes::voxel_shape,
+};
+
+const STRUCTURE_FOLDER: &str = "./src/resources/structures/";
+
+lazy_static! {
+    static ref STRUCTURES: RwLock<HashMap<String, Arc<Structure>>> = RwLock::new(load_structures());
+}
+
+// Structures that fail to parse are logged and left out, the rest still load
+fn load_structures() -> HashMap<String, Arc<Structure>> {
+    let mut map = HashMap::new();
+    let paths = match fs::read_dir(STRUCTURE_FOLDER) {
+        Ok(paths) => paths,
+        Err(e) => {
+            println!("[INFO] No structures loaded: {e}");
+            return map;
+        }
+    };
+    for structure_file in paths.into_iter().flatten() {
+        let name = structure_file
+            .file_name()
+            .to_string_lossy()
+            .replace(".json", "");
+        let structure = fs::read_to_string(structure_file.path())
+            .map_err(|e| format!("Unable to read file: {e}"))
+            .and_then(|data| Structure::from_json(&data));
+        match structure {
+            Ok(structure) => {
+                println!(
+                    "[INFO] Loaded structure {name} with {} voxels",
+                    structure.voxels.len()
+                );
+                map.insert(name, Arc::new(structure));
+            }
+            Err(e) => println!("[INFO] Skipping structure {name}: {e}"),
+        }
+    }
+    map
+}
+
+// Structures that no longer parse are dropped, chunks generated afterwards won't have them
+pub fn reload_structures() {
+    *STRUCTURES.write() = load_structures();
+}
+
+pub fn get_structure_by_name(name: &str) -> Option<Arc<Structure>> {
+    STRUCTURES.read().get(name).cloned()
+}
+
+// A template of voxels placed on the terrain. Offsets are from the voxel right above the surface it grows on
+pub struct Structure {
+    pub voxels: Vec<(IVec3, VoxelData)>,
+}
+
+impl Structure {
+    // Entries are either a single voxel at an "Offset" or a box of voxels from "From" to "To", both inclusive, with an
+    // optional "Variant". Later entries replace earlier ones where they overlap
+    pub fn from_json(data: &str) -> Result<Self, String> {
+        fn offset(entry: &serde_json::Value, key: &str) -> Result<Option<IVec3>, String> {
+            match entry.get(key) {
+                None => Ok(None),
+                Some(value) => match value.as_array().map(|value| &value[..]) {
+                    Some([x, y, z]) if x.is_i64() && y.is_i64() && z.is_i64() => {
+                        Ok(Some(IVec3::new(
+                            x.as_i64().unwrap() as i32,
+                            y.as_i64().unwrap() as i32,
+                            z.as_i64().unwrap() as i32,
+                        )))
+                    }
+                    _ => Err(format!("'{key}' must be an [x, y, z] list of integers")),
+                },
+            }
+        }
+
+        let json: serde_json::Value =
+            serde_json::from_str(data).map_err(|e| format!("Invalid JSON: {e}"))?;
+        let entries = json
+            .get("Voxels")
+            .and_then(|voxels| voxels.as_array())
+            .ok_or_else(|| "Missing list 'Voxels'".to_string())?;
+
+        // Ordered so the voxels of a structure are always written in the same order
+        let mut voxels = BTreeMap::new();
+        for entry in entries {
+            let name = entry
+                .get("Voxel")
+                .and_then(|name| name.as_str())
+                .ok_or_else(|| "Missing text 'Voxel'".to_string())?;
+            let variant = entry.get("Variant").and_then(|variant| variant.as_u64());
+            let voxel = VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: get_voxel_by_name(name.to_string())
+                    .ok_or_else(|| format!("Unknown voxel '{name}'"))?
+                    .id,
+            }
+            .with_variant(variant.unwrap_or(0) as u8);
+            let (from, to) = match (
+                offset(entry, "Offset")?,
+                offset(entry, "From")?,
+                offset(entry, "To")?,
+            ) {
+                (Some(offset), None, None) => (offset, offset),
+                (None, Some(from), Some(to)) => (from.min(to), from.max(to)),
+                _ => {
+                    return Err(
+                        "Every voxel needs either an 'Offset' or a 'From' and a 'To'".to_string(),
+                    )
+                }
+            };
+            for x in from.x..=to.x {
+                for y in from.y..=to.y {
+                    for z in from.z..=to.z {
+                        voxels.insert((x, y, z), voxel);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            voxels: voxels
+                .into_iter()
+                .map(|((x, y, z), voxel)| (IVec3::new(x, y, z), voxel))
+                .collect(),
+        })
+    }
+
+    // The smallest box around every offset, both corners inclusive
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        self.voxels.iter().fold(
+            (IVec3::splat(i32::MAX), IVec3::splat(i32::MIN)),
+            |(min, max), (offset, _)| (min.min(*offset), max.max(*offset)),
+        )
+    }
+
+    // Whether the part of the structure meant to stand in the air is clear of solid terrain, voxels below the anchor
+    // are meant to sink into the ground. Only the chunk it grows out of is checked, neighbours may not be generated
+    // yet and the structures of a chunk mustn't depend on which ones are
+    fn has_room(&self, chunk: &VoxelChunk, anchor: IVec3) -> bool {
+        let (mut min, max) = self.bounds();
+        min.y = min.y.max(0);
+        let mut solid = Vec::new();
+        chunk.solid_voxels_in(anchor + min, anchor + max, &mut solid);
+        solid.is_empty()
+    }
+}
+
+// A value between 0 and 1 that only depends on the seed, the column and which spawn of the biome is rolled for
+fn spawn_roll(seed: u64, x: i32, z: i32, spawn: usize) -> f32 {
+    let mut hash = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (z as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (spawn as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    // Splitmix finalizer, neighbouring columns end up unrelated
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// The scenespace writes of every structure growing out of the chunk. Only the chunk holding a column's surface voxel
+// places on it, so a structure is placed once no matter which chunks are generated first
+pub fn plan_structures(
+    chunk: &VoxelChunk,
+    biome_map: &BiomeMap,
+    heights: &ColumnHeights,
+    seed: u64,
+) -> Vec<(IVec3, VoxelData)> {
+    let origin = chunk.scenespace_pos();
+    let mut writes = Vec::new();
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            let height = match heights[(x * CHUNK_SIZE + z) as usize] {
+                Some(height) if height >= origin.y && height < origin.y + CHUNK_SIZE as i32 => {
+                    height
+                }
+                _ => continue,
+            };
+            let surface = *chunk.voxel_at(&UVec3::new(x, (height - origin.y) as u32, z));
+            let (world_x, world_z) = (origin.x + x as i32, origin.z + z as i32);
+            let biome = biome_map.biome_at(world_x, world_z);
+            let spawn = biome
+                .structures()
+                .iter()
+                .enumerate()
+                .find(|(index, spawn)| {
+                    spawn.on.map_or(surface.id != 0, |id| id == surface.id)
+                        && spawn_roll(seed, world_x, world_z, *index) < spawn.rate
+                });
+            let structure =
+                match spawn.and_then(|(_, spawn)| get_structure_by_name(&spawn.structure)) {
+                    Some(structure) => structure,
+                    None => continue,
+                };
+            let anchor = IVec3::new(world_x, height + 1, world_z);
+            if !structure.has_room(chunk, anchor) {
+                continue;
+            }
+            writes.extend(
+                structure
+                    .voxels
+                    .iter()
+                    .map(|(offset, voxel)| (anchor + *offset, *voxel)),
+            );
+        }
+    }
+    writes
+}
+
+// Structures only grow into air, terrain they run into is kept
+fn write_structure_voxel(chunk: &mut VoxelChunk, position: IVec3, voxel: VoxelData) {
+    let local = (position - chunk.scenespace_pos()).as_uvec3();
+    if chunk.voxel_at(&local).id == 0 {
+        chunk.set_voxel_at(&local, voxel);
+        chunk.set_density(&local, 1.0);
+        chunk.is_empty = false;
+    }
+}
+
+// Writes that structures made into chunks which weren't loaded yet, applied once those are generated.
+// Writing and inserting chunks happen under the same lock, so no write can slip in between a chunk being checked
+// for and it being inserted
+#[derive(Default)]
+pub struct StructureWrites {
+    pending: Mutex<HashMap<IVec3, Vec<(IVec3, VoxelData)>, ahash::RandomState>>,
+}
+
+impl StructureWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Writes into the chunk being generated, into loaded neighbours or queues them for later.
+    // Returns the loaded neighbours that were written into, they have to be remeshed
+    pub fn place(
+        &self,
+        chunk: &mut VoxelChunk,
+        writes: Vec<(IVec3, VoxelData)>,
+        chunks: &ChunkMap,
+        config: &WorldConfig,
+    ) -> Vec<IVec3> {
+        let mut pending = self.pending.lock();
+        let mut touched = Vec::new();
+        for (position, voxel) in writes {
+            if !config.height_in_bounds(position.y) {
+                continue;
+            }
+            let chunk_pos = VoxelScene::chunk_at(&position);
+            if chunk_pos == chunk.position {
+                write_structure_voxel(chunk, position, voxel);
+            } else if let Some(mut neighbour) = chunks.get_mut(&chunk_pos) {
+                write_structure_voxel(&mut neighbour, position, voxel);
+                touched.push(chunk_pos);
+            } else {
+                pending
+                    .entry(chunk_pos)
+                    .or_default()
+                    .push((position, voxel));
+            }
+        }
+        touched.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+        touched.dedup();
+        touched
+    }
+
+    // Applies the writes waiting for a freshly generated chunk and inserts it. Chunks loaded from storage already
+    // had their structures written before they were saved, the writes for them are dropped
+    pub fn insert_chunk(&self, mut chunk: VoxelChunk, chunks: &ChunkMap, generated: bool) {
+        let mut pending = self.pending.lock();
+        if let Some(writes) = pending.remove(&chunk.position) {
+            if generated {
+                for (position, voxel) in writes {
+                    write_structure_voxel(&mut chunk, position, voxel);
+                }
+            }
+        }
+        chunks.insert(chunk.position, chunk);
+    }
+
+    // Drops the writes for chunks the predicate rejects, so writes into chunks that are never generated don't pile up
+    pub fn retain(&self, keep: impl Fn(&IVec3) -> bool) {
+        self.pending.lock().retain(|chunk_pos, _| keep(chunk_pos));
+    }
+
+    pub fn pending_chunk_count(&self) -> usize {
+        self.pending.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod structure_tests {
+    use dashmap::DashMap;
+
+    use super::*;
+
+    #[test]
+    fn writes_cross_into_chunks_generated_later() {
+        let stone = get_voxel_by_name("stone".to_string()).unwrap().id;
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: stone,
+        };
+        let config = WorldConfig::default();
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let writes = StructureWrites::new();
+
+        // A column of voxels from the last x of one chunk over into the next one
+        let edge = CHUNK_SIZE as i32 - 1;
+        let planned = vec![
+            (IVec3::new(edge, 10, 0), voxel),
+            (IVec3::new(edge + 1, 10, 0), voxel),
+        ];
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        assert!(writes
+            .place(&mut chunk, planned, &chunks, &config)
+            .is_empty());
+        assert_eq!(chunk.voxel_at(&UVec3::new(edge as u32, 10, 0)).id, stone);
+        assert_eq!(writes.pending_chunk_count(), 1);
+
+        writes.insert_chunk(VoxelChunk::new(IVec3::X), &chunks, true);
+        assert_eq!(writes.pending_chunk_count(), 0);
+        let neighbour = chunks.get(&IVec3::X).unwrap();
+        assert_eq!(neighbour.voxel_at(&UVec3::new(0, 10, 0)).id, stone);
+        assert!(!neighbour.is_empty);
+    }
+
+    #[test]
+    fn writes_for_far_chunks_are_dropped() {
+        let stone = get_voxel_by_name("stone".to_string()).unwrap().id;
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: stone,
+        };
+        let config = WorldConfig::default();
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let writes = StructureWrites::new();
+        let edge = CHUNK_SIZE as i32;
+        let planned = vec![
+            (IVec3::new(edge, 10, 0), voxel),
+            (IVec3::new(-1, 10, 0), voxel),
+        ];
+        writes.place(&mut VoxelChunk::new(IVec3::ZERO), planned, &chunks, &config);
+        assert_eq!(writes.pending_chunk_count(), 2);
+        writes.retain(|chunk_pos| chunk_pos.x >= 0);
+        assert_eq!(writes.pending_chunk_count(), 1);
+    }
+
+    #[test]
+    fn structures_need_room_above_their_anchor() {
+        let structure = Structure::from_json(
+            r#"{ "Voxels": [
+                { "From": [-1, -1, -1], "To": [1, 0, 1], "Voxel": "stone" },
+                { "Offset": [0, 2, 0], "Voxel": "log" }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            structure.bounds(),
+            (IVec3::new(-1, -1, -1), IVec3::new(1, 2, 1))
+        );
+
+        let stone = get_voxel_by_name("stone".to_string()).unwrap().id;
+        let mut chunk = VoxelChunk::new(IVec3::new(-1, 0, -1));
+        let ground = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: stone,
+        };
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set_voxel_at(&UVec3::new(x, 4, z), ground);
+            }
+        }
+        chunk.is_empty = false;
+
+        // Sinking into the ground is fine, a voxel next to the anchor isn't
+        let anchor = IVec3::new(-8, 5, -8);
+        assert!(structure.has_room(&chunk, anchor));
+        chunk.set_voxel_at(&UVec3::new(9, 6, 8), ground);
+        assert!(!structure.has_room(&chunk, anchor));
+        // Reaching past the edge of the chunk, only the part inside it is checked
+        assert!(structure.has_room(&chunk, IVec3::new(-16, 5, -8)));
+    }
+
+    #[test]
+    fn spawn_rolls_are_deterministic() {
+        let rolls: Vec<f32> = (0..1000).map(|x| spawn_roll(7, x, 3, 0)).collect();
+        assert!(rolls.iter().all(|roll| (0.0..1.0).contains(roll)));
+        assert_eq!(rolls[10], spawn_roll(7, 10, 3, 0));
+        assert_ne!(rolls[10], spawn_roll(8, 10, 3, 0));
+        // Roughly uniform, so a rate of 0.1 spawns on about a tenth of the columns
+        let below = rolls.iter().filter(|roll| **roll < 0.1).count();
+        assert!((50..150).contains(&below));
+    }
+}
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use glam::IVec2;
+
+use super::voxel_scene::CHUNK_SIZE;
+
+// Height of the highest solid voxel of every column in a chunk column, indexed by x * CHUNK_SIZE + z.
+// None when the whole column is air
+pub type ColumnHeights = [Option<i32>; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+
+// Surface heights shared by every chunk stacked on the same chunk column, so they agree on where the surface is
+// no matter which of them is generated first. Keyed by the x and z of the chunk position
+#[derive(Default)]
+pub struct SurfaceCache {
+    columns: DashMap<IVec2, Arc<ColumnHeights>, ahash::RandomState>,
+}
+
+impl SurfaceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Computes the heights the first time a chunk column is asked for. Chunks asking at the same time may both
+    // compute them, they get the same heights either way
+    pub fn get_or_compute(
+        &self,
+        column: IVec2,
+        compute: impl FnOnce() -> ColumnHeights,
+    ) -> Arc<ColumnHeights> {
+        if let Some(heights) = self.columns.get(&column) {
+            return Arc::clone(heights.value());
+        }
+        let heights = Arc::new(compute());
+        Arc::clone(self.columns.entry(column).or_insert(heights).value())
+    }
+
+    pub fn contains(&self, column: IVec2) -> bool {
+        self.columns.contains_key(&column)
+    }
+
+    pub fn retain(&self, keep: impl Fn(IVec2) -> bool) {
+        self.columns.retain(|column, _| keep(*column));
+    }
+
+    pub fn clear(&self) {
+        self.columns.clear();
+    }
+}
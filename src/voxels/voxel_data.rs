@@ -1,9 +1,67 @@
 use super::voxel_shapes::VoxelShape;
 
-#[derive(Clone, Copy)]
+// The state byte is split into fields:
+// - bits 0-1: variant, picks one of the profile's variant colors and textures
+// - bits 2-4: growth stage
+// - bits 5-7: unused
+pub mod voxel_state {
+    pub const VARIANT_MASK: u8 = 0b0000_0011;
+    pub const MAX_VARIANTS: u8 = VARIANT_MASK + 1;
+    pub const GROWTH_SHIFT: u8 = 2;
+    pub const GROWTH_MASK: u8 = 0b0001_1100;
+}
+
+// 6 bytes, up from 4 since the shape needs a ninth bit for its quarter turn around Y
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(packed(4))]
 pub struct VoxelData {
     pub shape: VoxelShape,
     pub state: u8,
     pub id: u16,
 }
+
+impl VoxelData {
+    pub fn get_variant(&self) -> u8 {
+        self.state & voxel_state::VARIANT_MASK
+    }
+
+    // Variants past the last one wrap around
+    pub fn set_variant(&mut self, variant: u8) {
+        self.state =
+            (self.state & !voxel_state::VARIANT_MASK) | (variant & voxel_state::VARIANT_MASK);
+    }
+
+    pub fn with_variant(mut self, variant: u8) -> Self {
+        self.set_variant(variant);
+        self
+    }
+
+    pub fn get_growth_stage(&self) -> u8 {
+        (self.state & voxel_state::GROWTH_MASK) >> voxel_state::GROWTH_SHIFT
+    }
+
+    pub fn set_growth_stage(&mut self, stage: u8) {
+        self.state = (self.state & !voxel_state::GROWTH_MASK)
+            | ((stage << voxel_state::GROWTH_SHIFT) & voxel_state::GROWTH_MASK);
+    }
+}
+
+#[cfg(test)]
+mod voxel_data_tests {
+    use super::*;
+    use crate::voxels::voxel_shapes::voxel_shape;
+
+    #[test]
+    fn state_fields_dont_overlap() {
+        let mut voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        voxel.set_variant(3);
+        voxel.set_growth_stage(5);
+        assert_eq!((voxel.get_variant(), voxel.get_growth_stage()), (3, 5));
+        voxel.set_variant(1);
+        assert_eq!((voxel.get_variant(), voxel.get_growth_stage()), (1, 5));
+    }
+}
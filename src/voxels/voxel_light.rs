@@ -0,0 +1,308 @@
+use std::collections::{HashSet, VecDeque};
+
+use glam::{IVec3, UVec3};
+use parking_lot::Mutex;
+
+use super::{
+    voxel_data::VoxelData,
+    voxel_registry,
+    voxel_scene::{ChunkMap, VoxelScene, CHUNK_SIZE},
+    voxel_shapes::voxel_directions,
+};
+
+pub const MAX_LIGHT: u8 = 15;
+// Block light is stored in the low nibble of a voxel's light, the high one is left for sky light
+pub const BLOCK_LIGHT_MASK: u8 = 0x0f;
+
+// Light spreads through air and transparent voxels, opaque voxels only hold the light they give off themselves
+pub fn transmits_light(voxel: &VoxelData) -> bool {
+    voxel.id == 0 || voxel_registry::is_transparent(voxel.id)
+}
+
+// Reads and writes light anywhere in the loaded chunks and remembers which chunks it changed.
+// Light stops at chunks that aren't loaded, they pull it in from their neighbours once they are
+struct LightAccess<'a> {
+    chunks: &'a ChunkMap,
+    changed: HashSet<IVec3>,
+}
+
+impl<'a> LightAccess<'a> {
+    fn new(chunks: &'a ChunkMap) -> Self {
+        Self {
+            chunks,
+            changed: HashSet::new(),
+        }
+    }
+
+    fn voxel(&self, position: IVec3) -> Option<VoxelData> {
+        self.chunks
+            .get(&VoxelScene::chunk_at(&position))
+            .and_then(|chunk| chunk.voxel_scenespace_at(&position).cloned())
+    }
+
+    fn light(&self, position: IVec3) -> u8 {
+        self.chunks
+            .get(&VoxelScene::chunk_at(&position))
+            .and_then(|chunk| chunk.block_light_scenespace_at(&position))
+            .unwrap_or(0)
+    }
+
+    fn set_light(&mut self, position: IVec3, level: u8) {
+        let chunk_pos = VoxelScene::chunk_at(&position);
+        if let Some(mut chunk) = self.chunks.get_mut(&chunk_pos) {
+            let local = (position - chunk.scenespace_pos()).as_uvec3();
+            if chunk.block_light_at(&local) != level {
+                chunk.set_block_light(&local, level);
+                self.changed.insert(chunk_pos);
+            }
+        }
+    }
+
+    // Every queued position passes its light on to its neighbours, one level dimmer with every step
+    fn spread(&mut self, additions: &mut VecDeque<IVec3>) {
+        while let Some(position) = additions.pop_front() {
+            let level = self.light(position);
+            if level <= 1 {
+                continue;
+            }
+            for direction in voxel_directions::ALL {
+                let neighbour = position + direction.as_vec();
+                if !self
+                    .voxel(neighbour)
+                    .map_or(false, |voxel| transmits_light(&voxel))
+                {
+                    continue;
+                }
+                if self.light(neighbour) + 1 < level {
+                    self.set_light(neighbour, level - 1);
+                    additions.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    // Darkens everything that was lit by the removed light. Neighbours at least as bright as the removed level are
+    // lit by something else, they're queued to spread their light back into the darkened area.
+    // Voxels giving off light themselves keep it
+    fn remove(&mut self, removals: &mut VecDeque<(IVec3, u8)>, additions: &mut VecDeque<IVec3>) {
+        while let Some((position, level)) = removals.pop_front() {
+            for direction in voxel_directions::ALL {
+                let neighbour = position + direction.as_vec();
+                let neighbour_level = self.light(neighbour);
+                if neighbour_level == 0 {
+                    continue;
+                }
+                if neighbour_level < level {
+                    self.set_light(neighbour, 0);
+                    removals.push_back((neighbour, neighbour_level));
+                    let emission = self
+                        .voxel(neighbour)
+                        .map_or(0, |voxel| voxel_registry::emission(voxel.id));
+                    if emission > 0 {
+                        self.set_light(neighbour, emission);
+                        additions.push_back(neighbour);
+                    }
+                } else {
+                    additions.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    fn into_changed(self) -> Vec<IVec3> {
+        let mut changed: Vec<IVec3> = self.changed.into_iter().collect();
+        changed.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+        changed
+    }
+}
+
+// Propagates block light through the loaded chunks. Chunks are lit as they're loaded and relit around every edit,
+// only ever as far as the light reaches. Propagation runs under one lock, so concurrent edits and loads can't
+// overwrite each other's light
+#[derive(Default)]
+pub struct BlockLight {
+    queues: Mutex<(VecDeque<(IVec3, u8)>, VecDeque<IVec3>)>, // Removals and additions, kept to reuse their memory
+}
+
+impl BlockLight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Lights a freshly loaded chunk from the voxels in it that give off light and from the light of its loaded
+    // neighbours. Returns the chunks whose light changed, the chunk itself included
+    pub fn light_chunk(&self, chunks: &ChunkMap, chunk_pos: IVec3) -> Vec<IVec3> {
+        let mut queues = self.queues.lock();
+        let (_, additions) = &mut *queues;
+        let mut access = LightAccess::new(chunks);
+
+        let origin = chunk_pos * CHUNK_SIZE as i32;
+        let mut emitters = Vec::new();
+        if let Some(chunk) = chunks.get(&chunk_pos) {
+            if !chunk.is_empty {
+                // Neighbouring voxels mostly share their id, which saves going to the registry for every one of
+                // them. Air never gives off light
+                let mut last_id = (0, 0);
+                for x in 0..CHUNK_SIZE {
+                    for y in 0..CHUNK_SIZE {
+                        for z in 0..CHUNK_SIZE {
+                            let local = UVec3::new(x, y, z);
+                            let id = chunk.voxel_at(&local).id;
+                            if id != last_id.0 {
+                                last_id = (id, voxel_registry::emission(id));
+                            }
+                            let emission = last_id.1;
+                            if emission > 0 {
+                                emitters.push((origin + local.as_ivec3(), emission));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for (position, emission) in emitters {
+            access.set_light(position, emission);
+            additions.push_back(position);
+        }
+
+        // Light from the neighbours spreads in from the voxels right outside of the chunk's faces
+        let size = CHUNK_SIZE as i32;
+        for direction in voxel_directions::ALL {
+            let normal = direction.as_vec();
+            let axis = (0..3).find(|axis| normal[*axis] != 0).unwrap();
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            for u in 0..size {
+                for v in 0..size {
+                    let mut outside = IVec3::ZERO;
+                    outside[axis] = if normal[axis] > 0 { size } else { -1 };
+                    outside[u_axis] = u;
+                    outside[v_axis] = v;
+                    if access.light(origin + outside) > 1 {
+                        additions.push_back(origin + outside);
+                    }
+                }
+            }
+        }
+
+        access.spread(additions);
+        access.into_changed()
+    }
+
+    // Relights around a voxel that changed from old to new. Returns the chunks whose light changed
+    pub fn update(
+        &self,
+        chunks: &ChunkMap,
+        position: IVec3,
+        old: VoxelData,
+        new: VoxelData,
+    ) -> Vec<IVec3> {
+        if old.id == new.id {
+            return Vec::new(); // Same light as before, only its shape or state changed
+        }
+        let mut queues = self.queues.lock();
+        let (removals, additions) = &mut *queues;
+        let mut access = LightAccess::new(chunks);
+
+        let level = access.light(position);
+        if level > 0 {
+            access.set_light(position, 0);
+            removals.push_back((position, level));
+            access.remove(removals, additions);
+        }
+
+        let emission = voxel_registry::emission(new.id);
+        if emission > 0 {
+            access.set_light(position, emission);
+            additions.push_back(position);
+        }
+        // Light flows into the space the voxel opened up
+        if transmits_light(&new) {
+            for direction in voxel_directions::ALL {
+                let neighbour = position + direction.as_vec();
+                if access.light(neighbour) > 1 {
+                    additions.push_back(neighbour);
+                }
+            }
+        }
+
+        access.spread(additions);
+        access.into_changed()
+    }
+}
+
+#[cfg(test)]
+mod light_tests {
+    use std::sync::Arc;
+
+    use dashmap::DashMap;
+
+    use super::*;
+    use crate::voxels::{voxel_scene::VoxelChunk, voxel_shapes::voxel_shape};
+
+    fn voxel(name: &str) -> VoxelData {
+        VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: voxel_registry::get_voxel_by_name(name.to_string()).map_or(0, |profile| profile.id),
+        }
+    }
+
+    fn light_at(chunks: &ChunkMap, position: IVec3) -> u8 {
+        LightAccess::new(chunks).light(position)
+    }
+
+    fn set_voxel(chunks: &ChunkMap, position: IVec3, voxel: VoxelData) -> VoxelData {
+        let mut chunk = chunks.get_mut(&VoxelScene::chunk_at(&position)).unwrap();
+        let local = (position - chunk.scenespace_pos()).as_uvec3();
+        let old = *chunk.voxel_at(&local);
+        chunk.set_voxel_at(&local, voxel);
+        old
+    }
+
+    #[test]
+    fn light_falls_off_and_is_removed_again() {
+        let block_light = BlockLight::new();
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        chunks.insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+
+        let source = IVec3::new(8, 8, 2);
+        let old = set_voxel(&chunks, source, voxel("glowstone"));
+        let relit = block_light.update(&chunks, source, old, voxel("glowstone"));
+        assert_eq!(relit, vec![IVec3::ZERO]);
+        assert_eq!(light_at(&chunks, source), MAX_LIGHT);
+        assert_eq!(
+            light_at(&chunks, source + IVec3::new(0, 0, 3)),
+            MAX_LIGHT - 3
+        );
+        assert_eq!(
+            light_at(&chunks, source + IVec3::new(1, 1, 1)),
+            MAX_LIGHT - 3
+        );
+
+        // A wall in between makes the light go around it
+        let old = set_voxel(&chunks, source + IVec3::Z, voxel("stone"));
+        block_light.update(&chunks, source + IVec3::Z, old, voxel("stone"));
+        assert_eq!(light_at(&chunks, source + IVec3::Z), 0);
+        assert_eq!(
+            light_at(&chunks, source + IVec3::new(0, 0, 2)),
+            MAX_LIGHT - 4
+        );
+
+        // A chunk loaded next to the light pulls it in over the border
+        let below = IVec3::new(0, 0, -1);
+        chunks.insert(below, VoxelChunk::new(below));
+        assert_eq!(block_light.light_chunk(&chunks, below), vec![below]);
+        assert_eq!(light_at(&chunks, IVec3::new(8, 8, -1)), MAX_LIGHT - 3);
+
+        let old = set_voxel(&chunks, source, voxel("Empty"));
+        let relit = block_light.update(&chunks, source, old, voxel("Empty"));
+        assert_eq!(relit, vec![below, IVec3::ZERO]);
+        for chunk in chunks.iter() {
+            assert!((0..CHUNK_SIZE).all(|x| {
+                (0..CHUNK_SIZE).all(|y| {
+                    (0..CHUNK_SIZE).all(|z| chunk.block_light_at(&UVec3::new(x, y, z)) == 0)
+                })
+            }));
+        }
+    }
+}
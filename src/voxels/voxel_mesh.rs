@@ -1,3 +1,5 @@
+use glam::Vec3;
+
 use crate::asset_types::mesh::Mesh;
 
 use self::voxel_meshes::SHAPE_MESHES;
@@ -38,11 +40,11 @@ mod voxel_meshes {
             east:   Mesh::new()
                 .append_custom(
                     vec![[0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [0.5, 0.0, 0.0], [0.5, 0.5, 0.0], [0.5, -0.5, -0.5], [0.5, 0.0, -0.5]], 
-                    vec![4, 1, 0, 2, 3, 1, 4, 5, 2], [1.0, 0.0, 0.0]),
+                    vec![4, 0, 1, 2, 1, 3, 4, 2, 5], [1.0, 0.0, 0.0]),
             west:   Mesh::new()
                 .append_custom(
                     vec![[-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5], [-0.5, 0.0, 0.0], [-0.5, 0.5, 0.0], [-0.5, -0.5, -0.5], [-0.5, 0.0, -0.5]], 
-                    vec![0, 1, 4, 1, 3, 2, 2, 5, 4], [-1.0, 0.0, 0.0]),
+                    vec![0, 4, 1, 1, 2, 3, 2, 4, 5], [-1.0, 0.0, 0.0]),
             top:    Mesh::new().append_quad([[-0.5, 0.5, 0.0], [-0.5, 0.5, 0.5], [0.5, 0.5, 0.0], [0.5, 0.5, 0.5]], [0.0, 1.0, 0.0]),
             bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
         };
@@ -56,16 +58,16 @@ mod voxel_meshes {
             south:  Mesh::new()
                 .append_custom(
                     vec![[-0.5, -0.5, -0.5], [-0.5, 0.0, -0.5], [0.5, -0.5, -0.5], [0.0, 0.0, -0.5], [0.0, 0.5, -0.5], [0.5, 0.5, -0.5]], 
-                    vec![0, 1, 2, 2, 1, 3, 3, 4, 5, 3, 5, 2], [0.0, 0.0, -1.0]),
+                    vec![0, 2, 1, 2, 3, 1, 3, 5, 4, 3, 2, 5], [0.0, 0.0, -1.0]),
             east:   Mesh::new().append_quad([[0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [0.5, 0.0, 0.0]),
             west:   Mesh::new()
                 .append_custom(
                     vec![[-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5], [-0.5, 0.0, 0.0], [-0.5, 0.5, 0.0], [-0.5, -0.5, -0.5], [-0.5, 0.0, -0.5]],
-                    vec![0, 1, 2, 2, 1, 3, 0, 2, 4, 4, 2, 5], [-1.0, 0.0, 0.0]),
+                    vec![0, 2, 1, 2, 3, 1, 0, 4, 2, 4, 5, 2], [-1.0, 0.0, 0.0]),
             top:    Mesh::new()
                 .append_custom(
                     vec![[-0.5, 0.5, 0.0], [-0.5, 0.5, 0.5], [0.0, 0.5, 0.0], [0.5, 0.5, 0.5], [0.0, 0.5, -0.5], [0.5, 0.5, -0.5]], 
-                    vec![1, 3, 5, 0, 1, 2, 4, 2, 5], [0.0, 1.0, 0.0]),
+                    vec![1, 5, 3, 0, 2, 1, 4, 5, 2], [0.0, 1.0, 0.0]),
             bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
         };
 
@@ -79,7 +81,54 @@ mod voxel_meshes {
             bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
         };
 
-        pub static ref SHAPE_MESHES: [&'static VoxelMesh; 8] = [&*CUBE, &*STAIR, &*CORNER_STAIR, &*SLAB, &*CUBE, &*CUBE, &*CUBE, &*PRISM];
+        // A cube with the top south west corner cut off, solid where y <= x + z + 0.5
+        pub static ref INNER_PRISM_JUNCTION: VoxelMesh = VoxelMesh {
+            always: Mesh::new().append_tri([[-0.5, 0.5, 0.5], [0.5, 0.5, -0.5], [-0.5, -0.5, -0.5]], [-0.5774, 0.5774, -0.5774]),
+            north:  Mesh::new().append_quad([[0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]], [0.0, 0.0, 1.0]),
+            south:  Mesh::new().append_tri([[-0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, -0.5]], [0.0, 0.0, -1.0]),
+            east:   Mesh::new().append_quad([[0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [1.0, 0.0, 0.0]),
+            west:   Mesh::new().append_tri([[-0.5, -0.5, -0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]], [-1.0, 0.0, 0.0]),
+            top:    Mesh::new().append_tri([[-0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [0.5, 0.5, -0.5]], [0.0, 1.0, 0.0]),
+            bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
+        };
+
+        // Two prisms rising towards north and east meeting in a valley, solid where y <= max(x, z)
+        pub static ref INNER_CORNER_PRISM: VoxelMesh = VoxelMesh {
+            always: Mesh::new()
+                .append_tri([[-0.5, -0.5, -0.5], [0.5, 0.5, 0.5], [0.5, 0.5, -0.5]], [-0.7071, 0.7071, 0.0])
+                .append_tri([[-0.5, -0.5, -0.5], [-0.5, 0.5, 0.5], [0.5, 0.5, 0.5]], [0.0, 0.7071, -0.7071]),
+            north:  Mesh::new().append_quad([[0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]], [0.0, 0.0, 1.0]),
+            south:  Mesh::new().append_tri([[-0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, -0.5]], [0.0, 0.0, -1.0]),
+            east:   Mesh::new().append_quad([[0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [1.0, 0.0, 0.0]),
+            west:   Mesh::new().append_tri([[-0.5, -0.5, -0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5]], [-1.0, 0.0, 0.0]),
+            top:    Mesh::new(),
+            bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
+        };
+
+        // Two prisms rising towards north and east meeting in a ridge, solid where y <= min(x, z)
+        pub static ref OUTER_CORNER_PRISM: VoxelMesh = VoxelMesh {
+            always: Mesh::new()
+                .append_tri([[-0.5, -0.5, -0.5], [-0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [-0.7071, 0.7071, 0.0])
+                .append_tri([[-0.5, -0.5, -0.5], [0.5, 0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, 0.7071, -0.7071]),
+            north:  Mesh::new().append_tri([[-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5]], [0.0, 0.0, 1.0]),
+            south:  Mesh::new(),
+            east:   Mesh::new().append_tri([[0.5, -0.5, -0.5], [0.5, 0.5, 0.5], [0.5, -0.5, 0.5]], [1.0, 0.0, 0.0]),
+            west:   Mesh::new(),
+            top:    Mesh::new(),
+            bottom: Mesh::new().append_quad([[-0.5, -0.5, 0.5], [-0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [0.5, -0.5, -0.5]], [0.0, -1.0, 0.0]),
+        };
+
+        // Indexed by VoxelShape::extract_shape
+        pub static ref SHAPE_MESHES: [&'static VoxelMesh; 8] = [
+            &*CUBE,
+            &*STAIR,
+            &*CORNER_STAIR,
+            &*SLAB,
+            &*INNER_PRISM_JUNCTION,
+            &*INNER_CORNER_PRISM,
+            &*OUTER_CORNER_PRISM,
+            &*PRISM,
+        ];
     }
 }
 
@@ -96,3 +145,198 @@ pub struct VoxelMesh {
 pub fn get_voxel_mesh(shape: VoxelShape) -> &'static VoxelMesh {
     SHAPE_MESHES[shape.extract_shape() as usize]
 }
+
+// Applies the flips and rotations of the shape's orientation to a position or normal of its mesh
+pub fn orient_vector(shape: VoxelShape, vector: [f32; 3]) -> [f32; 3] {
+    let [mut x, mut y, mut z] = vector;
+    if shape.extract_flip_x() {
+        x = -x;
+    }
+    if shape.extract_flip_y() {
+        y = -y;
+    }
+    if shape.extract_flip_z() {
+        z = -z;
+    }
+    if shape.extract_rotate_x() {
+        (y, z) = (z, -y);
+    }
+    if shape.extract_rotate_z() {
+        (x, y) = (y, -x);
+    }
+    if shape.extract_rotate_y() {
+        (x, z) = (z, -x);
+    }
+    [x, y, z]
+}
+
+// The box around the oriented shape, relative to the centre of its voxel
+pub fn get_voxel_bounds(shape: VoxelShape) -> (Vec3, Vec3) {
+    let mesh = get_voxel_mesh(shape);
+    [
+        &mesh.always,
+        &mesh.north,
+        &mesh.south,
+        &mesh.east,
+        &mesh.west,
+        &mesh.top,
+        &mesh.bottom,
+    ]
+    .iter()
+    .flat_map(|part| part.get_vertices().iter())
+    .map(|vertex| Vec3::from(orient_vector(shape, vertex.position)))
+    .fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), position| (min.min(position), max.max(position)),
+    )
+}
+
+#[cfg(test)]
+mod voxel_mesh_tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::voxels::voxel_shapes::{
+        permutation_tests::slice_centre, voxel_directions, voxel_shape, VoxelDirection,
+    };
+
+    fn face_mesh(mesh: &VoxelMesh, direction: VoxelDirection) -> &Mesh {
+        match direction.data {
+            0 => &mesh.north,
+            1 => &mesh.south,
+            2 => &mesh.east,
+            3 => &mesh.west,
+            4 => &mesh.top,
+            _ => &mesh.bottom,
+        }
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[Vec3; 3]> {
+        let vertices = mesh.get_vertices();
+        mesh.get_indices()
+            .chunks(3)
+            .map(|tri| [0, 1, 2].map(|k| Vec3::from(vertices[tri[k] as usize].position)))
+            .collect()
+    }
+
+    fn covers(mesh: &Mesh, normal: Vec3, point: Vec3) -> bool {
+        triangles(mesh).iter().any(|[a, b, c]| {
+            let sides = [
+                (*b - *a).cross(point - *a).dot(normal),
+                (*c - *b).cross(point - *b).dot(normal),
+                (*a - *c).cross(point - *c).dot(normal),
+            ];
+            sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0)
+        })
+    }
+
+    #[test]
+    fn face_meshes_have_expected_vertex_counts() {
+        // always, north, south, east, west, top, bottom
+        let expected: [(VoxelShape, [usize; 7]); 8] = [
+            (voxel_shape::CUBE, [0, 4, 4, 4, 4, 4, 4]),
+            (voxel_shape::STAIR, [8, 4, 4, 6, 6, 4, 4]),
+            (voxel_shape::CORNER_STAIR, [12, 4, 6, 4, 6, 6, 4]),
+            (voxel_shape::SLAB, [4, 4, 4, 4, 4, 0, 4]),
+            (voxel_shape::INNER_PRISM_JUNCTION, [3, 4, 3, 4, 3, 3, 4]),
+            (voxel_shape::INNER_CORNER_PRISM, [6, 4, 3, 4, 3, 0, 4]),
+            (voxel_shape::OUTER_CORNER_PRISM, [6, 3, 0, 3, 0, 0, 4]),
+            (voxel_shape::PRISM, [4, 4, 0, 3, 3, 0, 4]),
+        ];
+        for (shape, counts) in expected {
+            let mesh = get_voxel_mesh(shape);
+            let actual = [
+                &mesh.always,
+                &mesh.north,
+                &mesh.south,
+                &mesh.east,
+                &mesh.west,
+                &mesh.top,
+                &mesh.bottom,
+            ]
+            .map(|m| m.get_vertices().len());
+            assert_eq!(actual, counts, "shape {}", shape.extract_shape());
+        }
+    }
+
+    #[test]
+    fn face_meshes_match_occlusion_slices() {
+        for index in 0..8 {
+            let shape = VoxelShape { data: index };
+            let mesh = get_voxel_mesh(shape);
+            for direction in voxel_directions::ALL {
+                let normal = direction.as_vec().as_vec3();
+                let face = face_mesh(mesh, direction);
+                let mask = VoxelShape::get_face_shape(shape, direction);
+                for slice in 0..8 {
+                    let point = slice_centre(direction, slice);
+                    assert_eq!(
+                        covers(face, normal, point),
+                        mask & (1 << slice) != 0,
+                        "shape {} direction {} slice {}",
+                        index,
+                        direction.data,
+                        slice
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_wind_against_their_normals() {
+        for index in 0..8 {
+            let mesh = get_voxel_mesh(VoxelShape { data: index });
+            for part in [
+                &mesh.always,
+                &mesh.north,
+                &mesh.south,
+                &mesh.east,
+                &mesh.west,
+                &mesh.top,
+                &mesh.bottom,
+            ] {
+                let vertices = part.get_vertices();
+                for (tri, indices) in triangles(part).iter().zip(part.get_indices().chunks(3)) {
+                    let normal = Vec3::from(vertices[indices[0] as usize].normal);
+                    let winding = (tri[1] - tri[0]).cross(tri[2] - tri[0]);
+                    assert!(
+                        winding.dot(normal) < 0.0,
+                        "shape {} triangle {:?}",
+                        index,
+                        tri
+                    );
+                }
+            }
+        }
+    }
+
+    // The cube faces are what every other shape and the greedy mesher are checked against
+    #[test]
+    fn cube_faces_lie_on_their_side_and_face_outwards() {
+        let cube = get_voxel_mesh(voxel_shape::CUBE);
+        for direction in voxel_directions::ALL {
+            let outwards = direction.as_vec().as_vec3();
+            let face = face_mesh(cube, direction);
+            assert_eq!(face.get_indices().len(), 6, "direction {}", direction.data);
+            for [a, b, c] in triangles(face) {
+                for corner in [a, b, c] {
+                    assert_eq!(corner.dot(outwards), 0.5, "direction {}", direction.data);
+                }
+                assert!(
+                    (b - a).cross(c - a).dot(outwards) < 0.0,
+                    "direction {}",
+                    direction.data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn slabs_are_bounded_by_half_a_voxel() {
+        let (min, max) = get_voxel_bounds(voxel_shape::SLAB);
+        assert_eq!((min, max), (Vec3::splat(-0.5), Vec3::new(0.5, 0.0, 0.5)));
+        let (min, max) = get_voxel_bounds(voxel_shape::CUBE);
+        assert_eq!((min, max), (Vec3::splat(-0.5), Vec3::splat(0.5)));
+    }
+}
@@ -1,102 +1,465 @@
-use std::fs;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::Arc,
+};
 
-use glam::Vec4;
+use glam::{Vec3, Vec4};
+use image::{imageops::FilterType, RgbaImage};
 use multi_map::MultiMap;
+use parking_lot::RwLock;
 
-type VoxelMap = MultiMap<u16, String, VoxelProfile>;
+use super::{voxel_data::voxel_state, voxel_light::MAX_LIGHT};
+
+type VoxelMap = MultiMap<u16, String, Arc<VoxelProfile>>;
+
+// Every layer of the voxel texture array is resized to this
+pub const VOXEL_TEXTURE_SIZE: u32 = 16;
+const VOXEL_TEXTURE_FOLDER: &str = "./src/resources/voxel_textures";
+const VOXEL_PROFILE_FOLDER: &str = "./src/resources/voxel_profiles";
+// The id of every voxel that was ever loaded, by name. Created on the first run and extended whenever a new
+// profile shows up, so ids don't depend on the order the profiles happen to be listed in
+const VOXEL_ID_FILE: &str = "./src/resources/voxel_ids.json";
+// Profiles that don't name a material are drawn with one of these
+pub const DEFAULT_MATERIAL: &str = "voxels/default";
+pub const TRANSPARENT_MATERIAL: &str = "voxels/transparent";
+pub const FOLIAGE_MATERIAL: &str = "voxels/foliage"; // Cut out instead of blended, for leaves
+
+// Seconds the break button has to be held on a voxel whose profile doesn't say
+pub const DEFAULT_HARDNESS: f32 = 0.5;
+pub const DEFAULT_FRICTION: f32 = 0.5;
+
+type IdTable = BTreeMap<String, u16>;
+
+struct VoxelRegistry {
+    voxels: VoxelMap,
+    ids: IdTable,
+    texture_files: Vec<String>, // Layer i + 1 of the texture array, layer 0 is plain white
+    version: u64,               // Bumped on every reload
+}
 
 lazy_static! {
-    static ref VOXELS: VoxelMap = load_voxels();
+    // Readers take a snapshot, a reload swaps in a whole new registry
+    static ref REGISTRY: RwLock<Arc<VoxelRegistry>> = RwLock::new(Arc::new(load_voxels(None)));
 }
 
-fn load_voxels() -> VoxelMap {
-    let paths = fs::read_dir("./src/resources/voxel_profiles").unwrap();
+// Reloading keeps the ids and texture layers of existing voxels, so chunks and meshes built before stay valid.
+// Profiles that fail to load keep their previous version
+fn load_voxels(previous: Option<&VoxelRegistry>) -> VoxelRegistry {
+    // Sorted so new profiles found in the same run are numbered the same on every machine
+    let mut paths: Vec<_> = match fs::read_dir(VOXEL_PROFILE_FOLDER) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(e) => {
+            // Looks for the previous profiles instead, those that can't be read keep their previous version
+            println!("[INFO] Failed to read voxel profile folder {VOXEL_PROFILE_FOLDER}: {e}");
+            previous.map_or_else(Vec::new, |previous| {
+                previous
+                    .ids
+                    .keys()
+                    .map(|name| PathBuf::from(format!("{VOXEL_PROFILE_FOLDER}/{name}.json")))
+                    .collect()
+            })
+        }
+    };
+    paths.sort();
+    let names: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .replace(".json", "")
+        })
+        .collect();
+
+    let mut ids = previous.map_or_else(load_id_table, |previous| previous.ids.clone());
+    if assign_ids(&mut ids, &names) {
+        save_id_table(&ids);
+    }
 
     let mut map = MultiMap::new();
+    let mut texture_files =
+        previous.map_or_else(Vec::new, |previous| previous.texture_files.clone());
 
     map.insert(
         0,
         "Empty".to_string(),
-        VoxelProfile {
+        Arc::new(VoxelProfile {
             id: 0,
             name: "Empty".to_string(),
             color: Vec4::ZERO,
-        },
+            tags: HashMap::new(),
+            textures: [0; 6],
+            variants: Vec::new(),
+            transparent: false,
+            emission: 0,
+            material: DEFAULT_MATERIAL.to_string(),
+            solid: false,
+            hardness: 0.0,
+            friction: DEFAULT_FRICTION,
+            fluid: false,
+        }),
     );
 
-    let mut id: u16 = 1;
-    for voxel_file in paths.into_iter() {
-        let voxel_file = voxel_file.unwrap();
-        let file_contents = fs::read_to_string(voxel_file.path()).unwrap();
-        let json: serde_json::Value =
-            serde_json::from_str(&file_contents).expect("JSON failed to parse");
-        let color = decode_color(json.get("color").map_or("#ffff", |v| v.as_str().unwrap()));
-        let name = voxel_file
-            .file_name()
-            .to_string_lossy()
-            .replace(".json", "");
-
-        let profile = VoxelProfile {
-            name: name.clone(),
-            id,
-            color,
+    for (path, name) in paths.iter().zip(names) {
+        let json: serde_json::Value = match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+        {
+            Ok(json) => json,
+            Err(e) => {
+                println!("[INFO] Failed to load voxel profile {name}: {e}");
+                if let Some(profile) = previous.and_then(|previous| previous.voxels.get_alt(&name))
+                {
+                    map.insert(profile.id, name, Arc::clone(profile));
+                }
+                continue;
+            }
         };
-        map.insert(id, name.clone(), profile);
+        let profile = parse_profile(&name, ids[&name], &json, &mut texture_files);
+        let (id, color) = (profile.id, profile.color);
+        map.insert(id, name.clone(), Arc::new(profile));
 
         println!("==Created Voxel Profile==");
         println!("Name: {name}");
         println!("id: {id}");
         println!("color: {color}");
         println!("");
+    }
+
+    VoxelRegistry {
+        voxels: map,
+        ids,
+        texture_files,
+        version: previous.map_or(0, |previous| previous.version + 1),
+    }
+}
+
+// Everything a profile's JSON describes, missing keys fall back to their defaults
+fn parse_profile(
+    name: &str,
+    id: u16,
+    json: &serde_json::Value,
+    texture_files: &mut Vec<String>,
+) -> VoxelProfile {
+    let read_color = |json: &serde_json::Value, default: Vec4| match json.get("color") {
+        None => default,
+        Some(value) => value
+            .as_str()
+            .ok_or_else(|| format!("{value} is not a string"))
+            .and_then(decode_color)
+            .unwrap_or_else(|e| {
+                println!("[INFO] Voxel profile {name} has an invalid color: {e}, using {default}");
+                default
+            }),
+    };
+    let color = read_color(json, Vec4::ONE);
+    // Free-form metadata for gameplay code, e.g. { "material": "stone" } to pick footstep sounds
+    let tags = json
+        .get("tags")
+        .and_then(|tags| tags.as_object())
+        .map_or_else(HashMap::new, |tags| {
+            tags.iter()
+                .filter_map(|(key, value)| {
+                    value.as_str().map(|value| (key.clone(), value.to_string()))
+                })
+                .collect()
+        });
+    let textures = decode_textures(json, texture_files);
+    // Variant i is picked by voxels whose state holds variant i, missing fields fall back to the profile's own
+    let variants = json
+        .get("variants")
+        .and_then(|variants| variants.as_array())
+        .map_or_else(Vec::new, |variants| {
+            if variants.len() > voxel_state::MAX_VARIANTS as usize {
+                println!(
+                    "[INFO] Voxel profile {name} has more than {} variants, the rest are ignored",
+                    voxel_state::MAX_VARIANTS
+                );
+            }
+            variants
+                .iter()
+                .take(voxel_state::MAX_VARIANTS as usize)
+                .map(|variant| {
+                    let has_textures =
+                        variant.get("texture").is_some() || variant.get("textures").is_some();
+                    VoxelVariant {
+                        color: read_color(variant, color),
+                        textures: if has_textures {
+                            decode_textures(variant, texture_files)
+                        } else {
+                            textures
+                        },
+                    }
+                })
+                .collect()
+        });
+    // Transparent voxels are meshed separately and blended, e.g. water
+    let transparent = json
+        .get("transparent")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    // Block light the voxel gives off, from 0 to MAX_LIGHT
+    let emission = json
+        .get("emission")
+        .and_then(|v| v.as_u64())
+        .map_or(0, |emission| emission.min(MAX_LIGHT as u64) as u8);
+    // The name of the material its faces are drawn with, looked up in the material registry
+    let material = json.get("material").and_then(|v| v.as_str()).map_or_else(
+        || {
+            if transparent {
+                TRANSPARENT_MATERIAL
+            } else {
+                DEFAULT_MATERIAL
+            }
+            .to_string()
+        },
+        |material| material.to_string(),
+    );
+    let physics = parse_physical_properties(name, json);
+
+    VoxelProfile {
+        name: name.to_string(),
+        id,
+        color,
+        tags,
+        textures,
+        variants,
+        transparent,
+        emission,
+        material,
+        solid: physics.solid,
+        hardness: physics.hardness,
+        friction: physics.friction,
+        fluid: physics.fluid,
+    }
+}
+
+struct PhysicalProperties {
+    solid: bool,
+    hardness: f32,
+    friction: f32,
+    fluid: bool,
+}
 
-        id += 1;
+// Fluids aren't solid unless they say so. Values of the wrong type or out of range are warned about and replaced with
+// the default
+fn parse_physical_properties(name: &str, json: &serde_json::Value) -> PhysicalProperties {
+    let read_bool = |key: &str, default: bool| match json.get(key) {
+        None => default,
+        Some(value) => value.as_bool().unwrap_or_else(|| {
+            println!("[INFO] Voxel profile {name} has an invalid {key} {value}, using {default}");
+            default
+        }),
+    };
+    let read_f32 = |key: &str, default: f32| match json.get(key) {
+        None => default,
+        Some(value) => match value.as_f64() {
+            Some(number) if number >= 0.0 && number.is_finite() => number as f32,
+            _ => {
+                println!(
+                    "[INFO] Voxel profile {name} has an invalid {key} {value}, using {default}"
+                );
+                default
+            }
+        },
+    };
+    let fluid = read_bool("fluid", false);
+    PhysicalProperties {
+        solid: read_bool("solid", !fluid),
+        hardness: read_f32("hardness", DEFAULT_HARDNESS),
+        friction: read_f32("friction", DEFAULT_FRICTION),
+        fluid,
+    }
+}
+
+// Entries with id 0, which is reserved for Empty, or with an id another voxel already has are dropped and get a
+// new id. A missing or unreadable table starts out empty
+fn parse_id_table(data: &str) -> IdTable {
+    let json: serde_json::Value = match serde_json::from_str(data) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("[INFO] Failed to read the voxel id table: {e}");
+            return IdTable::new();
+        }
+    };
+    let mut ids = IdTable::new();
+    let mut taken = HashSet::new();
+    for (name, id) in json.as_object().into_iter().flatten() {
+        match id.as_u64() {
+            Some(id) if id > 0 && id <= u16::MAX as u64 && taken.insert(id as u16) => {
+                ids.insert(name.clone(), id as u16);
+            }
+            _ => println!(
+                "[INFO] Voxel {name} has an invalid or duplicate id {id}, it gets a new one"
+            ),
+        }
     }
+    ids
+}
 
-    return map;
+fn load_id_table() -> IdTable {
+    fs::read_to_string(VOXEL_ID_FILE).map_or_else(|_| IdTable::new(), |data| parse_id_table(&data))
 }
 
-fn decode_color(color_string: &str) -> Vec4 {
-    let len = color_string.len() - 1; // -1 because of the hashtag at the front of the string
-                                      // RGB
-    if len == 3 {
-        let r = u8::from_str_radix(&color_string.get(1..2).unwrap(), 16).unwrap() as f32 / 15.0;
-        let g = u8::from_str_radix(&color_string.get(2..3).unwrap(), 16).unwrap() as f32 / 15.0;
-        let b = u8::from_str_radix(&color_string.get(3..4).unwrap(), 16).unwrap() as f32 / 15.0;
-        return Vec4::new(r, g, b, 1.0);
+fn save_id_table(ids: &IdTable) {
+    let json = serde_json::to_string_pretty(ids).unwrap();
+    if let Err(e) = fs::write(VOXEL_ID_FILE, json) {
+        println!("[INFO] Failed to save the voxel id table: {e}");
     }
-    // RGBA
-    if len == 4 {
-        let r = u8::from_str_radix(&color_string.get(1..2).unwrap(), 16).unwrap() as f32 / 15.0;
-        let g = u8::from_str_radix(&color_string.get(2..3).unwrap(), 16).unwrap() as f32 / 15.0;
-        let b = u8::from_str_radix(&color_string.get(3..4).unwrap(), 16).unwrap() as f32 / 15.0;
-        let a = u8::from_str_radix(&color_string.get(4..5).unwrap(), 16).unwrap() as f32 / 15.0;
-        return Vec4::new(r, g, b, a);
+}
+
+// Gives the names that don't have an id yet the ids after the highest one handed out so far, existing ids never
+// change. Ids of removed profiles stay taken, so saves holding them don't turn into a different voxel.
+// Returns whether any ids were added
+fn assign_ids(ids: &mut IdTable, names: &[String]) -> bool {
+    let mut next_id = ids.values().max().map_or(1, |id| id + 1);
+    let mut added = false;
+    for name in names {
+        if !ids.contains_key(name) {
+            ids.insert(name.clone(), next_id);
+            next_id += 1;
+            added = true;
+        }
     }
-    // RRGGBB
-    if len == 6 {
-        let r = u8::from_str_radix(&color_string.get(1..3).unwrap(), 16).unwrap() as f32 / 255.0;
-        let g = u8::from_str_radix(&color_string.get(3..5).unwrap(), 16).unwrap() as f32 / 255.0;
-        let b = u8::from_str_radix(&color_string.get(5..7).unwrap(), 16).unwrap() as f32 / 255.0;
-        return Vec4::new(r, g, b, 1.0);
+    added
+}
+
+// Re-reads the voxel profiles, readers holding on to old profiles keep them until they let go
+pub fn reload_voxels() {
+    let previous = Arc::clone(&REGISTRY.read());
+    let registry = load_voxels(Some(&previous));
+    *REGISTRY.write() = Arc::new(registry);
+}
+
+pub fn registry_version() -> u64 {
+    REGISTRY.read().version
+}
+
+// Faces are textured by "texture": "file.png", or per face by "textures": { "top", "bottom", "side" } where "side"
+// can be overridden by "north", "south", "east" and "west". Faces without a texture get layer 0
+fn decode_textures(json: &serde_json::Value, texture_files: &mut Vec<String>) -> [u32; 6] {
+    let mut layer_of = |file: Option<&str>| {
+        file.map_or(0, |file| {
+            let index = texture_files
+                .iter()
+                .position(|existing| existing == file)
+                .unwrap_or_else(|| {
+                    texture_files.push(file.to_string());
+                    texture_files.len() - 1
+                });
+            index as u32 + 1
+        })
+    };
+
+    let all = json.get("texture").and_then(|v| v.as_str());
+    let faces = json.get("textures");
+    let face = |name: &str| {
+        faces
+            .and_then(|faces| faces.get(name))
+            .and_then(|v| v.as_str())
+    };
+    let side = face("side").or(all);
+    // Same order as the voxel direction data: north, south, east, west, up, down
+    [
+        layer_of(face("north").or(side)),
+        layer_of(face("south").or(side)),
+        layer_of(face("east").or(side)),
+        layer_of(face("west").or(side)),
+        layer_of(face("top").or(all)),
+        layer_of(face("bottom").or(all)),
+    ]
+}
+
+// Loads every texture referenced by a voxel profile, in layer order and starting with the plain white layer 0.
+// Missing files are replaced with magenta so they stand out
+pub fn load_voxel_texture_layers() -> Vec<RgbaImage> {
+    let size = VOXEL_TEXTURE_SIZE;
+    let mut layers = vec![RgbaImage::from_pixel(size, size, image::Rgba([255; 4]))];
+    let registry = Arc::clone(&REGISTRY.read());
+    for file in &registry.texture_files {
+        let layer = match image::open(format!("{VOXEL_TEXTURE_FOLDER}/{file}")) {
+            Ok(image) => {
+                image::imageops::resize(&image.to_rgba8(), size, size, FilterType::Nearest)
+            }
+            Err(e) => {
+                println!("[INFO] Failed to load voxel texture {file}: {e}");
+                RgbaImage::from_pixel(size, size, image::Rgba([255, 0, 255, 255]))
+            }
+        };
+        layers.push(layer);
     }
-    // RRGGBBAA
-    if len == 8 {
-        let r = u8::from_str_radix(&color_string.get(1..3).unwrap(), 16).unwrap() as f32 / 255.0;
-        let g = u8::from_str_radix(&color_string.get(3..5).unwrap(), 16).unwrap() as f32 / 255.0;
-        let b = u8::from_str_radix(&color_string.get(5..7).unwrap(), 16).unwrap() as f32 / 255.0;
-        let a = u8::from_str_radix(&color_string.get(7..9).unwrap(), 16).unwrap() as f32 / 255.0;
-        return Vec4::new(r, g, b, a);
+    layers
+}
+
+// #RGB, #RGBA, #RRGGBB or #RRGGBBAA
+fn decode_color(color_string: &str) -> Result<Vec4, String> {
+    let digits = color_string
+        .strip_prefix('#')
+        .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| format!("{color_string} is not a hex color"))?;
+    let (width, max) = match digits.len() {
+        3 | 4 => (1, 15.0),
+        6 | 8 => (2, 255.0),
+        _ => return Err(format!("{color_string} has the wrong number of digits")),
+    };
+    let channel = |i: usize| {
+        u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap() as f32 / max
+    };
+    let alpha = if digits.len() == 4 * width {
+        channel(3)
+    } else {
+        1.0
+    };
+    Ok(Vec4::new(channel(0), channel(1), channel(2), alpha))
+}
+
+pub fn get_voxel_by_name(name: String) -> Option<Arc<VoxelProfile>> {
+    return REGISTRY.read().voxels.get_alt(&name).cloned();
+}
+
+pub fn get_voxel_by_id(id: u16) -> Option<Arc<VoxelProfile>> {
+    return REGISTRY.read().voxels.get(&id).cloned();
+}
+
+// Also knows the ids of profiles that were removed, so those can still be told apart
+pub fn get_voxel_id(name: &str) -> Option<u16> {
+    if name == "Empty" {
+        return Some(0);
     }
+    REGISTRY.read().ids.get(name).copied()
+}
 
-    return Vec4::new(0.0, 0.0, 0.0, 1.0);
+pub fn get_voxel_name(id: u16) -> Option<String> {
+    get_voxel_by_id(id).map(|profile| profile.name.clone())
 }
 
-pub fn get_voxel_by_name(name: String) -> Option<&'static VoxelProfile> {
-    return VOXELS.get_alt(&name).clone();
+// Unknown ids are treated as opaque
+pub fn is_transparent(id: u16) -> bool {
+    REGISTRY
+        .read()
+        .voxels
+        .get(&id)
+        .map_or(false, |profile| profile.transparent)
 }
 
-pub fn get_voxel_by_id(id: u16) -> Option<&'static VoxelProfile> {
-    return VOXELS.get(&id);
+// Unknown ids are solid
+pub fn is_solid(id: u16) -> bool {
+    REGISTRY
+        .read()
+        .voxels
+        .get(&id)
+        .map_or(true, |profile| profile.solid)
+}
+
+// Unknown ids don't give off light
+pub fn emission(id: u16) -> u8 {
+    REGISTRY
+        .read()
+        .voxels
+        .get(&id)
+        .map_or(0, |profile| profile.emission)
 }
 
 #[derive(Clone)]
@@ -104,4 +467,171 @@ pub struct VoxelProfile {
     pub id: u16,
     pub name: String,
     pub color: Vec4,
+    pub tags: HashMap<String, String>,
+    pub textures: [u32; 6], // Texture array layer per face, indexed by voxel direction
+    pub variants: Vec<VoxelVariant>, // Empty when every variant looks like the profile itself
+    pub transparent: bool,
+    pub emission: u8,
+    pub material: String,
+    pub solid: bool,   // Whether its faces go into the collision mesh
+    pub hardness: f32, // Seconds the break button has to be held on it, 0 breaks it on the click
+    pub friction: f32, // Of the chunk colliders its faces are built into, see collision_mesh_name
+    pub fluid: bool,
+}
+
+#[derive(Clone)]
+pub struct VoxelVariant {
+    pub color: Vec4,
+    pub textures: [u32; 6],
+}
+
+impl VoxelProfile {
+    pub fn get_tag(&self, tag: &str) -> Option<&str> {
+        self.tags.get(tag).map(|value| value.as_str())
+    }
+
+    // The texture layer for a face pointing along the normal, picked by its dominant axis
+    pub fn texture_layer(&self, normal: Vec3) -> u32 {
+        self.textures[face_index(normal)]
+    }
+
+    // Variants the profile doesn't define look like the profile itself
+    pub fn variant_color(&self, variant: u8) -> Vec4 {
+        self.variants
+            .get(variant as usize)
+            .map_or(self.color, |variant| variant.color)
+    }
+
+    pub fn variant_texture_layer(&self, variant: u8, normal: Vec3) -> u32 {
+        self.variants
+            .get(variant as usize)
+            .map_or(self.textures, |variant| variant.textures)[face_index(normal)]
+    }
+}
+
+// Index into the per face data, in voxel direction order. Picked by the normal's dominant axis
+pub fn face_index(normal: Vec3) -> usize {
+    let abs = normal.abs();
+    if abs.y >= abs.x && abs.y >= abs.z {
+        if normal.y >= 0.0 {
+            4
+        } else {
+            5
+        }
+    } else if abs.x >= abs.z {
+        if normal.x >= 0.0 {
+            2
+        } else {
+            3
+        }
+    } else if normal.z >= 0.0 {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use super::*;
+
+    #[test]
+    fn grass_has_its_own_top_texture() {
+        let grass = get_voxel_by_name("grass".to_string()).unwrap();
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap();
+        let top = grass.texture_layer(Vec3::Y);
+        let side = grass.texture_layer(Vec3::X);
+        assert!(top != 0 && side != 0 && top != side);
+        assert_eq!(grass.texture_layer(-Vec3::Y), dirt.texture_layer(Vec3::Y));
+        assert_eq!(
+            load_voxel_texture_layers().len(),
+            REGISTRY.read().texture_files.len() + 1
+        );
+    }
+
+    #[test]
+    fn reloading_keeps_ids_and_layers() {
+        let grass = get_voxel_by_name("grass".to_string()).unwrap();
+        let version = registry_version();
+        reload_voxels();
+        let reloaded = get_voxel_by_name("grass".to_string()).unwrap();
+        assert_eq!(reloaded.id, grass.id);
+        assert_eq!(reloaded.textures, grass.textures);
+        assert!(registry_version() > version);
+        assert!(!Arc::ptr_eq(&grass, &reloaded));
+    }
+
+    #[test]
+    fn new_profiles_dont_shift_existing_ids() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut ids = IdTable::new();
+        assert!(assign_ids(&mut ids, &names(&["dirt", "grass", "stone"])));
+        let before = ids.clone();
+
+        // Sorts in between the existing ones
+        assert!(assign_ids(
+            &mut ids,
+            &names(&["dirt", "glowstone", "grass", "stone"])
+        ));
+        assert!(before.iter().all(|(name, id)| ids[name] == *id));
+        assert_eq!(ids["glowstone"], 4);
+        assert!(!assign_ids(&mut ids, &names(&["dirt", "glowstone"])));
+
+        let parsed = parse_id_table(r#"{ "dirt": 1, "grass": 1, "stone": 0, "water": 2 }"#);
+        assert_eq!(parsed.get("dirt"), Some(&1));
+        assert_eq!(parsed.get("water"), Some(&2));
+        assert!(!parsed.contains_key("grass") && !parsed.contains_key("stone"));
+    }
+
+    #[test]
+    fn physical_properties_fall_back_to_defaults() {
+        let mut texture_files = Vec::new();
+        let plain = parse_profile("plain", 1, &serde_json::json!({}), &mut texture_files);
+        assert!(plain.solid && !plain.fluid);
+        assert_eq!(plain.hardness, DEFAULT_HARDNESS);
+        assert_eq!(plain.friction, DEFAULT_FRICTION);
+
+        let json = serde_json::json!({ "fluid": true, "hardness": 2.5, "friction": 0.1 });
+        let fluid = parse_profile("fluid", 2, &json, &mut texture_files);
+        assert!(!fluid.solid && fluid.fluid);
+        assert_eq!(fluid.hardness, 2.5);
+        assert_eq!(fluid.friction, 0.1);
+
+        let json = serde_json::json!({ "solid": "yes", "hardness": -1, "friction": "rough" });
+        let malformed = parse_profile("malformed", 3, &json, &mut texture_files);
+        assert!(malformed.solid);
+        assert_eq!(malformed.hardness, DEFAULT_HARDNESS);
+        assert_eq!(malformed.friction, DEFAULT_FRICTION);
+
+        assert!(!is_solid(
+            get_voxel_by_name("water".to_string()).unwrap().id
+        ));
+        assert!(is_solid(get_voxel_by_name("stone".to_string()).unwrap().id));
+    }
+
+    #[test]
+    fn malformed_colors_fall_back_to_the_default() {
+        assert_eq!(decode_color("#f00"), Ok(Vec4::new(1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(decode_color("#00ff0000"), Ok(Vec4::new(0.0, 1.0, 0.0, 0.0)));
+        for color in ["", "#", "#zz0000", "#+f0", "ff0000", "#ff000"] {
+            assert!(decode_color(color).is_err(), "{color}");
+        }
+
+        let mut texture_files = Vec::new();
+        for color in [
+            serde_json::json!(5),
+            serde_json::json!(""),
+            serde_json::json!("#zz0000"),
+        ] {
+            let json = serde_json::json!({ "color": color, "variants": [{ "color": color }] });
+            let profile = parse_profile("malformed", 1, &json, &mut texture_files);
+            assert_eq!(profile.color, Vec4::ONE);
+            assert_eq!(profile.variants[0].color, Vec4::ONE);
+        }
+    }
 }
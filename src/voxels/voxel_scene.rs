@@ -1,51 +1,351 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
+use dashmap::mapref::entry::Entry;
 use dashmap::{DashMap, DashSet};
-use flume::{Receiver, Sender};
-use glam::{IVec3, UVec3, Vec3};
-use rayon::ThreadPool;
+use flume::{Receiver, RecvTimeoutError, Sender};
+use glam::{IVec2, IVec3, UVec3, Vec3};
+use parking_lot::{Mutex, RwLock};
+use rayon::prelude::*;
 
 use crate::asset_types::mesh::Mesh;
-use crate::rendering::vertex::Vertex;
-use crate::voxels::biome_profile::{get_biome_by_name, SampleContext};
+use crate::asset_types::vertex::Vertex;
+use crate::ecs::events::{BlockChanged, Events};
+use crate::noise::{noise_cache::CachedNoise, NoiseField};
+use crate::voxels::biome_profile::{shared_biome_map, BiomeMap, BiomeProfile, SampleContext};
+use crate::voxels::structures::{plan_structures, StructureWrites};
 use crate::voxels::voxel_data::VoxelData;
 use crate::voxels::voxel_shapes::voxel_shape;
 
-use super::voxel_mesh::get_voxel_mesh;
+use super::chunk_mesh::ChunkMesh;
+use super::chunk_neighborhood::ChunkNeighborhood;
+use super::chunk_queue::ChunkQueue;
+use super::chunk_storage::ChunkStorage;
+use super::edit_history::{EditHistory, VoxelEdit};
+use super::marching_cubes;
+use super::pending_work::PendingWork;
+use super::processor_threads::{ProcessorThreads, ProcessorUtilization};
+use super::surface_cache::{ColumnHeights, SurfaceCache};
+use super::voxel_light::{BlockLight, BLOCK_LIGHT_MASK, MAX_LIGHT};
+use super::voxel_mesh::{get_voxel_mesh, orient_vector};
 use super::voxel_registry;
 use super::voxel_shapes::{voxel_directions, VoxelDirection, VoxelShape};
+use super::voxel_storage::{VoxelStorage, CHUNK_VOLUME};
 
 pub const CHUNK_SIZE: u32 = 16;
-type ChunkMap = Arc<DashMap<IVec3, VoxelChunk, ahash::RandomState>>;
+// Densities are stored as i8 within this range, only values close to the surface need to be precise
+pub const DENSITY_RANGE: f32 = 4.0;
+// Size of a voxel in the chunk byte format, see VoxelChunk::to_bytes
+pub const VOXEL_BYTES: usize = 6;
+// How long the generation pre-processor waits before checking again on chunks whose neighbours aren't loaded yet.
+// Doubled every retry that doesn't get a chunk going, up to the max
+const NEIGHBOUR_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+const MAX_NEIGHBOUR_RETRY_INTERVAL: Duration = Duration::from_millis(80);
+// Queued chunks batched into the noise call of the chunk being generated
+const NOISE_BATCH_CHUNKS: usize = 16;
+pub type InitializationQueue = Arc<ChunkQueue<Option<Sender<IVec3>>>>;
+pub type ChunkMap = Arc<DashMap<IVec3, VoxelChunk, ahash::RandomState>>;
+pub type ChunkMeshMap = Arc<DashMap<IVec3, ChunkMesh, ahash::RandomState>>;
+// The faces of a chunk drawn with the same material, indices are local to the bucket
+#[derive(Clone, Default)]
+pub struct MeshBucket {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+pub type MeshBuckets = BTreeMap<String, MeshBucket>;
+// The bucket holding the faces of solid voxels only, colliders are built from it. It's never drawn. Voxels with a
+// friction other than the default collide through a bucket of their own, see collision_mesh_name
+pub const COLLISION_MESH: &str = "collision";
+pub fn collision_mesh_name(friction: f32) -> String {
+    if friction == voxel_registry::DEFAULT_FRICTION {
+        COLLISION_MESH.to_string()
+    } else {
+        format!("{COLLISION_MESH}@{friction}")
+    }
+}
+
+// The friction of the colliders built from a mesh, None for the meshes that are drawn
+pub fn collision_friction(name: &str) -> Option<f32> {
+    if name == COLLISION_MESH {
+        return Some(voxel_registry::DEFAULT_FRICTION);
+    }
+    name.strip_prefix(COLLISION_MESH)?
+        .strip_prefix('@')?
+        .parse()
+        .ok()
+}
+
+// A generated chunk's position with its mesh for every material
+pub type ChunkMeshMessage = (IVec3, Vec<(String, Arc<RwLock<Mesh>>)>);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VoxelSceneStats {
+    pub loaded_chunks: usize,
+    pub empty_chunks: usize,
+    pub pending_initialization: usize,
+    pub pending_generation: usize,
+    pub voxel_bytes: usize,
+}
+
+// Shaped voxels are hit as if they were full cubes, the shape is part of the voxel data so callers can refine the hit
+#[derive(Clone, Copy)]
+pub struct VoxelRaycastHit {
+    pub position: IVec3,
+    pub voxel: VoxelData,
+    pub face: VoxelDirection, // The face the ray entered through
+    pub distance: f32,
+    pub point: Vec3, // Where the ray entered the voxel
+}
+
+impl VoxelRaycastHit {
+    // The hit point relative to the voxel's centre, each axis within -0.5 and 0.5
+    pub fn fraction(&self) -> Vec3 {
+        self.point - self.position.as_vec3()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelEditError {
+    OutOfBounds,
+    ChunkNotLoaded, // The chunk has been queued, the edit can be retried once it is loaded
+}
+
+// Whether a box holds solid voxels. A box reaching into chunks that aren't loaded is only reported as such when the
+// loaded part of it is empty, solid voxels are certain to block it either way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionQuery {
+    Empty,
+    Blocked(Vec<IVec3>),  // The solid voxels in the box
+    Unloaded(Vec<IVec3>), // The chunks the box reaches into that aren't loaded
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshingMode {
+    Blocky,
+    Greedy, // Blocky with merged faces, edits always regenerate the whole chunk
+    Smooth, // Marching cubes over the density field
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshingStrategy {
+    Naive,  // A quad per exposed face
+    Greedy, // Coplanar faces of full cubes with the same id are merged, other shapes are meshed per face
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorldConfig {
+    pub seed: u64,
+    pub min_height: i32, // Inclusive, in voxels
+    pub max_height: i32, // Exclusive, in voxels
+    pub meshing: MeshingMode,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min_height: 0,
+            max_height: 80,
+            meshing: MeshingMode::Blocky,
+        }
+    }
+}
+
+impl WorldConfig {
+    pub fn min_chunk_y(&self) -> i32 {
+        self.min_height.div_floor(CHUNK_SIZE as i32)
+    }
+
+    pub fn max_chunk_y(&self) -> i32 {
+        (self.max_height - 1).div_floor(CHUNK_SIZE as i32)
+    }
+
+    pub fn chunk_in_bounds(&self, chunk_pos: &IVec3) -> bool {
+        chunk_pos.y >= self.min_chunk_y() && chunk_pos.y <= self.max_chunk_y()
+    }
+
+    pub fn height_in_bounds(&self, y: i32) -> bool {
+        y >= self.min_height && y < self.max_height
+    }
+}
 
 pub struct VoxelScene {
     pub chunks: ChunkMap,
+    pub chunk_meshes: ChunkMeshMap,
+    pub config: WorldConfig,
     initialization_queue: Arc<DashSet<IVec3>>,
-    initialization_channel: (
-        Sender<(IVec3, Option<Sender<IVec3>>)>,
-        Receiver<(IVec3, Option<Sender<IVec3>>)>,
-    ),
-    generation_channel: (Sender<IVec3>, Receiver<IVec3>),
+    cancelled_chunks: Arc<DashSet<IVec3>>, // Unloaded while waiting to be initialized
+    unload_sender: Option<Sender<IVec3>>,
+    mesh_sender: Option<Sender<ChunkMeshMessage>>, // Told about edits that empty a mesh or give an empty mesh triangles
+    initialization_channel: InitializationQueue,
+    generation_channel: Arc<ChunkQueue<()>>,
     generation_pre_processor_channel: (Sender<IVec3>, Receiver<IVec3>),
-    thread_pool: ThreadPool,
+    threads: ProcessorThreads,
+    utilization: Arc<ProcessorUtilization>,
+    storage: Option<Arc<ChunkStorage>>,
+    dirty_chunks: Arc<DashSet<IVec3>>, // Edited since they were last saved
+    surfaces: Arc<SurfaceCache>,
+    structure_writes: Arc<StructureWrites>,
+    block_light: Arc<BlockLight>,
+    block_changed: Option<Arc<Events<BlockChanged>>>, // Told about every edit, see set_block_events
+    edit_history: Mutex<EditHistory>,                 // Only the edits made through edit_voxel
+    noise: Option<Arc<CachedNoise>>,                  // Replaces the biome densities when set
+    pending_work: Arc<PendingWork>,
+    shutdown_sender: Option<Sender<()>>, // Dropped on shutdown, which disconnects every shutdown receiver
+    shutdown_receiver: Receiver<()>,
+    processors_finished: Option<Receiver<()>>,
 }
 
 impl VoxelScene {
     pub fn new() -> Self {
+        Self::new_with_config(WorldConfig::default())
+    }
+
+    pub fn new_with_seed(seed: u64) -> Self {
+        Self::new_with_config(WorldConfig {
+            seed,
+            ..Default::default()
+        })
+    }
+
+    pub fn new_with_config(config: WorldConfig) -> Self {
+        let (shutdown_sender, shutdown_receiver) = flume::bounded(0);
         Self {
             chunks: Arc::new(DashMap::default()),
+            chunk_meshes: Arc::new(DashMap::default()),
+            config,
             initialization_queue: Arc::new(DashSet::default()),
-            initialization_channel: flume::unbounded(),
-            generation_channel: flume::unbounded(),
+            cancelled_chunks: Arc::new(DashSet::default()),
+            unload_sender: None,
+            mesh_sender: None,
+            initialization_channel: Arc::new(ChunkQueue::new()),
+            generation_channel: Arc::new(ChunkQueue::new()),
             generation_pre_processor_channel: flume::unbounded(),
-            thread_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(8)
-                .build()
-                .unwrap(),
+            threads: ProcessorThreads::default(),
+            utilization: Arc::new(ProcessorUtilization::default()),
+            storage: None,
+            dirty_chunks: Arc::new(DashSet::default()),
+            surfaces: Arc::new(SurfaceCache::new()),
+            structure_writes: Arc::new(StructureWrites::new()),
+            block_light: Arc::new(BlockLight::new()),
+            block_changed: None,
+            edit_history: Mutex::new(EditHistory::default()),
+            noise: None,
+            pending_work: Arc::new(PendingWork::new()),
+            shutdown_sender: Some(shutdown_sender),
+            shutdown_receiver,
+            processors_finished: None,
+        }
+    }
+
+    // Chunks are loaded from the storage before falling back to generation, must be set before the processors start
+    pub fn set_storage(&mut self, storage: ChunkStorage) {
+        self.storage = Some(Arc::new(storage));
+    }
+
+    // Must be set before the processors start
+    pub fn set_processor_threads(&mut self, threads: ProcessorThreads) {
+        self.threads = threads;
+    }
+
+    pub fn utilization(&self) -> &ProcessorUtilization {
+        &self.utilization
+    }
+
+    // Terrain density comes from the noise, on the GPU or the CPU, instead of from the biome formulas. The biomes
+    // still pick the voxels. Must be set before the processors start
+    pub fn set_noise(&mut self, noise: Arc<dyn NoiseField>) {
+        self.noise = Some(Arc::new(CachedNoise::new(noise)));
+    }
+
+    // Every edit is sent to the channel after it's written, relit and marked for saving. Whoever sets it updates it
+    // every tick, a scene without one keeps no events around
+    pub fn set_block_events(&mut self, block_changed: Arc<Events<BlockChanged>>) {
+        self.block_changed = Some(block_changed);
+    }
+
+    // Writes every chunk edited since the last save, returns how many were written
+    pub fn save_dirty_chunks(&self) -> usize {
+        save_dirty(&self.chunks, &self.dirty_chunks, &self.storage)
+    }
+
+    // Saves the edited chunks in the background every interval, does nothing without a storage
+    pub fn start_autosave(&self, interval: Duration) {
+        let chunks = Arc::clone(&self.chunks);
+        let dirty_chunks = Arc::clone(&self.dirty_chunks);
+        let storage = match &self.storage {
+            Some(storage) => Some(Arc::clone(storage)),
+            None => return,
+        };
+        let shutdown = self.shutdown_receiver.clone();
+        std::thread::spawn(move || loop {
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(interval) {
+                break; // Whatever is still dirty is saved along with the world
+            }
+            let saved = save_dirty(&chunks, &dirty_chunks, &storage);
+            if saved > 0 {
+                println!("[INFO] Autosaved {} chunks", saved);
+            }
+        });
+    }
+
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn pending_initialization_count(&self) -> usize {
+        self.initialization_channel.len()
+    }
+
+    // Chunks waiting for their neighbours as well as those ready to be meshed
+    pub fn pending_generation_count(&self) -> usize {
+        self.generation_pre_processor_channel.0.len() + self.generation_channel.len()
+    }
+
+    // True once every requested chunk has been initialized and meshed, including the neighbours they pulled in
+    pub fn is_idle(&self) -> bool {
+        self.pending_work.count() == 0
+    }
+
+    // Blocks until the generation pipeline has drained, false when the timeout passed first
+    pub fn wait_until_idle(&self, timeout: Duration) -> bool {
+        self.pending_work.wait_idle(timeout)
+    }
+
+    // Chunks through the pipeline so far and chunks ever queued, counting neighbours pulled in and skipped chunks
+    pub fn generation_progress(&self) -> (usize, usize) {
+        self.pending_work.progress()
+    }
+
+    // Receives an event every time the generation pipeline drains
+    pub fn subscribe_idle(&self) -> Receiver<()> {
+        self.pending_work.subscribe()
+    }
+
+    // Snapshot of the generation pipeline, counts may be slightly off while the processors are running
+    pub fn stats(&self) -> VoxelSceneStats {
+        VoxelSceneStats {
+            loaded_chunks: self.chunks.len(),
+            empty_chunks: self.chunks.iter().filter(|chunk| chunk.is_empty).count(),
+            pending_initialization: self.pending_initialization_count(),
+            pending_generation: self.pending_generation_count(),
+            voxel_bytes: self.voxel_bytes(),
         }
     }
 
+    // Memory taken by the voxels and densities of every loaded chunk, walks every chunk
+    pub fn voxel_bytes(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.bytes_used()).sum()
+    }
+
+    pub fn density_at(&self, position: &IVec3) -> Option<f32> {
+        self.chunks
+            .get(&Self::chunk_at(position))
+            .and_then(|chunk| chunk.density_scenespace_at(position))
+    }
+
     pub fn voxel_at(&self, position: &IVec3) -> Option<VoxelData> {
         let chunk_pos = Self::chunk_at(position);
         self.chunks
@@ -53,6 +353,149 @@ impl VoxelScene {
             .map(|chunk| chunk.voxel_scenespace_at(position).unwrap().to_owned())
     }
 
+    // Height of the highest voxel that isn't air in the column, walking the loaded chunks from the top of the world
+    // down. None when a chunk above the surface isn't loaded yet, or when the whole column is air
+    pub fn surface_height_at(&self, x: i32, z: i32) -> Option<i32> {
+        let column = Self::chunk_at(&IVec3::new(x, 0, z));
+        for chunk_y in (self.config.min_chunk_y()..=self.config.max_chunk_y()).rev() {
+            let chunk = self.chunks.get(&IVec3::new(column.x, chunk_y, column.z))?;
+            if chunk.is_empty {
+                continue;
+            }
+            let top = chunk.scenespace_pos().y + CHUNK_SIZE as i32 - 1;
+            let surface = (chunk.scenespace_pos().y..=top).rev().find(|y| {
+                chunk
+                    .voxel_scenespace_at(&IVec3::new(x, *y, z))
+                    .map_or(false, |voxel| voxel.id != 0)
+            });
+            if surface.is_some() {
+                return surface;
+            }
+        }
+        None
+    }
+
+    // The first and last voxel overlapped by the box, both inclusive. Voxels are centered on integer positions, a box
+    // that only touches a voxel's face doesn't overlap it. Empty along an axis when the box has no size on it
+    pub fn cells_in_region(min: Vec3, max: Vec3) -> (IVec3, IVec3) {
+        let first = (min + Vec3::splat(0.5)).floor().as_ivec3();
+        let last = (max + Vec3::splat(0.5)).ceil().as_ivec3() - IVec3::ONE;
+        (first, last)
+    }
+
+    // Checks every voxel the box overlaps, chunk by chunk, against the voxel profiles without touching the physics
+    pub fn is_region_empty(&self, min: Vec3, max: Vec3) -> RegionQuery {
+        let (first, last) = Self::cells_in_region(min, max);
+        if first.cmpgt(last).any() {
+            return RegionQuery::Empty;
+        }
+        let (first_chunk, last_chunk) = (Self::chunk_at(&first), Self::chunk_at(&last));
+        let mut solid = Vec::new();
+        let mut unloaded = Vec::new();
+        for x in first_chunk.x..=last_chunk.x {
+            for y in first_chunk.y..=last_chunk.y {
+                for z in first_chunk.z..=last_chunk.z {
+                    let chunk_pos = IVec3::new(x, y, z);
+                    match self.chunks.get(&chunk_pos) {
+                        Some(chunk) => chunk.solid_voxels_in(first, last, &mut solid),
+                        None => unloaded.push(chunk_pos),
+                    }
+                }
+            }
+        }
+        if !solid.is_empty() {
+            RegionQuery::Blocked(solid)
+        } else if !unloaded.is_empty() {
+            RegionQuery::Unloaded(unloaded)
+        } else {
+            RegionQuery::Empty
+        }
+    }
+
+    // Steps through the voxel grid one voxel at a time (Amanatides & Woo). A voxel containing the origin counts as a hit
+    // at distance 0 entered through the face opposing the ray, voxels with id 0 are air
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<VoxelRaycastHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        // Voxels are centered on integer positions, shift by half a voxel so cells start at integer positions
+        let start = origin + Vec3::splat(0.5);
+        let mut cell = start.floor().as_ivec3();
+        let step = IVec3::new(
+            direction.x.signum() as i32,
+            direction.y.signum() as i32,
+            direction.z.signum() as i32,
+        );
+        let axis_distance = |axis: usize| {
+            if direction[axis] > 0.0 {
+                (cell[axis] as f32 + 1.0 - start[axis]) / direction[axis]
+            } else if direction[axis] < 0.0 {
+                (start[axis] - cell[axis] as f32) / -direction[axis]
+            } else {
+                f32::INFINITY
+            }
+        };
+        let mut t_max = Vec3::new(axis_distance(0), axis_distance(1), axis_distance(2));
+        let t_delta = Vec3::ONE / direction.abs(); // Infinite along axes the ray doesn't move on
+
+        let dominant_axis = direction.abs().max_element();
+        let mut entered_axis = (0..3)
+            .find(|axis| direction.abs()[*axis] == dominant_axis)
+            .unwrap();
+        let mut distance = 0.0;
+
+        // Chunks are only looked up when the ray enters them, empty ones are stepped through without voxel lookups
+        let mut current_chunk = Self::chunk_at(&cell);
+        let mut chunk = self.chunks.get(&current_chunk);
+        loop {
+            let chunk_pos = Self::chunk_at(&cell);
+            if chunk_pos != current_chunk {
+                current_chunk = chunk_pos;
+                chunk = self.chunks.get(&current_chunk);
+            }
+            if let Some(chunk) = chunk.as_ref().filter(|chunk| !chunk.is_empty) {
+                let voxel = *chunk.voxel_scenespace_at(&cell).unwrap();
+                if voxel.id != 0 {
+                    let mut normal = IVec3::ZERO;
+                    normal[entered_axis] = -step[entered_axis];
+                    let face = *voxel_directions::ALL
+                        .iter()
+                        .find(|direction| direction.as_vec() == normal)
+                        .unwrap();
+                    return Some(VoxelRaycastHit {
+                        position: cell,
+                        voxel,
+                        face,
+                        distance,
+                        point: origin + direction * distance,
+                    });
+                }
+            }
+
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            distance = t_max[axis];
+            if distance > max_distance {
+                return None;
+            }
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            entered_axis = axis;
+        }
+    }
+
     pub fn chunk_at(position: &IVec3) -> IVec3 {
         IVec3::new(
             position.x.div_floor(CHUNK_SIZE as i32),
@@ -63,65 +506,165 @@ impl VoxelScene {
 
     pub fn request_initialize_chunk(
         queue: Arc<DashSet<IVec3>>,
-        sender: Sender<(IVec3, Option<Sender<IVec3>>)>,
+        sender: InitializationQueue,
+        pending_work: &PendingWork,
         request: (IVec3, Option<Sender<IVec3>>),
     ) {
         if queue.contains(&request.0) {
             return;
         }
         queue.insert(request.0);
-        sender.send(request).unwrap();
+        pending_work.add();
+        sender.push(request.0, request.1);
+    }
+
+    // Queued chunks closest to this chunk position are processed first
+    pub fn set_focus(&self, chunk_pos: IVec3) {
+        self.initialization_channel.set_focus(chunk_pos);
+        self.generation_channel.set_focus(chunk_pos);
     }
 
-    pub fn setup_chunk_processors(&mut self, mesh_sender: Sender<(IVec3, Mesh)>) {
-        for _i in 0..3 {
+    // Unloaded chunk positions are sent to the unload sender so their entities can be cleaned up
+    pub fn setup_chunk_processors(
+        &mut self,
+        mesh_sender: Sender<ChunkMeshMessage>,
+        unload_sender: Sender<IVec3>,
+    ) {
+        self.unload_sender = Some(unload_sender);
+        self.mesh_sender = Some(mesh_sender.clone());
+        // Every processor holds a sender until it returns, shutdown waits for this to disconnect
+        let (finished_sender, finished_receiver) = flume::bounded::<()>(0);
+        self.processors_finished = Some(finished_receiver);
+        let threads = self.threads;
+        // Dropped once the processors are spawned, its workers keep running until the processors return
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.total())
+            .build()
+            .unwrap();
+        self.utilization
+            .initialization
+            .set_workers(threads.initialization);
+        self.utilization.generation.set_workers(threads.generation);
+        self.utilization
+            .pre_processor
+            .set_workers(threads.pre_processor);
+        for _i in 0..threads.initialization {
             let chunks_clone = Arc::clone(&self.chunks);
-            let initialization_channel_receiver = self.initialization_channel.1.clone();
-            self.thread_pool.spawn(move || {
-                VoxelScene::initialization_processor(chunks_clone, initialization_channel_receiver);
+            let initialization_channel_receiver = Arc::clone(&self.initialization_channel);
+            let cancelled_chunks_clone = Arc::clone(&self.cancelled_chunks);
+            let storage = self.storage.clone();
+            let surfaces = Arc::clone(&self.surfaces);
+            let structure_writes = Arc::clone(&self.structure_writes);
+            let dirty_chunks = Arc::clone(&self.dirty_chunks);
+            let chunk_meshes = Arc::clone(&self.chunk_meshes);
+            let block_light = Arc::clone(&self.block_light);
+            let generation_channel = Arc::clone(&self.generation_channel);
+            let noise = self.noise.clone();
+            let pending_work = Arc::clone(&self.pending_work);
+            let utilization = Arc::clone(&self.utilization);
+            let config = self.config;
+            let finished = finished_sender.clone();
+            thread_pool.spawn(move || {
+                let _finished = finished;
+                VoxelScene::initialization_processor(
+                    chunks_clone,
+                    initialization_channel_receiver,
+                    cancelled_chunks_clone,
+                    storage,
+                    surfaces,
+                    structure_writes,
+                    dirty_chunks,
+                    chunk_meshes,
+                    block_light,
+                    generation_channel,
+                    noise,
+                    pending_work,
+                    utilization,
+                    config,
+                );
             });
         }
 
-        for _i in 0..3 {
+        for _i in 0..threads.generation {
             let chunks_clone = Arc::clone(&self.chunks);
-            let generation_channel_receiver = self.generation_channel.1.clone();
+            let chunk_meshes_clone = Arc::clone(&self.chunk_meshes);
+            let generation_channel_receiver = Arc::clone(&self.generation_channel);
             let mesh_sender_clone = mesh_sender.clone();
-            self.thread_pool.spawn(move || {
+            let pending_work = Arc::clone(&self.pending_work);
+            let utilization = Arc::clone(&self.utilization);
+            let config = self.config;
+            let finished = finished_sender.clone();
+            thread_pool.spawn(move || {
+                let _finished = finished;
                 VoxelScene::generation_processor(
                     chunks_clone,
+                    chunk_meshes_clone,
                     generation_channel_receiver,
                     mesh_sender_clone,
+                    pending_work,
+                    utilization,
+                    config,
                 );
             });
         }
 
-        for _i in 0..2 {
+        for _i in 0..threads.pre_processor {
             let chunks_clone = Arc::clone(&self.chunks);
             let generation_pre_processor_receiver = self.generation_pre_processor_channel.1.clone();
             let initialization_queue_clone = Arc::clone(&self.initialization_queue);
-            let initialization_sender = self.initialization_channel.0.clone();
-            let generation_sender_clone = self.generation_channel.0.clone();
-            self.thread_pool.spawn(move || {
+            let initialization_sender = Arc::clone(&self.initialization_channel);
+            let generation_sender_clone = Arc::clone(&self.generation_channel);
+            let shutdown = self.shutdown_receiver.clone();
+            let pending_work = Arc::clone(&self.pending_work);
+            let utilization = Arc::clone(&self.utilization);
+            let config = self.config;
+            let finished = finished_sender.clone();
+            thread_pool.spawn(move || {
+                let _finished = finished;
                 VoxelScene::generation_pre_processor(
                     chunks_clone,
                     generation_pre_processor_receiver,
                     initialization_queue_clone,
                     initialization_sender,
                     generation_sender_clone,
+                    shutdown,
+                    pending_work,
+                    utilization,
+                    config,
                 );
             });
         }
 
         println!(
-            "[INFO] World generation initialized with {} threads",
-            self.thread_pool.current_num_threads()
+            "[INFO] World generation initialized with {} threads ({} initializing, {} meshing, {} pre-processing)",
+            thread_pool.current_num_threads(),
+            threads.initialization,
+            threads.generation,
+            threads.pre_processor
         );
     }
 
+    // Stops the chunk processors and waits for them to return, queued work is dropped.
+    // The loaded chunks can still be read and saved afterwards, nothing new is loaded or meshed
+    pub fn shutdown(&mut self) {
+        self.shutdown_sender = None;
+        self.initialization_channel.close();
+        self.generation_channel.close();
+        self.initialization_queue.clear();
+        if let Some(finished) = self.processors_finished.take() {
+            let _ = finished.recv(); // Only returns once every sender is gone
+        }
+        println!("[INFO] World generation shut down");
+    }
+
     pub fn initialize_and_generate_chunk(&self, position: IVec3) {
+        if !self.config.chunk_in_bounds(&position) {
+            return;
+        }
         VoxelScene::request_initialize_chunk(
             Arc::clone(&self.initialization_queue),
-            self.initialization_channel.0.clone(),
+            Arc::clone(&self.initialization_channel),
+            &self.pending_work,
             (
                 position,
                 Some(self.generation_pre_processor_channel.0.clone()),
@@ -129,141 +672,745 @@ impl VoxelScene {
         );
     }
 
+    // Runs initialization again for every loaded chunk, e.g. after the profiles were reloaded, then remeshes them.
+    // Chunks keep their old data and mesh until their replacement is ready, edits to them are lost
+    pub fn regenerate_loaded_chunks(&self) {
+        let positions = self.chunks.iter().map(|chunk| chunk.position).collect();
+        self.regenerate_chunks(positions);
+    }
+
+    // Like regenerate_loaded_chunks for some of them, positions that aren't loaded are skipped
+    pub fn regenerate_chunks(&self, positions: Vec<IVec3>) {
+        let chunks = Arc::clone(&self.chunks);
+        let chunk_meshes = Arc::clone(&self.chunk_meshes);
+        let generation_channel = Arc::clone(&self.generation_channel);
+        let surfaces = Arc::clone(&self.surfaces);
+        let structure_writes = Arc::clone(&self.structure_writes);
+        let block_light = Arc::clone(&self.block_light);
+        let noise = self.noise.clone();
+        let pending_work = Arc::clone(&self.pending_work);
+        let config = self.config;
+        // The scene's own pool is busy running the processors
+        rayon::spawn(move || {
+            let positions: Vec<IVec3> = positions
+                .into_iter()
+                .filter(|chunk_pos| chunks.contains_key(chunk_pos))
+                .collect();
+            let biome_map = shared_biome_map(config.seed);
+            let columns: HashSet<IVec2> = positions
+                .iter()
+                .map(|chunk_pos| IVec2::new(chunk_pos.x, chunk_pos.z))
+                .collect();
+            surfaces.retain(|column| !columns.contains(&column));
+            // The chunks above may still hold the old data, so depth always comes from the surface heights
+            positions.par_iter().for_each(|chunk_pos| {
+                let chunk = generate_chunk(
+                    *chunk_pos,
+                    &biome_map,
+                    &config,
+                    &surfaces,
+                    None,
+                    noise.as_deref().map(|noise| noise as &dyn NoiseField),
+                );
+                if let Some(mut loaded) = chunks.get_mut(chunk_pos) {
+                    *loaded = chunk;
+                }
+            });
+            // Structures reach into their neighbours, so they're only grown once all the terrain is back
+            for chunk_pos in &positions {
+                let mut chunk = match chunks.get(chunk_pos) {
+                    Some(chunk) => chunk.clone(),
+                    None => continue,
+                };
+                grow_structures(
+                    &mut chunk,
+                    &biome_map,
+                    &config,
+                    &surfaces,
+                    &chunks,
+                    &structure_writes,
+                );
+                chunks.insert(*chunk_pos, chunk);
+            }
+            // The regenerated chunks start out dark, every chunk is remeshed below anyway
+            for chunk_pos in &positions {
+                block_light.light_chunk(&chunks, *chunk_pos);
+            }
+
+            // Meshing waits until every chunk is regenerated, so faces on chunk borders are culled against new data
+            println!("[INFO] Regenerated {} chunks", positions.len());
+            for chunk_pos in positions {
+                let needs_mesh = chunks
+                    .get(&chunk_pos)
+                    .map_or(false, |chunk| !chunk.is_empty);
+                if needs_mesh || chunk_meshes.contains_key(&chunk_pos) {
+                    pending_work.add();
+                    generation_channel.push(chunk_pos, ());
+                }
+            }
+        });
+    }
+
+    // Removes every chunk further than the radius from the center (in chunks, ignoring height) along with its mesh.
+    // Chunks that are still queued are cancelled, all of them can be requested again later
+    pub fn unload_chunks_outside(&self, center: IVec3, radius: u32) {
+        let is_outside = |chunk_pos: &IVec3| {
+            let offset = (*chunk_pos - center).abs();
+            offset.x.max(offset.z) > radius as i32
+        };
+
+        let pending: Vec<IVec3> = self
+            .initialization_queue
+            .iter()
+            .map(|chunk_pos| *chunk_pos)
+            .filter(|chunk_pos| is_outside(chunk_pos))
+            .collect();
+        for chunk_pos in pending {
+            self.initialization_queue.remove(&chunk_pos);
+            if !self.chunks.contains_key(&chunk_pos) {
+                self.cancelled_chunks.insert(chunk_pos);
+            }
+        }
+
+        let unloaded: Vec<IVec3> = self
+            .chunks
+            .iter()
+            .map(|chunk| chunk.position)
+            .filter(|chunk_pos| is_outside(chunk_pos))
+            .collect();
+        for chunk_pos in &unloaded {
+            // Edits would be lost otherwise, the chunk is loaded back from storage when it's requested again
+            if self.dirty_chunks.remove(chunk_pos).is_some() {
+                if let (Some(storage), Some(chunk)) = (&self.storage, self.chunks.get(chunk_pos)) {
+                    if let Err(e) = storage.save_chunk(*chunk_pos, &chunk) {
+                        println!("[INFO] Failed to save chunk {}: {}", chunk_pos, e);
+                    }
+                }
+            }
+            self.chunks.remove(chunk_pos);
+            self.chunk_meshes.remove(chunk_pos);
+            self.unload_sender.as_ref().map(|s| s.send(*chunk_pos));
+        }
+        self.surfaces
+            .retain(|column| !is_outside(&IVec3::new(column.x, 0, column.y)));
+        if let Some(noise) = &self.noise {
+            noise.retain(|chunk_pos| !is_outside(chunk_pos));
+        }
+        // A chunk one past the radius can still be written into by a structure growing out of a loaded one
+        self.structure_writes.retain(|chunk_pos| {
+            let offset = (*chunk_pos - center).abs();
+            offset.x.max(offset.z) <= radius as i32 + 1
+        });
+        if !unloaded.is_empty() {
+            println!("[INFO] Unloaded {} chunks", unloaded.len());
+        }
+    }
+
     pub fn initialization_processor(
         chunks: ChunkMap,
-        pos_receiver: Receiver<(IVec3, Option<Sender<IVec3>>)>,
+        pos_receiver: InitializationQueue,
+        cancelled_chunks: Arc<DashSet<IVec3>>,
+        storage: Option<Arc<ChunkStorage>>,
+        surfaces: Arc<SurfaceCache>,
+        structure_writes: Arc<StructureWrites>,
+        dirty_chunks: Arc<DashSet<IVec3>>,
+        chunk_meshes: ChunkMeshMap,
+        block_light: Arc<BlockLight>,
+        generation_channel: Arc<ChunkQueue<()>>,
+        noise: Option<Arc<CachedNoise>>,
+        pending_work: Arc<PendingWork>,
+        utilization: Arc<ProcessorUtilization>,
+        config: WorldConfig,
     ) {
         println!("Started initialization processor");
-        loop {
-            let mut chunks_to_process = pos_receiver.try_iter().collect::<Vec<_>>();
-            if chunks_to_process.len() == 0 {
-                chunks_to_process = vec![pos_receiver.recv().unwrap()]; // Nothing to process, wait for something
-            }
-            chunks_to_process.iter().for_each(|(chunk_pos, callback)| {
-                if chunks.contains_key(&chunk_pos) {
-                    println!("INITIALIZING CHUNK THAT ALREADY EXISTS!");
-                    return;
+        let forward = |callback: &Option<Sender<IVec3>>, chunk_pos: IVec3| {
+            if let Some(sender) = callback {
+                pending_work.add();
+                let _ = sender.send(chunk_pos);
+            }
+        };
+        // Waits for something to process, stops once the queue is closed
+        while let Some((chunk_pos, callback)) = pos_receiver.pop_nearest() {
+            let _busy = utilization.initialization.start();
+            if cancelled_chunks.remove(&chunk_pos).is_some() {
+                pending_work.finish();
+                continue; // Unloaded before it got here
+            }
+            if chunks.contains_key(&chunk_pos) {
+                // Already loaded, e.g. from a save, only pass it along
+                forward(&callback, chunk_pos);
+                pending_work.finish();
+                continue;
+            }
+            let stored = storage
+                .as_ref()
+                .and_then(|storage| storage.load_chunk(chunk_pos));
+            let generated = stored.is_none();
+            let mut touched = Vec::new();
+            let chunk = stored.unwrap_or_else(|| {
+                // Copied so the chunk above isn't locked for the whole fill
+                let chunk_above = chunks
+                    .get(&(chunk_pos + IVec3::Y))
+                    .map(|chunk| chunk.clone());
+                let biome_map = shared_biome_map(config.seed);
+                // Chunks waiting nearby share the noise call, their fields are cached until they're generated
+                if let Some(noise) = &noise {
+                    let mut positions = noise_layers(chunk_pos, &surfaces, &config);
+                    positions.extend(pos_receiver.positions_near(chunk_pos, NOISE_BATCH_CHUNKS));
+                    noise.prefetch(&positions);
                 }
-                let mut chunk = VoxelChunk::new(*chunk_pos);
-
-                // Set chunk data
-                let biome = get_biome_by_name("plains".to_string()).unwrap();
-                let chunk_pos_scenespace = chunk.scenespace_pos();
-                let mut context = SampleContext {
-                    position: chunk_pos_scenespace,
-                    slope: Vec3::ZERO,
-                    depth: 0.0,
-                    moisture: 0.0,
-                    temperature: 0.0,
-                    density: 0.0,
-                };
+                let mut chunk = generate_chunk(
+                    chunk_pos,
+                    &biome_map,
+                    &config,
+                    &surfaces,
+                    chunk_above.as_ref(),
+                    noise.as_deref().map(|noise| noise as &dyn NoiseField),
+                );
+                touched = grow_structures(
+                    &mut chunk,
+                    &biome_map,
+                    &config,
+                    &surfaces,
+                    &chunks,
+                    &structure_writes,
+                );
                 chunk
-                    .voxels
-                    .iter_mut()
-                    .enumerate()
-                    .for_each(|(index, voxel)| {
-                        let voxel_pos = index_to_pos(index as u32);
-                        context.position = voxel_pos.as_ivec3() + chunk_pos_scenespace;
-                        context.density = biome.sample_density(&context);
-                        if context.density > 0.0 {
-                            chunk.is_empty = false;
-                            *voxel = biome.sample_voxel(&context);
-                        }
-                    });
-
-                chunks.insert(*chunk_pos, chunk);
-                callback.as_ref().map(|s| s.send(*chunk_pos));
             });
+
+            if let Some(noise) = &noise {
+                noise.forget(chunk_pos);
+            }
+            // Loaded neighbours a structure grew into show it once they're remeshed, and keep it once they're saved
+            for neighbour in touched {
+                mark_dirty(&chunks, &dirty_chunks, neighbour);
+                pending_work.add();
+                generation_channel.push(neighbour, ());
+            }
+            if cancelled_chunks.remove(&chunk_pos).is_some() {
+                pending_work.finish();
+                continue; // Unloaded while it was being initialized
+            }
+            structure_writes.insert_chunk(chunk, &chunks, generated);
+            // Light isn't saved, so loaded chunks are lit the same way as generated ones.
+            // Meshed neighbours the light spread into are remeshed as well, the others pick it up with their first mesh
+            for relit in block_light.light_chunk(&chunks, chunk_pos) {
+                if relit != chunk_pos && chunk_meshes.contains_key(&relit) {
+                    pending_work.add();
+                    generation_channel.push(relit, ());
+                }
+            }
+            forward(&callback, chunk_pos);
+            pending_work.finish();
         }
     }
 
     pub fn generation_processor(
         chunks: ChunkMap,
-        pos_receiver: Receiver<IVec3>,
-        mesh_sender: Sender<(IVec3, Mesh)>,
+        chunk_meshes: ChunkMeshMap,
+        pos_receiver: Arc<ChunkQueue<()>>,
+        mesh_sender: Sender<ChunkMeshMessage>,
+        pending_work: Arc<PendingWork>,
+        utilization: Arc<ProcessorUtilization>,
+        config: WorldConfig,
     ) {
         println!("Started generation processor");
-        loop {
-            let chunk_pos = pos_receiver.recv().unwrap();
-            let chunk = (*chunks.get(&chunk_pos).unwrap()).clone();
-            let chunks_clone = Arc::clone(&chunks);
-            let mesh = chunk.generate_mesh(chunks_clone);
-            mesh_sender.send((chunk_pos, mesh)).unwrap();
+        while let Some((chunk_pos, _)) = pos_receiver.pop_nearest() {
+            let _busy = utilization.generation.start();
+            // The copy shares the chunk's voxels, the guard isn't held while the neighbours are looked up
+            let chunk = match chunks.get(&chunk_pos) {
+                Some(chunk) => chunk.clone(),
+                None => {
+                    pending_work.finish();
+                    continue; // Unloaded while queued
+                }
+            };
+            let neighborhood = ChunkNeighborhood::new(chunk, &chunks);
+            let chunk_mesh = ChunkMesh::generate(&neighborhood, config.meshing);
+            if !chunks.contains_key(&chunk_pos) {
+                pending_work.finish();
+                continue; // Unloaded while meshing
+            }
+            // A remeshed chunk keeps its meshes, the entities showing them are reused
+            let chunk_mesh = match chunk_meshes.entry(chunk_pos) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().replace_with(chunk_mesh);
+                    entry.into_ref()
+                }
+                Entry::Vacant(entry) => entry.insert(chunk_mesh),
+            };
+            let meshes = chunk_mesh.meshes();
+            drop(chunk_mesh);
+            let sent = mesh_sender.send((chunk_pos, meshes));
+            // Finished after the send, so a receiver that saw the scene go idle has every mesh waiting for it
+            pending_work.finish();
+            if sent.is_err() {
+                break; // Nothing is listening for meshes anymore
+            }
         }
     }
 
-    pub fn generation_pre_processor(
-        chunks: ChunkMap,
-        pos_receiver: Receiver<IVec3>,
-        initialization_queue: Arc<DashSet<IVec3>>,
-        initialization_sender: Sender<(IVec3, Option<Sender<IVec3>>)>,
-        pos_sender: Sender<IVec3>,
-    ) {
-        println!("Started generation pre-processor");
-        // store a list of chunk positions
-        let mut chunks_to_generate = VecDeque::new();
-        loop {
-            let mut chunk_positions = pos_receiver.try_iter().collect::<Vec<_>>();
-            chunk_positions.extend(chunks_to_generate.iter());
-            chunks_to_generate.clear();
-            if chunk_positions.len() == 0 {
-                chunk_positions = vec![pos_receiver.recv().unwrap()]; // Nothing left in queue, wait for something
+    // Writes the voxel and remeshes the chunks whose faces it affects
+    pub fn set_voxel(&self, position: IVec3, data: VoxelData) -> Result<(), VoxelEditError> {
+        self.replace_voxel(position, data).map(|_| ())
+    }
+
+    // set_voxel, returning the voxel it replaced
+    fn replace_voxel(&self, position: IVec3, data: VoxelData) -> Result<VoxelData, VoxelEditError> {
+        if !self.config.height_in_bounds(position.y) {
+            return Err(VoxelEditError::OutOfBounds);
+        }
+        let chunk_pos = Self::chunk_at(&position);
+        let old = match self.chunks.get_mut(&chunk_pos) {
+            Some(mut chunk) => {
+                let local_pos = (position - chunk.scenespace_pos()).as_uvec3();
+                chunk.write_voxel(&local_pos, data)
             }
-            for chunk_pos in chunk_positions {
-                // get a list of neighbours
-                let mut failed = false;
-                for direction in voxel_directions::ALL {
-                    let neighbour_pos = chunk_pos + direction.as_vec();
-                    if !chunks.contains_key(&neighbour_pos) {
-                        failed = true;
-                        VoxelScene::request_initialize_chunk(
-                            initialization_queue.clone(),
-                            initialization_sender.clone(),
-                            (neighbour_pos, None),
-                        );
-                    }
+            None => {
+                self.initialize_and_generate_chunk(chunk_pos);
+                return Err(VoxelEditError::ChunkNotLoaded);
+            }
+        };
+        if old == data {
+            return Ok(old);
+        }
+        self.mark_dirty(chunk_pos);
+        self.remesh_voxel(&position);
+        self.relight(position, old, data);
+        self.send_block_changed(BlockChanged {
+            pos: position,
+            old,
+            new: data,
+        });
+        Ok(old)
+    }
+
+    // Writes the whole batch taking every chunk's lock once, then queues each chunk whose faces changed for a single
+    // remesh, including neighbours of edits on chunk borders. Returns the positions that were skipped because they're
+    // out of bounds or their chunk isn't loaded, those chunks are queued like they are by set_voxel
+    pub fn set_voxels(&self, edits: &[(IVec3, VoxelData)]) -> Vec<IVec3> {
+        let mut skipped = Vec::new();
+        let mut by_chunk: HashMap<IVec3, Vec<(IVec3, VoxelData)>> = HashMap::new();
+        for &(position, data) in edits {
+            if self.config.height_in_bounds(position.y) {
+                by_chunk
+                    .entry(Self::chunk_at(&position))
+                    .or_default()
+                    .push((position, data));
+            } else {
+                skipped.push(position);
+            }
+        }
+
+        let mut remesh = HashSet::new();
+        for (chunk_pos, edits) in by_chunk {
+            let mut chunk = match self.chunks.get_mut(&chunk_pos) {
+                Some(chunk) => chunk,
+                None => {
+                    self.initialize_and_generate_chunk(chunk_pos);
+                    skipped.extend(edits.iter().map(|(position, _)| *position));
+                    continue;
+                }
+            };
+            let origin = chunk.scenespace_pos();
+            let mut changed = Vec::new();
+            for (position, data) in edits {
+                let old = chunk.write_voxel(&(position - origin).as_uvec3(), data);
+                if old == data {
+                    continue;
                 }
+                changed.push(BlockChanged {
+                    pos: position,
+                    old,
+                    new: data,
+                });
+                remesh.insert(chunk_pos);
+                remesh.extend(
+                    voxel_directions::ALL
+                        .iter()
+                        .map(|direction| Self::chunk_at(&(position + direction.as_vec()))),
+                );
+            }
+            drop(chunk);
+            if !changed.is_empty() {
+                self.mark_dirty(chunk_pos);
+            }
+            for event in changed {
+                self.relight(event.pos, event.old, event.new);
+                self.send_block_changed(event);
+            }
+        }
 
-                // if all neighbours are initialized, schedule the chunk to be generated
-                if !failed && chunks.contains_key(&chunk_pos) {
-                    if !chunks.get(&chunk_pos).unwrap().is_empty {
-                        pos_sender.send(chunk_pos).unwrap();
+        for chunk_pos in remesh {
+            let has_faces = match self.chunks.get(&chunk_pos) {
+                Some(chunk) => !chunk.is_empty || self.chunk_meshes.contains_key(&chunk_pos),
+                None => false,
+            };
+            if has_faces {
+                self.pending_work.add();
+                self.generation_channel.push(chunk_pos, ());
+            }
+        }
+        skipped
+    }
+
+    // Sets every voxel in the box, both corners are inclusive. See set_voxels
+    pub fn fill_region(&self, min: IVec3, max: IVec3, data: VoxelData) -> Vec<IVec3> {
+        let edits: Vec<(IVec3, VoxelData)> = region_positions(min.min(max), min.max(max))
+            .map(|position| (position, data))
+            .collect();
+        self.set_voxels(&edits)
+    }
+
+    // Visits every voxel in the box, one chunk at a time so each chunk is only looked up once. Both corners are
+    // inclusive. Returns the positions that weren't visited because their chunk isn't loaded
+    pub fn for_each_in_region(
+        &self,
+        min: IVec3,
+        max: IVec3,
+        mut f: impl FnMut(IVec3, &VoxelData),
+    ) -> Vec<IVec3> {
+        let (min, max) = (min.min(max), min.max(max));
+        let mut skipped = Vec::new();
+        for chunk_pos in region_positions(Self::chunk_at(&min), Self::chunk_at(&max)) {
+            let origin = chunk_pos * CHUNK_SIZE as i32;
+            let positions = region_positions(
+                min.max(origin),
+                max.min(origin + IVec3::splat(CHUNK_SIZE as i32 - 1)),
+            );
+            match self.chunks.get(&chunk_pos) {
+                Some(chunk) => {
+                    for position in positions {
+                        f(position, chunk.voxel_scenespace_at(&position).unwrap());
                     }
-                } else {
-                    chunks_to_generate.push_front(chunk_pos);
                 }
+                None => skipped.extend(positions),
             }
         }
+        skipped
     }
-}
 
-#[derive(Clone)]
-pub struct VoxelChunk {
-    pub position: IVec3,
-    pub is_empty: bool,
-    voxels: Vec<VoxelData>,
-}
+    // An edit made by the player, recorded so it can be undone. Edits made since the last begin_stroke are undone
+    // together
+    pub fn edit_voxel(&self, position: IVec3, data: VoxelData) -> Result<(), VoxelEditError> {
+        let old = self.replace_voxel(position, data)?;
+        if old != data {
+            self.edit_history.lock().record(VoxelEdit {
+                pos: position,
+                old,
+                new: data,
+            });
+        }
+        Ok(())
+    }
 
-impl VoxelChunk {
-    pub fn new(position: IVec3) -> Self {
-        Self {
-            position,
-            is_empty: true,
-            voxels: vec![
-                VoxelData {
-                    shape: voxel_shape::CUBE,
-                    state: 0,
-                    id: 0,
-                };
-                (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize
-            ],
+    pub fn begin_stroke(&self) {
+        self.edit_history.lock().begin_stroke();
+    }
+
+    // Reverts the last stroke of the player's edits, returns how many voxels it changed. Nothing is reverted while
+    // one of its chunks isn't loaded, the missing chunks are queued and the undo can be retried once they are
+    pub fn undo(&self) -> Result<usize, VoxelEditError> {
+        let mut history = self.edit_history.lock();
+        match history.peek_undo() {
+            Some(stroke) => self.load_edited_chunks(stroke)?,
+            None => return Ok(0),
+        }
+        let stroke = history.undo().unwrap();
+        for edit in stroke.iter().rev() {
+            self.set_voxel(edit.pos, edit.old)?;
         }
+        Ok(stroke.len())
     }
 
-    pub fn voxel_scenespace_at_mut(&mut self, position: &IVec3) -> Option<&mut VoxelData> {
-        let localized_pos = *position - (self.position * CHUNK_SIZE as i32);
-        if localized_pos.x >= CHUNK_SIZE as i32
-            || localized_pos.y >= CHUNK_SIZE as i32
+    // Makes the last undone stroke again, see undo
+    pub fn redo(&self) -> Result<usize, VoxelEditError> {
+        let mut history = self.edit_history.lock();
+        match history.peek_redo() {
+            Some(stroke) => self.load_edited_chunks(stroke)?,
+            None => return Ok(0),
+        }
+        let stroke = history.redo().unwrap();
+        for edit in &stroke {
+            self.set_voxel(edit.pos, edit.new)?;
+        }
+        Ok(stroke.len())
+    }
+
+    fn load_edited_chunks<'a>(
+        &self,
+        edits: impl IntoIterator<Item = &'a VoxelEdit>,
+    ) -> Result<(), VoxelEditError> {
+        let mut missing = false;
+        for edit in edits {
+            let chunk_pos = Self::chunk_at(&edit.pos);
+            if !self.chunks.contains_key(&chunk_pos) {
+                self.initialize_and_generate_chunk(chunk_pos);
+                missing = true;
+            }
+        }
+        if missing {
+            return Err(VoxelEditError::ChunkNotLoaded);
+        }
+        Ok(())
+    }
+
+    // Spreads or removes the block light around an edited voxel, the edit is already in the chunk.
+    // Only the chunks the light reaches are relit, their faces all have to be rebuilt
+    fn relight(&self, position: IVec3, old: VoxelData, new: VoxelData) {
+        for relit in self.block_light.update(&self.chunks, position, old, new) {
+            if self.chunk_meshes.contains_key(&relit) {
+                self.pending_work.add();
+                self.generation_channel.push(relit, ());
+            }
+        }
+    }
+
+    fn send_block_changed(&self, event: BlockChanged) {
+        if let Some(block_changed) = &self.block_changed {
+            block_changed.send(event);
+        }
+    }
+
+    // The chunk is written on the next save, or when it's unloaded
+    pub fn mark_dirty(&self, chunk_pos: IVec3) {
+        mark_dirty(&self.chunks, &self.dirty_chunks, chunk_pos);
+    }
+
+    // Rebuilds only the faces affected by a change to the voxel at the given position,
+    // including the faces of neighbouring chunks when the voxel sits on a chunk border.
+    // Chunks that were never meshed (they used to be empty) are queued for generation instead
+    pub fn remesh_voxel(&self, position: &IVec3) {
+        let mut affected = vec![*position];
+        affected.extend(
+            voxel_directions::ALL
+                .iter()
+                .map(|direction| *position + direction.as_vec()),
+        );
+
+        let mut chunk_positions: Vec<IVec3> = affected.iter().map(Self::chunk_at).collect();
+        chunk_positions.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+        chunk_positions.dedup();
+        for chunk_pos in chunk_positions {
+            let chunk = match self.chunks.get(&chunk_pos) {
+                Some(chunk) => chunk.clone(),
+                None => continue,
+            };
+            if let Some(mut chunk_mesh) = self.chunk_meshes.get_mut(&chunk_pos) {
+                let was_empty = chunk_mesh.empty_meshes();
+                let neighborhood = ChunkNeighborhood::new(chunk, &self.chunks);
+                chunk_mesh.remesh_voxels(&neighborhood, &affected);
+                // Empty meshes have no entity, so one has to be spawned or despawned
+                if chunk_mesh.empty_meshes() != was_empty {
+                    if let Some(sender) = &self.mesh_sender {
+                        let _ = sender.send((chunk_pos, chunk_mesh.meshes()));
+                    }
+                }
+            } else if !chunk.is_empty {
+                self.pending_work.add();
+                self.generation_channel.push(chunk_pos, ());
+            }
+        }
+    }
+
+    pub fn generation_pre_processor(
+        chunks: ChunkMap,
+        pos_receiver: Receiver<IVec3>,
+        initialization_queue: Arc<DashSet<IVec3>>,
+        initialization_sender: InitializationQueue,
+        pos_sender: Arc<ChunkQueue<()>>,
+        shutdown: Receiver<()>,
+        pending_work: Arc<PendingWork>,
+        utilization: Arc<ProcessorUtilization>,
+        config: WorldConfig,
+    ) {
+        println!("Started generation pre-processor");
+        // store a list of chunk positions
+        let mut chunks_to_generate = VecDeque::new();
+        let mut retry_interval = NEIGHBOUR_RETRY_INTERVAL;
+        while !shutdown.is_disconnected() {
+            let mut chunk_positions = pos_receiver.try_iter().collect::<Vec<_>>();
+            let mut timed_out = false;
+            if chunk_positions.is_empty() {
+                // Nothing new, wait for something. Chunks waiting on their neighbours are retried after a pause
+                let selector = flume::Selector::new()
+                    .recv(&pos_receiver, |chunk_pos| chunk_pos.ok())
+                    .recv(&shutdown, |_| None);
+                let received = if chunks_to_generate.is_empty() {
+                    Ok(selector.wait())
+                } else {
+                    selector.wait_timeout(retry_interval)
+                };
+                match received {
+                    Ok(Some(chunk_pos)) => chunk_positions.push(chunk_pos),
+                    Ok(None) => break,          // Shut down
+                    Err(_) => timed_out = true, // Retry the waiting chunks
+                }
+            }
+            let _busy = utilization.pre_processor.start();
+            chunk_positions.extend(chunks_to_generate.iter());
+            chunks_to_generate.clear();
+            let mut progressed = false;
+            for chunk_pos in chunk_positions {
+                if !initialization_queue.contains(&chunk_pos) {
+                    pending_work.finish();
+                    progressed = true;
+                    continue; // Unloaded, don't pull its neighbours back in
+                }
+
+                // get a list of neighbours
+                let mut failed = false;
+                for direction in voxel_directions::ALL {
+                    let neighbour_pos = chunk_pos + direction.as_vec();
+                    if !config.chunk_in_bounds(&neighbour_pos) {
+                        continue; // Nothing exists past the top or bottom of the world
+                    }
+                    if !chunks.contains_key(&neighbour_pos) {
+                        failed = true;
+                        VoxelScene::request_initialize_chunk(
+                            initialization_queue.clone(),
+                            initialization_sender.clone(),
+                            &pending_work,
+                            (neighbour_pos, None),
+                        );
+                    }
+                }
+
+                // if all neighbours are initialized, schedule the chunk to be generated
+                if !failed && chunks.contains_key(&chunk_pos) {
+                    if !chunks.get(&chunk_pos).unwrap().is_empty {
+                        pending_work.add();
+                        pos_sender.push(chunk_pos, ());
+                    }
+                    pending_work.finish();
+                    progressed = true;
+                } else {
+                    chunks_to_generate.push_front(chunk_pos);
+                }
+            }
+            // Backs off while only chunks stuck on their neighbours are left, so waiting doesn't burn a core
+            if progressed {
+                retry_interval = NEIGHBOUR_RETRY_INTERVAL;
+            } else if timed_out {
+                retry_interval = (retry_interval * 2).min(MAX_NEIGHBOUR_RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+impl Default for VoxelScene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unloaded chunks were saved already, they aren't marked
+fn mark_dirty(chunks: &ChunkMap, dirty_chunks: &DashSet<IVec3>, chunk_pos: IVec3) {
+    if chunks.contains_key(&chunk_pos) {
+        dirty_chunks.insert(chunk_pos);
+    }
+}
+
+fn save_dirty(
+    chunks: &ChunkMap,
+    dirty_chunks: &DashSet<IVec3>,
+    storage: &Option<Arc<ChunkStorage>>,
+) -> usize {
+    let storage = match storage {
+        Some(storage) => storage,
+        None => return 0,
+    };
+    let positions: Vec<IVec3> = dirty_chunks.iter().map(|chunk_pos| *chunk_pos).collect();
+    let mut saved = 0;
+    for chunk_pos in positions {
+        dirty_chunks.remove(&chunk_pos);
+        // Cloned so the chunk isn't locked while it's written, unloaded chunks were already saved
+        let chunk = match chunks.get(&chunk_pos) {
+            Some(chunk) => chunk.clone(),
+            None => continue,
+        };
+        match storage.save_chunk(chunk_pos, &chunk) {
+            Ok(()) => saved += 1,
+            Err(e) => {
+                println!("[INFO] Failed to save chunk {}: {}", chunk_pos, e);
+                dirty_chunks.insert(chunk_pos); // Retried on the next save
+            }
+        }
+    }
+    saved
+}
+
+#[derive(Clone)]
+pub struct VoxelChunk {
+    pub position: IVec3,
+    pub is_empty: bool,
+    voxels: VoxelStorage<VoxelData>,
+    densities: VoxelStorage<i8>,
+    light: VoxelStorage<u8>, // Packed light levels, recomputed when the chunk is loaded rather than saved
+}
+
+impl VoxelChunk {
+    pub fn new(position: IVec3) -> Self {
+        Self {
+            position,
+            is_empty: true,
+            voxels: VoxelStorage::new(VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: 0,
+            }),
+            densities: VoxelStorage::new(i8::MIN),
+            light: VoxelStorage::new(0),
+        }
+    }
+
+    pub fn block_light_at(&self, position: &UVec3) -> u8 {
+        *self.light.get(pos_to_index(position) as usize) & BLOCK_LIGHT_MASK
+    }
+
+    // Leaves the other light stored alongside the block light untouched
+    pub fn set_block_light(&mut self, position: &UVec3, level: u8) {
+        let index = pos_to_index(position) as usize;
+        let packed = *self.light.get(index);
+        self.light.set(
+            index,
+            (packed & !BLOCK_LIGHT_MASK) | (level & BLOCK_LIGHT_MASK),
+        );
+    }
+
+    pub fn block_light_scenespace_at(&self, position: &IVec3) -> Option<u8> {
+        let localized_pos = *position - self.scenespace_pos();
+        if localized_pos.cmplt(IVec3::ZERO).any()
+            || localized_pos.cmpge(IVec3::splat(CHUNK_SIZE as i32)).any()
+        {
+            return None;
+        }
+        Some(self.block_light_at(&localized_pos.as_uvec3()))
+    }
+
+    pub fn density_at(&self, position: &UVec3) -> f32 {
+        dequantize_density(*self.densities.get(pos_to_index(position) as usize))
+    }
+
+    pub fn set_density(&mut self, position: &UVec3, density: f32) {
+        self.densities
+            .set(pos_to_index(position) as usize, quantize_density(density));
+    }
+
+    pub fn density_scenespace_at(&self, position: &IVec3) -> Option<f32> {
+        let localized_pos = *position - self.scenespace_pos();
+        if localized_pos.cmplt(IVec3::ZERO).any()
+            || localized_pos.cmpge(IVec3::splat(CHUNK_SIZE as i32)).any()
+        {
+            return None;
+        }
+        Some(self.density_at(&localized_pos.as_uvec3()))
+    }
+
+    pub fn voxel_scenespace_at_mut(&mut self, position: &IVec3) -> Option<&mut VoxelData> {
+        let localized_pos = *position - (self.position * CHUNK_SIZE as i32);
+        if localized_pos.x >= CHUNK_SIZE as i32
+            || localized_pos.y >= CHUNK_SIZE as i32
             || localized_pos.z >= CHUNK_SIZE as i32
             || localized_pos.x < 0
             || localized_pos.y < 0
@@ -288,51 +1435,292 @@ impl VoxelChunk {
         Some(self.voxel_at(&localized_pos.as_uvec3()))
     }
 
+    // Collects the solid voxels between the two scenespace positions, both inclusive, that are within this chunk
+    pub fn solid_voxels_in(&self, first: IVec3, last: IVec3, solid: &mut Vec<IVec3>) {
+        if self.is_empty {
+            return;
+        }
+        let origin = self.scenespace_pos();
+        let first = (first - origin).max(IVec3::ZERO);
+        let last = (last - origin).min(IVec3::splat(CHUNK_SIZE as i32 - 1));
+        // Neighbouring voxels mostly share their id, which saves going to the registry for every one of them. Air is
+        // never solid
+        let mut last_id = (0, false);
+        for x in first.x..=last.x {
+            for y in first.y..=last.y {
+                for z in first.z..=last.z {
+                    let local = IVec3::new(x, y, z);
+                    let id = self.voxel_at(&local.as_uvec3()).id;
+                    if id != last_id.0 {
+                        last_id = (id, voxel_registry::is_solid(id));
+                    }
+                    if last_id.1 {
+                        solid.push(origin + local);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn voxel_at(&self, position: &UVec3) -> &VoxelData {
-        &self.voxels.get(pos_to_index(&position) as usize).unwrap()
+        self.voxels.get(pos_to_index(position) as usize)
     }
 
+    // Gives a uniform chunk its own copy of every voxel, set_voxel_at only does when the voxel changes
     pub fn voxel_at_mut(&mut self, position: &UVec3) -> &mut VoxelData {
-        self.voxels
-            .get_mut(pos_to_index(&position) as usize)
-            .unwrap()
+        self.voxels.get_mut(pos_to_index(position) as usize)
+    }
+
+    pub fn set_voxel_at(&mut self, position: &UVec3, voxel: VoxelData) {
+        self.voxels.set(pos_to_index(position) as usize, voxel);
+    }
+
+    // An edit after generation, the density follows whether the voxel is solid. Returns the voxel it replaced
+    pub fn write_voxel(&mut self, position: &UVec3, voxel: VoxelData) -> VoxelData {
+        let old = *self.voxel_at(position);
+        self.set_voxel_at(position, voxel);
+        self.set_density(position, if voxel.id != 0 { 1.0 } else { -1.0 });
+        if voxel.id != 0 {
+            self.is_empty = false;
+        }
+        old
+    }
+
+    // Goes back to storing a single voxel, density and light where the whole chunk is the same, done after generation
+    pub fn compact(&mut self) {
+        self.voxels.compact();
+        self.densities.compact();
+        self.light.compact();
+    }
+
+    // Memory taken by the chunk, including its voxels, densities and light
+    pub fn bytes_used(&self) -> usize {
+        std::mem::size_of::<Self>()
+            - std::mem::size_of::<VoxelStorage<VoxelData>>()
+            - std::mem::size_of::<VoxelStorage<i8>>()
+            - std::mem::size_of::<VoxelStorage<u8>>()
+            + self.voxels.bytes_used()
+            + self.densities.bytes_used()
+            + self.light.bytes_used()
     }
 
     pub fn set_voxel_shape(&mut self, position: &UVec3, shape: VoxelShape) {
         self.voxel_at_mut(position).shape = shape
     }
 
-    pub fn generate_mesh(&self, scene_chunks: ChunkMap) -> Mesh {
-        let mut vertices = vec![];
-        let mut indices = vec![];
+    // One mesh per material the chunk's voxels are drawn with, keyed by the material's name.
+    // The meshing functions take the neighborhood around this chunk for the voxels across its borders
+    pub fn generate_meshes(
+        &self,
+        neighborhood: &ChunkNeighborhood,
+        strategy: MeshingStrategy,
+    ) -> BTreeMap<String, Mesh> {
+        self.generate_buckets(neighborhood, strategy)
+            .into_iter()
+            .map(|(material, mut bucket)| {
+                let mut mesh = Mesh::new();
+                mesh.append_vertices(&mut bucket.vertices);
+                mesh.append_indices(&mut bucket.indices);
+                (material, mesh)
+            })
+            .collect()
+    }
+
+    pub fn generate_buckets(
+        &self,
+        neighborhood: &ChunkNeighborhood,
+        strategy: MeshingStrategy,
+    ) -> MeshBuckets {
+        match strategy {
+            MeshingStrategy::Greedy => self.generate_buckets_greedy(neighborhood),
+            MeshingStrategy::Naive => self.generate_buckets_naive(neighborhood),
+        }
+    }
+
+    fn generate_buckets_naive(&self, neighborhood: &ChunkNeighborhood) -> MeshBuckets {
+        let mut buckets = MeshBuckets::new();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    self.generate_voxel_faces(neighborhood, &UVec3::new(x, y, z), &mut buckets);
+                }
+            }
+        }
+        buckets
+    }
+
+    // The faces go into the bucket of the material the voxel's profile names
+    pub fn generate_voxel_faces(
+        &self,
+        neighborhood: &ChunkNeighborhood,
+        position: &UVec3,
+        buckets: &mut MeshBuckets,
+    ) {
+        let voxel = self.voxel_at(position);
+        if voxel.id == 0 {
+            return;
+        }
+        append_faces(buckets, voxel.id, |vertices, indices| {
+            generate_faces(voxel, neighborhood, self, position, vertices, indices)
+        });
+    }
+
+    fn generate_buckets_greedy(&self, neighborhood: &ChunkNeighborhood) -> MeshBuckets {
+        let mut buckets = MeshBuckets::new();
 
+        // Shaped voxels can't be merged, transparent ones are never merged
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
                     let pos = UVec3::new(x, y, z);
                     let voxel = self.voxel_at(&pos);
-                    if voxel.id != 0 {
-                        // Voxel is not air
-                        let scene_chunks_clone = Arc::clone(&scene_chunks);
-                        generate_faces(
-                            voxel,
-                            scene_chunks_clone,
-                            self,
-                            &pos,
-                            &mut vertices,
-                            &mut indices,
-                        );
+                    if !is_full_cube(voxel.shape) || voxel_registry::is_transparent(voxel.id) {
+                        self.generate_voxel_faces(neighborhood, &pos, &mut buckets);
                     }
                 }
             }
         }
 
-        let mut mesh = Mesh::new();
+        let size = CHUNK_SIZE as usize;
+        for direction in voxel_directions::ALL {
+            let normal = direction.as_vec();
+            let axis = (0..3).find(|axis| normal[*axis] != 0).unwrap();
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
 
-        mesh.append_vertices(&mut vertices);
-        mesh.append_indices(&mut indices);
+            for slice in 0..CHUNK_SIZE as i32 {
+                // Id, variant, light and corner occlusion of every full cube in the slice whose face is visible in
+                // this direction
+                let mut mask: Vec<Option<(u16, u8, u8, u8)>> = vec![None; size * size];
+                for u in 0..size {
+                    for v in 0..size {
+                        let mut local_pos = IVec3::ZERO;
+                        local_pos[axis] = slice;
+                        local_pos[u_axis] = u as i32;
+                        local_pos[v_axis] = v as i32;
+                        let voxel = self.voxel_at(&local_pos.as_uvec3());
+                        let global_pos = local_pos + self.scenespace_pos();
+                        if voxel.id != 0
+                            && is_full_cube(voxel.shape)
+                            && !voxel_registry::is_transparent(voxel.id)
+                            && face_visible(voxel, neighborhood, global_pos, direction)
+                        {
+                            let light = face_light(neighborhood, global_pos, normal);
+                            let ao = face_occlusion(neighborhood, global_pos, direction);
+                            mask[u * size + v] = Some((voxel.id, voxel.get_variant(), light, ao));
+                        }
+                    }
+                }
+
+                // Grow each rectangle along u first, then along v for as long as every row matches. Only faces
+                // whose corners are all equally occluded merge, the others keep their own quad with its gradient
+                for u in 0..size {
+                    for v in 0..size {
+                        let face = match mask[u * size + v] {
+                            Some(face) => face,
+                            None => continue,
+                        };
+                        let mergeable = uniform_occlusion(face.3);
+                        let mut width = 1;
+                        while mergeable
+                            && u + width < size
+                            && mask[(u + width) * size + v] == Some(face)
+                        {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        while mergeable
+                            && v + height < size
+                            && (u..u + width).all(|u| mask[u * size + v + height] == Some(face))
+                        {
+                            height += 1;
+                        }
+                        for u in u..u + width {
+                            for v in v..v + height {
+                                mask[u * size + v] = None;
+                            }
+                        }
+
+                        let mut start = IVec3::ZERO;
+                        start[axis] = slice;
+                        start[u_axis] = u as i32;
+                        start[v_axis] = v as i32;
+                        let mut extent = IVec3::ONE;
+                        extent[u_axis] = width as i32;
+                        extent[v_axis] = height as i32;
+                        append_faces(&mut buckets, face.0, |vertices, indices| {
+                            append_merged_face(face, direction, start, extent, vertices, indices)
+                        });
+                    }
+                }
+            }
+        }
+        buckets
+    }
+
+    pub fn generate_mesh_smooth(&self, neighborhood: &ChunkNeighborhood) -> Mesh {
+        let density_at = |position: IVec3| {
+            neighborhood.density_at(position).unwrap_or(-1.0) // Missing chunks are treated as air
+        };
+        let surface_at = |position: IVec3, normal: Vec3| {
+            neighborhood
+                .voxel_at(position)
+                .and_then(|voxel| {
+                    voxel_registry::get_voxel_by_id(voxel.id)
+                        .map(|profile| (profile, voxel.get_variant()))
+                })
+                .map_or(([1.0; 4], 0), |(profile, variant)| {
+                    (
+                        profile.variant_color(variant).into(),
+                        profile.variant_texture_layer(variant, normal),
+                    )
+                })
+        };
+
+        let light_at = |position: IVec3| light_value(neighborhood.block_light_at(position));
+
+        marching_cubes::generate_mesh(
+            self.scenespace_pos(),
+            IVec3::splat(CHUNK_SIZE as i32),
+            density_at,
+            surface_at,
+            light_at,
+        )
+    }
+
+    // Every voxel is stored as shape, state, id (shape and id little endian) and density
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CHUNK_VOLUME * VOXEL_BYTES);
+        for (voxel, density) in self.voxels.iter().zip(self.densities.iter()) {
+            let (shape, id) = (voxel.shape.data, voxel.id);
+            bytes.extend_from_slice(&shape.to_le_bytes());
+            bytes.push(voxel.state);
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.push(density as u8);
+        }
+        bytes
+    }
 
-        mesh
+    pub fn from_bytes(position: IVec3, bytes: &[u8]) -> Option<Self> {
+        let mut chunk = Self::new(position);
+        if bytes.len() != CHUNK_VOLUME * VOXEL_BYTES {
+            return None;
+        }
+        for (index, data) in bytes.chunks_exact(VOXEL_BYTES).enumerate() {
+            let voxel = VoxelData {
+                shape: VoxelShape {
+                    data: u16::from_le_bytes([data[0], data[1]]),
+                },
+                state: data[2],
+                id: u16::from_le_bytes([data[3], data[4]]),
+            };
+            if voxel.id != 0 {
+                chunk.is_empty = false;
+            }
+            chunk.voxels.set(index, voxel);
+            chunk.densities.set(index, data[5] as i8);
+        }
+        chunk.compact();
+        Some(chunk)
     }
 
     pub fn scenespace_pos(&self) -> IVec3 {
@@ -340,6 +1728,273 @@ impl VoxelChunk {
     }
 }
 
+pub fn quantize_density(density: f32) -> i8 {
+    let quantized =
+        (density.clamp(-DENSITY_RANGE, DENSITY_RANGE) / DENSITY_RANGE * 127.0).round() as i8;
+    // Solid voxels must stay solid after rounding
+    if density > 0.0 {
+        quantized.max(1)
+    } else {
+        quantized
+    }
+}
+
+pub fn dequantize_density(density: i8) -> f32 {
+    (density.max(-127) as f32) / 127.0 * DENSITY_RANGE
+}
+
+// Where the terrain density comes from, the biome formulas or a noise field
+trait DensitySource {
+    fn density(&self, biomes: &[(Arc<BiomeProfile>, f32)], context: &SampleContext) -> f32;
+}
+
+// Density is blended between the biomes of a column
+struct BiomeDensity;
+
+impl DensitySource for BiomeDensity {
+    fn density(&self, biomes: &[(Arc<BiomeProfile>, f32)], context: &SampleContext) -> f32 {
+        biomes
+            .iter()
+            .map(|(biome, weight)| biome.sample_density(context) * weight)
+            .sum()
+    }
+}
+
+// Density fields of some of the chunks in a chunk column, positions in other chunks are air
+struct NoiseDensity {
+    fields: HashMap<i32, Vec<f32>>, // Keyed by chunk y
+}
+
+impl NoiseDensity {
+    fn generate(noise: &dyn NoiseField, column: IVec2, layers: impl Iterator<Item = i32>) -> Self {
+        let layers: Vec<i32> = layers.collect();
+        let chunk_positions: Vec<IVec3> = layers
+            .iter()
+            .map(|y| IVec3::new(column.x, *y, column.y))
+            .collect();
+        let fields = layers
+            .into_iter()
+            .zip(noise.build_noise_batch(&chunk_positions))
+            .collect();
+        Self { fields }
+    }
+}
+
+impl DensitySource for NoiseDensity {
+    fn density(&self, _biomes: &[(Arc<BiomeProfile>, f32)], context: &SampleContext) -> f32 {
+        let chunk_pos = VoxelScene::chunk_at(&context.position);
+        let local = (context.position - chunk_pos * CHUNK_SIZE as i32).as_uvec3();
+        self.fields
+            .get(&chunk_pos.y)
+            .map_or(0.0, |field| field[pos_to_index(&local) as usize])
+    }
+}
+
+// Positions outside of the world are air
+fn sample_density(
+    source: &impl DensitySource,
+    biomes: &[(Arc<BiomeProfile>, f32)],
+    context: &SampleContext,
+    config: &WorldConfig,
+) -> f32 {
+    if !config.height_in_bounds(context.position.y) {
+        return 0.0;
+    }
+    source.density(biomes, context)
+}
+
+// Scans every column of the chunk column down from the top of the world until it hits a solid voxel
+fn surface_heights(
+    column: IVec2,
+    biome_map: &BiomeMap,
+    config: &WorldConfig,
+    source: &impl DensitySource,
+) -> ColumnHeights {
+    let mut heights = [None; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+    let origin = IVec3::new(column.x, 0, column.y) * CHUNK_SIZE as i32;
+    let mut context = SampleContext::new(config.seed, origin);
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            context.position.x = origin.x + x as i32;
+            context.position.z = origin.z + z as i32;
+            (context.moisture, context.temperature) =
+                biome_map.climate_at(context.position.x, context.position.z);
+            let biomes = biome_map.weights_for_climate(context.moisture, context.temperature);
+            heights[(x * CHUNK_SIZE + z) as usize] =
+                (config.min_height..config.max_height).rev().find(|y| {
+                    context.position.y = *y;
+                    sample_density(source, &biomes, &context, config) > 0.0
+                });
+        }
+    }
+    heights
+}
+
+// Solid voxels stacked on top of each other from the bottom of the chunk, None if the chunk is solid all the way up
+fn solid_run_from_bottom(chunk: &VoxelChunk, x: u32, z: u32) -> Option<i32> {
+    (0..CHUNK_SIZE)
+        .find(|y| chunk.voxel_at(&UVec3::new(x, *y, z)).id == 0)
+        .map(|y| y as i32)
+}
+
+// The chunks whose noise a chunk needs. The surface heights need the whole column, once they're known the chunk and
+// the one above are enough
+fn noise_layers(chunk_pos: IVec3, surfaces: &SurfaceCache, config: &WorldConfig) -> Vec<IVec3> {
+    let column = IVec2::new(chunk_pos.x, chunk_pos.z);
+    let layers = if surfaces.contains(column) {
+        chunk_pos.y..=chunk_pos.y + 1
+    } else {
+        config.min_chunk_y()..=config.max_chunk_y()
+    };
+    layers.map(|y| IVec3::new(column.x, y, column.y)).collect()
+}
+
+// Uses the noise for the density when there is some, otherwise the biomes
+fn generate_chunk(
+    chunk_pos: IVec3,
+    biome_map: &BiomeMap,
+    config: &WorldConfig,
+    surfaces: &SurfaceCache,
+    chunk_above: Option<&VoxelChunk>,
+    noise: Option<&dyn NoiseField>,
+) -> VoxelChunk {
+    let mut chunk = VoxelChunk::new(chunk_pos);
+    match noise {
+        Some(noise) => {
+            let column = IVec2::new(chunk_pos.x, chunk_pos.z);
+            let layers = noise_layers(chunk_pos, surfaces, config);
+            let density = NoiseDensity::generate(noise, column, layers.iter().map(|layer| layer.y));
+            fill_chunk_with(
+                &mut chunk,
+                biome_map,
+                config,
+                surfaces,
+                chunk_above,
+                &density,
+            );
+        }
+        None => fill_chunk(&mut chunk, biome_map, config, surfaces, chunk_above),
+    }
+    // Chunks of air or deep underground end up the same everywhere
+    chunk.compact();
+    chunk
+}
+
+// Places the structures growing out of a freshly generated chunk, which runs after the terrain fill and before meshing.
+// Returns the loaded neighbours that were written into
+fn grow_structures(
+    chunk: &mut VoxelChunk,
+    biome_map: &BiomeMap,
+    config: &WorldConfig,
+    surfaces: &SurfaceCache,
+    chunks: &ChunkMap,
+    structure_writes: &StructureWrites,
+) -> Vec<IVec3> {
+    // Cached by the fill already, whichever density source it used
+    let column = IVec2::new(chunk.position.x, chunk.position.z);
+    let heights = surfaces.get_or_compute(column, || {
+        surface_heights(column, biome_map, config, &BiomeDensity)
+    });
+    let writes = plan_structures(chunk, biome_map, &heights, config.seed);
+    structure_writes.place(chunk, writes, chunks, config)
+}
+
+fn fill_chunk(
+    chunk: &mut VoxelChunk,
+    biome_map: &BiomeMap,
+    config: &WorldConfig,
+    surfaces: &SurfaceCache,
+    chunk_above: Option<&VoxelChunk>,
+) {
+    fill_chunk_with(
+        chunk,
+        biome_map,
+        config,
+        surfaces,
+        chunk_above,
+        &BiomeDensity,
+    );
+}
+
+// Samples the density for every voxel of the chunk. Columns are walked top down with a count of the solid voxels
+// above the current one, which is its depth below the surface. The count starts from the chunk above when it's
+// loaded, otherwise it's estimated from the surface height as if the column was solid down to the chunk.
+// Either way a voxel right below air always has depth 0, so every generation order puts the surface in the same place.
+// The voxels themselves come from the dominant biome of the column
+fn fill_chunk_with(
+    chunk: &mut VoxelChunk,
+    biome_map: &BiomeMap,
+    config: &WorldConfig,
+    surfaces: &SurfaceCache,
+    chunk_above: Option<&VoxelChunk>,
+    source: &impl DensitySource,
+) {
+    let chunk_pos_scenespace = chunk.scenespace_pos();
+    let top = chunk_pos_scenespace.y + CHUNK_SIZE as i32 - 1;
+    let column = IVec2::new(chunk.position.x, chunk.position.z);
+    let heights = surfaces.get_or_compute(column, || {
+        surface_heights(column, biome_map, config, source)
+    });
+    let mut context = SampleContext::new(config.seed, chunk_pos_scenespace);
+
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            // Everything that doesn't depend on the height is set up once per column
+            context.position.x = chunk_pos_scenespace.x + x as i32;
+            context.position.z = chunk_pos_scenespace.z + z as i32;
+            (context.moisture, context.temperature) =
+                biome_map.climate_at(context.position.x, context.position.z);
+            let biomes = biome_map.weights_for_climate(context.moisture, context.temperature);
+
+            let estimate =
+                heights[(x * CHUNK_SIZE + z) as usize].map_or(0, |surface| (surface - top).max(0));
+            let mut depth = match chunk_above {
+                Some(chunk_above) => solid_run_from_bottom(chunk_above, x, z)
+                    .unwrap_or_else(|| estimate.max(CHUNK_SIZE as i32)),
+                None => {
+                    context.position.y = top + 1;
+                    if sample_density(source, &biomes, &context, config) > 0.0 {
+                        estimate.max(1)
+                    } else {
+                        0
+                    }
+                }
+            };
+
+            for y in (chunk_pos_scenespace.y..=top).rev() {
+                if !config.height_in_bounds(y) {
+                    depth = 0; // Chunks on the edge of the world can reach past its bounds
+                    continue;
+                }
+                context.position.y = y;
+                let density = sample_density(source, &biomes, &context, config);
+                let index = pos_to_index(&UVec3::new(x, (y - chunk_pos_scenespace.y) as u32, z));
+                chunk
+                    .densities
+                    .set(index as usize, quantize_density(density));
+                if density > 0.0 {
+                    context.density = density;
+                    context.depth = depth as f32;
+                    chunk.is_empty = false;
+                    chunk
+                        .voxels
+                        .set(index as usize, biomes[0].0.sample_voxel(&context));
+                    depth += 1;
+                } else {
+                    depth = 0;
+                }
+            }
+        }
+    }
+}
+
+// Every position in the box, both corners inclusive. Empty when min is past max on any axis
+fn region_positions(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
 fn index_to_pos(index: u32) -> UVec3 {
     let x = index / (CHUNK_SIZE * CHUNK_SIZE);
     let y = index % (CHUNK_SIZE * CHUNK_SIZE) / CHUNK_SIZE;
@@ -356,9 +2011,221 @@ pub fn pos_to_index_inverse(pos: &UVec3) -> u32 {
 }
 
 #[inline(always)]
+fn is_full_cube(shape: VoxelShape) -> bool {
+    std::ptr::eq(get_voxel_mesh(shape), get_voxel_mesh(voxel_shape::CUBE))
+}
+
+// Generates faces into the bucket of the voxel's material, and copies them into the collision bucket of its friction
+// when the voxel is solid. Unknown voxels go with the default material and are solid
+fn append_faces(
+    buckets: &mut MeshBuckets,
+    id: u16,
+    generate: impl FnOnce(&mut Vec<Vertex>, &mut Vec<u32>),
+) {
+    let profile = voxel_registry::get_voxel_by_id(id);
+    let (material, solid, friction) = profile.as_ref().map_or(
+        (
+            voxel_registry::DEFAULT_MATERIAL,
+            true,
+            voxel_registry::DEFAULT_FRICTION,
+        ),
+        |profile| (profile.material.as_str(), profile.solid, profile.friction),
+    );
+    let bucket = buckets.entry(material.to_string()).or_default();
+    let (vertex_start, index_start) = (bucket.vertices.len(), bucket.indices.len());
+    generate(&mut bucket.vertices, &mut bucket.indices);
+    if !solid || bucket.indices.len() == index_start {
+        return;
+    }
+
+    let vertices = bucket.vertices[vertex_start..].to_vec();
+    let indices: Vec<u32> = bucket.indices[index_start..].to_vec();
+    let collision = buckets.entry(collision_mesh_name(friction)).or_default();
+    let index_offset = collision.vertices.len() as u32;
+    collision.vertices.extend(vertices);
+    collision.indices.extend(
+        indices
+            .iter()
+            .map(|index| index - vertex_start as u32 + index_offset),
+    );
+}
+
+// The face of a full cube pointing in the direction
+fn cube_face(direction: VoxelDirection) -> &'static Mesh {
+    let cube_mesh = get_voxel_mesh(voxel_shape::CUBE);
+    match direction.data {
+        0 => &cube_mesh.north,
+        1 => &cube_mesh.south,
+        2 => &cube_mesh.east,
+        3 => &cube_mesh.west,
+        4 => &cube_mesh.top,
+        _ => &cube_mesh.bottom,
+    }
+}
+
+// The occlusion level, 0 to 3, of each corner of a full cube's face, two bits per corner in the order of the cube
+// face's vertices
+fn face_occlusion(
+    neighborhood: &ChunkNeighborhood,
+    global_position: IVec3,
+    direction: VoxelDirection,
+) -> u8 {
+    let occupied = |offset: IVec3| {
+        neighborhood
+            .voxel_at(global_position + offset)
+            .map_or(false, |voxel| {
+                voxel.id != 0 && !voxel_registry::is_transparent(voxel.id)
+            })
+    };
+    let normal = direction.as_vec().as_vec3();
+    cube_face(direction)
+        .get_vertices()
+        .iter()
+        .enumerate()
+        .fold(0, |packed, (corner, vertex)| {
+            let ao = corner_occlusion(&occupied, Vec3::from(vertex.position), normal);
+            packed | ((ao * 3.0).round() as u8) << (corner * 2)
+        })
+}
+
+fn uniform_occlusion(packed: u8) -> bool {
+    packed == (packed & 0b11) * 0b0101_0101
+}
+
+// Stretches the cube face for the direction over a box of voxels starting at the given chunk local position.
+// UVs run over the whole box so textures tile instead of stretching. Boxes of more than one voxel are occluded
+// evenly, single faces keep the occlusion of each of their corners
+fn append_merged_face(
+    (id, variant, light, ao): (u16, u8, u8, u8),
+    direction: VoxelDirection,
+    start: IVec3,
+    extent: IVec3,
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+) {
+    let face_mesh = cube_face(direction);
+    let face_vertices = face_mesh.get_vertices();
+    let profile = voxel_registry::get_voxel_by_id(id).unwrap();
+    let color = profile.variant_color(variant).into();
+    let normal = direction.as_vec().as_vec3();
+    let texture_layer = profile.variant_texture_layer(variant, normal);
+
+    let corner_ao = |corner: usize| ((ao >> (corner * 2)) & 0b11) as f32 / 3.0;
+    let index_offset = vertices.len() as u32;
+    // Split along the diagonal between the brighter corners, like the faces of single voxels
+    if corner_ao(0) + corner_ao(3) > corner_ao(1) + corner_ao(2) {
+        indices.extend([0, 2, 3, 0, 3, 1].iter().map(|index| index + index_offset));
+    } else {
+        indices.extend(
+            face_mesh
+                .get_indices()
+                .iter()
+                .map(|index| index + index_offset),
+        );
+    }
+    for (corner, vertex) in face_vertices.iter().enumerate() {
+        let mut position = start.as_vec3();
+        for axis in 0..3 {
+            position[axis] += if vertex.position[axis] > 0.0 {
+                extent[axis] as f32 - 0.5
+            } else {
+                -0.5
+            };
+        }
+        vertices.push(Vertex {
+            position: position.into(),
+            color,
+            normal: normal.into(),
+            uv: face_uv(position - start.as_vec3(), normal),
+            tangent: vertex.tangent,
+            texture_layer,
+            ao: corner_ao(corner),
+            light: light_value(light),
+        });
+    }
+}
+
+// Projects a position relative to a voxel's center onto the face pointing along the normal. Neighbouring faces
+// continue each other's UVs and the top of the texture always points up on side faces
+fn face_uv(position: Vec3, normal: Vec3) -> [f32; 2] {
+    let abs = normal.abs();
+    if abs.y >= abs.x && abs.y >= abs.z {
+        [position.x + 0.5, position.z + 0.5]
+    } else if abs.x >= abs.z {
+        [0.5 + position.z * normal.x.signum(), 0.5 - position.y]
+    } else {
+        [0.5 - position.x * normal.z.signum(), 0.5 - position.y]
+    }
+}
+
+// The light of the voxel a face looks out into. Voxels giving off light hold their own level, so their faces
+// are never darker than that
+fn face_light(neighborhood: &ChunkNeighborhood, global_position: IVec3, normal: IVec3) -> u8 {
+    neighborhood
+        .block_light_at(global_position + normal)
+        .max(neighborhood.block_light_at(global_position))
+}
+
+fn light_value(level: u8) -> f32 {
+    level as f32 / MAX_LIGHT as f32
+}
+
+// Ambient occlusion of a face corner from the three voxels touching it in front of the face, from 0 (fully
+// occluded) to 1. The corner is relative to the voxel's center, only corners of faces on the voxel's surface are
+// occluded. Voxels in chunks that aren't loaded don't occlude
+fn corner_occlusion(occupied: impl Fn(IVec3) -> bool, corner: Vec3, normal: Vec3) -> f32 {
+    let abs = normal.abs();
+    let axis = if abs.y >= abs.x && abs.y >= abs.z {
+        1
+    } else if abs.x >= abs.z {
+        0
+    } else {
+        2
+    };
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    let on_surface = |value: f32| (value.abs() - 0.5).abs() < 1e-4;
+    if !on_surface(corner[axis]) || !on_surface(corner[a]) || !on_surface(corner[b]) {
+        return 1.0;
+    }
+
+    let offset = |axis: usize, value: f32| {
+        let mut offset = [0; 3];
+        offset[axis] = value.signum() as i32;
+        IVec3::from(offset)
+    };
+    let front = offset(axis, normal[axis]);
+    let side_a = occupied(front + offset(a, corner[a]));
+    let side_b = occupied(front + offset(b, corner[b]));
+    let diagonal = occupied(front + offset(a, corner[a]) + offset(b, corner[b]));
+    let level = if side_a && side_b {
+        0
+    } else {
+        3 - (side_a as u32 + side_b as u32 + diagonal as u32)
+    };
+    level as f32 / 3.0
+}
+
+// A face is hidden when the neighbouring voxel covers it completely, missing chunks never hide faces.
+// Transparent voxels only hide faces of the same voxel, so water hides the faces between its voxels but not the shore
+fn face_visible(
+    voxel: &VoxelData,
+    neighborhood: &ChunkNeighborhood,
+    global_position: IVec3,
+    direction: VoxelDirection,
+) -> bool {
+    let neighbour = neighborhood.voxel_at(global_position + direction.as_vec());
+    neighbour.map_or(true, |neighbour| {
+        neighbour.id == 0
+            || (neighbour.id != voxel.id && voxel_registry::is_transparent(neighbour.id))
+            || !neighbour
+                .shape
+                .face_contains(direction.flip(), (voxel.shape, direction))
+    })
+}
+
 fn generate_faces(
     voxel: &VoxelData,
-    scene_chunks: ChunkMap,
+    neighborhood: &ChunkNeighborhood,
     chunk: &VoxelChunk,
     position: &UVec3,
     vertices: &mut Vec<Vertex>,
@@ -369,29 +2236,20 @@ fn generate_faces(
     let global_position = position + chunk.scenespace_pos();
 
     let face_check = |direction: VoxelDirection| -> bool {
-        let sample_position = global_position + direction.as_vec();
-        let neighbour = chunk.voxel_scenespace_at(&sample_position).map_or_else(
-            || {
-                scene_chunks
-                    .get(&VoxelScene::chunk_at(&sample_position))
-                    .map_or(None, |chunk| {
-                        chunk.voxel_scenespace_at(&sample_position).cloned()
-                    })
-            },
-            |&voxel| Some(voxel),
-        );
-        neighbour.map_or(true, |neighbour| {
-            neighbour.id == 0
-                || !neighbour
-                    .shape
-                    .face_contains(direction.flip(), (voxel.shape, direction))
-        })
+        face_visible(voxel, neighborhood, global_position, direction)
+    };
+
+    let occupied = |offset: IVec3| {
+        neighborhood
+            .voxel_at(global_position + offset)
+            .map_or(false, |voxel| {
+                voxel.id != 0 && !voxel_registry::is_transparent(voxel.id)
+            })
     };
 
-    let color = voxel_registry::get_voxel_by_id(voxel.id)
-        .unwrap()
-        .color
-        .into();
+    let profile = voxel_registry::get_voxel_by_id(voxel.id).unwrap();
+    let variant = voxel.get_variant();
+    let color = profile.variant_color(variant).into();
     let mut append_mesh = |mesh: &Mesh| {
         let index_offset = vertices.len() as u32;
 
@@ -400,52 +2258,44 @@ fn generate_faces(
         let flip_z = voxel.shape.extract_flip_z();
         let flip_count = (flip_x as u32 + flip_y as u32 + flip_z as u32) % 2;
 
-        if flip_count & 1 == 0 {
-            let mut new_indices = mesh.get_indices().clone();
-            new_indices
-                .iter_mut()
-                .for_each(|index| *index += index_offset);
-            indices.append(&mut new_indices);
-        } else {
-            indices.reserve(mesh.index_count);
-            let mut new_indices = mesh.get_indices().clone();
-            new_indices
-                .iter_mut()
-                .for_each(|index| *index += index_offset);
-            new_indices.reverse();
-            indices.append(&mut new_indices);
-        }
-
         vertices.reserve(mesh.vertex_count);
 
         mesh.get_vertices().iter().for_each(|v| {
             let mut vert = v.clone();
             vert.color = color;
-            if flip_x {
-                vert.position[0] *= -1.0;
-                vert.normal[0] *= -1.0;
-            }
-            if voxel.shape.extract_flip_y() {
-                vert.position[1] *= -1.0;
-                vert.normal[1] *= -1.0;
-            }
-            if voxel.shape.extract_flip_z() {
-                vert.position[2] *= -1.0;
-                vert.normal[2] *= -1.0;
-            }
-            if voxel.shape.extract_rotate_x() {
-                (vert.position[1], vert.position[2]) = (vert.position[2], -vert.position[1]);
-                (vert.normal[1], vert.normal[2]) = (vert.normal[2], -vert.normal[1]);
-            }
-            if voxel.shape.extract_rotate_z() {
-                (vert.position[0], vert.position[1]) = (vert.position[1], -vert.position[0]);
-                (vert.normal[0], vert.normal[1]) = (vert.normal[1], -vert.normal[0]);
-            }
+            vert.position = orient_vector(voxel.shape, vert.position);
+            vert.normal = orient_vector(voxel.shape, vert.normal);
+            let normal = Vec3::from(vert.normal);
+            vert.uv = face_uv(Vec3::from(vert.position), normal);
+            vert.texture_layer = profile.variant_texture_layer(variant, normal);
+            vert.ao = corner_occlusion(&occupied, Vec3::from(vert.position), normal);
+            vert.light = light_value(face_light(
+                neighborhood,
+                global_position,
+                voxel_directions::ALL[voxel_registry::face_index(normal)].as_vec(),
+            ));
             vert.position[0] += f_position.x;
             vert.position[1] += f_position.y;
             vert.position[2] += f_position.z;
             vertices.push(vert);
         });
+
+        // Quads are split along the diagonal between their brighter corners, otherwise the occlusion of a
+        // single corner bleeds across the whole quad unevenly
+        let mut new_indices = mesh.get_indices().clone();
+        if mesh.vertex_count == 4 && new_indices.len() == 6 {
+            let ao = |index: usize| vertices[index_offset as usize + index].ao;
+            if ao(0) + ao(3) > ao(1) + ao(2) {
+                new_indices = vec![0, 2, 3, 0, 3, 1];
+            }
+        }
+        new_indices
+            .iter_mut()
+            .for_each(|index| *index += index_offset);
+        if flip_count & 1 == 1 {
+            new_indices.reverse();
+        }
+        indices.append(&mut new_indices);
     };
 
     let shape_mesh = get_voxel_mesh(voxel.shape);
@@ -485,3 +2335,1027 @@ fn generate_faces(
         append_mesh(&shape_mesh.bottom);
     }
 }
+
+#[cfg(test)]
+mod serialization_tests {
+    use super::*;
+
+    #[test]
+    fn chunk_round_trips_through_bytes() {
+        let mut chunk = VoxelChunk::new(IVec3::new(1, -2, 3));
+        *chunk.voxel_at_mut(&UVec3::new(1, 2, 3)) = VoxelData {
+            shape: voxel_shape::STAIR,
+            state: 7,
+            id: 513,
+        };
+        chunk.set_density(&UVec3::new(1, 2, 3), 1.5);
+
+        let loaded = VoxelChunk::from_bytes(chunk.position, &chunk.to_bytes()).unwrap();
+        let voxel = *loaded.voxel_at(&UVec3::new(1, 2, 3));
+        let (shape, state, id) = (voxel.shape, voxel.state, voxel.id);
+        assert_eq!((shape, state, id), (voxel_shape::STAIR, 7, 513));
+        assert_eq!(
+            loaded.density_at(&UVec3::new(1, 2, 3)),
+            chunk.density_at(&UVec3::new(1, 2, 3))
+        );
+        assert!(!loaded.is_empty);
+        assert!(VoxelChunk::from_bytes(chunk.position, &[0; 3]).is_none());
+    }
+
+    #[test]
+    fn air_chunks_take_constant_memory() {
+        let air = VoxelChunk::new(IVec3::ZERO);
+        let small = air.bytes_used();
+        assert!(small < 100, "an air chunk takes {small} bytes");
+        let loaded = VoxelChunk::from_bytes(air.position, &air.to_bytes()).unwrap();
+        assert_eq!(loaded.bytes_used(), small);
+
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        let stone = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        chunk.set_voxel_at(&UVec3::new(4, 5, 6), stone);
+        assert!(chunk.bytes_used() > CHUNK_VOLUME * std::mem::size_of::<VoxelData>());
+        chunk.set_voxel_at(&UVec3::new(4, 5, 6), *air.voxel_at(&UVec3::ZERO));
+        chunk.compact();
+        assert_eq!(chunk.bytes_used(), small);
+    }
+}
+
+#[cfg(test)]
+mod density_tests {
+    use super::*;
+
+    #[test]
+    fn quantized_density_keeps_sign_and_precision() {
+        for density in [-3.9, -0.5, -0.01, 0.01, 0.5, 3.9] {
+            let restored = dequantize_density(quantize_density(density));
+            assert!((restored - density).abs() <= DENSITY_RANGE / 127.0);
+            assert_eq!(restored > 0.0, density > 0.0);
+        }
+        assert_eq!(dequantize_density(quantize_density(100.0)), DENSITY_RANGE);
+        assert_eq!(dequantize_density(i8::MIN), -DENSITY_RANGE);
+    }
+}
+
+#[cfg(test)]
+mod placement_tests {
+    use dashmap::DashMap;
+
+    use super::*;
+    use crate::voxels::voxel_registry::get_voxel_by_name;
+    use crate::voxels::voxel_shapes::VoxelOrientation;
+
+    #[test]
+    fn upper_slab_meshes_in_upper_half() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let orientation = VoxelOrientation::for_placement(
+            voxel_directions::EAST,
+            Vec3::new(0.5, 0.3, 0.0),
+            Vec3::X,
+        );
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        *chunk.voxel_at_mut(&UVec3::new(8, 8, 8)) = VoxelData {
+            shape: voxel_shape::SLAB.oriented(orientation),
+            state: 0,
+            id: dirt,
+        };
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        chunks.insert(chunk.position, chunk.clone());
+
+        let mesh = chunk
+            .generate_meshes(
+                &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                MeshingStrategy::Naive,
+            )
+            .remove(voxel_registry::DEFAULT_MATERIAL)
+            .unwrap();
+        assert!(mesh.vertex_count > 0);
+        for vertex in mesh.get_vertices() {
+            assert!(vertex.position[1] >= 8.0 && vertex.position[1] <= 8.5);
+        }
+    }
+
+    #[test]
+    fn stair_step_rises_away_from_player() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        for (look_dir, back) in [(Vec3::new(0.2, -0.5, -1.0), -1.0), (Vec3::Z, 1.0)] {
+            let orientation =
+                VoxelOrientation::for_placement(voxel_directions::UP, Vec3::ZERO, look_dir);
+            let mut chunk = VoxelChunk::new(IVec3::ZERO);
+            *chunk.voxel_at_mut(&UVec3::new(8, 8, 8)) = VoxelData {
+                shape: voxel_shape::STAIR.oriented(orientation),
+                state: 0,
+                id: dirt,
+            };
+            let chunks: ChunkMap = Arc::new(DashMap::default());
+            chunks.insert(chunk.position, chunk.clone());
+
+            let mesh = chunk
+                .generate_meshes(
+                    &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                    MeshingStrategy::Naive,
+                )
+                .remove(voxel_registry::DEFAULT_MATERIAL)
+                .unwrap();
+            // The upper step only covers the back half
+            for vertex in mesh.get_vertices() {
+                if vertex.position[1] > 8.0 {
+                    assert!((vertex.position[2] - 8.0) * back >= 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn each_variant_meshes_with_its_own_color() {
+        let grass = get_voxel_by_name("grass".to_string()).unwrap();
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        for variant in 0..4 {
+            let voxel = VoxelData {
+                shape: voxel_shape::CUBE,
+                state: 0,
+                id: grass.id,
+            };
+            chunk.set_voxel_at(
+                &UVec3::new(variant * 2, 4, 4),
+                voxel.with_variant(variant as u8),
+            );
+        }
+        chunk.is_empty = false;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        chunks.insert(chunk.position, chunk.clone());
+
+        // Every voxel is on its own, so its vertices lie within half a voxel of it
+        let mesh = chunk
+            .generate_meshes(
+                &ChunkNeighborhood::new(chunk.clone(), &chunks),
+                MeshingStrategy::Naive,
+            )
+            .remove(voxel_registry::DEFAULT_MATERIAL)
+            .unwrap();
+        let colors: Vec<[f32; 4]> = (0..4)
+            .map(|variant| {
+                let vertex = mesh
+                    .get_vertices()
+                    .iter()
+                    .find(|vertex| (vertex.position[0] - variant as f32 * 2.0).abs() <= 0.5)
+                    .unwrap();
+                assert_eq!(vertex.color, <[f32; 4]>::from(grass.variant_color(variant)));
+                vertex.color
+            })
+            .collect();
+        for a in 0..4 {
+            for b in a + 1..4 {
+                assert_ne!(colors[a], colors[b]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unload_tests {
+    use super::*;
+
+    #[test]
+    fn chunks_outside_radius_are_unloaded_and_cancelled() {
+        let mut scene = VoxelScene::new();
+        let (unload_sender, unload_receiver) = flume::unbounded();
+        scene.unload_sender = Some(unload_sender);
+        for x in 0..4 {
+            scene
+                .chunks
+                .insert(IVec3::new(x, 0, 0), VoxelChunk::new(IVec3::new(x, 0, 0)));
+        }
+        scene.initialization_queue.insert(IVec3::new(5, 0, 0));
+
+        scene.unload_chunks_outside(IVec3::ZERO, 1);
+
+        assert_eq!(scene.loaded_chunk_count(), 2);
+        let mut unloaded: Vec<i32> = unload_receiver.try_iter().map(|pos| pos.x).collect();
+        unloaded.sort_unstable();
+        assert_eq!(unloaded, vec![2, 3]);
+        assert!(!scene.initialization_queue.contains(&IVec3::new(5, 0, 0)));
+        assert!(scene.cancelled_chunks.contains(&IVec3::new(5, 0, 0)));
+    }
+
+    #[test]
+    fn shutdown_returns_once_processors_stopped() {
+        let mut scene = VoxelScene::new();
+        let (mesh_sender, mesh_receiver) = flume::unbounded();
+        let (unload_sender, _unload_receiver) = flume::unbounded();
+        scene.setup_chunk_processors(mesh_sender, unload_sender);
+        scene.initialize_and_generate_chunk(IVec3::new(0, 1, 0));
+
+        scene.shutdown();
+
+        // Every generation processor dropped its mesh sender on the way out
+        while mesh_receiver.recv().is_ok() {}
+        scene.initialize_and_generate_chunk(IVec3::new(4, 1, 0));
+        assert_eq!(scene.stats().pending_initialization, 0);
+    }
+
+    #[test]
+    fn progress_converges_once_idle_is_announced() {
+        let mut scene = VoxelScene::new_with_config(WorldConfig {
+            max_height: 32,
+            ..Default::default()
+        });
+        // Already loaded and empty, it's passed along without being generated and never meshed
+        scene
+            .chunks
+            .insert(IVec3::new(0, 1, 0), VoxelChunk::new(IVec3::new(0, 1, 0)));
+        let idle = scene.subscribe_idle();
+        // Queued before the processors start, so the pipeline can't drain between two requests
+        for x in 0..3 {
+            for y in 0..2 {
+                scene.initialize_and_generate_chunk(IVec3::new(x, y, 0));
+            }
+        }
+        let (mesh_sender, _mesh_receiver) = flume::unbounded();
+        let (unload_sender, _unload_receiver) = flume::unbounded();
+        scene.setup_chunk_processors(mesh_sender, unload_sender);
+
+        // The timeout only keeps a broken pipeline from hanging the test
+        idle.recv_timeout(Duration::from_secs(120))
+            .expect("generation never went idle");
+        let (completed, queued) = scene.generation_progress();
+        assert_eq!(completed, queued);
+        assert!(queued >= 6);
+        assert!(scene.is_idle());
+        scene.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod raycast_tests {
+    use super::*;
+
+    fn scene_with_voxels(positions: &[IVec3]) -> VoxelScene {
+        let scene = VoxelScene::new();
+        for position in positions {
+            let chunk_pos = VoxelScene::chunk_at(position);
+            let mut chunk = scene
+                .chunks
+                .get(&chunk_pos)
+                .map(|chunk| chunk.clone())
+                .unwrap_or_else(|| VoxelChunk::new(chunk_pos));
+            chunk.voxel_scenespace_at_mut(position).unwrap().id = 1;
+            chunk.is_empty = false;
+            scene.chunks.insert(chunk_pos, chunk);
+        }
+        // An empty chunk in between to step through
+        if !scene.chunks.contains_key(&IVec3::ZERO) {
+            scene
+                .chunks
+                .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+        }
+        scene
+    }
+
+    fn assert_hit(
+        hit: Option<VoxelRaycastHit>,
+        position: IVec3,
+        face: VoxelDirection,
+        distance: f32,
+    ) {
+        let hit = hit.expect("ray should hit");
+        assert_eq!(hit.position, position);
+        assert_eq!(hit.face, face);
+        assert!(
+            (hit.distance - distance).abs() < 1e-4,
+            "distance {}",
+            hit.distance
+        );
+    }
+
+    #[test]
+    fn ray_crosses_chunk_borders() {
+        let scene = scene_with_voxels(&[IVec3::new(20, 2, 3), IVec3::new(-3, 2, 3)]);
+        let origin = Vec3::new(0.0, 2.0, 3.0);
+        assert_hit(
+            scene.raycast(origin, Vec3::X, 100.0),
+            IVec3::new(20, 2, 3),
+            voxel_directions::WEST,
+            19.5,
+        );
+        assert_hit(
+            scene.raycast(origin, -Vec3::X, 100.0),
+            IVec3::new(-3, 2, 3),
+            voxel_directions::EAST,
+            2.5,
+        );
+    }
+
+    #[test]
+    fn ray_reports_entered_face() {
+        let scene = scene_with_voxels(&[IVec3::new(5, 2, 3)]);
+        assert_hit(
+            scene.raycast(Vec3::new(5.0, 10.0, 3.0), -Vec3::Y, 100.0),
+            IVec3::new(5, 2, 3),
+            voxel_directions::UP,
+            7.5,
+        );
+        assert_hit(
+            scene.raycast(Vec3::new(5.0, 2.0, -4.0), Vec3::Z, 100.0),
+            IVec3::new(5, 2, 3),
+            voxel_directions::SOUTH,
+            6.5,
+        );
+        // Diagonal ray entering through the top face
+        let hit = scene.raycast(Vec3::new(3.0, 4.2, 3.0), Vec3::new(1.0, -1.0, 0.0), 100.0);
+        assert_hit(
+            hit,
+            IVec3::new(5, 2, 3),
+            voxel_directions::UP,
+            1.7 * 2f32.sqrt(),
+        );
+        let fraction = hit.unwrap().fraction();
+        assert!((fraction - Vec3::new(-0.3, 0.5, 0.0)).length() < 1e-4);
+    }
+
+    #[test]
+    fn ray_stops_at_max_distance() {
+        let scene = scene_with_voxels(&[IVec3::new(5, 2, 3)]);
+        assert!(scene
+            .raycast(Vec3::new(0.0, 2.0, 3.0), Vec3::X, 4.0)
+            .is_none());
+        assert!(scene
+            .raycast(Vec3::new(0.0, 2.0, 3.0), Vec3::Y, 100.0)
+            .is_none());
+    }
+}
+
+#[cfg(test)]
+mod region_tests {
+    use super::*;
+    use crate::{time::fastest_of, voxels::voxel_registry::get_voxel_by_name};
+
+    fn voxel(name: &str) -> VoxelData {
+        VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: get_voxel_by_name(name.to_string()).unwrap().id,
+        }
+    }
+
+    fn scene_with_chunks(chunk_positions: &[IVec3], voxels: &[(IVec3, VoxelData)]) -> VoxelScene {
+        let scene = VoxelScene::new();
+        for chunk_pos in chunk_positions {
+            scene.chunks.insert(*chunk_pos, VoxelChunk::new(*chunk_pos));
+        }
+        for (position, voxel) in voxels {
+            let mut chunk = scene
+                .chunks
+                .get_mut(&VoxelScene::chunk_at(position))
+                .unwrap();
+            *chunk.voxel_scenespace_at_mut(position).unwrap() = *voxel;
+            chunk.is_empty = false;
+        }
+        scene
+    }
+
+    #[test]
+    fn cells_round_towards_the_overlapped_voxels() {
+        // Faces the box only touches don't count, on both sides of zero
+        let (first, last) =
+            VoxelScene::cells_in_region(Vec3::new(-1.5, -0.2, 0.5), Vec3::new(-0.5, 0.2, 1.6));
+        assert_eq!(first, IVec3::new(-1, 0, 1));
+        assert_eq!(last, IVec3::new(-1, 0, 2));
+
+        let (first, last) = VoxelScene::cells_in_region(Vec3::splat(-17.2), Vec3::splat(-16.9));
+        assert_eq!(first, IVec3::splat(-17));
+        assert_eq!(last, IVec3::splat(-17));
+    }
+
+    #[test]
+    fn solid_voxels_block_across_chunk_borders() {
+        let stone = voxel("stone");
+        let water = voxel("water");
+        let scene = scene_with_chunks(
+            &[IVec3::new(-1, 0, 0), IVec3::ZERO],
+            &[(IVec3::new(-1, 2, 3), stone), (IVec3::new(0, 2, 3), water)],
+        );
+
+        assert_eq!(
+            scene.is_region_empty(Vec3::new(-1.4, 1.8, 2.8), Vec3::new(0.4, 2.2, 3.2)),
+            RegionQuery::Blocked(vec![IVec3::new(-1, 2, 3)])
+        );
+        // Water isn't solid, and the stone's face is only touched
+        assert_eq!(
+            scene.is_region_empty(Vec3::new(-0.5, 1.8, 2.8), Vec3::new(0.4, 2.2, 3.2)),
+            RegionQuery::Empty
+        );
+        assert_eq!(
+            scene.is_region_empty(Vec3::new(10.0, 1.0, 1.0), Vec3::new(17.0, 2.0, 2.0)),
+            RegionQuery::Unloaded(vec![IVec3::X])
+        );
+        // Whatever isn't loaded, the stone blocks the box already
+        assert_eq!(
+            scene.is_region_empty(Vec3::new(-20.0, 1.8, 2.8), Vec3::new(-0.6, 2.2, 3.2)),
+            RegionQuery::Blocked(vec![IVec3::new(-1, 2, 3)])
+        );
+    }
+
+    #[test]
+    #[ignore] // Timing, run with --ignored --nocapture
+    fn region_query_benchmark() {
+        // 3x3x3 loaded chunks around the origin, solid below y = 0 and air above it
+        let mut chunk_positions = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    chunk_positions.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+        let scene = scene_with_chunks(&chunk_positions, &[]);
+        let stone = voxel("stone");
+        for chunk_pos in chunk_positions.iter().filter(|chunk_pos| chunk_pos.y < 0) {
+            let mut chunk = scene.chunks.get_mut(chunk_pos).unwrap();
+            for index in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+                let local = UVec3::new(
+                    index / (CHUNK_SIZE * CHUNK_SIZE),
+                    index / CHUNK_SIZE % CHUNK_SIZE,
+                    index % CHUNK_SIZE,
+                );
+                chunk.set_voxel_at(&local, stone);
+            }
+            chunk.is_empty = false;
+        }
+
+        // Player sized boxes sweeping through the ground, the surface and the air above it
+        let half_extents = Vec3::new(0.3, 0.9, 0.3);
+        let count = 100_000;
+        let centers: Vec<Vec3> = (0..count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                Vec3::new(t * 40.0 - 20.0, (t * 50.0).sin() * 6.0, 20.0 - t * 40.0)
+            })
+            .collect();
+
+        let mut blocked = 0;
+        let query = fastest_of(3, || {
+            blocked = centers
+                .iter()
+                .filter(|center| {
+                    matches!(
+                        scene.is_region_empty(**center - half_extents, **center + half_extents),
+                        RegionQuery::Blocked(_)
+                    )
+                })
+                .count();
+        });
+        // The baseline looks every overlapped voxel up through the scene on its own
+        let mut naive_blocked = 0;
+        let naive = fastest_of(3, || {
+            naive_blocked = centers
+                .iter()
+                .filter(|center| {
+                    let (first, last) = VoxelScene::cells_in_region(
+                        **center - half_extents,
+                        **center + half_extents,
+                    );
+                    let mut solid = false;
+                    for x in first.x..=last.x {
+                        for y in first.y..=last.y {
+                            for z in first.z..=last.z {
+                                solid |= scene
+                                    .voxel_at(&IVec3::new(x, y, z))
+                                    .map_or(false, |voxel| voxel_registry::is_solid(voxel.id));
+                            }
+                        }
+                    }
+                    solid
+                })
+                .count();
+        });
+
+        assert!(blocked > 0 && blocked < count);
+        assert_eq!(blocked, naive_blocked);
+        println!(
+            "{count} queries in {query:?}, {:?} each, {blocked} blocked. Voxel by voxel: {naive:?} ({:.2}x)",
+            query / count as u32,
+            naive.as_secs_f64() / query.as_secs_f64()
+        );
+        assert!(
+            query < naive,
+            "region queries were slower than looking voxels up one by one, {query:?} against {naive:?}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod edit_tests {
+    use super::*;
+    use crate::voxels::voxel_registry::get_voxel_by_name;
+
+    // Without an engine there are no systems running, the scene relights and saves its edits by itself
+    #[test]
+    fn edits_without_an_engine_are_relit_and_saved() {
+        let glowstone = get_voxel_by_name("glowstone".to_string()).unwrap().id;
+        let folder = std::env::temp_dir().join(format!("edited_scene_{}", std::process::id()));
+        let mut scene = VoxelScene::new();
+        scene.set_storage(ChunkStorage::new(&folder));
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO));
+
+        let mut light = scene.voxel_at(&IVec3::new(4, 4, 4)).unwrap();
+        light.id = glowstone;
+        scene.set_voxel(IVec3::new(4, 4, 4), light).unwrap();
+        assert!(scene
+            .set_voxels(&[(IVec3::new(10, 4, 4), light)])
+            .is_empty());
+
+        let lit = |position: IVec3| {
+            let chunk = scene.chunks.get(&IVec3::ZERO).unwrap();
+            chunk.block_light_scenespace_at(&position).unwrap()
+        };
+        assert_eq!(lit(IVec3::new(4, 5, 4)), MAX_LIGHT - 1);
+        assert_eq!(lit(IVec3::new(10, 5, 4)), MAX_LIGHT - 1);
+
+        assert_eq!(scene.save_dirty_chunks(), 1);
+        let saved = ChunkStorage::new(&folder).load_chunk(IVec3::ZERO).unwrap();
+        assert_eq!(saved.voxel_at(&UVec3::new(4, 4, 4)).id, glowstone);
+        assert_eq!(saved.voxel_at(&UVec3::new(10, 4, 4)).id, glowstone);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn editing_border_voxel_remeshes_both_chunks() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let scene = VoxelScene::new();
+        for chunk_x in 0..2 {
+            let mut chunk = VoxelChunk::new(IVec3::new(chunk_x, 0, 0));
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    chunk.voxel_at_mut(&UVec3::new(x, 0, z)).id = dirt;
+                }
+            }
+            chunk.is_empty = false;
+            scene.chunks.insert(chunk.position, chunk);
+        }
+        for chunk_x in 0..2 {
+            let chunk_pos = IVec3::new(chunk_x, 0, 0);
+            let chunk = scene.chunks.get(&chunk_pos).unwrap().clone();
+            let neighborhood = ChunkNeighborhood::new(chunk, &scene.chunks);
+            let chunk_mesh = ChunkMesh::generate(&neighborhood, MeshingMode::Blocky);
+            scene.chunk_meshes.insert(chunk_pos, chunk_mesh);
+        }
+        let vertex_count = |chunk_x: i32| {
+            let chunk_mesh = scene.chunk_meshes.get(&IVec3::new(chunk_x, 0, 0)).unwrap();
+            let count = chunk_mesh
+                .meshes
+                .values()
+                .map(|mesh| mesh.read().get_vertices().len())
+                .sum::<usize>();
+            count
+        };
+        let before = (vertex_count(0), vertex_count(1));
+
+        // Digging out the last voxel of the first chunk exposes a side face in the second one
+        let mut air = scene.voxel_at(&IVec3::new(15, 0, 4)).unwrap();
+        air.id = 0;
+        scene.set_voxel(IVec3::new(15, 0, 4), air).unwrap();
+
+        assert_eq!(scene.voxel_at(&IVec3::new(15, 0, 4)).unwrap().id, 0);
+        assert_ne!(vertex_count(0), before.0);
+        assert!(vertex_count(1) > before.1);
+    }
+
+    #[test]
+    fn editing_unloaded_chunk_queues_it() {
+        let scene = VoxelScene::new();
+        let voxel = VoxelChunk::new(IVec3::ZERO)
+            .voxel_at(&UVec3::ZERO)
+            .to_owned();
+        assert_eq!(
+            scene.set_voxel(IVec3::new(3, 3, 3), voxel),
+            Err(VoxelEditError::ChunkNotLoaded)
+        );
+        assert!(scene.initialization_queue.contains(&IVec3::ZERO));
+        assert_eq!(
+            scene.set_voxel(IVec3::new(3, -3, 3), voxel),
+            Err(VoxelEditError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn undo_reverts_whole_strokes_and_remeshes() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let scene = VoxelScene::new();
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.voxel_at_mut(&UVec3::new(x, 0, z)).id = dirt;
+            }
+        }
+        chunk.is_empty = false;
+        let neighborhood = ChunkNeighborhood::new(chunk.clone(), &scene.chunks);
+        scene.chunk_meshes.insert(
+            IVec3::ZERO,
+            ChunkMesh::generate(&neighborhood, MeshingMode::Blocky),
+        );
+        scene.chunks.insert(IVec3::ZERO, chunk);
+        let vertex_count = || {
+            let chunk_mesh = scene.chunk_meshes.get(&IVec3::ZERO).unwrap();
+            let count = chunk_mesh
+                .meshes
+                .values()
+                .map(|mesh| mesh.read().get_vertices().len())
+                .sum::<usize>();
+            count
+        };
+
+        let block = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: dirt,
+        };
+        let mut counts = Vec::new();
+        for x in [2, 6, 10] {
+            scene.begin_stroke();
+            scene.edit_voxel(IVec3::new(x, 1, 4), block).unwrap();
+            counts.push(vertex_count());
+        }
+        // Loading a chunk back in doesn't lose the stroke boundaries
+        let reloaded = scene.chunks.remove(&IVec3::ZERO).unwrap().1;
+        assert_eq!(scene.undo(), Err(VoxelEditError::ChunkNotLoaded));
+        scene.chunks.insert(IVec3::ZERO, reloaded);
+
+        assert_eq!(scene.undo(), Ok(1));
+        assert_eq!(scene.undo(), Ok(1));
+        assert_eq!(scene.voxel_at(&IVec3::new(2, 1, 4)).unwrap().id, dirt);
+        assert_eq!(scene.voxel_at(&IVec3::new(6, 1, 4)).unwrap().id, 0);
+        assert_eq!(scene.voxel_at(&IVec3::new(10, 1, 4)).unwrap().id, 0);
+        assert_eq!(vertex_count(), counts[0]);
+
+        assert_eq!(scene.redo(), Ok(1));
+        assert_eq!(scene.voxel_at(&IVec3::new(6, 1, 4)).unwrap().id, dirt);
+        assert_eq!(vertex_count(), counts[1]);
+    }
+
+    #[test]
+    fn filling_a_region_remeshes_every_touched_chunk_once() {
+        let scene = VoxelScene::new();
+        for chunk_pos in region_positions(IVec3::new(-2, 0, -2), IVec3::new(3, 4, 3)) {
+            let mut chunk = VoxelChunk::new(chunk_pos);
+            chunk.is_empty = false;
+            scene.chunks.insert(chunk_pos, chunk);
+        }
+        let unloaded = IVec3::new(2, 2, 1);
+        scene.chunks.remove(&unloaded);
+
+        let (min, max) = (IVec3::new(0, 5, -8), IVec3::new(39, 44, 31));
+        let voxel = VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id: 1,
+        };
+        let skipped = scene.fill_region(min, max, voxel);
+        assert_eq!(skipped.len(), 8 * 13 * 16);
+        assert!(scene.initialization_queue.contains(&unloaded));
+
+        // The touched chunks, and the ones across the region's faces at x = -1 and z = 32
+        let mut expected: HashSet<IVec3> =
+            region_positions(IVec3::new(-1, 0, -1), IVec3::new(2, 2, 2))
+                .filter(|chunk_pos| !(chunk_pos.x == -1 && chunk_pos.z == 2))
+                .collect();
+        expected.remove(&unloaded);
+        let queued: Vec<IVec3> = (0..scene.generation_channel.len())
+            .map(|_| scene.generation_channel.pop_nearest().unwrap().0)
+            .collect();
+        assert_eq!(queued.len(), expected.len());
+        assert_eq!(queued.into_iter().collect::<HashSet<_>>(), expected);
+
+        let mut visited = 0;
+        let skipped = scene.for_each_in_region(min, max, |_, data| {
+            assert_eq!(data.id, 1);
+            visited += 1;
+        });
+        assert_eq!(visited + skipped.len(), 40 * 40 * 40);
+        assert_eq!(scene.voxel_at(&IVec3::new(0, 4, 0)).unwrap().id, 0);
+    }
+
+    #[test]
+    fn surface_height_needs_the_column_above_it() {
+        let scene = VoxelScene::new_with_config(WorldConfig {
+            max_height: 32,
+            ..Default::default()
+        });
+        let mut ground = VoxelChunk::new(IVec3::ZERO);
+        ground.voxel_at_mut(&UVec3::new(3, 9, 5)).id = 1;
+        ground.voxel_at_mut(&UVec3::new(3, 2, 5)).id = 1;
+        ground.is_empty = false;
+        scene.chunks.insert(ground.position, ground);
+        assert_eq!(scene.surface_height_at(3, 5), None); // The empty chunk on top isn't loaded yet
+
+        scene.chunks.insert(IVec3::Y, VoxelChunk::new(IVec3::Y));
+        assert_eq!(scene.surface_height_at(3, 5), Some(9));
+        assert_eq!(scene.surface_height_at(4, 5), None);
+    }
+}
+
+#[cfg(test)]
+mod greedy_tests {
+    use dashmap::DashMap;
+
+    use super::*;
+    use crate::voxels::voxel_registry::get_voxel_by_name;
+
+    fn filled_chunk(id: u16) -> VoxelChunk {
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        chunk.voxels.fill(VoxelData {
+            shape: voxel_shape::CUBE,
+            state: 0,
+            id,
+        });
+        chunk.is_empty = false;
+        chunk
+    }
+
+    #[test]
+    fn solid_chunk_merges_into_six_quads() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let chunk = filled_chunk(dirt);
+
+        let neighborhood = ChunkNeighborhood::new(chunk.clone(), &chunks);
+        let naive = chunk
+            .generate_meshes(&neighborhood, MeshingStrategy::Naive)
+            .remove(voxel_registry::DEFAULT_MATERIAL)
+            .unwrap();
+        let greedy = chunk
+            .generate_meshes(&neighborhood, MeshingStrategy::Greedy)
+            .remove(voxel_registry::DEFAULT_MATERIAL)
+            .unwrap();
+        assert_eq!(naive.get_vertices().len(), 6 * 256 * 4); // 1536 quads
+        assert_eq!(greedy.get_vertices().len(), 24);
+        assert_eq!(greedy.get_indices().len(), 36);
+
+        // UVs tile once per voxel
+        let top_uvs: Vec<[f32; 2]> = greedy
+            .get_vertices()
+            .iter()
+            .filter(|vertex| vertex.normal[1] > 0.5)
+            .map(|vertex| vertex.uv)
+            .collect();
+        assert_eq!(top_uvs.len(), 4);
+        assert!(top_uvs.contains(&[0.0, 0.0]));
+        assert!(top_uvs.contains(&[CHUNK_SIZE as f32, CHUNK_SIZE as f32]));
+    }
+
+    #[test]
+    fn neighbouring_chunk_still_hides_border_faces() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut neighbour = filled_chunk(dirt);
+        neighbour.position = IVec3::X;
+        chunks.insert(neighbour.position, neighbour);
+
+        let chunk = filled_chunk(dirt);
+        let neighborhood = ChunkNeighborhood::new(chunk.clone(), &chunks);
+        let greedy = chunk
+            .generate_meshes(&neighborhood, MeshingStrategy::Greedy)
+            .remove(voxel_registry::DEFAULT_MATERIAL)
+            .unwrap();
+        assert_eq!(greedy.get_vertices().len(), 20);
+        assert!(greedy
+            .get_vertices()
+            .iter()
+            .all(|vertex| vertex.normal != [1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn voxels_are_bucketed_by_their_material() {
+        let stone = get_voxel_by_name("stone".to_string()).unwrap().id;
+        let leaves = get_voxel_by_name("leaves".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        chunk.voxel_at_mut(&UVec3::new(2, 2, 2)).id = stone;
+        chunk.voxel_at_mut(&UVec3::new(8, 2, 2)).id = leaves;
+        chunk.is_empty = false;
+
+        let neighborhood = ChunkNeighborhood::new(chunk.clone(), &chunks);
+        let meshes = chunk.generate_meshes(&neighborhood, MeshingStrategy::Greedy);
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes[voxel_registry::DEFAULT_MATERIAL].vertex_count, 24);
+        assert_eq!(meshes[voxel_registry::FOLIAGE_MATERIAL].vertex_count, 24);
+    }
+
+    #[test]
+    fn merged_faces_keep_their_occlusion() {
+        let dirt = get_voxel_by_name("dirt".to_string()).unwrap().id;
+        let chunks: ChunkMap = Arc::new(DashMap::default());
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.voxel_at_mut(&UVec3::new(x, 0, z)).id = dirt;
+            }
+        }
+        // Darkens the floor around it
+        chunk.voxel_at_mut(&UVec3::new(8, 1, 8)).id = dirt;
+        chunk.is_empty = false;
+
+        let neighborhood = ChunkNeighborhood::new(chunk.clone(), &chunks);
+        let meshes = |strategy| {
+            chunk
+                .generate_meshes(&neighborhood, strategy)
+                .remove(voxel_registry::DEFAULT_MATERIAL)
+                .unwrap()
+        };
+        let (naive, greedy) = (
+            meshes(MeshingStrategy::Naive),
+            meshes(MeshingStrategy::Greedy),
+        );
+        let floor = |mesh: &Mesh| -> Vec<([f32; 3], f32)> {
+            mesh.get_vertices()
+                .iter()
+                .filter(|vertex| vertex.normal[1] > 0.5 && vertex.position[1] == 0.5)
+                .map(|vertex| (vertex.position, vertex.ao))
+                .collect()
+        };
+        let (naive_floor, greedy_floor) = (floor(&naive), floor(&greedy));
+        assert!(greedy_floor.len() < naive_floor.len());
+        assert!(greedy_floor.iter().any(|(_, ao)| *ao < 1.0));
+        // Every corner is as dark as the same corner of the unmerged faces
+        for corner in &greedy_floor {
+            assert!(naive_floor.contains(corner), "{corner:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod occlusion_tests {
+    use super::*;
+
+    #[test]
+    fn corner_levels_follow_neighbours() {
+        let corner = Vec3::new(0.5, 0.5, 0.5);
+        let up = Vec3::Y;
+        assert_eq!(corner_occlusion(|_| false, corner, up), 1.0);
+        let one_side = |offset: IVec3| offset == IVec3::new(1, 1, 0);
+        assert!((corner_occlusion(one_side, corner, up) - 2.0 / 3.0).abs() < 1e-6);
+        let both_sides =
+            |offset: IVec3| offset == IVec3::new(1, 1, 0) || offset == IVec3::new(0, 1, 1);
+        assert_eq!(corner_occlusion(both_sides, corner, up), 0.0);
+        // Corners inside the voxel, like on a slab's top face, are left alone
+        assert_eq!(
+            corner_occlusion(|_| true, Vec3::new(0.5, 0.0, 0.5), up),
+            1.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod biome_fill_tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    use super::*;
+    use crate::voxels::biome_profile::BiomeProfile;
+    use crate::voxels::voxel_registry::get_voxel_by_name;
+
+    #[test]
+    fn voxel_types_follow_depth_below_surface() {
+        let biome = BiomeProfile::from_json(
+            "test.json",
+            r#"{
+                "Samplers": [],
+                "Voxel Density": "Sub(20, Y)",
+                "Voxel Type": "If(Less(Depth, 1), Voxel(grass), If(Less(Depth, 3), Voxel(dirt), Voxel(stone)))",
+                "Voxel Shape": "CUBE"
+            }"#,
+        )
+        .unwrap();
+        let biomes = BiomeMap::from_biomes(0, vec![Arc::new(biome)]);
+        let id = |name: &str| get_voxel_by_name(name.to_string()).unwrap().id;
+
+        // The surface lies in the chunk above, it's still found by sampling past the chunk top
+        let surfaces = SurfaceCache::new();
+        let mut chunk = VoxelChunk::new(IVec3::ZERO);
+        fill_chunk(
+            &mut chunk,
+            &biomes,
+            &WorldConfig::default(),
+            &surfaces,
+            None,
+        );
+        assert_eq!(chunk.voxel_at(&UVec3::new(3, 15, 3)).id, id("stone"));
+
+        let mut chunk = VoxelChunk::new(IVec3::new(0, 1, 0));
+        fill_chunk(
+            &mut chunk,
+            &biomes,
+            &WorldConfig::default(),
+            &surfaces,
+            None,
+        );
+        let id_at = |y: u32| chunk.voxel_at(&UVec3::new(3, y, 3)).id;
+        assert_eq!(id_at(4), 0);
+        assert_eq!(id_at(3), id("grass"));
+        assert_eq!(id_at(2), id("dirt"));
+        assert_eq!(id_at(1), id("dirt"));
+        assert_eq!(id_at(0), id("stone"));
+    }
+
+    #[test]
+    fn one_grass_layer_in_any_generation_order() {
+        let biome = BiomeProfile::from_json(
+            "test.json",
+            r#"{
+                "Samplers": [{ "Type": "Simplex", "Name": "Hills", "Wavelength": 40, "Amplitude": 8 }],
+                "Voxel Density": "Sub(Add(Hills, 48), Y)",
+                "Voxel Type": "If(Less(Depth, 1), Voxel(grass), Voxel(dirt))",
+                "Voxel Shape": "CUBE"
+            }"#,
+        )
+        .unwrap();
+        // Gentle hills around a chunk border, steep 3D noise would make overhangs with a grass layer of their own
+        let biomes = BiomeMap::from_biomes(0, vec![Arc::new(biome)]);
+        let config = WorldConfig::default();
+        let grass = get_voxel_by_name("grass".to_string()).unwrap().id;
+        let layers: Vec<i32> = (config.min_chunk_y()..=config.max_chunk_y()).collect();
+
+        // Top down every chunk sees the one above it, bottom up none of them do
+        let generate = |order: Vec<i32>| {
+            let surfaces = SurfaceCache::new();
+            let chunks: HashMap<i32, VoxelChunk> = HashMap::new();
+            order.into_iter().fold(chunks, |mut chunks, y| {
+                let mut chunk = VoxelChunk::new(IVec3::new(0, y, 0));
+                fill_chunk(
+                    &mut chunk,
+                    &biomes,
+                    &config,
+                    &surfaces,
+                    chunks.get(&(y + 1)),
+                );
+                chunks.insert(y, chunk);
+                chunks
+            })
+        };
+        let top_down = generate(layers.iter().rev().cloned().collect());
+        let bottom_up = generate(layers.clone());
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = |chunks: &HashMap<i32, VoxelChunk>| -> Vec<u16> {
+                    layers
+                        .iter()
+                        .flat_map(|y| {
+                            (0..CHUNK_SIZE)
+                                .map(|local_y| chunks[y].voxel_at(&UVec3::new(x, local_y, z)).id)
+                        })
+                        .collect()
+                };
+                let ids = column(&top_down);
+                assert_eq!(ids, column(&bottom_up));
+                assert_eq!(ids.iter().filter(|id| **id == grass).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_generates_identical_chunks() {
+        let biome = Arc::new(
+            BiomeProfile::from_json(
+                "test.json",
+                r#"{
+                    "Samplers": [
+                        { "Type": "Simplex", "Name": "Hills", "Wavelength": 20, "Amplitude": 10 }
+                    ],
+                    "Voxel Density": "Sub(Add(Hills, 24), Y)",
+                    "Voxel Type": "If(Less(Depth, 1), Voxel(grass), Voxel(dirt))",
+                    "Voxel Shape": "CUBE"
+                }"#,
+            )
+            .unwrap(),
+        );
+        // The surface runs through this chunk, so the noise decides every column
+        let chunk_hash = |seed: u64| {
+            let config = WorldConfig {
+                seed,
+                ..Default::default()
+            };
+            let biomes = BiomeMap::from_biomes(seed, vec![Arc::clone(&biome)]);
+            let chunk = generate_chunk(
+                IVec3::new(2, 1, -3),
+                &biomes,
+                &config,
+                &SurfaceCache::new(),
+                None,
+                None,
+            );
+            let mut hasher = DefaultHasher::new();
+            chunk.to_bytes().hash(&mut hasher);
+            hasher.finish()
+        };
+
+        assert_eq!(chunk_hash(42), chunk_hash(42));
+        assert_ne!(chunk_hash(42), chunk_hash(43));
+    }
+}
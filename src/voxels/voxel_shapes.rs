@@ -1,4 +1,4 @@
-use glam::IVec3;
+use glam::{IVec3, Vec3};
 mod occlussion_shapes {
     const CUBE: [u8; 6] = [
         0b_1111_1111, // North
@@ -84,16 +84,17 @@ mod occlussion_shapes {
     ];
 
     lazy_static! {
-        pub static ref SHAPE_ORIENTATIONS: Box<[u8; 1536]> = Box::new(get_shape_permutations());
+        pub static ref SHAPE_ORIENTATIONS: Box<[u8; 3072]> = Box::new(get_shape_permutations());
     }
 
-    fn get_shape_permutations() -> [u8; 1536] {
-        let mut r = [0; 1536];
+    fn get_shape_permutations() -> [u8; 3072] {
+        let mut r = [0; 3072];
 
-        for i in 0_u8..255 {
-            let mut shape = SHAPES[(i << 5 >> 5) as usize]; // 32 rotations per shape
+        // The low 3 bits pick the shape and the 6 above them the orientation, so every value is a valid entry
+        for i in 0..super::SHAPE_VALUES {
+            let mut shape = SHAPES[(i & 0b_0000_0111) as usize];
 
-            // Iterate through all possible permutations of the orientation, assigning it to a NOT RANDOM spot in the orientation array
+            // Applied in the same order the mesher transforms the vertices
             if i & 0b_0000_1000 != 0 {
                 shape = flip_east_west(shape);
             }
@@ -109,6 +110,9 @@ mod occlussion_shapes {
             if i & 0b_1000_0000 != 0 {
                 shape = rotate_z(shape);
             }
+            if i & 0b1_0000_0000 != 0 {
+                shape = rotate_y(shape);
+            }
             for b in 0..6 {
                 r[((i as usize) * 6) + b] = shape[b];
             }
@@ -117,10 +121,14 @@ mod occlussion_shapes {
         r
     }
 
+    // Slices go around each face starting at the bottom (or south) edge, east and west faces are laid out as (z, y),
+    // north and south as (-x, y) and top and bottom as (x, z). Mirroring the first axis of a face reverses the bits,
+    // mirroring the second axis reverses them and turns them half way around
+
     fn flip_north_south(sides: [u8; 6]) -> [u8; 6] {
         let mut r = [0; 6];
-        r[0] = sides[1].reverse_bits(); // North
-        r[1] = sides[0].reverse_bits(); // South
+        r[0] = sides[1]; // North
+        r[1] = sides[0]; // South
         r[2] = sides[2].reverse_bits(); // East
         r[3] = sides[3].reverse_bits(); // West
         r[4] = sides[4].reverse_bits().rotate_left(4); // Top
@@ -130,10 +138,10 @@ mod occlussion_shapes {
 
     fn flip_east_west(sides: [u8; 6]) -> [u8; 6] {
         let mut r = [0; 6];
-        r[0] = sides[1].reverse_bits(); // North
-        r[1] = sides[0].reverse_bits(); // South
-        r[2] = sides[3].reverse_bits(); // East
-        r[3] = sides[2].reverse_bits(); // West
+        r[0] = sides[0].reverse_bits(); // North
+        r[1] = sides[1].reverse_bits(); // South
+        r[2] = sides[3]; // East
+        r[3] = sides[2]; // West
         r[4] = sides[4].reverse_bits(); // Top
         r[5] = sides[5].reverse_bits(); // Bottom
         r
@@ -145,30 +153,44 @@ mod occlussion_shapes {
         r[1] = sides[1].reverse_bits().rotate_left(4); // South
         r[2] = sides[2].reverse_bits().rotate_left(4); // East
         r[3] = sides[3].reverse_bits().rotate_left(4); // West
-        r[4] = sides[5].reverse_bits(); // Top
-        r[5] = sides[4].reverse_bits(); // Bottom
+        r[4] = sides[5]; // Top
+        r[5] = sides[4]; // Bottom
         r
     }
 
+    // (y, z) -> (z, -y)
     fn rotate_x(sides: [u8; 6]) -> [u8; 6] {
         let mut r = [0; 6];
-        r[0] = sides[5]; // North
-        r[1] = sides[4]; // South
+        r[0] = sides[5].reverse_bits(); // North
+        r[1] = sides[4].reverse_bits(); // South
         r[2] = sides[2].rotate_right(2); // East
         r[3] = sides[3].rotate_right(2); // West
-        r[4] = sides[0]; // Top
-        r[5] = sides[1]; // Bottom
+        r[4] = sides[0].rotate_left(4); // Top
+        r[5] = sides[1].rotate_left(4); // Bottom
         r
     }
 
+    // (x, y) -> (y, -x)
     fn rotate_z(sides: [u8; 6]) -> [u8; 6] {
         let mut r = [0; 6];
         r[0] = sides[0].rotate_right(2); // North
         r[1] = sides[1].rotate_right(2); // South
-        r[2] = sides[4]; // East
-        r[3] = sides[5]; // West
-        r[4] = sides[2]; // Top
-        r[5] = sides[3]; // Bottom
+        r[2] = sides[4].rotate_left(2); // East
+        r[3] = sides[5].rotate_left(2); // West
+        r[4] = sides[3].reverse_bits().rotate_left(2); // Top
+        r[5] = sides[2].reverse_bits().rotate_left(2); // Bottom
+        r
+    }
+
+    // (x, z) -> (z, -x)
+    fn rotate_y(sides: [u8; 6]) -> [u8; 6] {
+        let mut r = [0; 6];
+        r[0] = sides[3].reverse_bits(); // North
+        r[1] = sides[2].reverse_bits(); // South
+        r[2] = sides[0]; // East
+        r[3] = sides[1]; // West
+        r[4] = sides[4].rotate_left(2); // Top
+        r[5] = sides[5].rotate_left(2); // Bottom
         r
     }
 }
@@ -240,8 +262,26 @@ impl VoxelDirection {
         }
     }
 
+    // The side the look direction points towards most, ignoring its vertical part
+    pub fn horizontal_facing(look_dir: Vec3) -> VoxelDirection {
+        if look_dir.x.abs() > look_dir.z.abs() {
+            if look_dir.x > 0.0 {
+                voxel_directions::EAST
+            } else {
+                voxel_directions::WEST
+            }
+        } else if look_dir.z < 0.0 {
+            voxel_directions::SOUTH
+        } else {
+            voxel_directions::NORTH
+        }
+    }
+
     pub fn get_oriented_directions(orientation: VoxelOrientation) -> OrientedVoxelDirections {
         let mut r = voxel_directions::ALL;
+        if orientation.extract_rotate_y() {
+            (r[0], r[1], r[2], r[3]) = (r[2], r[3], r[1], r[0]);
+        }
         if orientation.extract_rotate_z() {
             (r[2], r[3], r[4], r[5]) = (r[5], r[4], r[2], r[3]);
         }
@@ -305,16 +345,20 @@ pub mod voxel_shape {
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default)]
 #[repr(packed(1))]
-// TODO: Refactor this to just wrap the u8 type
+// TODO: Refactor this to just wrap the u16 type
 pub struct VoxelShape {
-    pub data: u8,
+    pub data: u16,
 }
 
+// Every valid shape value, see the layout below
+pub const SHAPE_VALUES: u16 = 512;
+
+// [8] Rotate Y, a quarter turn from north to east
 // [7] Rotate Z
 // [6] Rotate X
-// [5] Flip Y
-// [4] Flip X
-// [3] Flip Z
+// [5] Flip Z
+// [4] Flip Y
+// [3] Flip X
 // [2, 1, 0] Index of shape
 
 impl VoxelShape {
@@ -339,7 +383,7 @@ impl VoxelShape {
     }
 
     pub fn extract_shape(&self) -> u8 {
-        self.data << 5 >> 5
+        (self.data & 0b_0000_0111) as u8
     }
 
     pub fn extract_flip_x(&self) -> bool {
@@ -357,10 +401,13 @@ impl VoxelShape {
     pub fn extract_rotate_z(&self) -> bool {
         self.data & 0b_1000_0000 == 0b_1000_0000
     }
+    pub fn extract_rotate_y(&self) -> bool {
+        self.data & 0b1_0000_0000 == 0b1_0000_0000
+    }
 
     pub fn extract_orientation(&self) -> VoxelOrientation {
         VoxelOrientation {
-            data: self.data & 0b_1111_1000,
+            data: self.data & 0b1_1111_1000,
         }
     }
 }
@@ -438,7 +485,7 @@ pub mod voxel_orientations {
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default)]
 #[repr(packed(1))]
 pub struct VoxelOrientation {
-    pub data: u8,
+    pub data: u16,
 }
 
 impl VoxelOrientation {
@@ -457,4 +504,301 @@ impl VoxelOrientation {
     pub fn extract_rotate_z(&self) -> bool {
         self.data & 0b_1000_0000 == 0b_1000_0000
     }
+    pub fn extract_rotate_y(&self) -> bool {
+        self.data & 0b1_0000_0000 == 0b1_0000_0000
+    }
+
+    // The orientation that turns a shape's back towards the facing and its bottom upwards when it's upside down.
+    // East and west turn the north or south back a quarter around the vertical axis
+    pub fn from_facing(facing: VoxelDirection, upside_down: bool) -> VoxelOrientation {
+        let mut data = 0;
+        if upside_down {
+            data |= 0b_0001_0000; // Flip Y
+        }
+        if facing == voxel_directions::SOUTH || facing == voxel_directions::WEST {
+            data |= 0b_0010_0000; // Flip Z
+        }
+        if facing == voxel_directions::EAST || facing == voxel_directions::WEST {
+            data |= 0b1_0000_0000; // Rotate Y
+        }
+        VoxelOrientation { data }
+    }
+
+    // Picks the orientation for a shaped voxel placed against the face of a hit voxel, hit_fraction is the hit point
+    // relative to the hit voxel's centre. Hitting the underside of a voxel, or the upper half of a side face, places
+    // the shape upside down, and its back is turned away from the player
+    pub fn for_placement(
+        face: VoxelDirection,
+        hit_fraction: Vec3,
+        look_dir: Vec3,
+    ) -> VoxelOrientation {
+        let upside_down = if face == voxel_directions::UP {
+            false
+        } else if face == voxel_directions::DOWN {
+            true
+        } else {
+            hit_fraction.y > 0.0
+        };
+
+        let facing = VoxelDirection::horizontal_facing(look_dir);
+        VoxelOrientation::from_facing(facing, upside_down)
+    }
+}
+
+#[cfg(test)]
+mod orientation_tests {
+    use glam::Vec3;
+
+    use super::{voxel_directions, voxel_shape, VoxelDirection, VoxelOrientation, VoxelShape};
+
+    const FULL: u8 = 0b_1111_1111;
+
+    // Looking towards each side, slightly off axis and down
+    fn look_dirs() -> [(Vec3, VoxelDirection); 4] {
+        [
+            (Vec3::new(0.1, -0.3, 1.0), voxel_directions::NORTH),
+            (Vec3::new(-0.1, -0.3, -1.0), voxel_directions::SOUTH),
+            (Vec3::new(1.0, -0.3, 0.2), voxel_directions::EAST),
+            (Vec3::new(-1.0, -0.3, -0.2), voxel_directions::WEST),
+        ]
+    }
+
+    // The two sides next to the back
+    fn sides_of(back: VoxelDirection) -> [VoxelDirection; 2] {
+        if back == voxel_directions::NORTH || back == voxel_directions::SOUTH {
+            [voxel_directions::EAST, voxel_directions::WEST]
+        } else {
+            [voxel_directions::NORTH, voxel_directions::SOUTH]
+        }
+    }
+
+    fn is_full(shape: VoxelShape, face: VoxelDirection) -> bool {
+        VoxelShape::get_face_shape(shape, face) == FULL
+    }
+
+    #[test]
+    fn horizontal_facing_ignores_height() {
+        let facing = VoxelDirection::horizontal_facing;
+        assert_eq!(facing(Vec3::new(0.1, 5.0, 1.0)), voxel_directions::NORTH);
+        assert_eq!(facing(Vec3::new(0.1, -5.0, -1.0)), voxel_directions::SOUTH);
+        assert_eq!(facing(Vec3::new(1.0, 5.0, -0.5)), voxel_directions::EAST);
+        assert_eq!(facing(Vec3::new(-1.0, -5.0, 0.5)), voxel_directions::WEST);
+    }
+
+    #[test]
+    fn placement_matrix() {
+        for face in voxel_directions::ALL {
+            for upper_half in [false, true] {
+                let fraction = Vec3::new(0.1, if upper_half { 0.3 } else { -0.3 }, -0.2)
+                    + face.as_vec().as_vec3() * 0.5;
+                let upside_down = if face == voxel_directions::UP {
+                    false
+                } else if face == voxel_directions::DOWN {
+                    true
+                } else {
+                    upper_half
+                };
+                let (full, open) = if upside_down {
+                    (voxel_directions::UP, voxel_directions::DOWN)
+                } else {
+                    (voxel_directions::DOWN, voxel_directions::UP)
+                };
+
+                for (look_dir, back) in look_dirs() {
+                    let orientation = VoxelOrientation::for_placement(face, fraction, look_dir);
+                    let case = format!("{:?} {:?} {}", face, look_dir, upper_half);
+
+                    let slab = voxel_shape::SLAB.oriented(orientation);
+                    assert!(is_full(slab, full), "slab {}", case);
+                    assert_eq!(VoxelShape::get_face_shape(slab, open), 0, "slab {}", case);
+
+                    let stair = voxel_shape::STAIR.oriented(orientation);
+                    assert!(is_full(stair, full), "stair {}", case);
+                    assert!(!is_full(stair, open), "stair {}", case);
+                    assert!(is_full(stair, back), "stair {}", case);
+                    assert!(!is_full(stair, back.flip()), "stair {}", case);
+                    for side in sides_of(back) {
+                        assert!(!is_full(stair, side), "stair {}", case);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn placed_stairs_occlude_neighbours() {
+        for (look_dir, back) in look_dirs() {
+            let orientation =
+                VoxelOrientation::for_placement(voxel_directions::UP, Vec3::ZERO, look_dir);
+            let stair = voxel_shape::STAIR.oriented(orientation);
+
+            // The back hides a neighbouring cube face, the stepped front doesn't
+            assert!(stair.face_contains(back, (voxel_shape::CUBE, back.flip())));
+            assert!(!stair.face_contains(back.flip(), (voxel_shape::CUBE, back)));
+            // A row of stairs hides the faces between them
+            let [side, other_side] = sides_of(back);
+            assert!(stair.face_contains(side, (stair, other_side)));
+            assert!(stair.face_contains(other_side, (stair, side)));
+        }
+    }
+
+    #[test]
+    fn stairs_face_every_side() {
+        let stair = |look_dir| {
+            let orientation =
+                VoxelOrientation::for_placement(voxel_directions::UP, Vec3::ZERO, look_dir);
+            voxel_shape::STAIR.oriented(orientation)
+        };
+        let east = stair(Vec3::X);
+        assert!(is_full(east, voxel_directions::EAST));
+        assert!(!is_full(east, voxel_directions::WEST));
+        let west = stair(-Vec3::X);
+        assert!(is_full(west, voxel_directions::WEST));
+        assert!(!is_full(west, voxel_directions::EAST));
+        // The mesh of the back is carried over to the east as well
+        let directions = VoxelDirection::get_oriented_directions(east.extract_orientation());
+        assert_eq!(
+            directions.get_direction(voxel_directions::NORTH),
+            voxel_directions::EAST
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod permutation_tests {
+    use glam::Vec3;
+
+    use super::{voxel_directions, VoxelDirection, VoxelShape, SHAPE_VALUES};
+
+    // Centres of the eight occlusion slices on an east/west face as (z, y), the other faces reuse them
+    const SLICE_CENTRES: [(f32, f32); 8] = [
+        (-1.0 / 6.0, -1.0 / 3.0),
+        (-1.0 / 3.0, -1.0 / 6.0),
+        (-1.0 / 3.0, 1.0 / 6.0),
+        (-1.0 / 6.0, 1.0 / 3.0),
+        (1.0 / 6.0, 1.0 / 3.0),
+        (1.0 / 3.0, 1.0 / 6.0),
+        (1.0 / 3.0, -1.0 / 6.0),
+        (1.0 / 6.0, -1.0 / 3.0),
+    ];
+
+    pub(crate) fn slice_centre(direction: VoxelDirection, slice: usize) -> Vec3 {
+        let (u, v) = SLICE_CENTRES[slice];
+        let offset = direction.as_vec().as_vec3() * 0.5;
+        match direction.data {
+            0 | 1 => Vec3::new(-u, v, offset.z),
+            2 | 3 => Vec3::new(offset.x, v, u),
+            _ => Vec3::new(u, offset.y, v),
+        }
+    }
+
+    fn slice_at(point: Vec3) -> (VoxelDirection, usize) {
+        let direction = voxel_directions::ALL
+            .into_iter()
+            .find(|d| d.as_vec().as_vec3().dot(point) == 0.5)
+            .unwrap();
+        let slice = (0..8)
+            .min_by(|a, b| {
+                let da = slice_centre(direction, *a).distance(point);
+                let db = slice_centre(direction, *b).distance(point);
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap();
+        (direction, slice)
+    }
+
+    // Moves a point the same way the mesher moves the vertices of an oriented shape
+    fn orient(shape: VoxelShape, mut p: Vec3) -> Vec3 {
+        if shape.extract_flip_x() {
+            p.x = -p.x;
+        }
+        if shape.extract_flip_y() {
+            p.y = -p.y;
+        }
+        if shape.extract_flip_z() {
+            p.z = -p.z;
+        }
+        if shape.extract_rotate_x() {
+            p = Vec3::new(p.x, p.z, -p.y);
+        }
+        if shape.extract_rotate_z() {
+            p = Vec3::new(p.y, -p.x, p.z);
+        }
+        if shape.extract_rotate_y() {
+            p = Vec3::new(p.z, p.y, -p.x);
+        }
+        p
+    }
+
+    // Moves every covered slice of the unoriented shape to wherever the orientation puts it
+    fn reference_masks(shape: VoxelShape) -> [u8; 6] {
+        let base = VoxelShape {
+            data: shape.extract_shape() as u16,
+        };
+        let mut r = [0; 6];
+        for direction in voxel_directions::ALL {
+            let mask = VoxelShape::get_face_shape(base, direction);
+            for slice in (0..8).filter(|s| mask & (1 << s) != 0) {
+                let (face, moved) = slice_at(orient(shape, slice_centre(direction, slice)));
+                r[face.data as usize] |= 1 << moved;
+            }
+        }
+        r
+    }
+
+    #[test]
+    fn table_matches_reference_for_every_shape_byte() {
+        for data in 0..SHAPE_VALUES {
+            let shape = VoxelShape { data };
+            let expected = reference_masks(shape);
+            for direction in voxel_directions::ALL {
+                assert_eq!(
+                    VoxelShape::get_face_shape(shape, direction),
+                    expected[direction.data as usize],
+                    "shape {:#011b} direction {}",
+                    data,
+                    direction.data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn oriented_directions_follow_the_mesh() {
+        for data in (0..SHAPE_VALUES).step_by(8) {
+            let shape = VoxelShape { data };
+            let directions = VoxelDirection::get_oriented_directions(shape.extract_orientation());
+            for direction in voxel_directions::ALL {
+                let moved = orient(shape, direction.as_vec().as_vec3());
+                assert_eq!(
+                    directions.get_direction(direction).as_vec().as_vec3(),
+                    moved,
+                    "shape {:#011b} direction {}",
+                    data,
+                    direction.data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn face_contains_agrees_with_reference() {
+        let masks: Vec<[u8; 6]> = (0..SHAPE_VALUES)
+            .map(|data| reference_masks(VoxelShape { data }))
+            .collect();
+        for data in 0..SHAPE_VALUES {
+            let shape = VoxelShape { data };
+            for direction in voxel_directions::ALL {
+                let face = masks[data as usize][direction.data as usize];
+                for other in 0..SHAPE_VALUES {
+                    let other_shape = VoxelShape { data: other };
+                    let other_face = masks[other as usize][direction.flip().data as usize];
+                    assert_eq!(
+                        shape.face_contains(direction, (other_shape, direction.flip())),
+                        face & other_face == other_face
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,126 @@
+use std::{mem::size_of, sync::Arc};
+
+use super::voxel_scene::CHUNK_SIZE;
+
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// One value per voxel of a chunk. Chunks that are all air, or all stone deep underground, only store a single value
+// until something different is written into them. Copies share their values until one of them is written to,
+// so snapshots of a chunk for meshing cost next to nothing
+#[derive(Clone)]
+pub enum VoxelStorage<T: Copy + PartialEq> {
+    Uniform(T),
+    Dense(Arc<[T; CHUNK_VOLUME]>),
+}
+
+impl<T: Copy + PartialEq> VoxelStorage<T> {
+    pub fn new(value: T) -> Self {
+        Self::Uniform(value)
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        match self {
+            Self::Uniform(value) => value,
+            Self::Dense(values) => &values[index],
+        }
+    }
+
+    // Always promotes to dense storage, the caller might write anything through the reference
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.make_dense()[index]
+    }
+
+    // Only promotes to dense storage when the value differs from the uniform one
+    pub fn set(&mut self, index: usize, value: T) {
+        match self {
+            Self::Uniform(uniform) if *uniform == value => {}
+            _ => self.make_dense()[index] = value,
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        *self = Self::Uniform(value);
+    }
+
+    // Demotes back to a single value if every voxel ended up the same
+    pub fn compact(&mut self) {
+        if let Self::Dense(values) = self {
+            let first = values[0];
+            if values.iter().all(|value| *value == first) {
+                *self = Self::Uniform(first);
+            }
+        }
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        matches!(self, Self::Uniform(_))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..CHUNK_VOLUME).map(|index| *self.get(index))
+    }
+
+    // Including the storage itself, not just what it allocated
+    pub fn bytes_used(&self) -> usize {
+        match self {
+            Self::Uniform(_) => size_of::<Self>(),
+            Self::Dense(_) => size_of::<Self>() + size_of::<[T; CHUNK_VOLUME]>(),
+        }
+    }
+
+    // Values still shared with a copy are copied first
+    fn make_dense(&mut self) -> &mut [T; CHUNK_VOLUME] {
+        let values = match self {
+            Self::Uniform(value) => Some(vec![*value; CHUNK_VOLUME]),
+            Self::Dense(values) if Arc::strong_count(values) > 1 => Some(values.to_vec()),
+            Self::Dense(_) => None,
+        };
+        if let Some(values) = values {
+            // Built on the heap, a chunk of voxels is too big to comfortably go through the stack
+            let values: Box<[T; CHUNK_VOLUME]> = values.into_boxed_slice().try_into().ok().unwrap();
+            *self = Self::Dense(Arc::from(values));
+        }
+        match self {
+            Self::Dense(values) => Arc::get_mut(values).unwrap(),
+            Self::Uniform(_) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod voxel_storage_tests {
+    use super::*;
+
+    #[test]
+    fn only_divergent_writes_promote() {
+        let mut storage = VoxelStorage::new(0u16);
+        storage.set(10, 0);
+        assert!(storage.is_uniform());
+
+        storage.set(10, 3);
+        assert!(!storage.is_uniform());
+        assert_eq!(*storage.get(10), 3);
+        assert_eq!(*storage.get(11), 0);
+
+        storage.set(10, 0);
+        storage.compact();
+        assert!(storage.is_uniform());
+        assert_eq!(storage.bytes_used(), size_of::<VoxelStorage<u16>>());
+    }
+
+    #[test]
+    fn copies_share_values_until_written() {
+        let mut storage = VoxelStorage::new(0u16);
+        storage.set(10, 3);
+        let mut copy = storage.clone();
+        match (&storage, &copy) {
+            (VoxelStorage::Dense(a), VoxelStorage::Dense(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected dense storage"),
+        }
+
+        copy.set(11, 4);
+        assert_eq!(*storage.get(11), 0);
+        assert_eq!(*copy.get(11), 4);
+        assert_eq!(*copy.get(10), 3);
+    }
+}
@@ -0,0 +1,93 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use glam::{IVec3, UVec3};
+use graphics_test::{
+    noise::{cpu_simplex::CpuSimplex3D, simplex::NoiseSettings},
+    voxels::voxel_scene::{pos_to_index, VoxelScene, WorldConfig, CHUNK_SIZE},
+};
+
+// Generates a 5x1x5 area of chunks without a window or GPU, the meshes arrive on the channel the renderer would use
+#[test]
+fn generates_an_area_without_a_gpu() {
+    // A single layer of chunks, nothing above or below gets pulled in as a neighbour
+    let mut scene = VoxelScene::new_with_config(WorldConfig {
+        min_height: 0,
+        max_height: CHUNK_SIZE as i32,
+        ..Default::default()
+    });
+    let (mesh_sender, mesh_receiver) = flume::unbounded();
+    let (unload_sender, _unload_receiver) = flume::unbounded();
+    scene.setup_chunk_processors(mesh_sender, unload_sender);
+
+    let mut requested = Vec::new();
+    for x in -2..=2 {
+        for z in -2..=2 {
+            requested.push(IVec3::new(x, 0, z));
+            scene.initialize_and_generate_chunk(IVec3::new(x, 0, z));
+        }
+    }
+    assert!(scene.wait_until_idle(Duration::from_secs(120)));
+
+    // The requested chunks plus the neighbours along the edges they needed for meshing, corners aren't needed
+    assert_eq!(scene.loaded_chunk_count(), 25 + 4 * 5);
+    let meshes: HashMap<IVec3, _> = mesh_receiver.try_iter().collect();
+    let solid: Vec<IVec3> = requested
+        .iter()
+        .copied()
+        .filter(|chunk_pos| !scene.chunks.get(chunk_pos).unwrap().is_empty)
+        .collect();
+    assert!(!solid.is_empty());
+    assert_eq!(meshes.len(), solid.len());
+    for chunk_pos in solid {
+        let index_count: usize = meshes[&chunk_pos]
+            .iter()
+            .map(|(_, mesh)| mesh.read().index_count)
+            .sum();
+        assert!(index_count > 0);
+    }
+    scene.shutdown();
+}
+
+// The terrain follows the simplex noise computed on the CPU, in batches, instead of the biome formulas
+#[test]
+fn generates_noise_terrain_without_a_gpu() {
+    let mut scene = VoxelScene::new_with_config(WorldConfig {
+        min_height: 0,
+        max_height: CHUNK_SIZE as i32,
+        ..Default::default()
+    });
+    // The surface lies within the chunk layer so there is both ground and air
+    let noise = Arc::new(CpuSimplex3D::new(NoiseSettings {
+        height: 8.0,
+        amplitude: 6.0,
+        ..Default::default()
+    }));
+    scene.set_noise(Arc::clone(&noise) as _);
+    let (mesh_sender, _mesh_receiver) = flume::unbounded();
+    let (unload_sender, _unload_receiver) = flume::unbounded();
+    scene.setup_chunk_processors(mesh_sender, unload_sender);
+
+    let requested = [IVec3::ZERO, IVec3::new(-1, 0, 2)];
+    for chunk_pos in requested {
+        scene.initialize_and_generate_chunk(chunk_pos);
+    }
+    assert!(scene.wait_until_idle(Duration::from_secs(120)));
+
+    for chunk_pos in requested {
+        let field = noise.build_noise(chunk_pos);
+        let chunk = scene.chunks.get(&chunk_pos).unwrap();
+        let mut solid = 0;
+        for index in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            let local = UVec3::new(
+                index / (CHUNK_SIZE * CHUNK_SIZE),
+                index / CHUNK_SIZE % CHUNK_SIZE,
+                index % CHUNK_SIZE,
+            );
+            let is_solid = chunk.density_at(&local) > 0.0;
+            assert_eq!(is_solid, field[pos_to_index(&local) as usize] > 0.0);
+            solid += is_solid as u32;
+        }
+        assert!(solid > 0 && solid < CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+    }
+    scene.shutdown();
+}